@@ -15,11 +15,12 @@ use lib::laser::gaussian::GaussianBeam;
 use lib::magnetic::quadrupole::QuadrupoleField3D;
 use lib::output::file;
 use lib::output::file::Text;
+use lib::run::RunController;
 use lib::shapes::Cuboid;
 use lib::sim_region::{SimulationVolume, VolumeType};
 use nalgebra::Vector3;
 use specs::prelude::*;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 fn main() {
     let now = Instant::now();
@@ -149,10 +150,7 @@ fn main() {
         .with(Position {
             pos: Vector3::new(-0.083, 0.0, 0.0),
         })
-        .with(MassDistribution::new(vec![MassRatio {
-            mass: 88.0,
-            ratio: 1.0,
-        }]))
+        .with(MassDistribution::new(vec![MassRatio::new(88.0, 1.0)]))
         .with(AtomicTransition::strontium())
         .with(AtomNumberToEmit {
             number: number_to_emit,
@@ -194,11 +192,15 @@ fn main() {
     // Also use a velocity cap so that fast atoms are not even simulated.
     world.insert(VelocityCap { value: 200.0 });
 
-    // Run the simulation for a number of steps.
-    for _i in 0..10000 {
-        dispatcher.dispatch(&mut world);
-        world.maintain();
-    }
+    // Run the simulation for 10ms, or a maximum of ten minutes.
+    let reason = RunController::new()
+        .until_time(1.0e-2)
+        .with_wall_clock_limit(Duration::from_secs(600))
+        .run(&mut dispatcher, &mut world);
 
-    println!("Simulation completed in {} ms.", now.elapsed().as_millis());
+    println!(
+        "Simulation completed in {} ms ({:?}).",
+        now.elapsed().as_millis(),
+        reason
+    );
 }
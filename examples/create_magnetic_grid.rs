@@ -9,12 +9,12 @@ extern crate serde;
 extern crate serde_json;
 
 fn main() {
-    let grid = PrecalculatedMagneticFieldGrid {
-        extent_spatial: Vector3::new(1.0, 1.0, 1.0),
-        extent_cells: Vector3::new(1, 1, 1),
-        position: Vector3::new(0.0, 0.0, 0.0),
-        grid: vec![Vector3::new(1.0, 1.0, 1.0)],
-    };
+    let grid = PrecalculatedMagneticFieldGrid::new(
+        Vector3::new(1.0, 1.0, 1.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(1, 1, 1),
+        vec![Vector3::new(1.0, 1.0, 1.0)],
+    );
     let file = File::create("grid.json").expect("Cant open file");
     serde_json::to_writer(file, &grid).expect("Could not serialize grid");
 }
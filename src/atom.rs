@@ -1,8 +1,10 @@
 //! Common atom components and systems.
 
 use crate::constant::{BOHRMAG, C};
+use crate::magnetic::zeeman::ZeemanModel;
 use crate::output::file::BinaryConversion;
 use crate::ramp::Lerp;
+use crate::substep::{active_atoms, SubstepMask};
 use nalgebra::Vector3;
 use specs::prelude::*;
 
@@ -106,6 +108,43 @@ pub struct Mass {
 impl Component for Mass {
 	type Storage = VecStorage<Self>;
 }
+impl BinaryConversion for Mass {
+	fn data(&self) -> Vec<f64> {
+		vec![self.value]
+	}
+}
+
+/// The statistical weight of an atom, ie the number of real atoms that it represents.
+///
+/// Atoms without a `Weight` have a weight of 1. Atom sources which use
+/// [ImportanceSampling](../atom_sources/importance/struct.ImportanceSampling.html) add a `Weight` to each
+/// atom they create, so that weighted totals are unbiased estimates of the real totals.
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct Weight {
+	pub value: f64,
+}
+impl Weight {
+	/// Gets the weight of an atom which may not have a `Weight` component.
+	pub fn of(weight: Option<&Weight>) -> f64 {
+		match weight {
+			Some(weight) => weight.value,
+			None => 1.0,
+		}
+	}
+}
+impl Component for Weight {
+	type Storage = VecStorage<Self>;
+}
+impl fmt::Display for Weight {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?}", self.value)
+	}
+}
+impl BinaryConversion for Weight {
+	fn data(&self) -> Vec<f64> {
+		vec![self.value]
+	}
+}
 
 /// Component that marks an entity as an [atom](struct.Atom.html).
 /// This provides a simple way for systems to get only [atom](struct.Atom.html)s, even though non-atom entities may also share components, eg [position](struct.Position.html).
@@ -138,6 +177,15 @@ pub struct AtomicTransition {
 	pub saturation_intensity: f64,
 	/// Precalculate prefactor used in the determination of rate coefficients.
 	pub rate_prefactor: f64,
+	/// Model used to calculate the Zeeman shift of the transition.
+	#[serde(default)]
+	pub zeeman_model: ZeemanModel,
+	/// Static polarizability of the ground state, in SI units of C m^2/V.
+	#[serde(default)]
+	pub polarizability_ground: f64,
+	/// Static polarizability of the excited state, in SI units of C m^2/V.
+	#[serde(default)]
+	pub polarizability_excited: f64,
 }
 
 impl Component for AtomicTransition {
@@ -164,6 +212,9 @@ impl AtomicTransition {
 			linewidth: 6.065e6,          // [Steck, Rubidium87]
 			saturation_intensity: 16.69, // [Steck, Rubidium 87, D2 cycling transition]
 			rate_prefactor: 0.0,         // set in calculate
+			zeeman_model: ZeemanModel::Linear,
+			polarizability_ground: 0.0,
+			polarizability_excited: 0.0,
 		}
 		.calculate()
 	}
@@ -179,6 +230,9 @@ impl AtomicTransition {
 			linewidth: 32e6,             // [Nosske2017]
 			saturation_intensity: 430.0, // [Nosske2017, 43mW/cm^2]
 			rate_prefactor: 0.0,         // set in calculate
+			zeeman_model: ZeemanModel::Linear,
+			polarizability_ground: 0.0,
+			polarizability_excited: 0.0,
 		}
 		.calculate()
 	}
@@ -194,6 +248,9 @@ impl AtomicTransition {
 			linewidth: 7_400.,               // [Schreck2013]
 			saturation_intensity: 0.0295,    // [SChreck2013, 3 µW/cm^2]
 			rate_prefactor: 0.0,             // set in calculate
+			zeeman_model: ZeemanModel::Linear,
+			polarizability_ground: 0.0,
+			polarizability_excited: 0.0,
 		}
 		.calculate()
 	}
@@ -208,6 +265,9 @@ impl AtomicTransition {
 			linewidth: 190e3,
 			saturation_intensity: 0.13,
 			rate_prefactor: 0.0, // set in calculate
+			zeeman_model: ZeemanModel::Linear,
+			polarizability_ground: 0.0,
+			polarizability_excited: 0.0,
 		}
 		.calculate()
 	}
@@ -221,6 +281,9 @@ impl AtomicTransition {
 			linewidth: 30e6,
 			saturation_intensity: 56.0,
 			rate_prefactor: 0.0, // set in calculate
+			zeeman_model: ZeemanModel::Linear,
+			polarizability_ground: 0.0,
+			polarizability_excited: 0.0,
 		}
 		.calculate()
 	}
@@ -228,17 +291,27 @@ impl AtomicTransition {
 	pub fn gamma(&self) -> f64 {
 		self.linewidth * 2.0 * std::f64::consts::PI
 	}
+
+	/// Returns a copy of the transition, with the frequency shifted by `shift` Hz.
+	///
+	/// Use this to create the transitions of different isotopes of an element.
+	pub fn with_isotope_shift(&self, shift: f64) -> Self {
+		let mut transition = self.clone();
+		transition.frequency = transition.frequency + shift;
+		transition
+	}
 }
 
 /// A system that sets force to zero at the start of each simulation step.
 pub struct ClearForceSystem;
 
 impl<'a> System<'a> for ClearForceSystem {
-	type SystemData = WriteStorage<'a, Force>;
-	fn run(&mut self, mut force: Self::SystemData) {
+	type SystemData = (WriteStorage<'a, Force>, Option<Read<'a, SubstepMask>>);
+	fn run(&mut self, (mut force, substep_mask): Self::SystemData) {
 		use rayon::prelude::*;
 
-		(&mut force).par_join().for_each(|force| {
+		let atoms = active_atoms(substep_mask.as_deref(), force.mask());
+		(&atoms, &mut force).par_join().for_each(|(_, force)| {
 			force.force = Vector3::new(0.0, 0.0, 0.0);
 		});
 	}
@@ -253,4 +326,5 @@ pub fn register_components(world: &mut World) {
 	world.register::<Atom>();
 	world.register::<InitialVelocity>();
 	world.register::<Velocity>();
+	world.register::<Weight>();
 }
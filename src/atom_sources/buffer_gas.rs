@@ -0,0 +1,410 @@
+//! Cryogenic buffer-gas beam sources.
+//!
+//! Molecules and atoms leaving a cryogenic buffer-gas cell are partially boosted by collisions with the
+//! buffer gas near the cell aperture. The resulting beam has a forward velocity distribution that is
+//! well described by a Maxwellian shifted to a mean forward velocity, and a transverse velocity spread
+//! set by a (different) transverse temperature. Neither is captured by the effusive j(theta) model
+//! of the [Oven](../oven/struct.Oven.html).
+//!
+//! A [BufferGasBeam](struct.BufferGasBeam.html) is created using a [BufferGasBeamBuilder](struct.BufferGasBeamBuilder.html).
+//! The source entity also requires a `Position`, an `AtomicTransition`, a
+//! [MassDistribution](../mass/struct.MassDistribution.html) and an [AtomNumberToEmit](../emit/struct.AtomNumberToEmit.html).
+//! Buffer-gas beams are usually pulsed; the time profile of each pulse is set by adding an
+//! [EmissionSchedule](../emit/struct.EmissionSchedule.html) to the source, for instance with an
+//! [EmissionProfile::ExponentialPulse](../emit/enum.EmissionProfile.html).
+
+extern crate nalgebra;
+use nalgebra::Vector3;
+
+use super::emit::AtomNumberToEmit;
+use super::mass::MassDistribution;
+use super::VelocityCap;
+use crate::atom::*;
+use crate::constant::{AMU, BOLTZCONST, PI};
+use crate::initiate::NewlyCreated;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+
+use specs::{Component, Entities, HashMapStorage, Join, LazyUpdate, Read, ReadStorage, System};
+
+/// The distribution of forward velocities of a [BufferGasBeam](struct.BufferGasBeam.html).
+#[derive(Copy, Clone)]
+pub enum ForwardVelocityDistribution {
+    /// Flux-weighted Maxwellian at `temperature` (K), shifted to a forward velocity `mean` (m/s).
+    ///
+    /// The probability of a forward velocity `v` is proportional to `v^3 exp(-m (v - mean)^2 / 2 k T)`.
+    /// For `mean = 0`, this is the velocity distribution of an effusive beam.
+    ShiftedMaxwellian { mean: f64, temperature: f64 },
+    /// Gaussian distribution of forward velocities, with the given `mean` and standard deviation `std`, in m/s.
+    ///
+    /// The distribution is truncated to positive forward velocities.
+    Gaussian { mean: f64, std: f64 },
+}
+impl ForwardVelocityDistribution {
+    /// Panics if the parameters of the distribution cannot describe a beam leaving the cell.
+    fn validate(&self) {
+        match *self {
+            ForwardVelocityDistribution::ShiftedMaxwellian { mean, temperature } => {
+                if !(mean >= 0.0 && mean.is_finite()) {
+                    panic!(
+                        "ShiftedMaxwellian requires a finite, non-negative mean forward velocity."
+                    );
+                }
+                if !(temperature > 0.0 && temperature.is_finite()) {
+                    panic!("ShiftedMaxwellian requires a finite, positive temperature.");
+                }
+            }
+            ForwardVelocityDistribution::Gaussian { mean, std } => {
+                if !(mean > 0.0 && mean.is_finite()) {
+                    panic!(
+                        "Gaussian forward velocity distribution requires a finite, positive mean."
+                    );
+                }
+                if !(std >= 0.0 && std.is_finite()) {
+                    panic!("Gaussian forward velocity distribution requires a finite, non-negative std.");
+                }
+            }
+        }
+    }
+
+    /// Draws a random forward velocity, in m/s, for a particle of the given mass, in kg.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R, mass: f64) -> f64 {
+        match *self {
+            ForwardVelocityDistribution::ShiftedMaxwellian { mean, temperature } => {
+                // Rejection sampling, using the shifted Gaussian as the proposal distribution.
+                let std = (BOLTZCONST * temperature / mass).sqrt();
+                let normal = Normal::new(mean, std).unwrap();
+                let max_velocity = mean.max(0.0) + 6.0 * std;
+                loop {
+                    let v = normal.sample(rng);
+                    if v <= 0.0 || v > max_velocity {
+                        continue;
+                    }
+                    if rng.gen::<f64>() < (v / max_velocity).powi(3) {
+                        return v;
+                    }
+                }
+            }
+            ForwardVelocityDistribution::Gaussian { mean, std } => {
+                let normal = Normal::new(mean, std).unwrap();
+                loop {
+                    let v = normal.sample(rng);
+                    if v > 0.0 {
+                        return v;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The aperture of the buffer-gas cell, which lies in the plane perpendicular to the beam direction.
+#[derive(Copy, Clone)]
+pub enum CellAperture {
+    /// Circular aperture, with radius in m.
+    Circular { radius: f64 },
+    /// Rectangular slit. `width` (m) is measured along the beam's first transverse axis, and
+    /// `height` (m) along the second.
+    Rectangular { width: f64, height: f64 },
+}
+
+/// Builder struct for creating a [BufferGasBeam](struct.BufferGasBeam.html).
+pub struct BufferGasBeamBuilder {
+    direction: Vector3<f64>,
+    forward_velocity: ForwardVelocityDistribution,
+    transverse_temperature: f64,
+    aperture: CellAperture,
+}
+impl BufferGasBeamBuilder {
+    /// Creates a builder for a beam travelling along `direction`.
+    ///
+    /// By default, the beam has a forward velocity of 150 m/s at a temperature of 4 K, a transverse
+    /// temperature of 4 K, and leaves the cell through a circular aperture of 2.5 mm radius.
+    pub fn new(direction: Vector3<f64>) -> Self {
+        Self {
+            direction: direction.normalize(),
+            forward_velocity: ForwardVelocityDistribution::ShiftedMaxwellian {
+                mean: 150.0,
+                temperature: 4.0,
+            },
+            transverse_temperature: 4.0,
+            aperture: CellAperture::Circular { radius: 2.5e-3 },
+        }
+    }
+
+    pub fn with_forward_velocity(
+        &mut self,
+        distribution: ForwardVelocityDistribution,
+    ) -> &mut Self {
+        self.forward_velocity = distribution;
+        return self;
+    }
+
+    /// Sets the transverse temperature of the beam, in Kelvin.
+    pub fn with_transverse_temperature(&mut self, temperature: f64) -> &mut Self {
+        self.transverse_temperature = temperature;
+        return self;
+    }
+
+    pub fn with_aperture(&mut self, aperture: CellAperture) -> &mut Self {
+        self.aperture = aperture;
+        return self;
+    }
+
+    /// Creates the [BufferGasBeam](struct.BufferGasBeam.html).
+    ///
+    /// Panics if the forward velocity distribution, transverse temperature or aperture are not physical,
+    /// eg a negative temperature or width.
+    pub fn build(&self) -> BufferGasBeam {
+        self.forward_velocity.validate();
+        if !(self.transverse_temperature >= 0.0 && self.transverse_temperature.is_finite()) {
+            panic!("BufferGasBeam requires a finite, non-negative transverse temperature.");
+        }
+        let aperture_valid = match self.aperture {
+            CellAperture::Circular { radius } => radius > 0.0 && radius.is_finite(),
+            CellAperture::Rectangular { width, height } => {
+                width > 0.0 && width.is_finite() && height > 0.0 && height.is_finite()
+            }
+        };
+        if !aperture_valid {
+            panic!("BufferGasBeam requires an aperture with finite, positive dimensions.");
+        }
+        let trial = if self.direction[0].abs() < 0.9 {
+            Vector3::x()
+        } else {
+            Vector3::y()
+        };
+        let perp_x = self.direction.cross(&trial).normalize();
+        let perp_y = self.direction.cross(&perp_x);
+        BufferGasBeam {
+            direction: self.direction,
+            perp_x: perp_x,
+            perp_y: perp_y,
+            forward_velocity: self.forward_velocity,
+            transverse_temperature: self.transverse_temperature,
+            aperture: self.aperture,
+        }
+    }
+}
+
+/// Component representing a cryogenic buffer-gas beam source.
+///
+/// Particles are created uniformly across the cell aperture, which is centred on the `Position` of the
+/// source. The velocity of each particle along `direction` is drawn from the forward velocity
+/// distribution, and the velocities along the two transverse axes are drawn from a Maxwell-Boltzmann
+/// distribution at the transverse temperature.
+pub struct BufferGasBeam {
+    /// A normalised vector, aligned to the direction of the beam.
+    pub direction: Vector3<f64>,
+    /// A normalised vector, perpendicular to the beam.
+    pub perp_x: Vector3<f64>,
+    /// A normalised vector, perpendicular to the beam and to `perp_x`.
+    pub perp_y: Vector3<f64>,
+    pub forward_velocity: ForwardVelocityDistribution,
+    /// Transverse temperature of the beam, in Kelvin.
+    pub transverse_temperature: f64,
+    pub aperture: CellAperture,
+}
+impl BufferGasBeam {
+    /// Gets a random position within the cell aperture, relative to the centre of the aperture.
+    pub fn get_random_spawn_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Vector3<f64> {
+        match self.aperture {
+            CellAperture::Circular { radius } => {
+                let r = radius * rng.gen_range(0.0..1.0_f64).sqrt();
+                let angle = rng.gen_range(0.0..2.0 * PI);
+                r * (self.perp_x * angle.cos() + self.perp_y * angle.sin())
+            }
+            CellAperture::Rectangular { width, height } => {
+                self.perp_x * rng.gen_range(-0.5 * width..0.5 * width)
+                    + self.perp_y * rng.gen_range(-0.5 * height..0.5 * height)
+            }
+        }
+    }
+
+    /// Gets a random velocity for a particle with the given mass, in atomic mass units.
+    pub fn get_random_velocity<R: Rng + ?Sized>(&self, rng: &mut R, mass: f64) -> Vector3<f64> {
+        let forward = self.forward_velocity.sample(rng, mass * AMU);
+        let transverse_std = (BOLTZCONST * self.transverse_temperature / (mass * AMU)).sqrt();
+        let transverse = Normal::new(0.0, transverse_std).unwrap();
+        self.direction * forward
+            + self.perp_x * transverse.sample(rng)
+            + self.perp_y * transverse.sample(rng)
+    }
+}
+impl Component for BufferGasBeam {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// This system creates particles from buffer-gas beam sources.
+pub struct BufferGasBeamCreateAtomsSystem;
+
+impl<'a> System<'a> for BufferGasBeamCreateAtomsSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, BufferGasBeam>,
+        ReadStorage<'a, AtomicTransition>,
+        ReadStorage<'a, AtomNumberToEmit>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, MassDistribution>,
+        Option<Read<'a, VelocityCap>>,
+        Read<'a, LazyUpdate>,
+        Option<Read<'a, FrameRandomKey>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            beams,
+            atom_infos,
+            numbers_to_emit,
+            positions,
+            mass_distributions,
+            velocity_cap,
+            updater,
+            random_key,
+        ): Self::SystemData,
+    ) {
+        let max_vel = match velocity_cap {
+            Some(cap) => cap.value,
+            None => std::f64::MAX,
+        };
+
+        let mut rng = RandomStreams::new(random_key.as_deref(), "buffer_gas_create_atoms").rng();
+        for (beam, atom_info, number_to_emit, source_position, mass_distribution) in (
+            &beams,
+            &atom_infos,
+            &numbers_to_emit,
+            &positions,
+            &mass_distributions,
+        )
+            .join()
+        {
+            for _i in 0..number_to_emit.number {
+                let isotope = mass_distribution.draw_random_isotope(&mut rng);
+                let mass = Mass {
+                    value: isotope.mass,
+                };
+                let velocity = beam.get_random_velocity(&mut rng, mass.value);
+                if velocity.norm() > max_vel {
+                    continue;
+                }
+                let position = source_position.pos + beam.get_random_spawn_position(&mut rng);
+
+                let new_atom = entities.create();
+                updater.insert(new_atom, Position { pos: position });
+                updater.insert(new_atom, Velocity { vel: velocity });
+                updater.insert(new_atom, Force::new());
+                updater.insert(new_atom, mass);
+                updater.insert(new_atom, isotope.get_transition(atom_info));
+                updater.insert(new_atom, Atom);
+                updater.insert(new_atom, InitialVelocity { vel: velocity });
+                updater.insert(new_atom, NewlyCreated);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_buffer_gas_beam_velocities() {
+        let mut rng = rand::thread_rng();
+        let mass = 191.0;
+        let transverse_temperature = 2.0;
+        let beam = BufferGasBeamBuilder::new(Vector3::new(0.0, 0.0, 1.0))
+            .with_forward_velocity(ForwardVelocityDistribution::Gaussian {
+                mean: 180.0,
+                std: 20.0,
+            })
+            .with_transverse_temperature(transverse_temperature)
+            .with_aperture(CellAperture::Rectangular {
+                width: 4e-3,
+                height: 1e-3,
+            })
+            .build();
+
+        let n = 20000;
+        let mut mean_forward = 0.0;
+        let mut transverse_variance = 0.0;
+        for _ in 0..n {
+            let velocity = beam.get_random_velocity(&mut rng, mass);
+            mean_forward += velocity[2] / n as f64;
+            transverse_variance += (velocity[0].powi(2) + velocity[1].powi(2)) / (2.0 * n as f64);
+
+            let position = beam.get_random_spawn_position(&mut rng);
+            assert_eq!(position[2], 0.0);
+            assert!(position.dot(&beam.perp_x).abs() <= 2e-3);
+            assert!(position.dot(&beam.perp_y).abs() <= 0.5e-3);
+        }
+        assert!((mean_forward - 180.0).abs() < 1.0);
+        let expected = BOLTZCONST * transverse_temperature / (mass * AMU);
+        assert!((transverse_variance / expected - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_shifted_maxwellian() {
+        let mut rng = rand::thread_rng();
+        let mass = 88.0 * AMU;
+        let temperature = 4.0;
+
+        // Without a shift, the forward velocity follows the effusive distribution with
+        // mean v_0 3 sqrt(pi) / 4, where v_0 = sqrt(2kT/m).
+        let distribution = ForwardVelocityDistribution::ShiftedMaxwellian {
+            mean: 0.0,
+            temperature: temperature,
+        };
+        let n = 20000;
+        let mean = (0..n)
+            .map(|_| distribution.sample(&mut rng, mass))
+            .sum::<f64>()
+            / n as f64;
+        let v0 = (2.0 * BOLTZCONST * temperature / mass).sqrt();
+        assert!((mean / (0.75 * PI.sqrt() * v0) - 1.0).abs() < 0.02);
+
+        // A strongly boosted beam has a mean velocity close to the shift.
+        let distribution = ForwardVelocityDistribution::ShiftedMaxwellian {
+            mean: 200.0,
+            temperature: temperature,
+        };
+        let mean = (0..n)
+            .map(|_| distribution.sample(&mut rng, mass))
+            .sum::<f64>()
+            / n as f64;
+        assert!(mean > 200.0 && mean < 210.0);
+    }
+    #[test]
+    fn test_gaussian_forward_velocities_are_positive() {
+        let mut rng = rand::thread_rng();
+        let distribution = ForwardVelocityDistribution::Gaussian {
+            mean: 10.0,
+            std: 20.0,
+        };
+        for _ in 0..1000 {
+            assert!(distribution.sample(&mut rng, 88.0 * AMU) > 0.0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "transverse temperature")]
+    fn test_negative_transverse_temperature() {
+        BufferGasBeamBuilder::new(Vector3::z())
+            .with_transverse_temperature(-1.0)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "non-negative std")]
+    fn test_invalid_forward_velocity_width() {
+        BufferGasBeamBuilder::new(Vector3::z())
+            .with_forward_velocity(ForwardVelocityDistribution::Gaussian {
+                mean: 150.0,
+                std: f64::NAN,
+            })
+            .build();
+    }
+}
@@ -7,17 +7,23 @@
 //! in the first MOT stage.
 //!
 //! This is accomplished by 5 different types of distributions, 3 scalar and 2 vector.
+//! Alternatively, the velocity can be drawn directly from a
+//! [VelocityDensityDistribution](enum.VelocityDensityDistribution.html), such as a thermal distribution.
+//! Use the [CentralCreatorBuilder](struct.CentralCreatorBuilder.html) to choose the distributions.
 
 extern crate nalgebra;
 use nalgebra::Vector3;
 use rand::Rng;
+use rand_distr::{Distribution, Normal};
 use specs::prelude::*;
 
 use super::emit::AtomNumberToEmit;
 use super::mass::MassDistribution;
 use super::VelocityCap;
 use crate::atom::*;
+use crate::constant::{AMU, BOLTZCONST};
 use crate::initiate::*;
+use crate::rng::{FrameRandomKey, RandomStreams};
 
 // Define some distributions that are necessary to custom-create the initial
 // conditions of the atoms created
@@ -30,8 +36,24 @@ use crate::initiate::*;
 /// More complicated distribution types will likely be introduced in the future
 #[derive(Copy, Clone)]
 pub enum PositionDensityDistribution {
-    UniformCuboidic { size: [f64; 3] },
-    UniformSpheric { radius: f64 },
+    UniformCuboidic {
+        size: [f64; 3],
+    },
+    UniformSpheric {
+        radius: f64,
+    },
+    /// Gaussian distribution with the given `mean` and standard deviation `std` along each axis, in m.
+    Gaussian {
+        mean: Vector3<f64>,
+        std: Vector3<f64>,
+    },
+    /// Thermal distribution of atoms at `temperature` (in K) in a harmonic trap centred on the origin.
+    ///
+    /// `trap_frequencies` are the angular frequencies of the trap along each axis, in rad/s.
+    ThermalHarmonic {
+        temperature: f64,
+        trap_frequencies: Vector3<f64>,
+    },
 }
 
 ///  Distribution of the characteristic velocity of a particle at a certain position in space
@@ -75,18 +97,55 @@ pub enum VectorDensityDistribution {
     Uniform {},
 }
 
+/// Distribution of the velocity of a created atom.
+#[derive(Copy, Clone)]
+pub enum VelocityDensityDistribution {
+    /// The speed and direction are drawn separately, from the speed and vector distributions of the
+    /// [CentralCreator](struct.CentralCreator.html).
+    SpeedAndDirection,
+    /// Gaussian distribution with the given `mean` and standard deviation `std` along each axis, in m/s.
+    Gaussian {
+        mean: Vector3<f64>,
+        std: Vector3<f64>,
+    },
+    /// Maxwell-Boltzmann distribution at `temperature` (in K), moving with a mean velocity `drift` (in m/s).
+    ///
+    /// The width of the distribution depends on the mass of each atom.
+    Thermal {
+        temperature: f64,
+        drift: Vector3<f64>,
+    },
+}
+
+/// Draws a random vector with normally-distributed components.
+fn sample_gaussian<R: Rng + ?Sized>(
+    rng: &mut R,
+    mean: Vector3<f64>,
+    std: Vector3<f64>,
+) -> Vector3<f64> {
+    let standard = Normal::new(0.0, 1.0).unwrap();
+    Vector3::new(
+        standard.sample(rng),
+        standard.sample(rng),
+        standard.sample(rng),
+    )
+    .component_mul(&std)
+        + mean
+}
+
 /// CentralCreator is a component that can be attached to a source entity.
 ///
 /// It spawns atoms based on position in a coordinate system (which normally has the
 /// same center as the entire setup), hence the name
 ///
-/// It is designed in analogy to the Oven but without a builder (yet, might come later)
+/// It is designed in analogy to the Oven, and can be created using a [CentralCreatorBuilder](struct.CentralCreatorBuilder.html).
 pub struct CentralCreator {
     position_density_distribution: PositionDensityDistribution,
     spatial_speed_distribution: SpatialSpeedDistribution,
     speed_density_distribution: SpeedDensityDistribution,
     spatial_vector_distribution: SpatialVectorDistribution,
     vector_density_distribution: VectorDensityDistribution,
+    velocity_density_distribution: VelocityDensityDistribution,
 }
 
 impl CentralCreator {
@@ -102,13 +161,18 @@ impl CentralCreator {
             },
             spatial_vector_distribution: SpatialVectorDistribution::Uniform {},
             vector_density_distribution: VectorDensityDistribution::Uniform {},
+            velocity_density_distribution: VelocityDensityDistribution::SpeedAndDirection,
         }
     }
 
     // sample frome the central_creator and get random position and velocity vectors
-    pub fn get_random_spawn_condition(&self) -> (Vector3<f64>, Vector3<f64>) {
-        let mut rng = rand::thread_rng();
-
+    //
+    // `mass` is the mass of the atom being created.
+    pub fn get_random_spawn_condition<R: Rng + ?Sized>(
+        &self,
+        mass: &Mass,
+        rng: &mut R,
+    ) -> (Vector3<f64>, Vector3<f64>) {
         let pos_vector = match self.position_density_distribution {
             PositionDensityDistribution::UniformCuboidic { size } => {
                 let size = size.clone();
@@ -117,12 +181,39 @@ impl CentralCreator {
                 let pos3 = rng.gen_range(-0.5 * size[2]..0.5 * size[2]);
                 nalgebra::Vector3::new(pos1, pos2, pos3)
             }
-            PositionDensityDistribution::UniformSpheric { radius: _ } => {
-                // Not implemented!
-                panic!("get_random_spawn_condition for PositionDensityDistribution::UniformSpheric not yet implemented!");
+            PositionDensityDistribution::UniformSpheric { radius } => loop {
+                let pos = Vector3::new(
+                    rng.gen_range(-radius..radius),
+                    rng.gen_range(-radius..radius),
+                    rng.gen_range(-radius..radius),
+                );
+                if pos.norm_squared() < radius.powi(2) {
+                    break pos;
+                }
+            },
+            PositionDensityDistribution::Gaussian { mean, std } => sample_gaussian(rng, mean, std),
+            PositionDensityDistribution::ThermalHarmonic {
+                temperature,
+                trap_frequencies,
+            } => {
+                let thermal_speed = (BOLTZCONST * temperature / (mass.value * AMU)).sqrt();
+                let std = trap_frequencies.map(|omega| thermal_speed / omega);
+                sample_gaussian(rng, Vector3::new(0.0, 0.0, 0.0), std)
             }
         };
 
+        match self.velocity_density_distribution {
+            VelocityDensityDistribution::SpeedAndDirection => (),
+            VelocityDensityDistribution::Gaussian { mean, std } => {
+                return (pos_vector, sample_gaussian(rng, mean, std));
+            }
+            VelocityDensityDistribution::Thermal { temperature, drift } => {
+                let thermal_speed = (BOLTZCONST * temperature / (mass.value * AMU)).sqrt();
+                let std = Vector3::new(thermal_speed, thermal_speed, thermal_speed);
+                return (pos_vector, sample_gaussian(rng, drift, std));
+            }
+        }
+
         let characteristic_speed: f64 = match self.spatial_speed_distribution {
             SpatialSpeedDistribution::Uniform { speed } => speed,
             SpatialSpeedDistribution::UniformCuboidic { speed: _, size: _ } => {
@@ -168,6 +259,108 @@ impl Component for CentralCreator {
     type Storage = HashMapStorage<Self>;
 }
 
+/// Builder struct for creating a [CentralCreator](struct.CentralCreator.html).
+///
+/// By default, atoms are created uniformly within a 1mm cube, with velocities drawn from a
+/// Maxwell-Boltzmann distribution at 1mK.
+pub struct CentralCreatorBuilder {
+    position_density_distribution: PositionDensityDistribution,
+    spatial_speed_distribution: SpatialSpeedDistribution,
+    speed_density_distribution: SpeedDensityDistribution,
+    spatial_vector_distribution: SpatialVectorDistribution,
+    vector_density_distribution: VectorDensityDistribution,
+    velocity_density_distribution: VelocityDensityDistribution,
+}
+impl CentralCreatorBuilder {
+    pub fn new() -> Self {
+        Self {
+            position_density_distribution: PositionDensityDistribution::UniformCuboidic {
+                size: [1e-3, 1e-3, 1e-3],
+            },
+            spatial_speed_distribution: SpatialSpeedDistribution::Uniform { speed: 0.0 },
+            speed_density_distribution: SpeedDensityDistribution::UniformCentral { width: 0.0 },
+            spatial_vector_distribution: SpatialVectorDistribution::Uniform {},
+            vector_density_distribution: VectorDensityDistribution::Uniform {},
+            velocity_density_distribution: VelocityDensityDistribution::Thermal {
+                temperature: 1e-3,
+                drift: Vector3::new(0.0, 0.0, 0.0),
+            },
+        }
+    }
+
+    pub fn with_position_distribution(
+        &mut self,
+        distribution: PositionDensityDistribution,
+    ) -> &mut Self {
+        self.position_density_distribution = distribution;
+        return self;
+    }
+
+    /// Creates atoms in a Gaussian cloud, with the given centre and widths along each axis, in m.
+    pub fn with_gaussian_position(&mut self, mean: Vector3<f64>, std: Vector3<f64>) -> &mut Self {
+        self.with_position_distribution(PositionDensityDistribution::Gaussian {
+            mean: mean,
+            std: std,
+        })
+    }
+
+    pub fn with_velocity_distribution(
+        &mut self,
+        distribution: VelocityDensityDistribution,
+    ) -> &mut Self {
+        self.velocity_density_distribution = distribution;
+        return self;
+    }
+
+    /// Draws velocities from a Maxwell-Boltzmann distribution at the given temperature (in K),
+    /// with mean velocity `drift` (in m/s).
+    pub fn with_thermal_velocity(&mut self, temperature: f64, drift: Vector3<f64>) -> &mut Self {
+        self.with_velocity_distribution(VelocityDensityDistribution::Thermal {
+            temperature: temperature,
+            drift: drift,
+        })
+    }
+
+    /// Draws velocities from a Gaussian distribution, with the given mean and widths along each axis, in m/s.
+    pub fn with_gaussian_velocity(&mut self, mean: Vector3<f64>, std: Vector3<f64>) -> &mut Self {
+        self.with_velocity_distribution(VelocityDensityDistribution::Gaussian {
+            mean: mean,
+            std: std,
+        })
+    }
+
+    /// Draws the speed and direction of each atom separately from the given distributions.
+    pub fn with_speed_and_direction(
+        &mut self,
+        spatial_speed_distribution: SpatialSpeedDistribution,
+        speed_density_distribution: SpeedDensityDistribution,
+        spatial_vector_distribution: SpatialVectorDistribution,
+        vector_density_distribution: VectorDensityDistribution,
+    ) -> &mut Self {
+        self.spatial_speed_distribution = spatial_speed_distribution;
+        self.speed_density_distribution = speed_density_distribution;
+        self.spatial_vector_distribution = spatial_vector_distribution;
+        self.vector_density_distribution = vector_density_distribution;
+        self.with_velocity_distribution(VelocityDensityDistribution::SpeedAndDirection)
+    }
+
+    pub fn build(&self) -> CentralCreator {
+        CentralCreator {
+            position_density_distribution: self.position_density_distribution,
+            spatial_speed_distribution: self.spatial_speed_distribution,
+            speed_density_distribution: self.speed_density_distribution,
+            spatial_vector_distribution: self.spatial_vector_distribution,
+            vector_density_distribution: self.vector_density_distribution,
+            velocity_density_distribution: self.velocity_density_distribution,
+        }
+    }
+}
+impl Default for CentralCreatorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Creates atoms from an `CentralCreator` source.
 pub struct CentralCreatorCreateAtomsSystem;
 
@@ -181,6 +374,7 @@ impl<'a> System<'a> for CentralCreatorCreateAtomsSystem {
         ReadStorage<'a, MassDistribution>,
         Option<Read<'a, VelocityCap>>,
         Read<'a, LazyUpdate>,
+        Option<Read<'a, FrameRandomKey>>,
     );
     fn run(
         &mut self,
@@ -193,6 +387,7 @@ impl<'a> System<'a> for CentralCreatorCreateAtomsSystem {
             mass_distribution,
             velocity_cap,
             updater,
+            random_key,
         ): Self::SystemData,
     ) {
         let max_vel = match velocity_cap {
@@ -200,6 +395,8 @@ impl<'a> System<'a> for CentralCreatorCreateAtomsSystem {
             None => std::f64::MAX,
         };
 
+        let mut rng = RandomStreams::new(random_key.as_deref(), "central_create_system").rng();
+
         for (central_creator, atom, number_to_emit, _creator_position, mass_distribution) in (
             &central_creator,
             &atom,
@@ -210,8 +407,12 @@ impl<'a> System<'a> for CentralCreatorCreateAtomsSystem {
             .join()
         {
             for _i in 0..number_to_emit.number {
-                let mass = mass_distribution.draw_random_mass();
-                let (start_position, start_velocity) = central_creator.get_random_spawn_condition();
+                let isotope = mass_distribution.draw_random_isotope(&mut rng);
+                let mass = Mass {
+                    value: isotope.mass,
+                };
+                let (start_position, start_velocity) =
+                    central_creator.get_random_spawn_condition(&mass, &mut rng);
 
                 if start_velocity.norm() > max_vel {
                     continue;
@@ -232,7 +433,7 @@ impl<'a> System<'a> for CentralCreatorCreateAtomsSystem {
                 );
                 updater.insert(new_atom, Force::new());
                 updater.insert(new_atom, mass);
-                updater.insert(new_atom, atom.clone());
+                updater.insert(new_atom, isotope.get_transition(atom));
                 updater.insert(new_atom, Atom);
                 updater.insert(
                     new_atom,
@@ -253,6 +454,46 @@ pub mod tests {
     extern crate nalgebra;
     use nalgebra::Vector3;
 
+    #[test]
+    fn test_thermal_central_creator() {
+        let temperature = 10e-6;
+        let drift = Vector3::new(0.0, 0.0, 0.5);
+        let creator = CentralCreatorBuilder::new()
+            .with_gaussian_position(Vector3::new(1e-3, 0.0, 0.0), Vector3::new(1e-4, 2e-4, 3e-4))
+            .with_thermal_velocity(temperature, drift)
+            .build();
+        let mass = Mass { value: 88.0 };
+
+        let mut rng = rand::thread_rng();
+        let n = 10000;
+        let samples: Vec<_> = (0..n)
+            .map(|_| creator.get_random_spawn_condition(&mass, &mut rng))
+            .collect();
+        let mean_position = samples
+            .iter()
+            .fold(Vector3::new(0.0, 0.0, 0.0), |acc, s| acc + s.0)
+            / n as f64;
+        let mean_velocity = samples
+            .iter()
+            .fold(Vector3::new(0.0, 0.0, 0.0), |acc, s| acc + s.1)
+            / n as f64;
+        let var_position = samples.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, s| {
+            acc + (s.0 - mean_position).component_mul(&(s.0 - mean_position))
+        }) / n as f64;
+        let var_velocity = samples
+            .iter()
+            .fold(0.0, |acc, s| acc + (s.1 - mean_velocity).norm_squared())
+            / (3.0 * n as f64);
+
+        assert!((mean_position - Vector3::new(1e-3, 0.0, 0.0)).norm() < 2e-5);
+        assert!((mean_velocity - drift).norm() < 2e-3);
+        for (var, std) in var_position.iter().zip(&[1e-4, 2e-4, 3e-4]) {
+            assert!((var.sqrt() / std - 1.0).abs() < 0.05);
+        }
+        let expected = BOLTZCONST * temperature / (88.0 * AMU);
+        assert!((var_velocity / expected - 1.0).abs() < 0.05);
+    }
+
     /// Tests the correct implementation of the `SampleLaserIntensitySystem`
     #[test]
     fn test_central_creator_create_atoms_system() {
@@ -282,10 +523,7 @@ pub mod tests {
                 pos: Vector3::new(0.0, 0.0, 0.0),
             })
             .with(MassDistribution::new(vec![
-                crate::atom_sources::mass::MassRatio {
-                    mass: 88.0,
-                    ratio: 1.0,
-                },
+                crate::atom_sources::mass::MassRatio::new(88.0, 1.0),
             ]))
             .with(AtomicTransition::strontium_red())
             .with(AtomNumberToEmit {
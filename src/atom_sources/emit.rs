@@ -1,8 +1,15 @@
 //! Emission of atoms (over time)
+//!
+//! Sources can emit a fixed number of atoms each frame ([EmitNumberPerFrame](struct.EmitNumberPerFrame.html)),
+//! emit at a fixed average rate ([EmitFixedRate](struct.EmitFixedRate.html)), or follow an
+//! [EmissionSchedule](struct.EmissionSchedule.html) in which the rate varies with simulation time.
+//! Schedules can be used to model pulsed sources, such as ablation sources or pulsed valves.
 
 extern crate nalgebra;
-use crate::integrator::Timestep;
-use rand;
+use crate::constant::PI;
+use crate::integrator::{SimulationTime, Timestep};
+use crate::maths::erf;
+use crate::rng::{FrameRandomKey, RandomStreams};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use specs::prelude::*;
@@ -32,6 +39,256 @@ impl Component for EmitOnce {
     type Storage = HashMapStorage<Self>;
 }
 
+/// The rate of emission of atoms during a pulse of an [EmissionSchedule](struct.EmissionSchedule.html).
+///
+/// All times are measured from the start of the pulse, in s. Rates are in atoms per second.
+pub enum EmissionProfile {
+    /// Atoms are emitted at a constant rate.
+    Constant { rate: f64 },
+    /// A total of `number` atoms are emitted in a Gaussian pulse, centred at `time` with standard deviation `width`.
+    GaussianPulse { number: f64, time: f64, width: f64 },
+    /// A total of `number` atoms are emitted in a pulse which rises instantly and decays exponentially with `time_constant`.
+    ///
+    /// This describes the extraction of particles from a buffer-gas cell after ablation.
+    ExponentialPulse { number: f64, time_constant: f64 },
+    /// The rate is linearly interpolated from a [RateTable](struct.RateTable.html), and is zero outside it.
+    Tabulated(RateTable),
+    /// The rate is given by a user-defined function of time.
+    Function(Box<dyn Fn(f64) -> f64 + Send + Sync>),
+}
+impl EmissionProfile {
+    /// Creates a [Tabulated](#variant.Tabulated) profile from the given `times` and `rates`.
+    ///
+    /// Panics if the table is invalid, see [RateTable::new](struct.RateTable.html#method.new).
+    pub fn tabulated(times: Vec<f64>, rates: Vec<f64>) -> Self {
+        EmissionProfile::Tabulated(RateTable::new(times, rates))
+    }
+
+    /// Gets the rate of emission at the given time since the start of the pulse.
+    pub fn rate(&self, time: f64) -> f64 {
+        match self {
+            EmissionProfile::Constant { rate } => *rate,
+            EmissionProfile::GaussianPulse {
+                number,
+                time: centre,
+                width,
+            } => {
+                number / ((2.0 * PI).sqrt() * width)
+                    * (-(time - centre).powi(2) / (2.0 * width.powi(2))).exp()
+            }
+            EmissionProfile::ExponentialPulse {
+                number,
+                time_constant,
+            } => number / time_constant * (-time / time_constant).exp(),
+            EmissionProfile::Tabulated(table) => table.rate(time),
+            EmissionProfile::Function(function) => function(time),
+        }
+    }
+
+    /// Gets the number of atoms emitted between the start of the pulse and the given time.
+    ///
+    /// Returns `None` for a [Function](#variant.Function) profile, which cannot be integrated in closed form.
+    fn number_before(&self, time: f64) -> Option<f64> {
+        if !(time > 0.0) {
+            return Some(0.0);
+        }
+        let number = match self {
+            EmissionProfile::Constant { rate } => rate * time,
+            EmissionProfile::GaussianPulse {
+                number,
+                time: centre,
+                width,
+            } => {
+                let scale = 2.0_f64.sqrt() * width;
+                0.5 * number * (erf((time - centre) / scale) - erf(-centre / scale))
+            }
+            EmissionProfile::ExponentialPulse {
+                number,
+                time_constant,
+            } => number * (1.0 - (-time / time_constant).exp()),
+            EmissionProfile::Tabulated(table) => table.number_before(time),
+            EmissionProfile::Function(_) => return None,
+        };
+        Some(number)
+    }
+}
+
+/// A table of emission rates, linearly interpolated in time.
+pub struct RateTable {
+    times: Vec<f64>,
+    rates: Vec<f64>,
+}
+impl RateTable {
+    /// Creates a table of emission `rates`, in atoms per second, at the given `times`, in s.
+    ///
+    /// Panics if `times` and `rates` have different lengths, if any entry is not finite,
+    /// or if `times` are not sorted in increasing order.
+    pub fn new(times: Vec<f64>, rates: Vec<f64>) -> Self {
+        if times.len() != rates.len() {
+            panic!(
+                "RateTable has {} times but {} rates.",
+                times.len(),
+                rates.len()
+            );
+        }
+        if times
+            .iter()
+            .chain(rates.iter())
+            .any(|value| !value.is_finite())
+        {
+            panic!("RateTable times and rates must be finite.");
+        }
+        if times.windows(2).any(|pair| pair[1] < pair[0]) {
+            panic!("RateTable times must be sorted in increasing order.");
+        }
+        RateTable { times, rates }
+    }
+
+    /// Gets the interpolated rate at the given time, which is zero outside the table.
+    pub fn rate(&self, time: f64) -> f64 {
+        let times = &self.times;
+        let rates = &self.rates;
+        if times.is_empty() || !(time >= times[0] && time <= times[times.len() - 1]) {
+            return 0.0;
+        }
+        let i = times.iter().rposition(|&t| t <= time).unwrap_or(0);
+        if i == times.len() - 1 {
+            return rates[i];
+        }
+        let fraction = (time - times[i]) / (times[i + 1] - times[i]);
+        rates[i] + fraction * (rates[i + 1] - rates[i])
+    }
+
+    /// Gets the integral of the rate from the start of the table up to the given time.
+    fn number_before(&self, time: f64) -> f64 {
+        let mut number = 0.0;
+        for i in 1..self.times.len() {
+            let (start, end) = (self.times[i - 1], self.times[i]);
+            if time <= start {
+                break;
+            }
+            if end > start {
+                let upper = time.min(end);
+                let upper_rate = self.rates[i - 1]
+                    + (upper - start) / (end - start) * (self.rates[i] - self.rates[i - 1]);
+                number = number + 0.5 * (self.rates[i - 1] + upper_rate) * (upper - start);
+            }
+        }
+        number
+    }
+}
+
+/// Component which indicates the source should emit atoms according to a schedule.
+///
+/// Atoms are emitted in pulses with the given `profile`. The first pulse begins at `start`, and each pulse
+/// lasts for `duration`. If a `period` is given, the pulse is repeated with that period, either indefinitely
+/// or for the given number of `repetitions`. All times are in s, measured in simulation time.
+pub struct EmissionSchedule {
+    pub profile: EmissionProfile,
+    pub start: f64,
+    pub duration: f64,
+    pub period: Option<f64>,
+    pub repetitions: Option<u32>,
+}
+impl EmissionSchedule {
+    /// Creates a schedule which follows the `profile` from the start of the simulation, without repetition.
+    pub fn new(profile: EmissionProfile) -> Self {
+        EmissionSchedule {
+            profile: profile,
+            start: 0.0,
+            duration: std::f64::INFINITY,
+            period: None,
+            repetitions: None,
+        }
+    }
+
+    /// Gets the rate of emission at the given simulation time, in atoms per second.
+    pub fn rate(&self, time: f64) -> f64 {
+        if time < self.start {
+            return 0.0;
+        }
+        let mut time_in_pulse = time - self.start;
+        if let Some(period) = self.period {
+            let pulse = (time_in_pulse / period).floor();
+            if let Some(repetitions) = self.repetitions {
+                if pulse >= repetitions as f64 {
+                    return 0.0;
+                }
+            }
+            time_in_pulse = time_in_pulse - pulse * period;
+        }
+        if time_in_pulse >= self.duration {
+            return 0.0;
+        }
+        self.profile.rate(time_in_pulse)
+    }
+
+    /// Gets the number of atoms emitted by the schedule before the given simulation time.
+    ///
+    /// Returns `None` if the profile cannot be integrated in closed form.
+    fn number_before(&self, time: f64) -> Option<f64> {
+        if !(time > self.start) {
+            return Some(0.0);
+        }
+        let elapsed = time - self.start;
+        match self.period {
+            None => self.profile.number_before(elapsed.min(self.duration)),
+            Some(period) => {
+                let pulse_length = self.duration.min(period);
+                let mut pulses = (elapsed / period).floor();
+                let mut time_in_pulse = elapsed - pulses * period;
+                if let Some(repetitions) = self.repetitions {
+                    if pulses >= repetitions as f64 {
+                        pulses = repetitions as f64;
+                        time_in_pulse = 0.0;
+                    }
+                }
+                let whole_pulses = if pulses > 0.0 {
+                    pulses * self.profile.number_before(pulse_length)?
+                } else {
+                    0.0
+                };
+                Some(
+                    whole_pulses
+                        + self
+                            .profile
+                            .number_before(time_in_pulse.min(pulse_length))?,
+                )
+            }
+        }
+    }
+
+    /// Gets the average number of atoms emitted between the simulation times `start` and `end`.
+    ///
+    /// The profile is integrated exactly over the interval, so pulses shorter than the interval are not missed.
+    /// [Function](enum.EmissionProfile.html#variant.Function) profiles are integrated numerically using Simpson's rule.
+    pub fn number_between(&self, start: f64, end: f64) -> f64 {
+        if let (Some(before_start), Some(before_end)) =
+            (self.number_before(start), self.number_before(end))
+        {
+            return (before_end - before_start).max(0.0);
+        }
+        let intervals = 16;
+        let step = (end - start) / intervals as f64;
+        let sum: f64 = (0..=intervals)
+            .map(|i| {
+                let weight = if i == 0 || i == intervals {
+                    1.0
+                } else if i % 2 == 1 {
+                    4.0
+                } else {
+                    2.0
+                };
+                weight * self.rate(start + i as f64 * step)
+            })
+            .sum();
+        sum * step / 3.0
+    }
+}
+impl Component for EmissionSchedule {
+    type Storage = HashMapStorage<Self>;
+}
+
 /// The number of atoms the oven should emit in the current frame.
 pub struct AtomNumberToEmit {
     pub number: i32,
@@ -65,10 +322,11 @@ impl<'a> System<'a> for EmitFixedRateSystem {
         ReadStorage<'a, EmitFixedRate>,
         ReadExpect<'a, Timestep>,
         WriteStorage<'a, AtomNumberToEmit>,
+        Option<Read<'a, FrameRandomKey>>,
     );
 
-    fn run(&mut self, (rates, timestep, mut emit_numbers): Self::SystemData) {
-        let mut rng = rand::thread_rng();
+    fn run(&mut self, (rates, timestep, mut emit_numbers, random_key): Self::SystemData) {
+        let mut rng = RandomStreams::new(random_key.as_deref(), "emit_fixed_rate").rng();
         for (rate, mut emit_numbers) in (&rates, &mut emit_numbers).join() {
             let avg_number_to_emit = rate.rate * timestep.delta;
             let guaranteed_number = avg_number_to_emit.floor();
@@ -83,6 +341,40 @@ impl<'a> System<'a> for EmitFixedRateSystem {
     }
 }
 
+/// Calculates the number of atoms to emit each frame for sources with an [EmissionSchedule](struct.EmissionSchedule.html).
+///
+/// The average number emitted during the frame is found by integrating the schedule over the frame.
+/// As for [EmitFixedRateSystem](struct.EmitFixedRateSystem.html), the number emitted fluctuates randomly
+/// so that the average is correct.
+pub struct EmitScheduledSystem;
+impl<'a> System<'a> for EmitScheduledSystem {
+    type SystemData = (
+        ReadStorage<'a, EmissionSchedule>,
+        ReadExpect<'a, Timestep>,
+        Read<'a, SimulationTime>,
+        WriteStorage<'a, AtomNumberToEmit>,
+        Option<Read<'a, FrameRandomKey>>,
+    );
+
+    fn run(&mut self, (schedules, timestep, time, mut emit_numbers, random_key): Self::SystemData) {
+        let mut rng = RandomStreams::new(random_key.as_deref(), "emit_scheduled").rng();
+        // The frame covers the step which ends at the current time.
+        let current_time = time.time;
+        for (schedule, emit_numbers) in (&schedules, &mut emit_numbers).join() {
+            let avg_number_to_emit =
+                schedule.number_between(current_time - timestep.delta, current_time);
+            let guaranteed_number = avg_number_to_emit.floor();
+            let number: i32;
+            if rng.gen::<f64>() < avg_number_to_emit - guaranteed_number {
+                number = guaranteed_number as i32 + 1;
+            } else {
+                number = guaranteed_number as i32;
+            }
+            emit_numbers.number = number;
+        }
+    }
+}
+
 /// Sets the number of atoms to emit to zero for EmitOnce sources.
 pub struct EmitOnceSystem;
 impl<'a> System<'a> for EmitOnceSystem {
@@ -139,6 +431,103 @@ pub mod tests {
         assert_eq!(total < (n as f64 * 1.1 * rate) as i32, true);
     }
 
+    #[test]
+    fn test_emission_schedule() {
+        let schedule = EmissionSchedule {
+            profile: EmissionProfile::tabulated(vec![0.0, 1.0, 2.0], vec![0.0, 10.0, 0.0]),
+            start: 1.0,
+            duration: 1.5,
+            period: Some(4.0),
+            repetitions: Some(2),
+        };
+        assert_eq!(schedule.rate(0.5), 0.0);
+        assert_eq!(schedule.rate(1.5), 5.0);
+        assert_eq!(schedule.rate(2.25), 7.5);
+        assert_eq!(schedule.rate(2.75), 0.0);
+        assert_eq!(schedule.rate(6.0), 10.0);
+        assert_eq!(schedule.rate(10.0), 0.0);
+        assert_eq!(schedule.rate(std::f64::NAN), 0.0);
+
+        // Each pulse emits 5.0 + 3.75 atoms before it is cut off at 1.5 s.
+        assert!((schedule.number_between(0.0, 2.0) - 5.0).abs() < 1e-12);
+        assert!((schedule.number_between(0.0, 20.0) - 2.0 * 8.75).abs() < 1e-12);
+        assert!((schedule.number_between(5.5, 6.0) - 3.75).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "3 times but 2 rates")]
+    fn test_mismatched_rate_table() {
+        RateTable::new(vec![0.0, 1.0, 2.0], vec![0.0, 1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite")]
+    fn test_nan_rate_table() {
+        RateTable::new(vec![0.0, std::f64::NAN], vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_short_pulses_within_frame() {
+        let dt = 1e-5;
+        // A pulse much narrower than the frame is emitted entirely within it.
+        let gaussian = EmissionSchedule::new(EmissionProfile::GaussianPulse {
+            number: 100.0,
+            time: 2.5e-5,
+            width: 1e-7,
+        });
+        assert!((gaussian.number_between(2e-5, 3e-5) - 100.0).abs() < 1e-6);
+        assert!(gaussian.number_between(1e-5, 2e-5) < 1e-6);
+
+        // An exponential pulse emits 1 - 1/e of its atoms in the first time constant.
+        let exponential = EmissionSchedule::new(EmissionProfile::ExponentialPulse {
+            number: 100.0,
+            time_constant: dt,
+        });
+        let expected = 100.0 * (1.0 - (-1.0_f64).exp());
+        assert!((exponential.number_between(0.0, dt) - expected).abs() < 1e-9);
+
+        // Function profiles are integrated numerically.
+        let function = EmissionSchedule::new(EmissionProfile::Function(Box::new(|t| 2.0 * t)));
+        assert!((function.number_between(1.0, 2.0) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_scheduled_emitter() {
+        let mut test_world = World::new();
+        test_world.register::<EmissionSchedule>();
+        test_world.register::<AtomNumberToEmit>();
+
+        let mut schedule = EmissionSchedule::new(EmissionProfile::GaussianPulse {
+            number: 1000.0,
+            time: 5e-3,
+            width: 1e-3,
+        });
+        schedule.start = 1e-3;
+        let emitter = test_world
+            .create_entity()
+            .with(schedule)
+            .with(AtomNumberToEmit { number: 0 })
+            .build();
+
+        test_world.insert(Timestep { delta: 1e-5 });
+
+        let mut system = EmitScheduledSystem;
+        let mut total = 0;
+        for n in 0..2000 {
+            test_world.insert(SimulationTime {
+                time: n as f64 * 1e-5,
+            });
+            system.run_now(&test_world);
+            let emits = test_world.read_storage::<AtomNumberToEmit>();
+            let number = emits.get(emitter).expect("Could not get entity").number;
+            if n < 100 {
+                assert_eq!(number, 0);
+            }
+            total = total + number;
+        }
+        assert!(total > 900 && total < 1100);
+    }
+
     #[test]
     fn test_fixed_number_emitter() {
         let mut test_world = World::new();
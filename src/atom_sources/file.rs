@@ -0,0 +1,754 @@
+//! Atom sources which create atoms read from file.
+//!
+//! An [AtomFileSource](struct.AtomFileSource.html) holds a list of [AtomRecord](struct.AtomRecord.html)s,
+//! which can be read from a CSV file or from the [Binary](../../output/file/struct.Binary.html) files
+//! written by the output systems. This allows a simulation to be seeded with atoms produced elsewhere,
+//! for instance the final state of a previous run.
+//!
+//! Atoms are created at the positions and velocities given in the file, which are absolute, rather than
+//! relative to the position of the source entity.
+
+use super::mass::MassDistribution;
+use crate::atom::*;
+use crate::initiate::*;
+use crate::integrator::SimulationTime;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use nalgebra::{Matrix3, Vector3};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use specs::{
+    Component, Entities, HashMapStorage, Join, LazyUpdate, Read, ReadStorage, System, WriteStorage,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+
+extern crate byteorder;
+use byteorder::{LittleEndian, ReadBytesExt};
+
+/// An atom to be created by an [AtomFileSource](struct.AtomFileSource.html).
+///
+/// In CSV files, each row describes one atom, with the column headers
+/// `x, y, z, vx, vy, vz, mass, species, time`. The `mass`, `species` and `time` columns are optional.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AtomRecord {
+    /// Position of the atom, in m.
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// Velocity of the atom, in m/s.
+    pub vx: f64,
+    pub vy: f64,
+    pub vz: f64,
+    /// Mass of the atom, in amu. If not specified, the mass is drawn from the
+    /// [MassDistribution](../mass/struct.MassDistribution.html) of the source entity.
+    #[serde(default)]
+    pub mass: Option<f64>,
+    /// Index of the atom's species in the list of species given when the
+    /// [AtomFileSource](struct.AtomFileSource.html) is created.
+    /// If not specified, the atom is created with the `AtomicTransition` of the source entity.
+    #[serde(default)]
+    pub species: Option<usize>,
+    /// Time at which the atom is created, in s. Only used for `FileEmission::Timed` sources.
+    #[serde(default)]
+    pub time: f64,
+    /// Statistical [Weight](../../atom/struct.Weight.html) of the atom. If not specified, the atom is
+    /// created without a `Weight`.
+    #[serde(default)]
+    pub weight: Option<f64>,
+}
+impl AtomRecord {
+    pub fn new(position: Vector3<f64>, velocity: Vector3<f64>) -> Self {
+        AtomRecord {
+            x: position[0],
+            y: position[1],
+            z: position[2],
+            vx: velocity[0],
+            vy: velocity[1],
+            vz: velocity[2],
+            mass: None,
+            species: None,
+            time: 0.0,
+            weight: None,
+        }
+    }
+
+    pub fn position(&self) -> Vector3<f64> {
+        Vector3::new(self.x, self.y, self.z)
+    }
+
+    pub fn velocity(&self) -> Vector3<f64> {
+        Vector3::new(self.vx, self.vy, self.vz)
+    }
+}
+
+/// Errors that can occur when loading atoms for an [AtomFileSource](struct.AtomFileSource.html).
+#[derive(Debug)]
+pub enum AtomFileError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A line of the file could not be parsed.
+    Parse { line: usize, message: String },
+    /// The time of a record is not finite. The index of the record in the file is given.
+    InvalidTime { record: usize },
+    /// A record refers to a species which is not in the list of species of the source.
+    UnknownSpecies(usize),
+    /// A binary file does not contain the requested step.
+    MissingStep { file: String, step: u64 },
+}
+impl fmt::Display for AtomFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtomFileError::Io(error) => write!(f, "could not read atom file: {}", error),
+            AtomFileError::Parse { line, message } => {
+                write!(f, "could not parse atom file at line {}: {}", line, message)
+            }
+            AtomFileError::InvalidTime { record } => {
+                write!(f, "atom record {} does not have a finite time", record)
+            }
+            AtomFileError::UnknownSpecies(species) => {
+                write!(f, "atom record refers to unknown species {}", species)
+            }
+            AtomFileError::MissingStep { file, step } => {
+                write!(f, "{} does not contain step {}", file, step)
+            }
+        }
+    }
+}
+impl Error for AtomFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AtomFileError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for AtomFileError {
+    fn from(error: io::Error) -> Self {
+        AtomFileError::Io(error)
+    }
+}
+impl From<csv::Error> for AtomFileError {
+    fn from(error: csv::Error) -> Self {
+        let line = error
+            .position()
+            .map(|position| position.line() as usize)
+            .unwrap_or(0);
+        let message = error.to_string();
+        match error.into_kind() {
+            csv::ErrorKind::Io(error) => AtomFileError::Io(error),
+            _ => AtomFileError::Parse {
+                line: line,
+                message: message,
+            },
+        }
+    }
+}
+
+/// Determines when the atoms of an [AtomFileSource](struct.AtomFileSource.html) are created.
+#[derive(Clone, Copy)]
+pub enum FileEmission {
+    /// All atoms are created in the first frame.
+    AllAtOnce,
+    /// Each atom is created at its recorded `time`.
+    Timed,
+}
+
+/// A transformation applied to the atoms created by an [AtomFileSource](struct.AtomFileSource.html).
+///
+/// Positions are transformed as `rotation * pos + translation`, and velocities as `rotation * vel`.
+/// The `time_offset` is added to the recorded time of each atom.
+#[derive(Clone)]
+pub struct ReplayTransform {
+    pub rotation: Matrix3<f64>,
+    pub translation: Vector3<f64>,
+    pub time_offset: f64,
+}
+impl ReplayTransform {
+    pub fn identity() -> Self {
+        ReplayTransform {
+            rotation: Matrix3::identity(),
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            time_offset: 0.0,
+        }
+    }
+
+    /// Returns the transformed record.
+    pub fn apply(&self, record: &AtomRecord) -> AtomRecord {
+        let mut transformed = AtomRecord::new(
+            self.rotation * record.position() + self.translation,
+            self.rotation * record.velocity(),
+        );
+        transformed.mass = record.mass;
+        transformed.species = record.species;
+        transformed.time = record.time + self.time_offset;
+        transformed.weight = record.weight;
+        transformed
+    }
+}
+
+/// A source which creates atoms from a list of [AtomRecord](struct.AtomRecord.html)s.
+pub struct AtomFileSource {
+    /// Atoms still to be created, sorted by time.
+    atoms: Vec<AtomRecord>,
+    /// Index of the next atom to create.
+    next: usize,
+    pub emission: FileEmission,
+    /// Species which atoms may be assigned, indexed by the `species` of each record.
+    species: Vec<AtomicTransition>,
+    /// Transformation applied to each atom before it is created.
+    pub transform: ReplayTransform,
+    /// Scales the number of atoms created. Each record creates on average `rate_scale` atoms.
+    pub rate_scale: f64,
+}
+impl AtomFileSource {
+    /// Creates a source from a list of atom records.
+    ///
+    /// `species` are the transitions which atoms may be assigned, indexed by the `species` of each record.
+    /// Returns an error if any record refers to a species outside of the list.
+    pub fn new(
+        mut atoms: Vec<AtomRecord>,
+        emission: FileEmission,
+        species: Vec<AtomicTransition>,
+    ) -> Result<Self, AtomFileError> {
+        if let Some(unknown) = atoms
+            .iter()
+            .filter_map(|record| record.species)
+            .find(|index| *index >= species.len())
+        {
+            return Err(AtomFileError::UnknownSpecies(unknown));
+        }
+        atoms.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(AtomFileSource {
+            atoms: atoms,
+            next: 0,
+            emission: emission,
+            species: species,
+            transform: ReplayTransform::identity(),
+            rate_scale: 1.0,
+        })
+    }
+
+    /// Sets the transformation applied to each atom, eg to move atoms recorded in one simulation
+    /// into the coordinate system of another.
+    pub fn with_transform(mut self, transform: ReplayTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Scales the rate at which atoms are created.
+    ///
+    /// For `rate_scale < 1`, each record is randomly skipped with probability `1 - rate_scale`.
+    /// For `rate_scale > 1`, some records create multiple atoms with identical position and velocity.
+    pub fn with_rate_scale(mut self, rate_scale: f64) -> Self {
+        self.rate_scale = rate_scale;
+        self
+    }
+
+    /// Reads atom records from a CSV file with headers.
+    ///
+    /// See [AtomRecord](struct.AtomRecord.html) for the expected columns. Records with a time which is
+    /// not finite, or which refer to a species outside of `species`, are rejected.
+    pub fn from_csv(
+        file_name: &str,
+        emission: FileEmission,
+        species: Vec<AtomicTransition>,
+    ) -> Result<Self, AtomFileError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(file_name)?;
+        let mut atoms = Vec::new();
+        for (index, record) in reader.deserialize().enumerate() {
+            let record: AtomRecord = record?;
+            if !record.time.is_finite() {
+                return Err(AtomFileError::InvalidTime { record: index });
+            }
+            atoms.push(record);
+        }
+        AtomFileSource::new(atoms, emission, species)
+    }
+
+    /// Reads atoms from files written by an [OutputSystem](../../output/file/struct.OutputSystem.html)
+    /// in the [Binary](../../output/file/struct.Binary.html) format.
+    ///
+    /// Atoms are matched between files by their entity id. Atoms missing from any of the files are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// `position_file`: file of `Position` output.
+    ///
+    /// `velocity_file`: file of `Velocity` output.
+    ///
+    /// `mass_file`: optional file of `Mass` output. If `None`, masses are drawn from the source's `MassDistribution`.
+    ///
+    /// `step`: the step of the frame to read. If `None`, the last frame in the files is used.
+    pub fn from_binary(
+        position_file: &str,
+        velocity_file: &str,
+        mass_file: Option<&str>,
+        step: Option<u64>,
+    ) -> Result<Self, AtomFileError> {
+        let positions = read_binary_frame(position_file, 3, step)?;
+        let velocities = read_binary_frame(velocity_file, 3, step)?;
+        let masses = match mass_file {
+            Some(file) => Some(read_binary_frame(file, 1, step)?),
+            None => None,
+        };
+
+        let mut ids: Vec<&(i32, u32)> = positions.keys().collect();
+        ids.sort();
+        let mut atoms = Vec::new();
+        for id in ids {
+            let velocity = match velocities.get(id) {
+                Some(velocity) => velocity,
+                None => continue,
+            };
+            let mass = match &masses {
+                Some(masses) => match masses.get(id) {
+                    Some(mass) => Some(mass[0]),
+                    None => continue,
+                },
+                None => None,
+            };
+            let position = &positions[id];
+            let mut record = AtomRecord::new(
+                Vector3::new(position[0], position[1], position[2]),
+                Vector3::new(velocity[0], velocity[1], velocity[2]),
+            );
+            record.mass = mass;
+            atoms.push(record);
+        }
+        AtomFileSource::new(atoms, FileEmission::AllAtOnce, Vec::new())
+    }
+
+    /// Returns the number of atoms which have not yet been created.
+    pub fn remaining(&self) -> usize {
+        self.atoms.len() - self.next
+    }
+}
+impl Component for AtomFileSource {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Reads a single frame of per-atom data from a binary output file.
+///
+/// Returns a map from the atom's (generation, id) to the atom's data, which has `width` elements.
+fn read_binary_frame(
+    file_name: &str,
+    width: usize,
+    step: Option<u64>,
+) -> Result<HashMap<(i32, u32), Vec<f64>>, AtomFileError> {
+    let file = File::open(Path::new(file_name))?;
+    let mut reader = BufReader::new(file);
+    let mut frame = HashMap::new();
+    loop {
+        let frame_step = match reader.read_u64::<LittleEndian>() {
+            Ok(frame_step) => frame_step,
+            Err(_) => break,
+        };
+        let atom_number = reader.read_u64::<LittleEndian>()?;
+        let mut atoms = HashMap::new();
+        for _ in 0..atom_number {
+            let gen = reader.read_i32::<LittleEndian>()?;
+            let id = reader.read_u32::<LittleEndian>()?;
+            let data = (0..width)
+                .map(|_| reader.read_f64::<LittleEndian>())
+                .collect::<Result<Vec<f64>, io::Error>>()?;
+            atoms.insert((gen, id), data);
+        }
+        frame = atoms;
+        if step == Some(frame_step) {
+            return Ok(frame);
+        }
+    }
+    match step {
+        Some(step) => Err(AtomFileError::MissingStep {
+            file: file_name.to_string(),
+            step: step,
+        }),
+        None => Ok(frame),
+    }
+}
+
+/// Creates atoms from [AtomFileSource](struct.AtomFileSource.html)s.
+pub struct AtomFileSourceCreateAtomsSystem;
+impl<'a> System<'a> for AtomFileSourceCreateAtomsSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, AtomFileSource>,
+        ReadStorage<'a, AtomicTransition>,
+        ReadStorage<'a, MassDistribution>,
+        Read<'a, SimulationTime>,
+        Read<'a, LazyUpdate>,
+        Option<Read<'a, FrameRandomKey>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut sources,
+            transitions,
+            mass_distributions,
+            time,
+            updater,
+            random_key,
+        ): Self::SystemData,
+    ) {
+        let current_time = time.time;
+        let mut rng = RandomStreams::new(random_key.as_deref(), "file_create_atoms").rng();
+        for (source, transition, mass_distribution) in (
+            &mut sources,
+            transitions.maybe(),
+            mass_distributions.maybe(),
+        )
+            .join()
+        {
+            while source.next < source.atoms.len() {
+                let record = source.transform.apply(&source.atoms[source.next]);
+                match source.emission {
+                    FileEmission::Timed if record.time > current_time => break,
+                    _ => (),
+                }
+                source.next += 1;
+
+                let guaranteed_copies = source.rate_scale.floor();
+                let copies = if rng.gen::<f64>() < source.rate_scale - guaranteed_copies {
+                    guaranteed_copies as i32 + 1
+                } else {
+                    guaranteed_copies as i32
+                };
+
+                for _ in 0..copies {
+                    let isotope = match (record.mass, mass_distribution) {
+                        (Some(_), _) => None,
+                        (None, Some(distribution)) => {
+                            Some(distribution.draw_random_isotope(&mut rng))
+                        }
+                        (None, None) => panic!(
+                            "Atom record has no mass, and the source has no MassDistribution."
+                        ),
+                    };
+                    let mass = match isotope {
+                        Some(isotope) => Mass {
+                            value: isotope.mass,
+                        },
+                        None => Mass {
+                            value: record.mass.unwrap(),
+                        },
+                    };
+                    let isotope_transition = isotope.and_then(|isotope| isotope.transition());
+                    let atomic_transition = match (record.species, isotope_transition, transition) {
+                        (Some(species), _, _) => source.species[species].clone(),
+                        (None, Some(transition), _) => transition.clone(),
+                        (None, None, Some(transition)) => transition.clone(),
+                        (None, None, None) => panic!(
+                            "Atom record has no species, and the source has no AtomicTransition."
+                        ),
+                    };
+
+                    let new_atom = entities.create();
+                    updater.insert(
+                        new_atom,
+                        Position {
+                            pos: record.position(),
+                        },
+                    );
+                    updater.insert(
+                        new_atom,
+                        Velocity {
+                            vel: record.velocity(),
+                        },
+                    );
+                    updater.insert(new_atom, Force::new());
+                    updater.insert(new_atom, mass);
+                    updater.insert(new_atom, atomic_transition);
+                    updater.insert(new_atom, Atom);
+                    updater.insert(
+                        new_atom,
+                        InitialVelocity {
+                            vel: record.velocity(),
+                        },
+                    );
+                    updater.insert(new_atom, NewlyCreated);
+                    if let Some(weight) = record.weight {
+                        updater.insert(new_atom, Weight { value: weight });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::output::file::{Binary, Format};
+    use byteorder::WriteBytesExt;
+    use specs::{Builder, RunNow, World, WorldExt};
+    use std::io::Write;
+
+    fn temporary_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("atomecs_{}_{}", std::process::id(), name))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let file_name = temporary_file("atoms.csv");
+        {
+            let mut file = File::create(&file_name).unwrap();
+            write!(
+                file,
+                "x, y, z, vx, vy, vz, mass, species, time\n\
+                 0.1, 0.2, 0.3, 1.0, 2.0, 3.0, 87.0, 1, 2e-6\n\
+                 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, , , 1e-6\n"
+            )
+            .unwrap();
+        }
+        let without_species = AtomFileSource::from_csv(&file_name, FileEmission::Timed, Vec::new());
+        let source = AtomFileSource::from_csv(
+            &file_name,
+            FileEmission::Timed,
+            vec![AtomicTransition::rubidium(), AtomicTransition::strontium()],
+        )
+        .unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert!(matches!(
+            without_species,
+            Err(AtomFileError::UnknownSpecies(1))
+        ));
+
+        assert_eq!(source.remaining(), 2);
+        // records are sorted by time
+        assert_eq!(source.atoms[0].mass, None);
+        assert_eq!(source.atoms[0].species, None);
+        let atom = &source.atoms[1];
+        assert_eq!(atom.position(), Vector3::new(0.1, 0.2, 0.3));
+        assert_eq!(atom.velocity(), Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(atom.mass, Some(87.0));
+        assert_eq!(atom.species, Some(1));
+        assert_eq!(atom.time, 2e-6);
+    }
+
+    #[test]
+    fn test_read_binary() {
+        let mut world = World::new();
+        let entities: Vec<_> = (0..3).map(|_| world.create_entity().build()).collect();
+        let write_frame = |file_name: &str, step: u64, data: Vec<Vec<f64>>| {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_name)
+                .unwrap();
+            <Binary as Format<Position, File>>::write_frame_header(&mut file, step, data.len())
+                .unwrap();
+            for (entity, values) in entities.iter().zip(data) {
+                file.write_i32::<LittleEndian>(entity.gen().id()).unwrap();
+                file.write_u32::<LittleEndian>(entity.id()).unwrap();
+                for value in values {
+                    file.write_f64::<LittleEndian>(value).unwrap();
+                }
+            }
+        };
+
+        let position_file = temporary_file("pos.bin");
+        let velocity_file = temporary_file("vel.bin");
+        write_frame(&position_file, 0, vec![vec![0.0, 0.0, 0.0]; 3]);
+        write_frame(&velocity_file, 0, vec![vec![0.0, 0.0, 0.0]; 3]);
+        write_frame(
+            &position_file,
+            10,
+            vec![
+                vec![1.0, 2.0, 3.0],
+                vec![4.0, 5.0, 6.0],
+                vec![7.0, 8.0, 9.0],
+            ],
+        );
+        write_frame(
+            &velocity_file,
+            10,
+            vec![vec![-1.0, -2.0, -3.0], vec![-4.0, -5.0, -6.0]],
+        );
+
+        let last = AtomFileSource::from_binary(&position_file, &velocity_file, None, None).unwrap();
+        let first =
+            AtomFileSource::from_binary(&position_file, &velocity_file, None, Some(0)).unwrap();
+        let missing = AtomFileSource::from_binary(&position_file, &velocity_file, None, Some(5));
+        std::fs::remove_file(&position_file).unwrap();
+        std::fs::remove_file(&velocity_file).unwrap();
+
+        assert_eq!(first.remaining(), 3);
+        // The third atom has no velocity in the last frame.
+        assert_eq!(last.remaining(), 2);
+        assert_eq!(last.atoms[1].position(), Vector3::new(4.0, 5.0, 6.0));
+        assert_eq!(last.atoms[1].velocity(), Vector3::new(-4.0, -5.0, -6.0));
+        assert!(matches!(
+            missing,
+            Err(AtomFileError::MissingStep { step: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let missing = AtomFileSource::from_csv(
+            &temporary_file("missing.csv"),
+            FileEmission::Timed,
+            Vec::new(),
+        );
+        assert!(matches!(missing, Err(AtomFileError::Io(_))));
+
+        let file_name = temporary_file("invalid.csv");
+        let read = |contents: &str| {
+            let mut file = File::create(&file_name).unwrap();
+            write!(
+                file,
+                "x, y, z, vx, vy, vz, mass, species, time\n{}",
+                contents
+            )
+            .unwrap();
+            AtomFileSource::from_csv(
+                &file_name,
+                FileEmission::Timed,
+                vec![AtomicTransition::strontium()],
+            )
+        };
+        let unparsable = read("0.0, 0.0, zero, 0.0, 0.0, 0.0, , , 0.0\n");
+        let nan_time = read(
+            "0.0, 0.0, 0.0, 0.0, 0.0, 0.0, , , 0.0\n\
+             0.0, 0.0, 0.0, 0.0, 0.0, 0.0, , , NaN\n",
+        );
+        let unknown_species = read("0.0, 0.0, 0.0, 0.0, 0.0, 0.0, , 1, 0.0\n");
+        std::fs::remove_file(&file_name).unwrap();
+
+        assert!(matches!(
+            unparsable,
+            Err(AtomFileError::Parse { line: 2, .. })
+        ));
+        assert!(matches!(
+            nan_time,
+            Err(AtomFileError::InvalidTime { record: 1 })
+        ));
+        assert!(matches!(
+            unknown_species,
+            Err(AtomFileError::UnknownSpecies(1))
+        ));
+    }
+
+    #[test]
+    fn test_timed_file_source() {
+        let mut test_world = World::new();
+        test_world.register::<AtomFileSource>();
+        test_world.register::<AtomicTransition>();
+        test_world.register::<MassDistribution>();
+        test_world.register::<Position>();
+        test_world.register::<Velocity>();
+        test_world.register::<Force>();
+        test_world.register::<Mass>();
+        test_world.register::<Atom>();
+        test_world.register::<InitialVelocity>();
+        test_world.register::<NewlyCreated>();
+        test_world.insert(SimulationTime::default());
+
+        let mut late = AtomRecord::new(Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        late.time = 1.5e-6;
+        late.mass = Some(85.0);
+        late.species = Some(0);
+        let mut early = AtomRecord::new(Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 2.0, 0.0));
+        early.mass = Some(87.0);
+        test_world
+            .create_entity()
+            .with(
+                AtomFileSource::new(
+                    vec![late, early],
+                    FileEmission::Timed,
+                    vec![AtomicTransition::strontium()],
+                )
+                .unwrap(),
+            )
+            .with(AtomicTransition::rubidium())
+            .build();
+
+        let mut system = AtomFileSourceCreateAtomsSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+        assert_eq!(test_world.read_storage::<NewlyCreated>().count(), 1);
+
+        test_world.insert(SimulationTime { time: 2e-6 });
+        system.run_now(&test_world);
+        test_world.maintain();
+        let positions = test_world.read_storage::<Position>();
+        let masses = test_world.read_storage::<Mass>();
+        let transitions = test_world.read_storage::<AtomicTransition>();
+        let created: Vec<_> = (&positions, &masses, &transitions).join().collect();
+        assert_eq!(created.len(), 2);
+        for (position, mass, transition) in created {
+            if position.pos[0] == 1.0 {
+                assert_eq!(mass.value, 85.0);
+                assert_eq!(
+                    transition.frequency,
+                    AtomicTransition::strontium().frequency
+                );
+            } else {
+                assert_eq!(mass.value, 87.0);
+                assert_eq!(transition.frequency, AtomicTransition::rubidium().frequency);
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_transform_and_rate_scale() {
+        let mut test_world = World::new();
+        test_world.register::<AtomFileSource>();
+        test_world.register::<AtomicTransition>();
+        test_world.register::<MassDistribution>();
+        test_world.register::<Position>();
+        test_world.register::<Velocity>();
+        test_world.register::<Force>();
+        test_world.register::<Mass>();
+        test_world.register::<Atom>();
+        test_world.register::<InitialVelocity>();
+        test_world.register::<NewlyCreated>();
+        test_world.insert(SimulationTime::default());
+
+        let mut record = AtomRecord::new(Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        record.mass = Some(87.0);
+        let transform = ReplayTransform {
+            rotation: Matrix3::new(0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0),
+            translation: Vector3::new(0.0, 0.0, 2.0),
+            time_offset: 0.0,
+        };
+        test_world
+            .create_entity()
+            .with(
+                AtomFileSource::new(vec![record], FileEmission::AllAtOnce, Vec::new())
+                    .unwrap()
+                    .with_transform(transform)
+                    .with_rate_scale(3.0),
+            )
+            .with(AtomicTransition::rubidium())
+            .build();
+
+        let mut system = AtomFileSourceCreateAtomsSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let positions = test_world.read_storage::<Position>();
+        let velocities = test_world.read_storage::<Velocity>();
+        let created: Vec<_> = (&positions, &velocities).join().collect();
+        assert_eq!(created.len(), 3);
+        for (position, velocity) in created {
+            assert_eq!(position.pos, Vector3::new(0.0, 1.0, 2.0));
+            assert_eq!(velocity.vel, Vector3::new(0.0, 1.0, 0.0));
+        }
+    }
+}
@@ -5,6 +5,7 @@ use crate::atom::*;
 use crate::atom_sources::emit::AtomNumberToEmit;
 use crate::constant::EXP;
 use crate::initiate::*;
+use crate::rng::{FrameRandomKey, RandomStreams};
 use nalgebra::Vector3;
 
 use rand;
@@ -118,13 +119,23 @@ impl<'a> System<'a> for GaussianCreateAtomsSystem {
         ReadStorage<'a, Position>,
         ReadStorage<'a, Mass>,
         Read<'a, LazyUpdate>,
+        Option<Read<'a, FrameRandomKey>>,
     );
 
     fn run(
         &mut self,
-        (entities, sources, atom_infos, numbers_to_emits, positions, masses, updater): Self::SystemData,
+        (
+            entities,
+            sources,
+            atom_infos,
+            numbers_to_emits,
+            positions,
+            masses,
+            updater,
+            random_key,
+        ): Self::SystemData,
     ) {
-        let mut rng = rand::thread_rng();
+        let mut rng = RandomStreams::new(random_key.as_deref(), "gaussian_create_atoms").rng();
         for (source, atom, number_to_emit, source_position, mass) in (
             &sources,
             &atom_infos,
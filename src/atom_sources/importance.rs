@@ -0,0 +1,90 @@
+//! Importance sampling of the atoms emitted by a source.
+//!
+//! Most atoms emitted by a thermal source are too fast, or travel at too large an angle, to be
+//! captured. Rather than discarding these atoms (eg with a [VelocityCap](../struct.VelocityCap.html),
+//! which biases any totals), an [ImportanceSampling](struct.ImportanceSampling.html) component can be
+//! added to an [Oven](../oven/struct.Oven.html) or [SurfaceSource](../surface/struct.SurfaceSource.html).
+//! The source then oversamples the interesting part of the distribution, and gives each atom a
+//! [Weight](../../atom/struct.Weight.html) which corrects for the oversampling.
+//!
+//! If an atom is drawn from a biased distribution `q(x) = b(x) p(x) / <b>`, where `p(x)` is the
+//! distribution of the source, `b(x)` the bias and `<b>` the mean bias over `p(x)`, it is given the
+//! weight `p(x) / q(x) = <b> / b(x)`. The weighted sum of atoms is then an unbiased estimate of the
+//! number of atoms that would be obtained without the bias.
+
+use serde::{Deserialize, Serialize};
+use specs::{Component, HashMapStorage};
+
+/// A bias which oversamples values below a threshold.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SamplingBias {
+    /// Values below the threshold are oversampled. Speeds are in m/s, angles in radians.
+    pub threshold: f64,
+    /// The factor by which values below the threshold are oversampled.
+    pub factor: f64,
+}
+impl SamplingBias {
+    /// Gets the bias `b(x)` for the given value.
+    pub fn bias(&self, value: f64) -> f64 {
+        if value < self.threshold {
+            self.factor
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Component which enables importance sampling for an atom source.
+///
+/// The bias on the emitted speed and on the angle to the emission direction are applied
+/// independently. The distributions are precalculated by the
+/// [PrecalculateForSpeciesSystem](../precalc/struct.PrecalculateForSpeciesSystem.html), so this
+/// component must be added when the source is created.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct ImportanceSampling {
+    /// Bias applied to the speed of emitted atoms.
+    pub speed: Option<SamplingBias>,
+    /// Bias applied to the polar angle between emitted atoms and the emission direction.
+    pub angle: Option<SamplingBias>,
+}
+impl Component for ImportanceSampling {
+    type Storage = HashMapStorage<Self>;
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::super::WeightedProbabilityDistribution;
+    use super::*;
+
+    #[test]
+    fn test_biased_distribution_is_unbiased_when_weighted() {
+        let values: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        let weights = vec![1.0; 100];
+        let bias = SamplingBias {
+            threshold: 10.0,
+            factor: 20.0,
+        };
+        let distribution = WeightedProbabilityDistribution::new_biased(values, weights, &bias);
+
+        let mut rng = rand::thread_rng();
+        let n = 100000;
+        let mut below = 0;
+        let mut total_weight = 0.0;
+        let mut weight_below = 0.0;
+        for _ in 0..n {
+            let (value, weight) = distribution.sample_with_weight(&mut rng);
+            total_weight += weight;
+            if value < 10.0 {
+                below += 1;
+                weight_below += weight;
+            }
+        }
+
+        // 10% of values are below the threshold, but these are sampled 20 times more often.
+        let expected_below = 20.0 * 0.1 / (20.0 * 0.1 + 0.9);
+        assert!((below as f64 / n as f64 - expected_below).abs() < 0.01);
+        assert!((total_weight / n as f64 - 1.0).abs() < 0.02);
+        assert!((weight_below / total_weight - 0.1).abs() < 0.01);
+    }
+}
@@ -1,7 +1,6 @@
 //! Masses and isotopes of atoms
 
-use crate::atom::Mass;
-use rand;
+use crate::atom::{AtomicTransition, Mass};
 use rand::Rng;
 extern crate specs;
 
@@ -15,6 +14,41 @@ pub struct MassRatio {
     pub mass: f64,
     /// The relative abundance of this mass.
     pub ratio: f64,
+    /// The transition of this isotope, set with [with_transition](struct.MassRatio.html#method.with_transition).
+    ///
+    /// If `None`, atoms of this isotope are created with the `AtomicTransition` of the source.
+    #[serde(default)]
+    transition: Option<AtomicTransition>,
+}
+impl MassRatio {
+    /// Creates a `MassRatio` for atoms of the given mass and relative abundance, which are created with the
+    /// `AtomicTransition` of the source.
+    pub fn new(mass: f64, ratio: f64) -> Self {
+        MassRatio {
+            mass: mass,
+            ratio: ratio,
+            transition: None,
+        }
+    }
+
+    /// Sets the transition for atoms of this isotope, in place of the transition of the source.
+    pub fn with_transition(mut self, transition: AtomicTransition) -> Self {
+        self.transition = Some(transition);
+        self
+    }
+
+    /// The transition of this isotope, if it differs from that of the source.
+    pub fn transition(&self) -> Option<&AtomicTransition> {
+        self.transition.as_ref()
+    }
+
+    /// Gets the transition for atoms of this isotope, falling back to the `default` transition of the source.
+    pub fn get_transition(&self, default: &AtomicTransition) -> AtomicTransition {
+        match &self.transition {
+            Some(transition) => transition.clone(),
+            None => default.clone(),
+        }
+    }
 }
 
 /// Describes the abundance of each mass.
@@ -55,22 +89,27 @@ impl MassDistribution {
     }
 
     /// Randomly draw a mass from the distribution.
-    pub fn draw_random_mass(&self) -> Mass {
+    pub fn draw_random_mass<R: Rng + ?Sized>(&self, rng: &mut R) -> Mass {
+        Mass {
+            value: self.draw_random_isotope(rng).mass,
+        }
+    }
+
+    /// Randomly draw an isotope from the distribution.
+    pub fn draw_random_isotope<R: Rng + ?Sized>(&self, rng: &mut R) -> &MassRatio {
         assert!(self.normalised);
         let mut level = 0.;
-        let mut rng = rand::thread_rng();
         let luck = rng.gen_range(0.0..1.0);
-        let mut finalmass = 0.;
         for masspercent in self.distribution.iter() {
             level = level + masspercent.ratio;
             if level > luck {
-                return Mass {
-                    value: masspercent.mass,
-                };
+                return masspercent;
             }
-            finalmass = masspercent.mass;
         }
-        return Mass { value: finalmass };
+        return self
+            .distribution
+            .last()
+            .expect("MassDistribution has no isotopes.");
     }
 }
 
@@ -82,16 +121,8 @@ pub mod tests {
 
     #[test]
     fn test_mass_distribution_normalised() {
-        let mass_distribution = MassDistribution::new(vec![
-            MassRatio {
-                mass: 1.0,
-                ratio: 10.0,
-            },
-            MassRatio {
-                mass: 2.0,
-                ratio: 1.0,
-            },
-        ]);
+        let mass_distribution =
+            MassDistribution::new(vec![MassRatio::new(1.0, 10.0), MassRatio::new(2.0, 1.0)]);
 
         let mut total_ratio = 0.0;
         for mr in &mass_distribution.distribution {
@@ -100,4 +131,26 @@ pub mod tests {
 
         assert_approx_eq!(total_ratio, 1., 0.0001);
     }
+
+    #[test]
+    fn test_isotope_transitions() {
+        let rb85 = AtomicTransition::rubidium().with_isotope_shift(-78e6);
+        let mass_distribution = MassDistribution::new(vec![
+            MassRatio::new(85.0, 1.0).with_transition(rb85.clone()),
+            MassRatio::new(87.0, 1.0),
+        ]);
+
+        let default = AtomicTransition::rubidium();
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let isotope = mass_distribution.draw_random_isotope(&mut rng);
+            let transition = isotope.get_transition(&default);
+            if isotope.mass == 85.0 {
+                assert_eq!(transition.frequency, default.frequency - 78e6);
+            } else {
+                assert_eq!(transition.frequency, default.frequency);
+            }
+        }
+        assert_eq!(rb85.linewidth, default.linewidth);
+    }
 }
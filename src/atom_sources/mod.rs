@@ -1,13 +1,18 @@
 //! Creation of atoms in a controlled manner and realease into the simulation
 
+pub mod buffer_gas;
 pub mod central_creator;
 pub mod emit;
+pub mod file;
 pub mod gaussian;
+pub mod importance;
 pub mod mass;
 pub mod oven;
 pub mod precalc;
 pub mod surface;
+pub mod vapour;
 
+use crate::shapes::{Cuboid, Cylinder, Sphere};
 use specs::prelude::*;
 
 use rand;
@@ -16,6 +21,10 @@ use rand::distributions::WeightedIndex;
 use rand::Rng;
 use std::marker::PhantomData;
 
+/// A resource which discards atoms emitted faster than a maximum speed.
+///
+/// Discarded atoms are not counted in any totals. To reduce the number of fast atoms without biasing
+/// totals, use [ImportanceSampling](importance/struct.ImportanceSampling.html) instead.
 pub struct VelocityCap {
     /// The maximum speed of an atom emitted by an atom source. See [Velocity](struct.Velocity.html) for units.
     pub value: f64,
@@ -42,6 +51,16 @@ pub fn add_systems_to_dispatch(
         "emit_fixed_rate",
         &["emit_number_per_frame"],
     );
+    builder.add(
+        vapour::EmitOvenFluxSystem,
+        "emit_oven_flux",
+        &["emit_fixed_rate"],
+    );
+    builder.add(
+        emit::EmitScheduledSystem,
+        "emit_scheduled",
+        &["emit_oven_flux"],
+    );
     builder.add(
         precalc::PrecalculateForSpeciesSystem::<oven::Oven> {
             marker: PhantomData,
@@ -61,34 +80,59 @@ pub fn add_systems_to_dispatch(
         "precalculate_gaussian",
         deps,
     );
+    // Systems which create atoms run one after another, so that entities are allocated in the same
+    // order each run. This keeps simulations with a RandomSeed reproducible.
     builder.add(
         oven::OvenCreateAtomsSystem,
         "oven_create_atoms",
-        &["emit_number_per_frame", "precalculated_oven"],
+        &["emit_scheduled", "precalculated_oven"],
+    );
+    builder.add(
+        surface::CreateAtomsOnSurfaceSystem::<Cylinder>::new("cylinder_surface_create_atoms"),
+        "cylinder_surface_create_atoms",
+        &["oven_create_atoms", "precalculated_surfaces"],
+    );
+    builder.add(
+        surface::CreateAtomsOnSurfaceSystem::<Sphere>::new("sphere_surface_create_atoms"),
+        "sphere_surface_create_atoms",
+        &["cylinder_surface_create_atoms"],
+    );
+    builder.add(
+        surface::CreateAtomsOnSurfaceSystem::<Cuboid>::new("cuboid_surface_create_atoms"),
+        "cuboid_surface_create_atoms",
+        &["sphere_surface_create_atoms"],
     );
     builder.add(
-        surface::CreateAtomsOnSurfaceSystem,
-        "surface_create_atoms",
-        &["emit_number_per_frame", "precalculated_surfaces"],
+        buffer_gas::BufferGasBeamCreateAtomsSystem,
+        "buffer_gas_create_atoms",
+        &["cuboid_surface_create_atoms"],
     );
     builder.add(
         gaussian::GaussianCreateAtomsSystem,
         "gaussian_create_atoms",
-        &["emit_number_per_frame", "precalculate_gaussian"],
+        &["buffer_gas_create_atoms", "precalculate_gaussian"],
     );
     builder.add(
         emit::EmitOnceSystem,
         "emit_once_system",
         &[
             "oven_create_atoms",
-            "surface_create_atoms",
+            "cylinder_surface_create_atoms",
+            "sphere_surface_create_atoms",
+            "cuboid_surface_create_atoms",
             "gaussian_create_atoms",
+            "buffer_gas_create_atoms",
         ],
     );
+    builder.add(
+        file::AtomFileSourceCreateAtomsSystem,
+        "file_create_atoms",
+        &["gaussian_create_atoms"],
+    );
     builder.add(
         central_creator::CentralCreatorCreateAtomsSystem,
         "central_create_system",
-        &[],
+        &["file_create_atoms"],
     )
 }
 
@@ -99,25 +143,67 @@ pub fn register_components(world: &mut World) {
     world.register::<emit::EmitFixedRate>();
     world.register::<emit::EmitNumberPerFrame>();
     world.register::<emit::EmitOnce>();
+    world.register::<emit::EmissionSchedule>();
+    world.register::<vapour::OvenFlux>();
+    world.register::<vapour::OvenReservoir>();
     world.register::<emit::AtomNumberToEmit>();
     world.register::<surface::SurfaceSource>();
     world.register::<gaussian::GaussianVelocityDistributionSource>();
     world.register::<gaussian::GaussianVelocityDistributionSourceDefinition>();
     world.register::<central_creator::CentralCreator>();
+    world.register::<buffer_gas::BufferGasBeam>();
+    world.register::<file::AtomFileSource>();
+    world.register::<importance::ImportanceSampling>();
 }
 
 /// A simple probability distribution which uses weighted indices to retrieve values.
 struct WeightedProbabilityDistribution {
     values: Vec<f64>,
     weighted_index: WeightedIndex<f64>,
+    /// Statistical weight of each value, if the distribution is biased.
+    sample_weights: Option<Vec<f64>>,
 }
 impl WeightedProbabilityDistribution {
     pub fn new(values: Vec<f64>, weights: Vec<f64>) -> Self {
         WeightedProbabilityDistribution {
             values: values,
             weighted_index: WeightedIndex::new(&weights).unwrap(),
+            sample_weights: None,
+        }
+    }
+
+    /// Creates a distribution which is biased by the given [SamplingBias](importance/struct.SamplingBias.html).
+    ///
+    /// Values drawn using [sample_with_weight](struct.WeightedProbabilityDistribution.html#method.sample_with_weight)
+    /// have a statistical weight which corrects for the bias.
+    pub fn new_biased(
+        values: Vec<f64>,
+        weights: Vec<f64>,
+        bias: &importance::SamplingBias,
+    ) -> Self {
+        let biases: Vec<f64> = values.iter().map(|&value| bias.bias(value)).collect();
+        let biased_weights: Vec<f64> = weights
+            .iter()
+            .zip(biases.iter())
+            .map(|(weight, bias)| weight * bias)
+            .collect();
+        let mean_bias = biased_weights.iter().sum::<f64>() / weights.iter().sum::<f64>();
+        WeightedProbabilityDistribution {
+            values: values,
+            weighted_index: WeightedIndex::new(&biased_weights).unwrap(),
+            sample_weights: Some(biases.iter().map(|bias| mean_bias / bias).collect()),
         }
     }
+
+    /// Draws a value from the distribution, along with its statistical weight.
+    pub fn sample_with_weight<R: Rng + ?Sized>(&self, rng: &mut R) -> (f64, f64) {
+        let index = self.weighted_index.sample(rng);
+        let weight = match &self.sample_weights {
+            Some(sample_weights) => sample_weights[index],
+            None => 1.0,
+        };
+        (self.values[index], weight)
+    }
 }
 impl Distribution<f64> for WeightedProbabilityDistribution {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
@@ -7,6 +7,7 @@ use super::precalc::{MaxwellBoltzmannSource, PrecalculatedSpeciesInformation};
 use crate::constant;
 use crate::constant::PI;
 use crate::initiate::*;
+use crate::rng::{FrameRandomKey, RandomStreams};
 
 use super::VelocityCap;
 use super::WeightedProbabilityDistribution;
@@ -20,21 +21,19 @@ use nalgebra::Vector3;
 
 use specs::{Component, Entities, HashMapStorage, Join, LazyUpdate, Read, ReadStorage, System};
 
-fn velocity_generate(
+fn velocity_generate<R: Rng + ?Sized>(
 	v_mag: f64,
 	new_dir: &Vector3<f64>,
-	theta_distribution: &WeightedProbabilityDistribution,
-) -> (Vector3<f64>, f64) {
+	theta: f64,
+	rng: &mut R,
+) -> Vector3<f64> {
 	let dir = &new_dir.normalize();
 	let dir_1 = new_dir.cross(&Vector3::new(2.0, 1.0, 0.5)).normalize();
 	let dir_2 = new_dir.cross(&dir_1).normalize();
-	let mut rng = rand::thread_rng();
-	let theta = theta_distribution.sample(&mut rng);
 	let phi = rng.gen_range(0.0..2.0 * PI);
 	let dir_div = dir_1 * theta.sin() * phi.cos() + dir_2 * theta.sin() * phi.sin();
 	let dirf = dir * theta.cos() + dir_div;
-	let v_out = dirf * v_mag;
-	(v_out, theta)
+	dirf * v_mag
 }
 /// Opening aperture of the oven
 #[derive(Copy, Clone)]
@@ -97,6 +96,8 @@ impl OvenBuilder {
 				self.microchannel_length,
 			),
 			max_theta: self.max_theta,
+			microchannel_radius: self.microchannel_radius,
+			microchannel_length: self.microchannel_length,
 		}
 	}
 }
@@ -129,6 +130,12 @@ pub struct Oven {
 
 	/// The maximum angle theta at which atoms can be emitted from the oven. This can be constricted eg by a heat shield, or 'hot lip'.
 	pub max_theta: f64,
+
+	/// Radius of the microchannels in the oven aperture, in m.
+	pub microchannel_radius: f64,
+
+	/// Length of the microchannels in the oven aperture, in m.
+	pub microchannel_length: f64,
 }
 impl MaxwellBoltzmannSource for Oven {
 	fn get_temperature(&self) -> f64 {
@@ -137,13 +144,55 @@ impl MaxwellBoltzmannSource for Oven {
 	fn get_v_dist_power(&self) -> f64 {
 		3.0
 	}
+	fn get_angular_distribution(&self, theta: f64) -> f64 {
+		jtheta(theta, self.microchannel_radius, self.microchannel_length) * theta.sin()
+	}
 }
 impl Component for Oven {
 	type Storage = HashMapStorage<Self>;
 }
 impl Oven {
-	pub fn get_random_spawn_position(&self) -> Vector3<f64> {
-		let mut rng = rand::thread_rng();
+	/// Area of the oven aperture through which atoms effuse, in m^2.
+	///
+	/// For a `Cubic` aperture, this is the area of the aperture projected along the oven direction.
+	pub fn aperture_area(&self) -> f64 {
+		match self.aperture {
+			OvenAperture::Cubic { size } => {
+				let dir = self.direction.normalize();
+				dir[0].abs() * size[1] * size[2]
+					+ dir[1].abs() * size[0] * size[2]
+					+ dir[2].abs() * size[0] * size[1]
+			}
+			OvenAperture::Circular { radius, .. } => PI * radius.powi(2),
+		}
+	}
+
+	/// Fraction of atoms entering the microchannels which are transmitted through them.
+	///
+	/// This is the Clausing factor of a cylindrical channel, in the approximation
+	/// `W = 1 / (1 + 3L / 8r)`, which is accurate to within ~10% for all channel aspect ratios.
+	pub fn transmission(&self) -> f64 {
+		1.0 / (1.0 + 3.0 * self.microchannel_length / (8.0 * self.microchannel_radius))
+	}
+
+	/// Calculates the flux of atoms leaving the oven, in atoms per second.
+	///
+	/// The flux is that of an effusive source, `n v_mean A W / 4`, where `n` is the number density
+	/// of the vapour, `v_mean` the mean thermal speed, `A` the aperture area and `W` the
+	/// [transmission](struct.Oven.html#method.transmission) of the microchannels.
+	///
+	/// # Arguments
+	///
+	/// `pressure`: vapour pressure inside the oven, in Pa.
+	///
+	/// `mass`: mass of the atoms, in kg.
+	pub fn atom_flux(&self, pressure: f64, mass: f64) -> f64 {
+		let density = pressure / (constant::BOLTZCONST * self.temperature);
+		let mean_speed = (8.0 * constant::BOLTZCONST * self.temperature / (PI * mass)).sqrt();
+		0.25 * density * mean_speed * self.aperture_area() * self.transmission()
+	}
+
+	pub fn get_random_spawn_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Vector3<f64> {
 		match self.aperture {
 			OvenAperture::Cubic { size } => {
 				let size = size.clone();
@@ -180,35 +229,50 @@ impl<'a> System<'a> for OvenCreateAtomsSystem {
 		ReadStorage<'a, PrecalculatedSpeciesInformation>,
 		Option<Read<'a, VelocityCap>>,
 		Read<'a, LazyUpdate>,
+		Option<Read<'a, FrameRandomKey>>,
 	);
 
 	fn run(
 		&mut self,
-		(entities, oven, atom, numbers_to_emit, pos, precalcs, velocity_cap, updater): Self::SystemData,
+		(
+			entities,
+			oven,
+			atom,
+			numbers_to_emit,
+			pos,
+			precalcs,
+			velocity_cap,
+			updater,
+			random_key,
+		): Self::SystemData,
 	) {
 		let max_vel = match velocity_cap {
 			Some(cap) => cap.value,
 			None => std::f64::MAX,
 		};
 
-		let mut rng = rand::thread_rng();
+		let mut rng = RandomStreams::new(random_key.as_deref(), "oven_create_atoms").rng();
 		for (oven, atom, number_to_emit, oven_position, precalcs) in
 			(&oven, &atom, &numbers_to_emit, &pos, &precalcs).join()
 		{
 			for _i in 0..number_to_emit.number {
-				let (mass, speed) = precalcs.generate_random_mass_v(&mut rng);
+				let (mass, speed, transition, speed_weight) =
+					precalcs.generate_weighted_species(&mut rng, atom);
 				if speed > max_vel {
 					continue;
 				}
 
 				let new_atom = entities.create();
-				let (new_vel, theta) =
-					velocity_generate(speed, &oven.direction, &oven.theta_distribution);
+				let (theta, angle_weight) = match precalcs.generate_weighted_angle(&mut rng) {
+					Some(sample) => sample,
+					None => (oven.theta_distribution.sample(&mut rng), 1.0),
+				};
+				let new_vel = velocity_generate(speed, &oven.direction, theta, &mut rng);
 
 				if theta > oven.max_theta {
 					continue;
 				}
-				let start_position = oven_position.pos + oven.get_random_spawn_position();
+				let start_position = oven_position.pos + oven.get_random_spawn_position(&mut rng);
 				updater.insert(
 					new_atom,
 					Position {
@@ -223,10 +287,18 @@ impl<'a> System<'a> for OvenCreateAtomsSystem {
 				);
 				updater.insert(new_atom, Force::new());
 				updater.insert(new_atom, Mass { value: mass });
-				updater.insert(new_atom, atom.clone());
+				updater.insert(new_atom, transition);
 				updater.insert(new_atom, Atom);
 				updater.insert(new_atom, InitialVelocity { vel: new_vel });
 				updater.insert(new_atom, NewlyCreated);
+				if precalcs.is_importance_sampled() {
+					updater.insert(
+						new_atom,
+						Weight {
+							value: speed_weight * angle_weight,
+						},
+					);
+				}
 			}
 		}
 	}
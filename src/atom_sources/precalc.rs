@@ -1,8 +1,10 @@
 //! Utilities for precalculating quantities such as mass and velocity distributions.
 
+use super::importance::{ImportanceSampling, SamplingBias};
 use super::mass::MassDistribution;
 use super::WeightedProbabilityDistribution;
-use crate::constant::{AMU, BOLTZCONST, EXP};
+use crate::atom::AtomicTransition;
+use crate::constant::{AMU, BOLTZCONST, EXP, PI};
 
 use rand;
 use rand::distributions::Distribution;
@@ -20,10 +22,13 @@ use specs::{Component, Entities, Entity, HashMapStorage, Join, ReadStorage, Syst
 /// `temperature`: The temperature of the oven, in units of Kelvin.
 ///
 /// `mass`: The mass of the particle, in SI units of kg.
+///
+/// `bias`: bias used to importance sample the velocities, if any.
 fn create_v_distribution(
     temperature: f64,
     mass: f64,
     power: f64,
+    bias: Option<&SamplingBias>,
 ) -> WeightedProbabilityDistribution {
     let max_velocity = 7.0 * (2.0 * BOLTZCONST * temperature / mass).powf(0.5);
 
@@ -40,7 +45,29 @@ fn create_v_distribution(
         weights.push(weight);
     }
 
-    WeightedProbabilityDistribution::new(velocities, weights)
+    match bias {
+        Some(bias) => WeightedProbabilityDistribution::new_biased(velocities, weights, bias),
+        None => WeightedProbabilityDistribution::new(velocities, weights),
+    }
+}
+
+/// Creates a [WeightedProbabilityDistribution](struct.WeightedProbabilityDistribution.html) of the
+/// polar angle `theta` at which atoms are emitted by the source, biased by the given [SamplingBias].
+fn create_angle_distribution<T: MaxwellBoltzmannSource>(
+    source: &T,
+    bias: &SamplingBias,
+) -> WeightedProbabilityDistribution {
+    let mut thetas = Vec::<f64>::new();
+    let mut weights = Vec::<f64>::new();
+
+    let n = 1000;
+    for i in 0..n {
+        let theta = (i as f64 + 0.5) / (n as f64 + 1.0) * PI / 2.0;
+        thetas.push(theta);
+        weights.push(source.get_angular_distribution(theta));
+    }
+
+    WeightedProbabilityDistribution::new_biased(thetas, weights, bias)
 }
 
 /// The probability distribution `p(v)` that a given `mass` has a velocity magnitude `v`.
@@ -66,12 +93,21 @@ pub struct Species {
     mass: f64,
     /// Distribution that can be used to generate random velocity magnitudes `v`.
     v_distribution: WeightedProbabilityDistribution,
+    /// Transition of the species, if it differs from that of the source.
+    transition: Option<AtomicTransition>,
 }
 impl Species {
-    fn create(mass: f64, temperature: f64, power: f64) -> Self {
+    fn create(
+        mass: f64,
+        temperature: f64,
+        power: f64,
+        transition: Option<AtomicTransition>,
+        speed_bias: Option<&SamplingBias>,
+    ) -> Self {
         Species {
             mass: mass,
-            v_distribution: create_v_distribution(temperature, mass * AMU, power),
+            v_distribution: create_v_distribution(temperature, mass * AMU, power, speed_bias),
+            transition: transition,
         }
     }
 }
@@ -82,6 +118,10 @@ pub struct PrecalculatedSpeciesInformation {
     species: Vec<Species>,
     /// weighted distribution holding the chance to create each species.
     distribution: WeightedIndex<f64>,
+    /// Biased distribution of the emission angle, if the angle is importance sampled.
+    angle_distribution: Option<WeightedProbabilityDistribution>,
+    /// True if the source uses [ImportanceSampling](../importance/struct.ImportanceSampling.html).
+    importance_sampled: bool,
 }
 impl PrecalculatedSpeciesInformation {
     /// Gets a random mass and velocity from the precalculated distributions.
@@ -94,16 +134,87 @@ impl PrecalculatedSpeciesInformation {
         (species.mass, species.v_distribution.sample(rng))
     }
 
-    fn create(temperature: f64, mass_distribution: &MassDistribution, power: f64) -> Self {
+    /// Gets a random mass, velocity and transition from the precalculated distributions.
+    ///
+    /// The tuple returned is of the form (mass, velocity, transition). The transition is that of the
+    /// randomly chosen isotope, or the `default` transition of the source if the isotope does not specify one.
+    pub fn generate_random_species<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        default: &AtomicTransition,
+    ) -> (f64, f64, AtomicTransition) {
+        let i = self.distribution.sample(rng);
+        let species = &self.species[i];
+        let transition = match &species.transition {
+            Some(transition) => transition.clone(),
+            None => default.clone(),
+        };
+        (species.mass, species.v_distribution.sample(rng), transition)
+    }
+
+    /// Gets a random mass, velocity and transition as for [generate_random_species](struct.PrecalculatedSpeciesInformation.html#method.generate_random_species),
+    /// along with the statistical weight of the velocity.
+    ///
+    /// The tuple returned is of the form (mass, velocity, transition, weight).
+    pub fn generate_weighted_species<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        default: &AtomicTransition,
+    ) -> (f64, f64, AtomicTransition, f64) {
+        let i = self.distribution.sample(rng);
+        let species = &self.species[i];
+        let transition = match &species.transition {
+            Some(transition) => transition.clone(),
+            None => default.clone(),
+        };
+        let (speed, weight) = species.v_distribution.sample_with_weight(rng);
+        (species.mass, speed, transition, weight)
+    }
+
+    /// Gets a random emission angle from the biased angular distribution, along with its statistical weight.
+    ///
+    /// Returns `None` if the emission angle is not importance sampled, in which case the angle should
+    /// be drawn from the source's own distribution.
+    pub fn generate_weighted_angle<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(f64, f64)> {
+        match &self.angle_distribution {
+            Some(distribution) => Some(distribution.sample_with_weight(rng)),
+            None => None,
+        }
+    }
+
+    /// Returns true if atoms generated from this information should be given a [Weight](../../atom/struct.Weight.html).
+    pub fn is_importance_sampled(&self) -> bool {
+        self.importance_sampled
+    }
+
+    fn create<T: MaxwellBoltzmannSource>(
+        source: &T,
+        mass_distribution: &MassDistribution,
+        importance_sampling: Option<&ImportanceSampling>,
+    ) -> Self {
+        let temperature = source.get_temperature();
+        let power = source.get_v_dist_power();
+        let speed_bias = importance_sampling.and_then(|sampling| sampling.speed);
+        let angle_bias = importance_sampling.and_then(|sampling| sampling.angle);
         let mut species = Vec::<Species>::new();
         let mut ratios = Vec::<f64>::new();
         for mr in &mass_distribution.distribution {
             ratios.push(mr.ratio);
-            species.push(Species::create(mr.mass, temperature, power));
+            species.push(Species::create(
+                mr.mass,
+                temperature,
+                power,
+                mr.transition().cloned(),
+                speed_bias.as_ref(),
+            ));
         }
         PrecalculatedSpeciesInformation {
             species: species,
             distribution: WeightedIndex::new(&ratios).unwrap(),
+            angle_distribution: angle_bias
+                .as_ref()
+                .map(|bias| create_angle_distribution(source, bias)),
+            importance_sampled: importance_sampling.is_some(),
         }
     }
 }
@@ -114,6 +225,9 @@ impl Component for PrecalculatedSpeciesInformation {
 pub trait MaxwellBoltzmannSource {
     fn get_temperature(&self) -> f64;
     fn get_v_dist_power(&self) -> f64;
+    /// Gets the probability density that an atom is emitted at polar angle `theta` to the emission
+    /// direction, per unit `theta`. The density does not need to be normalised.
+    fn get_angular_distribution(&self, theta: f64) -> f64;
 }
 
 /// Precalculates different distributions used by the Oven systems.
@@ -134,19 +248,26 @@ where
         ReadStorage<'a, T>,
         WriteStorage<'a, MassDistribution>,
         WriteStorage<'a, PrecalculatedSpeciesInformation>,
+        ReadStorage<'a, ImportanceSampling>,
     );
 
-    fn run(&mut self, (entities, sources, mut mass_distributions, mut precalcs): Self::SystemData) {
+    fn run(
+        &mut self,
+        (entities, sources, mut mass_distributions, mut precalcs, importance_samplings): Self::SystemData,
+    ) {
         // Precalculate for ovens which do not currently have precalculated information.
         let mut precalculated_data = Vec::<(Entity, PrecalculatedSpeciesInformation)>::new();
-        for (entity, source, mass_dist, _) in
-            (&entities, &sources, &mass_distributions, !&precalcs).join()
+        for (entity, source, mass_dist, _, importance_sampling) in (
+            &entities,
+            &sources,
+            &mass_distributions,
+            !&precalcs,
+            importance_samplings.maybe(),
+        )
+            .join()
         {
-            let precalculated = PrecalculatedSpeciesInformation::create(
-                source.get_temperature(),
-                mass_dist,
-                source.get_v_dist_power(),
-            );
+            let precalculated =
+                PrecalculatedSpeciesInformation::create(source, mass_dist, importance_sampling);
             //mass_distributions.remove(entity);
             //precalcs.insert(entity, precalculated);
             precalculated_data.push((entity, precalculated));
@@ -1,4 +1,11 @@
 //! Surface sources
+//!
+//! A [SurfaceSource](struct.SurfaceSource.html) emits atoms from the surface of a shape attached to the
+//! same entity. Any shape implementing [Surface](../../shapes/trait.Surface.html) can be used; a
+//! [CreateAtomsOnSurfaceSystem](struct.CreateAtomsOnSurfaceSystem.html) is added to the dispatcher for
+//! each of the shapes in [shapes](../../shapes/index.html).
+//!
+//! Atoms are emitted into the volume enclosed by the surface, with directions following Lambert's cosine law.
 
 extern crate nalgebra;
 use nalgebra::Vector3;
@@ -11,7 +18,9 @@ use rand::Rng;
 use super::precalc::{MaxwellBoltzmannSource, PrecalculatedSpeciesInformation};
 use crate::atom::*;
 use crate::initiate::NewlyCreated;
-use crate::shapes::{Cylinder, Surface};
+use crate::rng::{FrameRandomKey, RandomStreams};
+use crate::shapes::Surface;
+use std::marker::PhantomData;
 
 extern crate specs;
 use specs::{Component, Entities, HashMapStorage, Join, LazyUpdate, Read, ReadStorage, System};
@@ -30,24 +39,78 @@ impl MaxwellBoltzmannSource for SurfaceSource {
 	fn get_v_dist_power(&self) -> f64 {
 		2.0
 	}
+	fn get_angular_distribution(&self, theta: f64) -> f64 {
+		theta.cos() * theta.sin()
+	}
 }
 
-/// This system creates atoms from an oven source.
+/// Generates a random direction about `direction` following Lambert's cosine law.
 ///
-/// The oven points in the direction [Oven.direction].
-pub struct CreateAtomsOnSurfaceSystem;
+/// The probability of emission at an angle `theta` to `direction` is proportional to `cos(theta)`,
+/// per unit solid angle.
+pub fn lambertian_direction<R: Rng + ?Sized>(
+	rng: &mut R,
+	direction: &Vector3<f64>,
+) -> Vector3<f64> {
+	// sin^2(theta) is uniformly distributed for a cosine law.
+	let sin_squared: f64 = rng.gen_range(0.0..1.0);
+	direction_at_angle(rng, direction, sin_squared.sqrt().asin())
+}
+
+/// Generates a random direction at polar angle `theta` to `direction`, with a uniformly distributed azimuthal angle.
+pub fn direction_at_angle<R: Rng + ?Sized>(
+	rng: &mut R,
+	direction: &Vector3<f64>,
+	theta: f64,
+) -> Vector3<f64> {
+	let direction = direction.normalize();
+
+	// construct two vectors perpendicular to direction.
+	let trial = if direction[0].abs() < 0.9 {
+		Vector3::x()
+	} else {
+		Vector3::y()
+	};
+	let perp_a = direction.cross(&trial).normalize();
+	let perp_b = direction.cross(&perp_a);
 
-impl<'a> System<'a> for CreateAtomsOnSurfaceSystem {
+	let phi: f64 = rng.gen_range(0.0..2.0 * std::f64::consts::PI);
+	theta.cos() * direction + theta.sin() * (perp_a * phi.cos() + perp_b * phi.sin())
+}
+
+/// This system creates atoms from surface sources with shape `T`.
+///
+/// Atoms are created uniformly over the surface, and travel inwards from the surface.
+pub struct CreateAtomsOnSurfaceSystem<T: Surface> {
+	pub marker: PhantomData<T>,
+	/// Name of the random stream used by the system, which must be unique to each shape.
+	pub stream_name: &'static str,
+}
+impl<T: Surface> CreateAtomsOnSurfaceSystem<T> {
+	/// Creates a system which draws from the random stream `stream_name`, eg `"cylinder_surface_create_atoms"`.
+	pub fn new(stream_name: &'static str) -> Self {
+		CreateAtomsOnSurfaceSystem {
+			marker: PhantomData,
+			stream_name: stream_name,
+		}
+	}
+}
+
+impl<'a, T> System<'a> for CreateAtomsOnSurfaceSystem<T>
+where
+	T: Surface + Component + Sync,
+{
 	type SystemData = (
 		Entities<'a>,
 		ReadStorage<'a, SurfaceSource>,
-		ReadStorage<'a, Cylinder>,
+		ReadStorage<'a, T>,
 		ReadStorage<'a, AtomicTransition>,
 		ReadStorage<'a, AtomNumberToEmit>,
 		ReadStorage<'a, Position>,
 		ReadStorage<'a, PrecalculatedSpeciesInformation>,
 		Option<Read<'a, VelocityCap>>,
 		Read<'a, LazyUpdate>,
+		Option<Read<'a, FrameRandomKey>>,
 	);
 
 	fn run(
@@ -62,6 +125,7 @@ impl<'a> System<'a> for CreateAtomsOnSurfaceSystem {
 			species,
 			velocity_cap,
 			updater,
+			random_key,
 		): Self::SystemData,
 	) {
 		// obey velocity cap.
@@ -70,7 +134,7 @@ impl<'a> System<'a> for CreateAtomsOnSurfaceSystem {
 			None => std::f64::MAX,
 		};
 
-		let mut rng = rand::thread_rng();
+		let mut rng = RandomStreams::new(random_key.as_deref(), self.stream_name).rng();
 		for (_, shape, atom_info, number_to_emit, source_position, species) in (
 			&surfaces,
 			&shapes,
@@ -83,37 +147,24 @@ impl<'a> System<'a> for CreateAtomsOnSurfaceSystem {
 		{
 			for _i in 0..number_to_emit.number {
 				// Get random speed and mass.
-				let (mass, speed) = species.generate_random_mass_v(&mut rng);
+				let (mass, speed, transition, speed_weight) =
+					species.generate_weighted_species(&mut rng, atom_info);
 				if speed > max_vel {
 					continue;
 				}
 
 				// generate a random position on the surface.
-				let (position, normal) = shape.get_random_point_on_surface(&source_position.pos);
-
-				// lambert cosine emission
-				let direction = -normal.normalize();
-				let random_dir = Vector3::new(
-					rng.gen_range(-1.0..1.0),
-					rng.gen_range(-1.0..1.0),
-					rng.gen_range(-1.0..1.0),
-				)
-				.normalize();
-				let perp_a = direction.cross(&random_dir);
-				let perp_b = direction.cross(&perp_a);
-
-				let domain: bool = rng.gen();
-				let var: f64 = rng.gen_range(0.0..1.0);
-				let phi: f64 = rng.gen_range(0.0..2.0 * std::f64::consts::PI);
-				let theta: f64;
-				if domain {
-					theta = var.acos() / 2.0;
-				} else {
-					theta = var.asin() / 2.0 + std::f64::consts::PI / 4.0;
-				}
-				let emission_direction = theta.cos() * direction
-					+ theta.sin() * (perp_a * phi.cos() + perp_b * phi.sin());
+				let (position, normal) =
+					shape.get_random_point_on_surface(&source_position.pos, &mut rng);
 
+				// lambert cosine emission, into the volume enclosed by the surface.
+				let (emission_direction, angle_weight) =
+					match species.generate_weighted_angle(&mut rng) {
+						Some((theta, weight)) => {
+							(direction_at_angle(&mut rng, &-normal, theta), weight)
+						}
+						None => (lambertian_direction(&mut rng, &-normal), 1.0),
+					};
 				let velocity = speed * emission_direction;
 
 				let new_atom = entities.create();
@@ -121,11 +172,76 @@ impl<'a> System<'a> for CreateAtomsOnSurfaceSystem {
 				updater.insert(new_atom, Velocity { vel: velocity });
 				updater.insert(new_atom, Force::new());
 				updater.insert(new_atom, Mass { value: mass });
-				updater.insert(new_atom, atom_info.clone());
+				updater.insert(new_atom, transition);
 				updater.insert(new_atom, Atom);
 				updater.insert(new_atom, InitialVelocity { vel: velocity });
 				updater.insert(new_atom, NewlyCreated);
+				if species.is_importance_sampled() {
+					updater.insert(
+						new_atom,
+						Weight {
+							value: speed_weight * angle_weight,
+						},
+					);
+				}
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+pub mod tests {
+
+	use super::*;
+	use crate::shapes::{Cuboid, Sphere};
+
+	#[test]
+	fn test_lambertian_direction() {
+		let mut rng = rand::thread_rng();
+		let normal = Vector3::new(1.0, 1.0, 0.0).normalize();
+		let n = 100000;
+		let mut mean_cos = 0.0;
+		for _ in 0..n {
+			let direction = lambertian_direction(&mut rng, &normal);
+			assert!((direction.norm() - 1.0).abs() < 1e-9);
+			let cos_theta = direction.dot(&normal);
+			assert!(cos_theta >= 0.0);
+			mean_cos += cos_theta / n as f64;
+		}
+		// <cos(theta)> = 2/3 for a cosine law.
+		assert!((mean_cos - 2.0 / 3.0).abs() < 0.01);
+	}
+
+	#[test]
+	fn test_surface_points_are_area_weighted() {
+		let mut rng = rand::thread_rng();
+		let cuboid = Cuboid {
+			half_width: Vector3::new(1.0, 1.0, 4.0),
+		};
+		let n = 100000;
+		let mut on_z_faces = 0;
+		for _ in 0..n {
+			let (_, normal) =
+				cuboid.get_random_point_on_surface(&Vector3::new(0.0, 0.0, 0.0), &mut rng);
+			if normal[2] != 0.0 {
+				on_z_faces += 1;
+			}
+		}
+		// the z faces have area 2 * 4, out of a total of 2 * (4 + 16 + 16).
+		assert!((on_z_faces as f64 / n as f64 - 8.0 / 72.0).abs() < 0.01);
+
+		let sphere = Sphere { radius: 2.0 };
+		let mut mean_z = 0.0;
+		let mut mean_z_squared = 0.0;
+		for _ in 0..n {
+			let (position, normal) =
+				sphere.get_random_point_on_surface(&Vector3::new(0.0, 0.0, 1.0), &mut rng);
+			assert!((position - Vector3::new(0.0, 0.0, 1.0) - 2.0 * normal).norm() < 1e-12);
+			mean_z += normal[2] / n as f64;
+			mean_z_squared += normal[2].powi(2) / n as f64;
+		}
+		// for points uniform over a sphere, <z^2> = 1/3.
+		assert!(mean_z.abs() < 0.01);
+		assert!((mean_z_squared - 1.0 / 3.0).abs() < 0.01);
+	}
+}
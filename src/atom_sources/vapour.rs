@@ -0,0 +1,255 @@
+//! Emission of atoms from an oven at a rate determined by the vapour pressure of the element.
+//!
+//! Add an [OvenFlux](struct.OvenFlux.html) component to an [Oven](../oven/struct.Oven.html) entity
+//! instead of a fixed emission rate. The flux of atoms leaving the oven is then calculated from the
+//! oven temperature, the aperture area and the transmission of the microchannels. An
+//! [OvenReservoir](struct.OvenReservoir.html) can also be added to model a finite charge of
+//! material, which is depleted by the flux leaving the oven.
+
+use super::emit::AtomNumberToEmit;
+use super::mass::MassDistribution;
+use super::oven::Oven;
+use crate::constant::AMU;
+use crate::integrator::Timestep;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+/// The vapour pressure curve of an element.
+///
+/// The vapour pressure is given by
+/// `log10(P / Pa) = 5.006 + a + b / T + c log10(T) + d / T^3`,
+/// the form used by Alcock et al, _Canadian Metallurgical Quarterly_ **23**, 309 (1984).
+/// Each curve is only valid for a single phase (solid or liquid) of the element.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct VapourPressureCurve {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+}
+impl VapourPressureCurve {
+    /// Calculates the vapour pressure at the given temperature.
+    ///
+    /// # Arguments
+    ///
+    /// `temperature`: temperature of the element, in Kelvin.
+    ///
+    /// Returns the vapour pressure, in Pa.
+    pub fn pressure(&self, temperature: f64) -> f64 {
+        10.0_f64.powf(
+            5.006
+                + self.a
+                + self.b / temperature
+                + self.c * temperature.log10()
+                + self.d / temperature.powi(3),
+        )
+    }
+
+    /// Liquid rubidium, valid from the melting point (312 K) to 550 K.
+    pub fn rubidium() -> Self {
+        VapourPressureCurve {
+            a: 4.312,
+            b: -4040.0,
+            c: 0.0,
+            d: 0.0,
+        }
+    }
+
+    /// Liquid potassium, valid from the melting point (337 K) to 600 K.
+    pub fn potassium() -> Self {
+        VapourPressureCurve {
+            a: 4.402,
+            b: -4453.0,
+            c: 0.0,
+            d: 0.0,
+        }
+    }
+
+    /// Solid strontium, valid from 298 K to the melting point (1050 K).
+    pub fn strontium() -> Self {
+        VapourPressureCurve {
+            a: 9.226,
+            b: -8572.0,
+            c: -1.1926,
+            d: 0.0,
+        }
+    }
+}
+
+/// Component which sets the emission rate of an [Oven](../oven/struct.Oven.html) from the vapour
+/// pressure of the element it contains.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OvenFlux {
+    /// Vapour pressure curve of the element in the oven.
+    pub vapour_pressure: VapourPressureCurve,
+    /// The fraction of atoms leaving the oven which are created in the simulation.
+    ///
+    /// Real ovens emit far more atoms than can be simulated, so typically `simulated_fraction << 1`.
+    pub simulated_fraction: f64,
+}
+impl OvenFlux {
+    /// Calculates the flux of atoms leaving the oven, in atoms per second.
+    ///
+    /// The flux is summed over the isotopes in the `mass_distribution`, each of which effuses with
+    /// its own mean thermal speed.
+    pub fn atom_flux(&self, oven: &Oven, mass_distribution: &MassDistribution) -> f64 {
+        let pressure = self.vapour_pressure.pressure(oven.temperature);
+        mass_distribution
+            .distribution
+            .iter()
+            .map(|isotope| isotope.ratio * oven.atom_flux(pressure, isotope.mass * AMU))
+            .sum()
+    }
+}
+impl Component for OvenFlux {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// A finite charge of material inside an oven.
+///
+/// The reservoir is depleted by the flux of atoms leaving the oven. Once empty, the oven stops
+/// emitting atoms.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OvenReservoir {
+    /// Mass of material remaining in the oven, in kg.
+    pub mass: f64,
+}
+impl Component for OvenReservoir {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Calculates the number of atoms to emit each frame for ovens with an [OvenFlux](struct.OvenFlux.html),
+/// and depletes any [OvenReservoir](struct.OvenReservoir.html).
+///
+/// As for [EmitFixedRateSystem](../emit/struct.EmitFixedRateSystem.html), the number emitted each
+/// frame fluctuates randomly so that the average rate is correct.
+pub struct EmitOvenFluxSystem;
+impl<'a> System<'a> for EmitOvenFluxSystem {
+    type SystemData = (
+        ReadStorage<'a, Oven>,
+        ReadStorage<'a, OvenFlux>,
+        ReadStorage<'a, MassDistribution>,
+        WriteStorage<'a, OvenReservoir>,
+        ReadExpect<'a, Timestep>,
+        WriteStorage<'a, AtomNumberToEmit>,
+        Option<Read<'a, FrameRandomKey>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            ovens,
+            fluxes,
+            mass_distributions,
+            mut reservoirs,
+            timestep,
+            mut emit_numbers,
+            random_key,
+        ): Self::SystemData,
+    ) {
+        let mut rng = RandomStreams::new(random_key.as_deref(), "emit_oven_flux").rng();
+        for (oven, flux, mass_distribution, reservoir, emit_number) in (
+            &ovens,
+            &fluxes,
+            &mass_distributions,
+            (&mut reservoirs).maybe(),
+            &mut emit_numbers,
+        )
+            .join()
+        {
+            let mut atoms_leaving = flux.atom_flux(oven, mass_distribution) * timestep.delta;
+            if let Some(reservoir) = reservoir {
+                let mean_mass: f64 = mass_distribution
+                    .distribution
+                    .iter()
+                    .map(|isotope| isotope.ratio * isotope.mass * AMU)
+                    .sum();
+                atoms_leaving = atoms_leaving.min(reservoir.mass / mean_mass);
+                reservoir.mass = (reservoir.mass - atoms_leaving * mean_mass).max(0.0);
+            }
+
+            let avg_number_to_emit = atoms_leaving * flux.simulated_fraction;
+            let guaranteed_number = avg_number_to_emit.floor();
+            if rng.gen::<f64>() < avg_number_to_emit - guaranteed_number {
+                emit_number.number = guaranteed_number as i32 + 1;
+            } else {
+                emit_number.number = guaranteed_number as i32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::atom_sources::mass::MassRatio;
+    use crate::atom_sources::oven::{OvenAperture, OvenBuilder};
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_vapour_pressure() {
+        // Rubidium vapour pressure at 373 K is approximately 3e-2 Pa.
+        let pressure = VapourPressureCurve::rubidium().pressure(373.0);
+        assert!(pressure > 2e-2 && pressure < 5e-2);
+        // Strontium vapour pressure at 800 K is approximately 1 Pa.
+        let pressure = VapourPressureCurve::strontium().pressure(800.0);
+        assert!(pressure > 0.5 && pressure < 2.0);
+    }
+
+    #[test]
+    fn test_oven_reservoir_depletes() {
+        let mut test_world = World::new();
+        test_world.register::<Oven>();
+        test_world.register::<OvenFlux>();
+        test_world.register::<MassDistribution>();
+        test_world.register::<OvenReservoir>();
+        test_world.register::<AtomNumberToEmit>();
+        test_world.insert(Timestep { delta: 1.0 });
+
+        let oven = OvenBuilder::new(800.0, Vector3::x())
+            .with_aperture(OvenAperture::Circular {
+                radius: 1e-3,
+                thickness: 1e-3,
+            })
+            .build();
+        let flux = OvenFlux {
+            vapour_pressure: VapourPressureCurve::strontium(),
+            simulated_fraction: 1e-10,
+        };
+        let mass_distribution = MassDistribution::new(vec![MassRatio::new(88.0, 1.0)]);
+        let atoms_per_second = flux.atom_flux(&oven, &mass_distribution);
+        let initial_mass = 2.5 * atoms_per_second * 88.0 * AMU;
+
+        let emitter = test_world
+            .create_entity()
+            .with(oven)
+            .with(flux)
+            .with(mass_distribution)
+            .with(OvenReservoir { mass: initial_mass })
+            .with(AtomNumberToEmit { number: 0 })
+            .build();
+
+        let mut system = EmitOvenFluxSystem;
+        system.run_now(&test_world);
+        {
+            let reservoirs = test_world.read_storage::<OvenReservoir>();
+            let remaining = reservoirs.get(emitter).expect("entity not found").mass;
+            assert_approx_eq!(remaining / initial_mass, 0.6, 1e-9);
+            let numbers = test_world.read_storage::<AtomNumberToEmit>();
+            let number = numbers.get(emitter).expect("entity not found").number as f64;
+            assert!((number - atoms_per_second * 1e-10).abs() <= 1.0);
+        }
+
+        for _ in 0..3 {
+            system.run_now(&test_world);
+        }
+        let reservoirs = test_world.read_storage::<OvenReservoir>();
+        assert_eq!(reservoirs.get(emitter).expect("entity not found").mass, 0.0);
+        let numbers = test_world.read_storage::<AtomNumberToEmit>();
+        assert_eq!(numbers.get(emitter).expect("entity not found").number, 0);
+    }
+}
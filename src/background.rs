@@ -0,0 +1,244 @@
+//! Collisions with the background gas in the vacuum chamber.
+//!
+//! The lifetime of trapped atoms in a real chamber is limited by collisions with background gas
+//! molecules. A single collision typically transfers enough momentum to eject the atom from the trap.
+//! To enable background collisions, add a [BackgroundGas](struct.BackgroundGas.html) resource to the world.
+//! Each frame, every atom undergoes a collision with probability `1 - exp(-rate * dt)`. The colliding
+//! atom is either removed from the simulation or given a random velocity kick, depending on the
+//! [CollisionOutcome](enum.CollisionOutcome.html).
+//!
+//! The total number of collisions is recorded in the [BackgroundCollisionCount](struct.BackgroundCollisionCount.html) resource.
+
+use crate::atom::{Atom, Velocity, Weight};
+use crate::constant::{AMU, BOLTZCONST, PI};
+use crate::destructor::ToBeDestroyed;
+use crate::integrator::Timestep;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use nalgebra::Vector3;
+use rand::Rng;
+use rand_distr::{Distribution, UnitSphere};
+use specs::prelude::*;
+
+/// Determines the rate of collisions between atoms and the background gas.
+#[derive(Copy, Clone)]
+pub enum CollisionRate {
+    /// Atoms collide with the background gas at a rate `1 / lifetime`, with `lifetime` in s.
+    Lifetime(f64),
+    /// Collision rate calculated from the properties of the background gas.
+    ///
+    /// The rate is `n sigma v`, where `n = P / k T` is the number density of the background gas and
+    /// `v` is the mean thermal speed of the background gas molecules. The trapped atoms are assumed
+    /// to be stationary by comparison.
+    Pressure {
+        /// Pressure of the background gas, in Pa.
+        pressure: f64,
+        /// Temperature of the background gas, in K.
+        temperature: f64,
+        /// Cross section for a trap-loss collision, in m^2.
+        cross_section: f64,
+        /// Mass of the background gas molecules, in atomic mass units.
+        mass: f64,
+    },
+}
+impl CollisionRate {
+    /// Gets the rate of collisions for each atom, in units of 1/s.
+    pub fn rate(&self) -> f64 {
+        match *self {
+            CollisionRate::Lifetime(lifetime) => 1.0 / lifetime,
+            CollisionRate::Pressure {
+                pressure,
+                temperature,
+                cross_section,
+                mass,
+            } => {
+                let density = pressure / (BOLTZCONST * temperature);
+                let mean_speed = (8.0 * BOLTZCONST * temperature / (PI * mass * AMU)).sqrt();
+                density * cross_section * mean_speed
+            }
+        }
+    }
+}
+
+/// What happens to an atom after it collides with the background gas.
+#[derive(Copy, Clone)]
+pub enum CollisionOutcome {
+    /// The atom is removed from the simulation.
+    Remove,
+    /// The atom receives a velocity kick in a random direction, with magnitude `speed` in m/s.
+    Kick { speed: f64 },
+}
+
+/// A resource that enables collisions with the background gas.
+#[derive(Copy, Clone)]
+pub struct BackgroundGas {
+    pub rate: CollisionRate,
+    pub outcome: CollisionOutcome,
+}
+
+/// A resource which counts the number of background gas collisions that have occurred.
+#[derive(Default)]
+pub struct BackgroundCollisionCount {
+    /// Total number of collisions since the start of the simulation.
+    pub total: u64,
+    /// Number of collisions in the last frame.
+    pub last_step: u64,
+    /// Total [Weight](../atom/struct.Weight.html) of the atoms which have collided since the start of the simulation.
+    pub total_weight: f64,
+    /// Total [Weight](../atom/struct.Weight.html) of the atoms which collided in the last frame.
+    pub last_step_weight: f64,
+}
+
+/// Randomly selects atoms to collide with the background gas, and applies the [CollisionOutcome](enum.CollisionOutcome.html).
+pub struct BackgroundCollisionSystem;
+impl<'a> System<'a> for BackgroundCollisionSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Atom>,
+        WriteStorage<'a, Velocity>,
+        ReadStorage<'a, Weight>,
+        Option<Read<'a, BackgroundGas>>,
+        Write<'a, BackgroundCollisionCount>,
+        ReadExpect<'a, Timestep>,
+        Read<'a, LazyUpdate>,
+        Option<Read<'a, FrameRandomKey>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            atoms,
+            mut velocities,
+            weights,
+            background,
+            mut count,
+            timestep,
+            updater,
+            random_key,
+        ): Self::SystemData,
+    ) {
+        count.last_step = 0;
+        count.last_step_weight = 0.0;
+        let background = match background {
+            Some(background) => background,
+            None => return,
+        };
+
+        let probability = 1.0 - (-background.rate.rate() * timestep.delta).exp();
+        let mut rng = RandomStreams::new(random_key.as_deref(), "background_collisions").rng();
+        for (entity, _, velocity, weight) in
+            (&entities, &atoms, &mut velocities, weights.maybe()).join()
+        {
+            if rng.gen::<f64>() >= probability {
+                continue;
+            }
+            count.last_step += 1;
+            count.last_step_weight += Weight::of(weight);
+            match background.outcome {
+                CollisionOutcome::Remove => updater.insert(entity, ToBeDestroyed),
+                CollisionOutcome::Kick { speed } => {
+                    let direction: [f64; 3] = UnitSphere.sample(&mut rng);
+                    velocity.vel = velocity.vel + speed * Vector3::from(direction);
+                }
+            }
+        }
+        count.total += count.last_step;
+        count.total_weight += count.last_step_weight;
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_collision_rate_from_pressure() {
+        // Room temperature hydrogen at 1e-9 mbar, with a cross section of 3e-18 m^2.
+        let rate = CollisionRate::Pressure {
+            pressure: 1e-7,
+            temperature: 300.0,
+            cross_section: 3e-18,
+            mass: 2.0,
+        };
+        let density = 1e-7 / (BOLTZCONST * 300.0);
+        let mean_speed = (8.0 * BOLTZCONST * 300.0 / (PI * 2.0 * AMU)).sqrt();
+        assert_approx_eq!(rate.rate(), density * 3e-18 * mean_speed, 1e-12);
+        assert_eq!(CollisionRate::Lifetime(2.0).rate(), 0.5);
+    }
+
+    #[test]
+    fn test_background_collisions_remove_atoms() {
+        let mut test_world = World::new();
+        test_world.register::<Atom>();
+        test_world.register::<Velocity>();
+        test_world.register::<Weight>();
+        test_world.register::<ToBeDestroyed>();
+        test_world.insert(BackgroundCollisionCount::default());
+        test_world.insert(Timestep { delta: 0.1 });
+        test_world.insert(BackgroundGas {
+            rate: CollisionRate::Lifetime(1.0),
+            outcome: CollisionOutcome::Remove,
+        });
+
+        let n = 10000;
+        for _ in 0..n {
+            test_world
+                .create_entity()
+                .with(Atom)
+                .with(Velocity {
+                    vel: Vector3::new(0.0, 0.0, 0.0),
+                })
+                .with(Weight { value: 0.5 })
+                .build();
+        }
+
+        let mut system = BackgroundCollisionSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let count = test_world.read_resource::<BackgroundCollisionCount>();
+        let destroyed = test_world.read_storage::<ToBeDestroyed>().join().count();
+        assert_eq!(destroyed as u64, count.total);
+        assert_eq!(count.total, count.last_step);
+        assert_approx_eq!(count.total_weight, 0.5 * count.total as f64, 1e-9);
+        let expected = n as f64 * (1.0 - (-0.1_f64).exp());
+        assert!((count.total as f64 - expected).abs() < 0.1 * expected);
+    }
+
+    #[test]
+    fn test_background_collisions_kick_atoms() {
+        let mut test_world = World::new();
+        test_world.register::<Atom>();
+        test_world.register::<Velocity>();
+        test_world.register::<Weight>();
+        test_world.register::<ToBeDestroyed>();
+        test_world.insert(BackgroundCollisionCount::default());
+        test_world.insert(Timestep { delta: 1.0 });
+        test_world.insert(BackgroundGas {
+            rate: CollisionRate::Lifetime(1e-6),
+            outcome: CollisionOutcome::Kick { speed: 10.0 },
+        });
+
+        let atom = test_world
+            .create_entity()
+            .with(Atom)
+            .with(Velocity {
+                vel: Vector3::new(0.0, 0.0, 0.0),
+            })
+            .build();
+
+        let mut system = BackgroundCollisionSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let velocities = test_world.read_storage::<Velocity>();
+        assert_approx_eq!(
+            velocities.get(atom).expect("entity not found").vel.norm(),
+            10.0,
+            1e-9
+        );
+        assert_eq!(test_world.read_storage::<ToBeDestroyed>().join().count(), 0);
+    }
+}
@@ -23,3 +23,6 @@ pub const AMU: f64 = 1.6605e-27;
 
 /// Speed of light in SI units of m/s
 pub const C: f64 = 2.998e8;
+
+/// Vacuum permeability in SI units of N/A^2
+pub const MU0: f64 = 1.25663706212e-6;
@@ -6,21 +6,28 @@
 use crate::atom;
 use crate::atom::ClearForceSystem;
 use crate::atom_sources;
+use crate::background::BackgroundCollisionSystem;
 use crate::destructor::DeleteToBeDestroyedEntitiesSystem;
+use crate::electric;
 //use crate::detector;
 //use crate::detector::DetectingInfo;
 use crate::gravity::ApplyGravitationalForceSystem;
 use crate::initiate::DeflagNewAtomsSystem;
 use crate::integrator::{
-	AddOldForceToNewAtomsSystem, Step, VelocityVerletIntegratePositionSystem,
+	AdaptTimestepSystem, AddOldForceToNewAtomsSystem, EulerIntegrationSystem,
+	IntegrationPlaceholderSystem, Integrator, MultistageIntegrationSystem, RecordOldForceSystem,
+	SimulationTime, Step, VelocityVerletIntegratePositionSystem,
 	VelocityVerletIntegrateVelocitySystem, INTEGRATE_POSITION_SYSTEM_NAME,
-	INTEGRATE_VELOCITY_SYSTEM_NAME,
+	INTEGRATE_VELOCITY_SYSTEM_NAME, RECORD_OLD_FORCE_SYSTEM_NAME,
 };
 use crate::laser;
 use crate::laser::repump::Dark;
 use crate::magnetic;
 use crate::output::console_output::ConsoleOutputSystem;
+use crate::output::escape::{RecordPlaneCrossingsSystem, RecordingPlane};
+use crate::rng::UpdateFrameRandomKeySystem;
 use crate::sim_region;
+use crate::substep::{SelectSubsteppedAtomsSystem, SubstepIntegrationSystem};
 
 use specs::prelude::*;
 
@@ -28,67 +35,123 @@ use specs::prelude::*;
 pub fn register_components(world: &mut World) {
 	atom::register_components(world);
 	magnetic::register_components(world);
+	electric::register_components(world);
 	laser::register_components(world);
 	atom_sources::register_components(world);
 	sim_region::register_components(world);
 	world.register::<Dark>();
+	world.register::<RecordingPlane>();
 }
 
 /// Struct that creates the ECS Dispatcher builder used in AtomECS.
 pub struct AtomecsDispatcherBuilder {
 	pub builder: DispatcherBuilder<'static, 'static>,
+	/// The method used to integrate the equations of motion.
+	pub integrator: Integrator,
 }
 impl AtomecsDispatcherBuilder {
 	pub fn new() -> AtomecsDispatcherBuilder {
 		AtomecsDispatcherBuilder {
 			builder: DispatcherBuilder::new(),
+			integrator: Integrator::default(),
 		}
 	}
 
-	pub fn add_frame_initialisation_systems(&mut self) {}
+	/// Selects the [Integrator](../integrator/enum.Integrator.html) used to integrate the equations of motion.
+	///
+	/// This must be called before the systems are added. Substepping of fast atoms, enabled by the
+	/// [Substepping](../substep/struct.Substepping.html) resource, is only performed by the
+	/// [VelocityVerlet](../integrator/enum.Integrator.html) integrator.
+	pub fn with_integrator(&mut self, integrator: Integrator) -> &mut Self {
+		self.integrator = integrator;
+		self
+	}
+
+	pub fn add_frame_initialisation_systems(&mut self) {
+		self.builder
+			.add(UpdateFrameRandomKeySystem, "update_random_key", &[]);
+		self.builder.add(AdaptTimestepSystem, "adapt_timestep", &[]);
+		if self.integrator == Integrator::VelocityVerlet {
+			self.builder
+				.add(SelectSubsteppedAtomsSystem, "select_substepped_atoms", &[]);
+		}
+	}
 
 	pub fn add_systems(&mut self) {
-		&self.builder.add(
-			VelocityVerletIntegratePositionSystem,
-			INTEGRATE_POSITION_SYSTEM_NAME,
-			&[],
-		);
+		match self.integrator {
+			Integrator::VelocityVerlet => self.builder.add(
+				VelocityVerletIntegratePositionSystem,
+				INTEGRATE_POSITION_SYSTEM_NAME,
+				&[],
+			),
+			_ => {
+				self.builder
+					.add(RecordOldForceSystem, RECORD_OLD_FORCE_SYSTEM_NAME, &[]);
+				self.builder.add(
+					IntegrationPlaceholderSystem,
+					INTEGRATE_POSITION_SYSTEM_NAME,
+					&[RECORD_OLD_FORCE_SYSTEM_NAME],
+				);
+			}
+		}
 		&self
 			.builder
 			.add(ClearForceSystem, "clear", &[INTEGRATE_POSITION_SYSTEM_NAME]);
 		&self.builder.add(DeflagNewAtomsSystem, "deflag", &[]);
-		&self.builder.add(AddOldForceToNewAtomsSystem, "", &[]);
+		self.builder.add(AddOldForceToNewAtomsSystem, "", &[]);
 
-		magnetic::add_systems_to_dispatch(&mut self.builder, &[]);
-		laser::add_systems_to_dispatch(&mut self.builder, &[]);
+		add_force_systems(&mut self.builder);
+		add_event_systems(&mut self.builder);
 		atom_sources::add_systems_to_dispatch(&mut self.builder, &[]);
-		self.builder.add(
-			ApplyGravitationalForceSystem,
+
+		let force_systems = [
+			"calculate_absorption_forces",
+			"calculate_emission_forces",
 			"add_gravity",
-			&["clear", INTEGRATE_POSITION_SYSTEM_NAME],
-		);
-
-		&self.builder.add(
-			VelocityVerletIntegrateVelocitySystem,
-			INTEGRATE_VELOCITY_SYSTEM_NAME,
-			&[
-				"calculate_absorption_forces",
-				"calculate_emission_forces",
-				"add_gravity",
-			],
-		);
+			"add_magnetic_force",
+		];
+		match self.integrator {
+			Integrator::VelocityVerlet => {
+				self.builder.add(
+					VelocityVerletIntegrateVelocitySystem,
+					INTEGRATE_VELOCITY_SYSTEM_NAME,
+					&force_systems,
+				);
+				self.builder
+					.add_thread_local(SubstepIntegrationSystem::new(create_force_dispatcher()));
+			}
+			Integrator::Euler => self.builder.add(
+				EulerIntegrationSystem,
+				INTEGRATE_VELOCITY_SYSTEM_NAME,
+				&force_systems,
+			),
+			method => {
+				self.builder.add(
+					IntegrationPlaceholderSystem,
+					INTEGRATE_VELOCITY_SYSTEM_NAME,
+					&force_systems,
+				);
+				self.builder
+					.add_thread_local(MultistageIntegrationSystem::new(
+						method,
+						create_force_dispatcher(),
+					));
+			}
+		}
 	}
 
+	/// Adds the systems which run at the end of each frame.
+	///
+	/// These are added as thread local systems, so that they run after any thread local integration system,
+	/// such as the [SubstepIntegrationSystem](../substep/struct.SubstepIntegrationSystem.html), and see every
+	/// atom at the end of the step.
 	pub fn add_frame_end_systems(&mut self) {
-		&self
-			.builder
-			.add(ConsoleOutputSystem, "", &[INTEGRATE_VELOCITY_SYSTEM_NAME]);
-		&self.builder.add(
-			DeleteToBeDestroyedEntitiesSystem,
-			"",
-			&[INTEGRATE_VELOCITY_SYSTEM_NAME],
-		);
-		sim_region::add_systems_to_dispatch(&mut self.builder, &[]);
+		self.builder.add_thread_local(ConsoleOutputSystem);
+		self.builder.add_thread_local(RecordPlaneCrossingsSystem);
+		self.builder.add_thread_local(BackgroundCollisionSystem);
+		self.builder
+			.add_thread_local(DeleteToBeDestroyedEntitiesSystem);
+		sim_region::add_thread_local_systems_to_dispatch(&mut self.builder);
 	}
 
 	pub fn build(mut self) -> DispatcherBuilder<'static, 'static> {
@@ -99,6 +162,53 @@ impl AtomecsDispatcherBuilder {
 	}
 }
 
+/// Adds the systems which calculate the [Force](../atom/struct.Force.html) on each atom.
+///
+/// Only field samplers and force systems are added, so that the forces can be evaluated several times per frame.
+/// Systems for events such as losses are added by [add_event_systems](fn.add_event_systems.html).
+fn add_force_systems(builder: &mut DispatcherBuilder<'static, 'static>) {
+	magnetic::add_field_systems_to_dispatch(builder, &[]);
+	electric::add_systems_to_dispatch(builder, &[]);
+	laser::add_force_systems_to_dispatch(builder, &["stark_shift"]);
+	builder.add(
+		ApplyGravitationalForceSystem,
+		"add_gravity",
+		&["clear", INTEGRATE_POSITION_SYSTEM_NAME],
+	);
+	builder.add(
+		magnetic::force::ApplyMagneticForceSystem,
+		"add_magnetic_force",
+		&["clear", "magnetics_magnitude_gradient"],
+	);
+}
+
+/// Adds the systems which must run once per frame alongside the force systems, such as spin flips, repump
+/// losses and the systems which attach components to new atoms.
+fn add_event_systems(builder: &mut DispatcherBuilder<'static, 'static>) {
+	magnetic::add_event_systems_to_dispatch(builder, &[]);
+	laser::add_event_systems_to_dispatch(builder, &[]);
+}
+
+/// Creates a [Dispatcher](specs::Dispatcher) which only calculates the forces on atoms, used by the
+/// [MultistageIntegrationSystem](../integrator/struct.MultistageIntegrationSystem.html) to evaluate the forces
+/// at each stage of a step, and by the [SubstepIntegrationSystem](../substep/struct.SubstepIntegrationSystem.html)
+/// at each substep.
+///
+/// Events such as spin flips and repump losses are not included, so that they occur once per frame. Stochastic
+/// forces, such as those from photon scattering, draw from the random streams of the current frame, and so use
+/// the same random numbers at each stage of a multistage step. Each substep uses its own random key.
+fn create_force_dispatcher() -> Dispatcher<'static, 'static> {
+	let mut builder = DispatcherBuilder::new();
+	builder.add(
+		IntegrationPlaceholderSystem,
+		INTEGRATE_POSITION_SYSTEM_NAME,
+		&[],
+	);
+	builder.add(ClearForceSystem, "clear", &[INTEGRATE_POSITION_SYSTEM_NAME]);
+	add_force_systems(&mut builder);
+	builder.build()
+}
+
 /// Creates a [Dispatcher](specs::Dispatcher) that is used to calculate each simulation frame.
 pub fn create_simulation_dispatcher() -> Dispatcher<'static, 'static> {
 	let builder = create_simulation_dispatcher_builder();
@@ -113,4 +223,87 @@ pub fn create_simulation_dispatcher_builder() -> DispatcherBuilder<'static, 'sta
 /// Add required resources to the world
 pub fn register_resources(world: &mut World) {
 	world.insert(Step { n: 0 });
+	world.insert(SimulationTime::default());
+}
+
+#[cfg(test)]
+pub mod tests {
+
+	use super::*;
+	use crate::integrator::Timestep;
+
+	#[test]
+	fn test_dispatcher_builds_for_each_integrator() {
+		for integrator in [
+			Integrator::Euler,
+			Integrator::VelocityVerlet,
+			Integrator::RungeKutta4,
+			Integrator::ForestRuth,
+			Integrator::StochasticHeun,
+		]
+		.iter()
+		{
+			let mut world = World::new();
+			register_components(&mut world);
+			register_resources(&mut world);
+			world.insert(Timestep { delta: 1e-6 });
+			let mut atomecs_builder = AtomecsDispatcherBuilder::new();
+			atomecs_builder.with_integrator(*integrator);
+			let mut dispatcher = atomecs_builder.build().build();
+			dispatcher.setup(&mut world);
+			dispatcher.dispatch(&mut world);
+			world.maintain();
+			assert_eq!(world.read_resource::<Step>().n, 1);
+		}
+	}
+
+	#[test]
+	fn test_force_dispatcher_does_not_repeat_events() {
+		use crate::atom::{Position, Velocity};
+		use crate::magnetic::force::MagneticDipole;
+		use crate::magnetic::gradient::MagneticGradientSampler;
+		use crate::magnetic::majorana::{MajoranaLoss, MajoranaLossOutcome};
+		use crate::magnetic::quadrupole::QuadrupoleField3D;
+		use crate::magnetic::MagneticFieldSampler;
+		use nalgebra::Vector3;
+
+		let mut world = World::new();
+		register_components(&mut world);
+		register_resources(&mut world);
+		world.insert(Timestep { delta: 1e-6 });
+		world.insert(MajoranaLoss {
+			outcome: MajoranaLossOutcome::Flip,
+		});
+		let mut forces = create_force_dispatcher();
+		forces.setup(&mut world);
+
+		world
+			.create_entity()
+			.with(QuadrupoleField3D::gauss_per_cm(20.0, Vector3::z()))
+			.with(Position {
+				pos: Vector3::new(0.0, 0.0, 0.0),
+			})
+			.build();
+		// An atom moving through the field zero, which would certainly undergo a spin flip.
+		let atom = world
+			.create_entity()
+			.with(Position {
+				pos: Vector3::new(0.0, 0.0, 0.0),
+			})
+			.with(Velocity {
+				vel: Vector3::new(1.0, 0.0, 0.0),
+			})
+			.with(MagneticDipole {
+				mf_gf: 1.0,
+				gf: 0.5,
+			})
+			.with(MagneticFieldSampler::default())
+			.with(MagneticGradientSampler::default())
+			.build();
+
+		forces.dispatch(&world);
+		world.maintain();
+		let dipoles = world.read_storage::<MagneticDipole>();
+		assert_eq!(dipoles.get(atom).expect("entity not found").mf_gf, 1.0);
+	}
 }
@@ -0,0 +1,167 @@
+//! Electric fields of charged electrodes.
+//!
+//! Each electrode is a component which is placed on an entity together with a `Position`,
+//! which defines the centre of the electrode.
+
+extern crate nalgebra;
+use crate::atom::Position;
+use crate::electric::ElectricFieldSampler;
+use crate::ramp::Lerp;
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::Vector3;
+use specs::{Component, HashMapStorage, Join, Read, ReadStorage, System, WriteStorage};
+use std::marker::PhantomData;
+
+/// An electrode, or arrangement of electrodes, held at fixed potentials.
+pub trait Electrode {
+    /// Calculates the electric field of the electrode, in units of V/m.
+    ///
+    /// # Arguments
+    ///
+    /// `electrode_position`: position of the electrode entity, m
+    ///
+    /// `pos`: position at which to calculate the field, m
+    fn field(&self, electrode_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64>;
+}
+
+/// A pair of parallel circular plates, with a potential difference between them.
+///
+/// The field between the plates is uniform, and fringing fields at the edges of the plates are neglected.
+/// The plate displaced along `+normal` is held at the higher potential, so that the field points along `-normal`
+/// for positive `voltage`.
+#[derive(Clone, Lerp)]
+pub struct PlateCapacitor {
+    /// Potential difference between the plates, in V.
+    pub voltage: f64,
+    /// Distance between the plates, in m.
+    pub separation: f64,
+    /// Radius of the plates, in m.
+    pub radius: f64,
+    /// A unit vector normal to the plates.
+    pub normal: Vector3<f64>,
+}
+impl PlateCapacitor {
+    pub fn new(voltage: f64, separation: f64, radius: f64, normal: Vector3<f64>) -> Self {
+        PlateCapacitor {
+            voltage: voltage,
+            separation: separation,
+            radius: radius,
+            normal: normal.normalize(),
+        }
+    }
+}
+impl Component for PlateCapacitor {
+    type Storage = HashMapStorage<Self>;
+}
+impl Electrode for PlateCapacitor {
+    fn field(&self, electrode_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let delta = pos - electrode_position;
+        let z = delta.dot(&self.normal);
+        let rho = (delta - z * self.normal).norm();
+        if z.abs() < self.separation / 2.0 && rho < self.radius {
+            -self.voltage / self.separation * self.normal
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// A small spherical electrode held at a potential with respect to a distant ground.
+///
+/// Outside the electrode, the field is that of a point charge, `E = V a r / |r|^3`.
+#[derive(Clone, Lerp)]
+pub struct PointElectrode {
+    /// Potential of the electrode, in V.
+    pub voltage: f64,
+    /// Radius of the electrode, in m.
+    pub radius: f64,
+}
+impl Component for PointElectrode {
+    type Storage = HashMapStorage<Self>;
+}
+impl Electrode for PointElectrode {
+    fn field(&self, electrode_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let delta = pos - electrode_position;
+        let distance = delta.norm();
+        if distance < self.radius {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        self.voltage * self.radius * delta / distance.powi(3)
+    }
+}
+
+/// Updates the values of electric field samplers to include the fields of electrodes of type `T`.
+pub struct SampleElectrodeFieldSystem<T: Electrode> {
+    pub marker: PhantomData<T>,
+}
+impl<T: Electrode> Default for SampleElectrodeFieldSystem<T> {
+    fn default() -> Self {
+        SampleElectrodeFieldSystem {
+            marker: PhantomData,
+        }
+    }
+}
+impl<'a, T> System<'a> for SampleElectrodeFieldSystem<T>
+where
+    T: Electrode + Component + Sync,
+{
+    type SystemData = (
+        WriteStorage<'a, ElectricFieldSampler>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, T>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut sampler, pos, electrodes, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for (centre, electrode) in (&pos, &electrodes).join() {
+            (&atoms, &pos, &mut sampler)
+                .par_join()
+                .for_each(|(_, pos, sampler)| {
+                    sampler.field = sampler.field + electrode.field(&centre.pos, &pos.pos);
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_plate_capacitor_field() {
+        let capacitor = PlateCapacitor::new(100.0, 0.01, 0.05, Vector3::z());
+        let centre = Vector3::new(0.0, 0.0, 1.0);
+        assert_eq!(
+            capacitor.field(&centre, &Vector3::new(0.01, 0.0, 1.002)),
+            Vector3::new(0.0, 0.0, -1.0e4)
+        );
+        assert_eq!(
+            capacitor.field(&centre, &Vector3::new(0.0, 0.0, 1.006)),
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            capacitor.field(&centre, &Vector3::new(0.06, 0.0, 1.0)),
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_point_electrode_field() {
+        let electrode = PointElectrode {
+            voltage: 10.0,
+            radius: 1e-3,
+        };
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+        let field = electrode.field(&centre, &Vector3::new(0.0, 0.02, 0.0));
+        let expected = Vector3::new(0.0, 10.0 * 1e-3 / 0.02_f64.powi(2), 0.0);
+        assert!((field - expected).norm() < 1e-12);
+        assert_eq!(
+            electrode.field(&centre, &Vector3::new(0.0, 0.0, 1e-4)),
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+    }
+}
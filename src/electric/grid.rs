@@ -0,0 +1,42 @@
+//! Define electric fields using grids.
+
+extern crate nalgebra;
+use crate::atom::Position;
+use crate::electric::ElectricFieldSampler;
+use crate::field_grid::FieldGrid;
+use crate::substep::{active_atoms, SubstepMask};
+use specs::{Join, Read, ReadStorage, System, WriteStorage};
+
+/// Marks a [FieldGrid](../../field_grid/struct.FieldGrid.html) which holds an electric field.
+pub struct Electric;
+
+/// Defines an electric field using a grid-based representation, with the field in each cell in units of V/m.
+///
+/// This shares its layout and interpolation with the
+/// [PrecalculatedMagneticFieldGrid](../../magnetic/grid/type.PrecalculatedMagneticFieldGrid.html).
+pub type PrecalculatedElectricFieldGrid = FieldGrid<Electric>;
+
+/// Samples from the PrecalculatedElectricFieldGrid at a `Position` and stores
+/// result in `ElectricFieldSampler`
+pub struct SampleElectricGridSystem;
+impl<'a> System<'a> for SampleElectricGridSystem {
+    type SystemData = (
+        WriteStorage<'a, ElectricFieldSampler>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, PrecalculatedElectricFieldGrid>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut sampler, pos, grids, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for grid in (&grids).join() {
+            (&atoms, &pos, &mut sampler)
+                .par_join()
+                .for_each(|(_, pos, sampler)| {
+                    sampler.field = sampler.field + grid.get_field(&pos.pos);
+                });
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Electric fields and DC Stark shifts
+//!
+//! This module mirrors the structure of the `magnetic` module. Electric field sources are
+//! components placed on entities, and their fields are summed into an
+//! [ElectricFieldSampler](struct.ElectricFieldSampler.html) attached to each atom.
+
+extern crate nalgebra;
+
+use crate::initiate::NewlyCreated;
+use crate::integrator::INTEGRATE_POSITION_SYSTEM_NAME;
+use crate::ramp::{Ramp, RampUpdateSystem};
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::Vector3;
+use specs::prelude::*;
+use std::fmt;
+
+pub mod electrode;
+pub mod grid;
+pub mod stark;
+pub mod uniform;
+
+/// A component that stores the electric field at an entity's location.
+#[derive(Copy, Clone)]
+pub struct ElectricFieldSampler {
+    /// Vector representing the electric field components along x,y,z in units of V/m.
+    pub field: Vector3<f64>,
+
+    /// Magnitude of the electric field in units of V/m.
+    pub magnitude: f64,
+}
+impl ElectricFieldSampler {
+    pub fn volts_per_metre(e_field: Vector3<f64>) -> Self {
+        ElectricFieldSampler {
+            field: e_field,
+            magnitude: e_field.norm(),
+        }
+    }
+}
+impl Component for ElectricFieldSampler {
+    type Storage = VecStorage<Self>;
+}
+impl fmt::Display for ElectricFieldSampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({:?},{:?},{:?})",
+            self.field[0], self.field[1], self.field[2]
+        )
+    }
+}
+impl Default for ElectricFieldSampler {
+    fn default() -> Self {
+        ElectricFieldSampler {
+            field: Vector3::new(0.0, 0.0, 0.0),
+            magnitude: 0.0,
+        }
+    }
+}
+
+/// System that clears the electric field samplers each frame.
+pub struct ClearElectricFieldSamplerSystem;
+impl<'a> System<'a> for ClearElectricFieldSamplerSystem {
+    type SystemData = (
+        WriteStorage<'a, ElectricFieldSampler>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut sampler, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+
+        let atoms = active_atoms(substep_mask.as_deref(), sampler.mask());
+        (&atoms, &mut sampler).par_join().for_each(|(_, sampler)| {
+            sampler.magnitude = 0.;
+            sampler.field = Vector3::new(0.0, 0.0, 0.0)
+        });
+    }
+}
+
+/// System that calculates the magnitude of the electric field.
+///
+/// This system runs after all other electric field systems.
+pub struct CalculateElectricFieldMagnitudeSystem;
+impl<'a> System<'a> for CalculateElectricFieldMagnitudeSystem {
+    type SystemData = (
+        WriteStorage<'a, ElectricFieldSampler>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut sampler, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+
+        let atoms = active_atoms(substep_mask.as_deref(), sampler.mask());
+        (&atoms, &mut sampler).par_join().for_each(|(_, sampler)| {
+            sampler.magnitude = sampler.field.norm();
+            if sampler.magnitude.is_nan() {
+                sampler.magnitude = 0.0;
+            }
+        });
+    }
+}
+
+/// Attachs the ElectricFieldSampler component to newly created atoms.
+pub struct AttachElectricFieldSamplersToNewlyCreatedAtomsSystem;
+impl<'a> System<'a> for AttachElectricFieldSamplersToNewlyCreatedAtomsSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, NewlyCreated>,
+        Read<'a, LazyUpdate>,
+    );
+    fn run(&mut self, (ent, newly_created, updater): Self::SystemData) {
+        for (ent, _nc) in (&ent, &newly_created).join() {
+            updater.insert(ent, ElectricFieldSampler::default());
+        }
+    }
+}
+
+/// Adds the systems required by electric fields to the dispatcher.
+///
+/// #Arguments
+///
+/// `builder`: the dispatch builder to modify
+///
+/// `deps`: any dependencies that must be completed before the electric systems run.
+pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>, deps: &[&str]) {
+    builder.add(ClearElectricFieldSamplerSystem, "electric_clear", deps);
+    builder.add(
+        RampUpdateSystem::<uniform::UniformElectricField>::default(),
+        "electric_uniform_ramp",
+        deps,
+    );
+    builder.add(
+        RampUpdateSystem::<electrode::PlateCapacitor>::default(),
+        "electric_plate_capacitor_ramp",
+        deps,
+    );
+    builder.add(
+        RampUpdateSystem::<electrode::PointElectrode>::default(),
+        "electric_point_electrode_ramp",
+        deps,
+    );
+    builder.add(
+        uniform::UniformElectricFieldSystem,
+        "electric_uniform",
+        &["electric_clear", "electric_uniform_ramp"],
+    );
+    builder.add(
+        electrode::SampleElectrodeFieldSystem::<electrode::PlateCapacitor>::default(),
+        "electric_plate_capacitor",
+        &[
+            "electric_uniform",
+            "electric_plate_capacitor_ramp",
+            INTEGRATE_POSITION_SYSTEM_NAME,
+        ],
+    );
+    builder.add(
+        electrode::SampleElectrodeFieldSystem::<electrode::PointElectrode>::default(),
+        "electric_point_electrode",
+        &["electric_plate_capacitor", "electric_point_electrode_ramp"],
+    );
+    builder.add(
+        grid::SampleElectricGridSystem,
+        "electric_grid",
+        &["electric_point_electrode"],
+    );
+    builder.add(
+        CalculateElectricFieldMagnitudeSystem,
+        "electric_magnitude",
+        &["electric_grid"],
+    );
+    builder.add(
+        AttachElectricFieldSamplersToNewlyCreatedAtomsSystem,
+        "add_electric_field_samplers",
+        &[],
+    );
+    builder.add(
+        stark::AttachStarkShiftSamplersToNewlyCreatedAtomsSystem,
+        "attach_stark_shift_samplers",
+        &[],
+    );
+    builder.add(
+        stark::CalculateStarkShiftSystem,
+        "stark_shift",
+        &["electric_magnitude"],
+    );
+}
+
+/// Registers resources required by electric fields to the ecs world.
+pub fn register_components(world: &mut World) {
+    world.register::<uniform::UniformElectricField>();
+    world.register::<electrode::PlateCapacitor>();
+    world.register::<electrode::PointElectrode>();
+    world.register::<Ramp<uniform::UniformElectricField>>();
+    world.register::<Ramp<electrode::PlateCapacitor>>();
+    world.register::<Ramp<electrode::PointElectrode>>();
+    world.register::<grid::PrecalculatedElectricFieldGrid>();
+    world.register::<ElectricFieldSampler>();
+    world.register::<stark::StarkShiftSampler>();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::atom::{AtomicTransition, Position};
+
+    /// Tests that the electric systems attach samplers to new atoms and calculate the (ramped) field at their location.
+    #[test]
+    fn test_electric_systems() {
+        let mut test_world = World::new();
+        register_components(&mut test_world);
+        test_world.register::<NewlyCreated>();
+        test_world.register::<AtomicTransition>();
+        let mut builder = DispatcherBuilder::new();
+        builder.add(
+            crate::integrator::VelocityVerletIntegratePositionSystem {},
+            INTEGRATE_POSITION_SYSTEM_NAME,
+            &[],
+        );
+        add_systems_to_dispatch(&mut builder, &[]);
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut test_world);
+        test_world.insert(crate::integrator::Step { n: 0 });
+        test_world.insert(crate::integrator::Timestep { delta: 1.0e-6 });
+        test_world.insert(crate::integrator::SimulationTime { time: 1.0 });
+
+        let field = |x| uniform::UniformElectricField::volts_per_metre(Vector3::new(x, 0.0, 0.0));
+        test_world
+            .create_entity()
+            .with(field(0.0))
+            .with(Ramp::new(vec![(0.0, field(0.0)), (0.5, field(100.0))]))
+            .build();
+        let atom = test_world
+            .create_entity()
+            .with(Position::new())
+            .with(NewlyCreated)
+            .with(AtomicTransition::rubidium())
+            .build();
+
+        // The first dispatch attaches the samplers, the second calculates the field.
+        dispatcher.dispatch(&mut test_world);
+        test_world.maintain();
+        test_world.write_storage::<NewlyCreated>().remove(atom);
+        dispatcher.dispatch(&mut test_world);
+        test_world.maintain();
+
+        let samplers = test_world.read_storage::<ElectricFieldSampler>();
+        let sampler = samplers.get(atom).expect("entity not found");
+        assert_eq!(sampler.field, Vector3::new(100.0, 0.0, 0.0));
+        assert_eq!(sampler.magnitude, 100.0);
+        assert!(test_world
+            .read_storage::<stark::StarkShiftSampler>()
+            .contains(atom));
+    }
+}
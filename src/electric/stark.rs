@@ -0,0 +1,111 @@
+//! Shift in an atom's transition frequency due to an electric field (DC Stark effect)
+//!
+//! In an electric field `E`, a level with static polarizability `alpha` is shifted in energy by
+//! `-alpha E^2 / 2`. The transition frequency is therefore shifted by
+//! `-(alpha_e - alpha_g) E^2 / (2 hbar)`, where the polarizabilities are given by the
+//! `AtomicTransition` of the atom.
+
+use super::ElectricFieldSampler;
+use crate::atom::AtomicTransition;
+use crate::constant::HBAR;
+use crate::initiate::NewlyCreated;
+use crate::substep::{active_atoms, SubstepMask};
+use specs::prelude::*;
+
+/// Represents the (angular) Stark shift of the atom's transition depending on the electric field it experiences
+#[derive(Clone, Copy)]
+pub struct StarkShiftSampler {
+    /// Stark shift of the transition in rad/s
+    pub shift: f64,
+}
+impl Default for StarkShiftSampler {
+    fn default() -> Self {
+        StarkShiftSampler { shift: f64::NAN }
+    }
+}
+impl Component for StarkShiftSampler {
+    type Storage = VecStorage<Self>;
+}
+
+/// Attaches the StarkShiftSampler component to newly created atoms.
+pub struct AttachStarkShiftSamplersToNewlyCreatedAtomsSystem;
+impl<'a> System<'a> for AttachStarkShiftSamplersToNewlyCreatedAtomsSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, NewlyCreated>,
+        ReadStorage<'a, AtomicTransition>,
+        Read<'a, LazyUpdate>,
+    );
+    fn run(&mut self, (ent, newly_created, atomic_transition, updater): Self::SystemData) {
+        for (ent, _nc, _at) in (&ent, &newly_created, &atomic_transition).join() {
+            updater.insert(ent, StarkShiftSampler::default());
+        }
+    }
+}
+
+/// Calculates the Stark shift of each atom's transition.
+pub struct CalculateStarkShiftSystem;
+impl<'a> System<'a> for CalculateStarkShiftSystem {
+    type SystemData = (
+        WriteStorage<'a, StarkShiftSampler>,
+        ReadStorage<'a, ElectricFieldSampler>,
+        ReadStorage<'a, AtomicTransition>,
+        Option<Read<'a, SubstepMask>>,
+    );
+
+    fn run(
+        &mut self,
+        (mut stark_sampler, electric_field_sampler, atomic_transition, substep_mask): Self::SystemData,
+    ) {
+        use rayon::prelude::*;
+
+        let atoms = active_atoms(substep_mask.as_deref(), stark_sampler.mask());
+        (
+            &atoms,
+            &mut stark_sampler,
+            &electric_field_sampler,
+            &atomic_transition,
+        )
+            .par_join()
+            .for_each(|(_, stark, electric_field, atom_info)| {
+                stark.shift = -(atom_info.polarizability_excited - atom_info.polarizability_ground)
+                    * electric_field.magnitude.powi(2)
+                    / (2.0 * HBAR);
+            });
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_calculate_stark_shift_system() {
+        let mut test_world = World::new();
+        test_world.register::<ElectricFieldSampler>();
+        test_world.register::<AtomicTransition>();
+        test_world.register::<StarkShiftSampler>();
+
+        let mut transition = AtomicTransition::rubidium();
+        transition.polarizability_ground = 1.0e-39;
+        transition.polarizability_excited = 3.0e-39;
+        let atom = test_world
+            .create_entity()
+            .with(ElectricFieldSampler::volts_per_metre(Vector3::new(
+                0.0, 1.0e4, 0.0,
+            )))
+            .with(transition)
+            .with(StarkShiftSampler::default())
+            .build();
+
+        let mut system = CalculateStarkShiftSystem;
+        system.run_now(&test_world);
+
+        let samplers = test_world.read_storage::<StarkShiftSampler>();
+        let shift = samplers.get(atom).expect("entity not found").shift;
+        assert_approx_eq!(shift, -2.0e-39 * 1.0e8 / (2.0 * HBAR), 1e-9);
+    }
+}
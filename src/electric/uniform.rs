@@ -0,0 +1,52 @@
+//! Uniform electric fields
+
+extern crate nalgebra;
+use super::ElectricFieldSampler;
+use crate::ramp::Lerp;
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::Vector3;
+use specs::{Component, HashMapStorage, Join, Read, ReadStorage, System, WriteStorage};
+
+/// A component representing a uniform electric field, of the form `E = [ E_x, E_y, E_z ]`
+#[derive(Clone, Lerp)]
+pub struct UniformElectricField {
+    /// Vector field components with respect to the x,y,z cartesian axes, in units of V/m.
+    pub field: Vector3<f64>,
+}
+impl Component for UniformElectricField {
+    type Storage = HashMapStorage<Self>;
+}
+impl UniformElectricField {
+    /// Create a UniformElectricField with components specified in units of V/cm.
+    pub fn volts_per_cm(components: Vector3<f64>) -> UniformElectricField {
+        UniformElectricField {
+            field: components * 100.0,
+        }
+    }
+
+    /// Create a UniformElectricField with components specified in units of V/m.
+    pub fn volts_per_metre(components: Vector3<f64>) -> UniformElectricField {
+        UniformElectricField { field: components }
+    }
+}
+
+/// Updates the values of electric field samplers to include uniform electric fields in the world.
+pub struct UniformElectricFieldSystem;
+impl<'a> System<'a> for UniformElectricFieldSystem {
+    type SystemData = (
+        WriteStorage<'a, ElectricFieldSampler>,
+        ReadStorage<'a, UniformElectricField>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut samplers, fields, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), samplers.mask());
+        for field in (&fields).join() {
+            (&atoms, &mut samplers).par_join().for_each(|(_, sampler)| {
+                sampler.field = sampler.field + field.field;
+            });
+        }
+    }
+}
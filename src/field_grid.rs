@@ -0,0 +1,321 @@
+//! Vector fields defined on regular grids.
+//!
+//! Both magnetic and electric fields can be defined using a [FieldGrid](struct.FieldGrid.html). The grid is
+//! generic over a marker type, so that grids of different fields are separate components, eg the
+//! [PrecalculatedMagneticFieldGrid](../magnetic/grid/type.PrecalculatedMagneticFieldGrid.html) and
+//! [PrecalculatedElectricFieldGrid](../electric/grid/type.PrecalculatedElectricFieldGrid.html).
+
+extern crate nalgebra;
+use crate::ramp::Lerp;
+use nalgebra::{Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+use specs::{Component, HashMapStorage};
+use std::marker::PhantomData;
+
+/// Defines a vector field using a grid-based representation.
+///
+/// The grid is ordered as a linear array, with elements ordered in priority z,y,x;
+/// items with dz=1 are adjacent in memory.
+///
+/// # Fields
+///
+/// `extent_spatial`: Size of the grid, in units of m.
+///
+/// `position`: Position of the grid center, in units of m.
+///
+/// `extent_cells`: Size of the grid in cells, along the (x,y,z) axes.
+///
+/// `grid`: `Vec<Vector3<f64>>` containing the field at each grid cell.
+///
+/// `interpolation`: How the field is calculated between cells. Defaults to
+/// [Nearest](enum.GridInterpolation.html) if missing from a serialized grid.
+#[derive(Serialize, Deserialize)]
+pub struct FieldGrid<F> {
+    pub extent_spatial: Vector3<f64>,
+    pub position: Vector3<f64>,
+    pub extent_cells: Vector3<i32>,
+    pub grid: Vec<Vector3<f64>>,
+    #[serde(default)]
+    pub interpolation: GridInterpolation,
+    #[serde(skip)]
+    marker: PhantomData<F>,
+}
+
+/// The method used to calculate the field of a grid between the centres of its cells.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GridInterpolation {
+    /// The field is that of the cell containing the position.
+    Nearest,
+    /// The field is trilinearly interpolated between the centres of the eight surrounding cells.
+    Trilinear,
+}
+impl Default for GridInterpolation {
+    fn default() -> Self {
+        GridInterpolation::Nearest
+    }
+}
+
+impl<F> FieldGrid<F> {
+    /// Creates a grid from the field in each cell, which is sampled from the nearest cell.
+    ///
+    /// # Arguments
+    ///
+    /// `extent_spatial`: Size of the grid, in units of m.
+    ///
+    /// `position`: Position of the grid center, in units of m.
+    ///
+    /// `extent_cells`: Size of the grid in cells, along the (x,y,z) axes.
+    ///
+    /// `grid`: the field at each grid cell, ordered as described for [FieldGrid](struct.FieldGrid.html).
+    pub fn new(
+        extent_spatial: Vector3<f64>,
+        position: Vector3<f64>,
+        extent_cells: Vector3<i32>,
+        grid: Vec<Vector3<f64>>,
+    ) -> Self {
+        FieldGrid {
+            extent_spatial: extent_spatial,
+            position: position,
+            extent_cells: extent_cells,
+            grid: grid,
+            interpolation: GridInterpolation::Nearest,
+            marker: PhantomData,
+        }
+    }
+
+    /// Sets the method used to calculate the field between the centres of the grid cells.
+    pub fn with_interpolation(mut self, interpolation: GridInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn position_to_grid_index(&self, pos: &Vector3<f64>) -> i32 {
+        let delta = pos - (self.position - self.extent_spatial / 2.0);
+        let fraction = delta.component_div(&self.extent_spatial);
+        // calculate cell ids
+        let cell_id = Vector3::new(
+            ((fraction[0] * self.extent_cells[0] as f64) as i32)
+                .max(0)
+                .min(self.extent_cells[0] - 1),
+            ((fraction[1] * self.extent_cells[1] as f64) as i32)
+                .max(0)
+                .min(self.extent_cells[1] - 1),
+            ((fraction[2] * self.extent_cells[2] as f64) as i32)
+                .max(0)
+                .min(self.extent_cells[2] - 1),
+        );
+        return self.extent_cells[2] as i32
+            * (self.extent_cells[1] as i32 * (cell_id[0] as i32) + (cell_id[1] as i32))
+            + (cell_id[2] as i32);
+    }
+
+    /// Calculates the field at the given position, using the grid's [GridInterpolation](enum.GridInterpolation.html).
+    ///
+    /// Positions outside the grid take the field at the nearest point inside.
+    pub fn get_field(&self, pos: &Vector3<f64>) -> Vector3<f64> {
+        match self.interpolation {
+            GridInterpolation::Nearest => self.grid[self.position_to_grid_index(pos) as usize],
+            GridInterpolation::Trilinear => self.get_interpolated_field(pos),
+        }
+    }
+
+    /// Trilinearly interpolates the field between the centres of the eight grid cells surrounding `pos`.
+    fn get_interpolated_field(&self, pos: &Vector3<f64>) -> Vector3<f64> {
+        let (lower, fraction, _) = self.get_interpolation_cell(pos);
+        let mut field = Vector3::zeros();
+        for corner in 0..8 {
+            let mut cell = [0i32; 3];
+            let mut weight = 1.0;
+            for axis in 0..3 {
+                let upper = (corner >> axis) & 1 == 1;
+                cell[axis] = (lower[axis] + upper as i32).min(self.extent_cells[axis] - 1);
+                weight = weight
+                    * if upper {
+                        fraction[axis]
+                    } else {
+                        1.0 - fraction[axis]
+                    };
+            }
+            field = field + weight * self.grid[self.cell_to_grid_index(&cell)];
+        }
+        field
+    }
+
+    /// Calculates the Jacobian of the field, `J_ij = dB_i/dx_j`, at the given position.
+    ///
+    /// The Jacobian is the derivative of the trilinear interpolation of the grid, whichever
+    /// [GridInterpolation](enum.GridInterpolation.html) is used for the field. The field is constant
+    /// outside the grid, so the derivative is zero along any axis on which `pos` lies beyond the
+    /// outermost cell centres. Axes with a single cell also have zero derivative.
+    pub fn get_jacobian(&self, pos: &Vector3<f64>) -> Matrix3<f64> {
+        let spacing = self.get_spacing();
+        let (lower, fraction, clamped) = self.get_interpolation_cell(pos);
+
+        let mut jacobian = Matrix3::zeros();
+        for corner in 0..8 {
+            let mut cell = [0i32; 3];
+            let mut weight = [0.0; 3];
+            let mut weight_derivative = [0.0; 3];
+            for axis in 0..3 {
+                let upper = (corner >> axis) & 1 == 1;
+                let cells = self.extent_cells[axis];
+                cell[axis] = (lower[axis] + upper as i32).min(cells - 1);
+                weight[axis] = if upper {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+                weight_derivative[axis] = match (cells < 2 || clamped[axis], upper) {
+                    (true, _) => 0.0,
+                    (false, true) => 1.0 / spacing[axis],
+                    (false, false) => -1.0 / spacing[axis],
+                };
+            }
+            let value = self.grid[self.cell_to_grid_index(&cell)];
+            for axis in 0..3 {
+                let factor =
+                    weight_derivative[axis] * weight[(axis + 1) % 3] * weight[(axis + 2) % 3];
+                let column = jacobian.column(axis) + factor * value;
+                jacobian.set_column(axis, &column);
+            }
+        }
+        jacobian
+    }
+
+    /// Size of each grid cell, in m.
+    fn get_spacing(&self) -> Vector3<f64> {
+        self.extent_spatial
+            .component_div(&self.extent_cells.map(|n| n as f64))
+    }
+
+    /// Gets the lower of the eight cells used to interpolate at `pos`, the fractional
+    /// distance of `pos` from its centre along each axis, and whether `pos` was clamped to
+    /// the outermost cell centres along each axis.
+    fn get_interpolation_cell(&self, pos: &Vector3<f64>) -> ([i32; 3], [f64; 3], [bool; 3]) {
+        let spacing = self.get_spacing();
+        let delta = pos - (self.position - self.extent_spatial / 2.0);
+        let mut lower = [0i32; 3];
+        let mut fraction = [0.0; 3];
+        let mut clamped = [false; 3];
+        for axis in 0..3 {
+            let cells = self.extent_cells[axis];
+            if cells < 2 {
+                continue;
+            }
+            let unclamped = delta[axis] / spacing[axis] - 0.5;
+            let f = unclamped.max(0.0).min((cells - 1) as f64);
+            clamped[axis] = f != unclamped;
+            lower[axis] = (f.floor() as i32).min(cells - 2);
+            fraction[axis] = f - lower[axis] as f64;
+        }
+        (lower, fraction, clamped)
+    }
+
+    fn cell_to_grid_index(&self, cell: &[i32; 3]) -> usize {
+        (self.extent_cells[2] * (self.extent_cells[1] * cell[0] + cell[1]) + cell[2]) as usize
+    }
+}
+
+impl<F> Clone for FieldGrid<F> {
+    fn clone(&self) -> Self {
+        FieldGrid {
+            extent_spatial: self.extent_spatial,
+            position: self.position,
+            extent_cells: self.extent_cells,
+            grid: self.grid.clone(),
+            interpolation: self.interpolation,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<F> Lerp<FieldGrid<F>> for FieldGrid<F> {
+    /// Interpolates the field at each grid cell. Both grids must have the same layout.
+    fn lerp(&self, b: &FieldGrid<F>, amount: f64) -> Self {
+        FieldGrid {
+            extent_spatial: self.extent_spatial,
+            position: self.position,
+            extent_cells: self.extent_cells,
+            interpolation: self.interpolation,
+            grid: self
+                .grid
+                .iter()
+                .zip(b.grid.iter())
+                .map(|(a, b)| a - (a - b) * amount)
+                .collect(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<F> Component for FieldGrid<F>
+where
+    F: Send + Sync + 'static,
+{
+    type Storage = HashMapStorage<Self>;
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_grid_jacobian() {
+        // A linear field is reproduced exactly by trilinear interpolation.
+        let gradient = Matrix3::new(1.0, 2.0, 0.0, -0.5, 0.0, 3.0, 0.0, 4.0, -1.0);
+        let offset = Vector3::new(0.1, 0.2, 0.3);
+        let extent_cells = Vector3::new(4, 5, 6);
+        let extent_spatial = Vector3::new(0.4, 1.0, 0.6);
+        let position = Vector3::new(0.0, 0.5, -0.1);
+        let spacing = extent_spatial.component_div(&extent_cells.map(|n| n as f64));
+        let mut grid = Vec::new();
+        for i in 0..extent_cells[0] {
+            for j in 0..extent_cells[1] {
+                for k in 0..extent_cells[2] {
+                    let centre = position - extent_spatial / 2.0
+                        + Vector3::new(i as f64 + 0.5, j as f64 + 0.5, k as f64 + 0.5)
+                            .component_mul(&spacing);
+                    grid.push(gradient * centre + offset);
+                }
+            }
+        }
+        let mut grid: FieldGrid<()> = FieldGrid::new(extent_spatial, position, extent_cells, grid);
+        for pos in &[
+            Vector3::new(0.03, 0.61, -0.05),
+            Vector3::new(-0.14, 0.15, 0.1),
+        ] {
+            assert!((grid.get_jacobian(pos) - gradient).norm() < 1e-12);
+        }
+
+        // Outside the grid the field is constant, so the clamped axes have zero derivative.
+        assert!(grid.get_jacobian(&Vector3::new(1.0, 2.0, 3.0)).norm() < 1e-12);
+        let mut expected = gradient;
+        expected.set_column(0, &Vector3::zeros());
+        assert!((grid.get_jacobian(&Vector3::new(1.0, 0.61, -0.05)) - expected).norm() < 1e-12);
+
+        // By default, the field is taken from the nearest cell.
+        let pos = Vector3::new(0.03, 0.61, -0.05);
+        let index = grid.position_to_grid_index(&pos) as usize;
+        assert_eq!(grid.get_field(&pos), grid.grid[index]);
+
+        // Trilinear interpolation is consistent with the Jacobian.
+        grid.interpolation = GridInterpolation::Trilinear;
+        for pos in &[
+            Vector3::new(0.03, 0.61, -0.05),
+            Vector3::new(-0.14, 0.1, 0.15),
+        ] {
+            assert!((grid.get_field(pos) - (gradient * pos + offset)).norm() < 1e-12);
+        }
+
+        // An axis with a single cell has zero derivative.
+        let flat: FieldGrid<()> = FieldGrid::new(
+            Vector3::new(1.0, 1.0, 1.0),
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(2, 1, 1),
+            vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)],
+        );
+        let expected = Matrix3::new(0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        assert!((flat.get_jacobian(&Vector3::new(0.1, 0.0, 0.0)) - expected).norm() < 1e-12);
+    }
+}
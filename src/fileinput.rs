@@ -54,14 +54,8 @@ pub fn write_file_template(file: &str) {
 		direction_quadru: Vector3::new(0., 0., 1.0),
 	};
 	let massrubidium = MassDistribution::new(vec![
-		MassRatio {
-			mass: 87.,
-			ratio: 0.2783,
-		},
-		MassRatio {
-			mass: 85.,
-			ratio: 0.7217,
-		},
+		MassRatio::new(87., 0.2783),
+		MassRatio::new(85., 0.7217),
 	]);
 	let detector = DetectorArchetype {
 		position: Vector3::new(1., 0., 0.),
@@ -2,6 +2,7 @@
 
 use crate::atom::{Force, Mass};
 use crate::constant;
+use crate::substep::{active_atoms, SubstepMask};
 use nalgebra::Vector3;
 use specs::prelude::*;
 
@@ -15,17 +16,19 @@ impl<'a> System<'a> for ApplyGravitationalForceSystem {
         WriteStorage<'a, Force>,
         ReadStorage<'a, Mass>,
         Option<Read<'a, ApplyGravityOption>>,
+        Option<Read<'a, SubstepMask>>,
     );
 
-    fn run(&mut self, (mut force, mass, gravity_option): Self::SystemData) {
+    fn run(&mut self, (mut force, mass, gravity_option, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
 
         match gravity_option {
             None => (),
             Some(_) => {
-                (&mut force, &mass)
+                let atoms = active_atoms(substep_mask.as_deref(), force.mask());
+                (&atoms, &mut force, &mass)
                     .par_join()
-                    .for_each(|(mut force, mass)| {
+                    .for_each(|(_, mut force, mass)| {
                         force.force = force.force
                             + mass.value * constant::AMU * constant::GC * Vector3::new(0., 0., -1.);
                     });
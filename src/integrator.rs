@@ -1,13 +1,23 @@
 //! Module that performs time-integration.
 //!
 //! This module implements the [EulerIntegrationSystem](struct.EulerIntegrationSystem.html),
-//! which uses the euler method to integrate classical equations of motion.
+//! which uses the euler method to integrate classical equations of motion, and the velocity-verlet
+//! integration systems used by default. Higher-order methods, which evaluate the forces more than once
+//! per step, are implemented by the [MultistageIntegrationSystem](struct.MultistageIntegrationSystem.html).
+//! The [Integrator](enum.Integrator.html) is selected when building the dispatcher.
+//!
+//! The duration of each step is set by the [Timestep](struct.Timestep.html) resource. The timestep may be
+//! adjusted each frame by adding an [AdaptiveTimestep](struct.AdaptiveTimestep.html) resource to the world.
+//! Systems which depend on the time should use the [SimulationTime](struct.SimulationTime.html) resource,
+//! which remains correct when the timestep changes.
 
 extern crate nalgebra;
 
 use crate::atom::*;
 use crate::constant;
 use crate::initiate::NewlyCreated;
+use crate::substep::Substepped;
+use nalgebra::Vector3;
 use specs::prelude::*;
 
 /// Tracks the number of the current integration step.
@@ -27,6 +37,140 @@ pub struct Timestep {
 	pub delta: f64,
 }
 
+/// The total simulation time elapsed, in SI units of seconds.
+///
+/// The time is advanced by the integrator at the start of each step, so that it is the time at which atoms
+/// are located during the rest of the frame.
+#[derive(Default)]
+pub struct SimulationTime {
+	pub time: f64,
+}
+
+/// A resource which enables adaptive adjustment of the [Timestep](struct.Timestep.html).
+///
+/// Each frame, the [AdaptTimestepSystem](struct.AdaptTimestepSystem.html) chooses the largest timestep
+/// which satisfies all of the enabled criteria, within the bounds `min` and `max`.
+/// Time-dependent systems use the duration of the current step, so that ramps, emission rates and
+/// photon scattering remain consistent as the timestep changes.
+pub struct AdaptiveTimestep {
+	/// Minimum duration of the timestep, in s.
+	pub min: f64,
+	/// Maximum duration of the timestep, in s.
+	pub max: f64,
+	/// Maximum distance any atom may travel in one step, in m.
+	pub max_displacement: Option<f64>,
+	/// Maximum change in the velocity of any atom in one step due to the force acting on it, in m/s.
+	pub max_velocity_change: Option<f64>,
+	/// Maximum estimated error in the velocity of any atom in one step, in m/s.
+	///
+	/// The error of the velocity-verlet method is estimated from the change in force between the last two frames,
+	/// `|F - F_old| dt / 2m`. Atoms without a recorded force from the previous frame are ignored. Random forces,
+	/// such as those due to spontaneous emission, also contribute to the change in force, so this criterion
+	/// should be used with care when they are present.
+	///
+	/// For integrators other than [VelocityVerlet](enum.Integrator.html#variant.VelocityVerlet), the force from the
+	/// previous frame is recorded by the [RecordOldForceSystem](struct.RecordOldForceSystem.html), and the same
+	/// estimate is used as a measure of how quickly the force changes over a step.
+	pub error_tolerance: Option<f64>,
+	/// The maximum factor by which the timestep may increase from one frame to the next.
+	pub max_growth: f64,
+}
+impl AdaptiveTimestep {
+	/// Creates an `AdaptiveTimestep` with the given bounds, in s, and no criteria enabled.
+	pub fn new(min: f64, max: f64) -> Self {
+		AdaptiveTimestep {
+			min: min,
+			max: max,
+			max_displacement: None,
+			max_velocity_change: None,
+			error_tolerance: None,
+			max_growth: 1.5,
+		}
+	}
+
+	/// Calculates the timestep for the next frame.
+	///
+	/// # Arguments
+	///
+	/// `previous`: duration of the previous step, in s.
+	///
+	/// `max_speed`: largest speed of any atom, in m/s.
+	///
+	/// `max_acceleration`: largest acceleration of any atom, in m/s^2.
+	///
+	/// `max_error`: largest estimated velocity error of any atom in the previous step, in m/s.
+	pub fn next_timestep(
+		&self,
+		previous: f64,
+		max_speed: f64,
+		max_acceleration: f64,
+		max_error: f64,
+	) -> f64 {
+		let mut delta = (previous * self.max_growth).min(self.max);
+		if let Some(max_displacement) = self.max_displacement {
+			if max_speed > 0.0 {
+				delta = delta.min(max_displacement / max_speed);
+			}
+		}
+		if let Some(max_velocity_change) = self.max_velocity_change {
+			if max_acceleration > 0.0 {
+				delta = delta.min(max_velocity_change / max_acceleration);
+			}
+		}
+		if let Some(error_tolerance) = self.error_tolerance {
+			// The error of each step scales as dt^2.
+			if max_error > 0.0 {
+				delta = delta.min(previous * (error_tolerance / max_error).sqrt());
+			}
+		}
+		delta.max(self.min)
+	}
+}
+
+/// Adjusts the [Timestep](struct.Timestep.html) at the start of each frame, if an
+/// [AdaptiveTimestep](struct.AdaptiveTimestep.html) resource has been added to the world.
+///
+/// This system must run before the integrator, and before any other system which uses the timestep.
+pub struct AdaptTimestepSystem;
+impl<'a> System<'a> for AdaptTimestepSystem {
+	type SystemData = (
+		Option<Read<'a, AdaptiveTimestep>>,
+		WriteExpect<'a, Timestep>,
+		ReadStorage<'a, Atom>,
+		ReadStorage<'a, Velocity>,
+		ReadStorage<'a, Force>,
+		ReadStorage<'a, OldForce>,
+		ReadStorage<'a, Mass>,
+	);
+
+	fn run(
+		&mut self,
+		(adaptive, mut timestep, atoms, velocities, forces, old_forces, masses): Self::SystemData,
+	) {
+		let adaptive = match adaptive {
+			Some(adaptive) => adaptive,
+			None => return,
+		};
+		let mut max_speed: f64 = 0.0;
+		let mut max_acceleration: f64 = 0.0;
+		let mut max_error: f64 = 0.0;
+		for (_, velocity, force, old_force, mass) in
+			(&atoms, &velocities, &forces, old_forces.maybe(), &masses).join()
+		{
+			let mass = constant::AMU * mass.value;
+			max_speed = max_speed.max(velocity.vel.norm());
+			max_acceleration = max_acceleration.max(force.force.norm() / mass);
+			if let Some(old_force) = old_force.filter(|old_force| old_force.0.force.norm() > 0.0) {
+				let error =
+					(force.force - old_force.0.force).norm() * timestep.delta / (2.0 * mass);
+				max_error = max_error.max(error);
+			}
+		}
+		timestep.delta =
+			adaptive.next_timestep(timestep.delta, max_speed, max_acceleration, max_error);
+	}
+}
+
 /// # Euler Integration
 ///
 /// The EulerIntegrationSystem integrates the classical equations of motion for particles using the euler method:
@@ -42,14 +186,16 @@ impl<'a> System<'a> for EulerIntegrationSystem {
 		WriteStorage<'a, Velocity>,
 		ReadExpect<'a, Timestep>,
 		WriteExpect<'a, Step>,
+		Write<'a, SimulationTime>,
 		ReadStorage<'a, Force>,
 		ReadStorage<'a, Mass>,
 	);
 
-	fn run(&mut self, (mut pos, mut vel, t, mut step, force, mass): Self::SystemData) {
+	fn run(&mut self, (mut pos, mut vel, t, mut step, mut time, force, mass): Self::SystemData) {
 		use rayon::prelude::*;
 
 		step.n = step.n + 1;
+		time.time = time.time + t.delta;
 		(&mut vel, &mut pos, &force, &mass).par_join().for_each(
 			|(mut vel, mut pos, force, mass)| {
 				euler_update(&mut vel, &mut pos, &force, &mass, t.delta);
@@ -64,6 +210,7 @@ pub const INTEGRATE_POSITION_SYSTEM_NAME: &str = "integrate_position";
 ///
 /// Integrates position using a velocity-verlet integration approach.
 /// Stores the value of `Force` from the previous frame in the `OldForce` component.
+/// Atoms marked as [Substepped](../substep/struct.Substepped.html) are not moved, but their `OldForce` is still stored.
 ///
 /// The timestep duration is specified by the [Timestep](struct.Timestep.html) system resource.
 pub struct VelocityVerletIntegratePositionSystem;
@@ -73,23 +220,38 @@ impl<'a> System<'a> for VelocityVerletIntegratePositionSystem {
 		ReadStorage<'a, Velocity>,
 		ReadExpect<'a, Timestep>,
 		WriteExpect<'a, Step>,
+		Write<'a, SimulationTime>,
 		ReadStorage<'a, Force>,
 		WriteStorage<'a, OldForce>,
 		ReadStorage<'a, Mass>,
+		ReadStorage<'a, Substepped>,
 	);
 
-	fn run(&mut self, (mut pos, vel, t, mut step, force, mut oldforce, mass): Self::SystemData) {
+	fn run(
+		&mut self,
+		(mut pos, vel, t, mut step, mut time, force, mut oldforce, mass, substepped): Self::SystemData,
+	) {
 		use rayon::prelude::*;
 
 		step.n = step.n + 1;
+		time.time = time.time + t.delta;
 		let dt = t.delta;
 
-		(&mut pos, &vel, &mut oldforce, &force, &mass)
+		(
+			&mut pos,
+			&vel,
+			&mut oldforce,
+			&force,
+			&mass,
+			substepped.maybe(),
+		)
 			.par_join()
-			.for_each(|(mut pos, vel, mut oldforce, force, mass)| {
-				pos.pos = pos.pos
-					+ vel.vel * dt + force.force / (constant::AMU * mass.value) / 2.0
-					* dt * dt;
+			.for_each(|(mut pos, vel, mut oldforce, force, mass, substepped)| {
+				if substepped.is_none() {
+					pos.pos = pos.pos
+						+ vel.vel * dt + force.force / (constant::AMU * mass.value) / 2.0
+						* dt * dt;
+				}
 				oldforce.0 = *force;
 			});
 	}
@@ -100,6 +262,7 @@ pub const INTEGRATE_VELOCITY_SYSTEM_NAME: &str = "integrate_velocity";
 /// # Velocity-Verlet Integrate Velocity
 ///
 /// Integrates velocity using the velocity-verlet method, and the average of `Force` this frame and `OldForce` from the previous frame.
+/// Atoms marked as [Substepped](../substep/struct.Substepped.html) are skipped.
 ///
 /// The timestep duration is specified by the [Timestep](struct.Timestep.html) system resource
 pub struct VelocityVerletIntegrateVelocitySystem;
@@ -110,19 +273,316 @@ impl<'a> System<'a> for VelocityVerletIntegrateVelocitySystem {
 		ReadStorage<'a, Force>,
 		ReadStorage<'a, OldForce>,
 		ReadStorage<'a, Mass>,
+		ReadStorage<'a, Substepped>,
 	);
 
-	fn run(&mut self, (mut vel, t, force, oldforce, mass): Self::SystemData) {
+	fn run(&mut self, (mut vel, t, force, oldforce, mass, substepped): Self::SystemData) {
 		use rayon::prelude::*;
 
 		let dt = t.delta;
 
-		(&mut vel, &force, &oldforce, &mass).par_join().for_each(
-			|(mut vel, force, oldforce, mass)| {
+		(&mut vel, &force, &oldforce, &mass, !&substepped)
+			.par_join()
+			.for_each(|(mut vel, force, oldforce, mass, _)| {
 				vel.vel = vel.vel
 					+ (force.force + oldforce.0.force) / (constant::AMU * mass.value) / 2.0 * dt;
-			},
-		);
+			});
+	}
+}
+
+/// The methods which can be used to integrate the equations of motion.
+///
+/// The integrator is selected when building the dispatcher, using
+/// [AtomecsDispatcherBuilder::with_integrator](../ecs/struct.AtomecsDispatcherBuilder.html#method.with_integrator).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Integrator {
+	/// The euler method. First order, with one force evaluation per step.
+	Euler,
+	/// The velocity-verlet method. Second order, with one force evaluation per step.
+	///
+	/// The force from the previous step is reused, which is only valid for forces that depend on position alone.
+	VelocityVerlet,
+	/// The classical fourth-order Runge-Kutta method, with four force evaluations per step.
+	///
+	/// Suitable for smooth forces which depend on both position and velocity.
+	RungeKutta4,
+	/// The fourth-order symplectic method of Forest and Ruth, with four force evaluations per step.
+	///
+	/// Conserves energy over long times for conservative forces which depend on position alone, such as
+	/// magnetic and optical dipole traps.
+	ForestRuth,
+	/// The stochastic Heun method, with two force evaluations per step.
+	///
+	/// The force is evaluated at the start of the step and at a predicted end point, using the same random
+	/// numbers for both evaluations. Velocity-dependent forces are evaluated at the correct velocity, and
+	/// random forces such as spontaneous emission are applied once per step.
+	StochasticHeun,
+}
+impl Default for Integrator {
+	fn default() -> Self {
+		Integrator::VelocityVerlet
+	}
+}
+impl Integrator {
+	/// Returns true if the method requires more than one force evaluation per step.
+	pub fn is_multistage(&self) -> bool {
+		match self {
+			Integrator::Euler | Integrator::VelocityVerlet => false,
+			_ => true,
+		}
+	}
+}
+
+/// Takes the place of an integration system in the dispatcher.
+///
+/// Many systems are ordered relative to [INTEGRATE_POSITION_SYSTEM_NAME](constant.INTEGRATE_POSITION_SYSTEM_NAME.html)
+/// and [INTEGRATE_VELOCITY_SYSTEM_NAME](constant.INTEGRATE_VELOCITY_SYSTEM_NAME.html). This system allows them
+/// to be added to the dispatcher when the velocity-verlet integrator is not used.
+pub struct IntegrationPlaceholderSystem;
+impl<'a> System<'a> for IntegrationPlaceholderSystem {
+	type SystemData = ();
+
+	fn run(&mut self, _: Self::SystemData) {}
+}
+
+/// State of an atom during a step of a [MultistageIntegrationSystem](struct.MultistageIntegrationSystem.html).
+#[derive(Clone, Copy)]
+struct AtomState {
+	entity: Entity,
+	/// Mass of the atom, in kg.
+	mass: f64,
+	position: Vector3<f64>,
+	velocity: Vector3<f64>,
+}
+
+/// Integrates the equations of motion using a method which requires more than one force evaluation per step.
+///
+/// The forces at the start of the step are those calculated during the frame. The remaining force evaluations
+/// are performed by running the `forces` dispatcher with the atoms moved to the intermediate positions and
+/// velocities of the method, and the [SimulationTime](struct.SimulationTime.html) set to the time of each stage.
+/// The random streams of the frame are reused for each evaluation.
+///
+/// This system must be added to the dispatcher as a thread local system, so that it runs at the end of the frame.
+/// It advances the [Step](struct.Step.html) and [SimulationTime](struct.SimulationTime.html) once the atoms
+/// have been moved to the end of the step.
+pub struct MultistageIntegrationSystem {
+	method: Integrator,
+	forces: Dispatcher<'static, 'static>,
+}
+impl MultistageIntegrationSystem {
+	/// Creates a new `MultistageIntegrationSystem`.
+	///
+	/// # Arguments
+	///
+	/// `method`: the integration method, which must be a [multistage](enum.Integrator.html#method.is_multistage) method.
+	///
+	/// `forces`: a dispatcher which calculates the [Force](../atom/struct.Force.html) on each atom.
+	pub fn new(method: Integrator, forces: Dispatcher<'static, 'static>) -> Self {
+		if !method.is_multistage() {
+			panic!(
+				"{:?} is not a multistage integrator, use its integration systems instead.",
+				method
+			);
+		}
+		MultistageIntegrationSystem {
+			method: method,
+			forces: forces,
+		}
+	}
+
+	/// Moves the atoms to the given states, evaluates the forces at the given time, and returns the
+	/// acceleration of each atom.
+	fn accelerations(
+		&mut self,
+		world: &World,
+		atoms: &[AtomState],
+		time: f64,
+	) -> Vec<Vector3<f64>> {
+		set_states(world, atoms);
+		world.write_resource::<SimulationTime>().time = time;
+		self.forces.dispatch(world);
+		let forces = world.read_storage::<Force>();
+		atoms
+			.iter()
+			.map(|atom| match forces.get(atom.entity) {
+				Some(force) => force.force / atom.mass,
+				None => Vector3::new(0.0, 0.0, 0.0),
+			})
+			.collect()
+	}
+
+	fn runge_kutta_4(
+		&mut self,
+		world: &World,
+		initial: &[AtomState],
+		a1: Vec<Vector3<f64>>,
+		t0: f64,
+		dt: f64,
+	) -> Vec<AtomState> {
+		let v1: Vec<Vector3<f64>> = initial.iter().map(|atom| atom.velocity).collect();
+		let stage2 = advance(initial, &v1, &a1, 0.5 * dt);
+		let a2 = self.accelerations(world, &stage2, t0 + 0.5 * dt);
+		let v2: Vec<Vector3<f64>> = stage2.iter().map(|atom| atom.velocity).collect();
+		let stage3 = advance(initial, &v2, &a2, 0.5 * dt);
+		let a3 = self.accelerations(world, &stage3, t0 + 0.5 * dt);
+		let v3: Vec<Vector3<f64>> = stage3.iter().map(|atom| atom.velocity).collect();
+		let stage4 = advance(initial, &v3, &a3, dt);
+		let a4 = self.accelerations(world, &stage4, t0 + dt);
+
+		initial
+			.iter()
+			.enumerate()
+			.map(|(i, atom)| AtomState {
+				position: atom.position
+					+ dt / 6.0 * (v1[i] + 2.0 * v2[i] + 2.0 * v3[i] + stage4[i].velocity),
+				velocity: atom.velocity + dt / 6.0 * (a1[i] + 2.0 * a2[i] + 2.0 * a3[i] + a4[i]),
+				..*atom
+			})
+			.collect()
+	}
+
+	fn forest_ruth(
+		&mut self,
+		world: &World,
+		initial: &[AtomState],
+		a1: Vec<Vector3<f64>>,
+		t0: f64,
+		dt: f64,
+	) -> Vec<AtomState> {
+		let theta = 1.0 / (2.0 - 2.0_f64.powf(1.0 / 3.0));
+		let drifts = [theta, 1.0 - 2.0 * theta, theta];
+		let kicks = [
+			0.5 * theta,
+			0.5 * (1.0 - theta),
+			0.5 * (1.0 - theta),
+			0.5 * theta,
+		];
+
+		let mut atoms = initial.to_vec();
+		let mut accelerations = a1;
+		let mut time = t0;
+		for (i, drift) in drifts.iter().enumerate() {
+			for (atom, acceleration) in atoms.iter_mut().zip(accelerations.iter()) {
+				atom.velocity = atom.velocity + kicks[i] * dt * acceleration;
+				atom.position = atom.position + drift * dt * atom.velocity;
+			}
+			time = time + drift * dt;
+			accelerations = self.accelerations(world, &atoms, time);
+		}
+		for (atom, acceleration) in atoms.iter_mut().zip(accelerations.iter()) {
+			atom.velocity = atom.velocity + kicks[3] * dt * acceleration;
+		}
+		atoms
+	}
+
+	fn stochastic_heun(
+		&mut self,
+		world: &World,
+		initial: &[AtomState],
+		a1: Vec<Vector3<f64>>,
+		t0: f64,
+		dt: f64,
+	) -> Vec<AtomState> {
+		let v1: Vec<Vector3<f64>> = initial.iter().map(|atom| atom.velocity).collect();
+		let predicted = advance(initial, &v1, &a1, dt);
+		let a2 = self.accelerations(world, &predicted, t0 + dt);
+
+		initial
+			.iter()
+			.enumerate()
+			.map(|(i, atom)| AtomState {
+				position: atom.position + 0.5 * dt * (v1[i] + predicted[i].velocity),
+				velocity: atom.velocity + 0.5 * dt * (a1[i] + a2[i]),
+				..*atom
+			})
+			.collect()
+	}
+}
+
+impl<'a> RunNow<'a> for MultistageIntegrationSystem {
+	fn run_now(&mut self, world: &'a World) {
+		let dt = world.read_resource::<Timestep>().delta;
+		let t0 = world.read_resource::<SimulationTime>().time;
+
+		let (initial, a1): (Vec<AtomState>, Vec<Vector3<f64>>) = {
+			let (entities, positions, velocities, forces, masses): (
+				Entities,
+				ReadStorage<Position>,
+				ReadStorage<Velocity>,
+				ReadStorage<Force>,
+				ReadStorage<Mass>,
+			) = world.system_data();
+			(&entities, &positions, &velocities, &forces, &masses)
+				.join()
+				.map(|(entity, position, velocity, force, mass)| {
+					let mass = constant::AMU * mass.value;
+					(
+						AtomState {
+							entity: entity,
+							mass: mass,
+							position: position.pos,
+							velocity: velocity.vel,
+						},
+						force.force / mass,
+					)
+				})
+				.unzip()
+		};
+
+		let final_states = match self.method {
+			Integrator::RungeKutta4 => self.runge_kutta_4(world, &initial, a1, t0, dt),
+			Integrator::ForestRuth => self.forest_ruth(world, &initial, a1, t0, dt),
+			Integrator::StochasticHeun => self.stochastic_heun(world, &initial, a1, t0, dt),
+			Integrator::Euler | Integrator::VelocityVerlet => unreachable!(),
+		};
+		set_states(world, &final_states);
+
+		world.write_resource::<SimulationTime>().time = t0 + dt;
+		let mut step = world.write_resource::<Step>();
+		step.n = step.n + 1;
+	}
+
+	fn setup(&mut self, world: &mut World) {
+		<(
+			ReadStorage<Position>,
+			ReadStorage<Velocity>,
+			ReadStorage<Force>,
+			ReadStorage<Mass>,
+			Write<SimulationTime>,
+		) as SystemData>::setup(world);
+		self.forces.setup(world);
+	}
+}
+
+/// Advances each atom from its `initial` state by `dt`, using the given velocities and accelerations.
+fn advance(
+	initial: &[AtomState],
+	velocities: &[Vector3<f64>],
+	accelerations: &[Vector3<f64>],
+	dt: f64,
+) -> Vec<AtomState> {
+	initial
+		.iter()
+		.zip(velocities.iter().zip(accelerations.iter()))
+		.map(|(atom, (velocity, acceleration))| AtomState {
+			position: atom.position + dt * velocity,
+			velocity: atom.velocity + dt * acceleration,
+			..*atom
+		})
+		.collect()
+}
+
+/// Writes the position and velocity of each atom to the world.
+fn set_states(world: &World, atoms: &[AtomState]) {
+	let mut positions = world.write_storage::<Position>();
+	let mut velocities = world.write_storage::<Velocity>();
+	for atom in atoms {
+		if let Some(position) = positions.get_mut(atom.entity) {
+			position.pos = atom.position;
+		}
+		if let Some(velocity) = velocities.get_mut(atom.entity) {
+			velocity.vel = atom.velocity;
+		}
 	}
 }
 
@@ -142,8 +602,29 @@ impl<'a> System<'a> for AddOldForceToNewAtomsSystem {
 	}
 }
 
+pub const RECORD_OLD_FORCE_SYSTEM_NAME: &str = "record_old_force";
+
+/// Stores the value of `Force` from the previous frame in the `OldForce` component.
+///
+/// The [VelocityVerletIntegratePositionSystem](struct.VelocityVerletIntegratePositionSystem.html) already does this,
+/// so this system is only used with the other integrators. It must run after the
+/// [AdaptTimestepSystem](struct.AdaptTimestepSystem.html) and before the forces are cleared.
+pub struct RecordOldForceSystem;
+impl<'a> System<'a> for RecordOldForceSystem {
+	type SystemData = (ReadStorage<'a, Force>, WriteStorage<'a, OldForce>);
+	fn run(&mut self, (force, mut oldforce): Self::SystemData) {
+		use rayon::prelude::*;
+
+		(&force, &mut oldforce)
+			.par_join()
+			.for_each(|(force, oldforce)| {
+				oldforce.0 = *force;
+			});
+	}
+}
+
 /// Stores the value of the force calculation from the previous frame.
-pub struct OldForce(Force);
+pub struct OldForce(pub(crate) Force);
 impl Component for OldForce {
 	type Storage = VecStorage<OldForce>;
 }
@@ -255,6 +736,219 @@ pub mod tests {
 		);
 	}
 
+	#[test]
+	fn test_adaptive_timestep_bounds() {
+		let mut adaptive = AdaptiveTimestep::new(1e-7, 1e-5);
+		adaptive.max_displacement = Some(1e-4);
+		// Limited by growth.
+		assert_approx_eq::assert_approx_eq!(
+			adaptive.next_timestep(1e-6, 1.0, 0.0, 0.0),
+			1.5e-6,
+			1e-15
+		);
+		// Limited by displacement of the fastest atom.
+		assert_approx_eq::assert_approx_eq!(
+			adaptive.next_timestep(1e-6, 500.0, 0.0, 0.0),
+			2e-7,
+			1e-15
+		);
+		// Limited by the bounds.
+		assert_eq!(adaptive.next_timestep(1e-6, 1e4, 0.0, 0.0), 1e-7);
+		assert_eq!(adaptive.next_timestep(1e-5, 0.0, 0.0, 0.0), 1e-5);
+
+		adaptive.error_tolerance = Some(1e-3);
+		// The error scales as dt^2.
+		assert_approx_eq::assert_approx_eq!(
+			adaptive.next_timestep(1e-6, 0.0, 0.0, 4e-3),
+			5e-7,
+			1e-15
+		);
+	}
+
+	#[test]
+	fn test_adapt_timestep_system() {
+		let mut world = World::new();
+		world.register::<Atom>();
+		world.register::<Velocity>();
+		world.register::<Force>();
+		world.register::<OldForce>();
+		world.register::<Mass>();
+		world.insert(Timestep { delta: 1e-6 });
+
+		world
+			.create_entity()
+			.with(Atom)
+			.with(Velocity {
+				vel: Vector3::new(200.0, 0.0, 0.0),
+			})
+			.with(Force {
+				force: Vector3::new(0.0, 0.0, 0.0),
+			})
+			.with(Mass { value: 87.0 })
+			.build();
+
+		// Without an AdaptiveTimestep, the timestep is unchanged.
+		let mut system = AdaptTimestepSystem;
+		system.run_now(&world);
+		assert_eq!(world.read_resource::<Timestep>().delta, 1e-6);
+
+		let mut adaptive = AdaptiveTimestep::new(1e-8, 1e-5);
+		adaptive.max_displacement = Some(1e-4);
+		world.insert(adaptive);
+		system.run_now(&world);
+		assert_approx_eq::assert_approx_eq!(world.read_resource::<Timestep>().delta, 5e-7, 1e-15);
+	}
+
+	#[test]
+	fn test_error_tolerance_with_recorded_old_force() {
+		let mut world = World::new();
+		world.register::<Atom>();
+		world.register::<Velocity>();
+		world.register::<Force>();
+		world.register::<OldForce>();
+		world.register::<Mass>();
+		world.insert(Timestep { delta: 1e-6 });
+
+		let atom = world
+			.create_entity()
+			.with(Atom)
+			.with(Velocity {
+				vel: Vector3::new(0.0, 0.0, 0.0),
+			})
+			.with(Force {
+				force: Vector3::new(1e-22, 0.0, 0.0),
+			})
+			.with(OldForce::default())
+			.with(Mass { value: 87.0 })
+			.build();
+
+		RecordOldForceSystem.run_now(&world);
+		assert_eq!(
+			world
+				.read_storage::<OldForce>()
+				.get(atom)
+				.expect("entity not found")
+				.0
+				.force,
+			Vector3::new(1e-22, 0.0, 0.0)
+		);
+
+		// The force doubles over the next frame, so the timestep is limited by the error estimate.
+		world
+			.write_storage::<Force>()
+			.get_mut(atom)
+			.expect("entity not found")
+			.force = Vector3::new(2e-22, 0.0, 0.0);
+		let mut adaptive = AdaptiveTimestep::new(1e-12, 1e-5);
+		adaptive.error_tolerance = Some(1e-3);
+		world.insert(adaptive);
+		AdaptTimestepSystem.run_now(&world);
+		let mass = constant::AMU * 87.0;
+		let error = 1e-22 * 1e-6 / (2.0 * mass);
+		assert_approx_eq::assert_approx_eq!(
+			world.read_resource::<Timestep>().delta,
+			1e-6 * (1e-3 / error).sqrt().min(1.5),
+			1e-15
+		);
+	}
+
+	/// A force `F = -k x - b v`, used to test the multistage integrators.
+	#[cfg(test)]
+	struct DampedSpringForceSystem {
+		k: f64,
+		b: f64,
+	}
+	#[cfg(test)]
+	impl<'a> System<'a> for DampedSpringForceSystem {
+		type SystemData = (
+			ReadStorage<'a, Position>,
+			ReadStorage<'a, Velocity>,
+			WriteStorage<'a, Force>,
+		);
+		fn run(&mut self, (positions, velocities, mut forces): Self::SystemData) {
+			for (position, velocity, force) in (&positions, &velocities, &mut forces).join() {
+				force.force = -self.k * position.pos - self.b * velocity.vel;
+			}
+		}
+	}
+
+	/// Integrates a damped harmonic oscillator with the given method, and returns the final position and velocity.
+	#[cfg(test)]
+	fn integrate_oscillator(
+		method: Integrator,
+		b: f64,
+		dt: f64,
+		n_steps: u64,
+	) -> (Vector3<f64>, Vector3<f64>) {
+		let mut world = World::new();
+		let forces = DispatcherBuilder::new()
+			.with(DampedSpringForceSystem { k: 1.0, b: b }, "spring", &[])
+			.build();
+		let mut dispatcher = DispatcherBuilder::new()
+			.with(DampedSpringForceSystem { k: 1.0, b: b }, "spring", &[])
+			.with_thread_local(MultistageIntegrationSystem::new(method, forces))
+			.build();
+		dispatcher.setup(&mut world);
+		world.insert(Timestep { delta: dt });
+		world.insert(Step { n: 0 });
+
+		let atom = world
+			.create_entity()
+			.with(Position {
+				pos: Vector3::new(1.0, 0.0, 0.0),
+			})
+			.with(Velocity {
+				vel: Vector3::new(0.0, 0.0, 0.0),
+			})
+			.with(Force::new())
+			.with(Mass {
+				value: 1.0 / constant::AMU,
+			})
+			.build();
+
+		for _ in 0..n_steps {
+			dispatcher.dispatch(&mut world);
+			world.maintain();
+		}
+		assert_eq!(world.read_resource::<Step>().n, n_steps);
+		assert_approx_eq::assert_approx_eq!(
+			world.read_resource::<SimulationTime>().time,
+			n_steps as f64 * dt,
+			1e-9
+		);
+		let position = world
+			.read_storage::<Position>()
+			.get(atom)
+			.expect("atom not found.")
+			.pos;
+		let velocity = world
+			.read_storage::<Velocity>()
+			.get(atom)
+			.expect("atom not found.")
+			.vel;
+		(position, velocity)
+	}
+
+	#[test]
+	fn test_multistage_integrators() {
+		// Undamped oscillator with unit angular frequency, integrated for ten periods.
+		let dt = 0.05;
+		let n_steps = (20.0 * constant::PI / dt) as u64;
+		let t = n_steps as f64 * dt;
+		for method in [Integrator::RungeKutta4, Integrator::ForestRuth].iter() {
+			let (position, velocity) = integrate_oscillator(*method, 0.0, dt, n_steps);
+			assert_approx_eq::assert_approx_eq!(position[0], t.cos(), 1e-4);
+			assert_approx_eq::assert_approx_eq!(velocity[0], -t.sin(), 1e-4);
+		}
+
+		// Velocity-dependent damping is handled by the stochastic Heun method.
+		let (position, _) = integrate_oscillator(Integrator::StochasticHeun, 0.5, 1e-3, 2000);
+		let omega = (1.0 - 0.25_f64.powi(2)).sqrt();
+		let expected =
+			(-0.25 * 2.0_f64).exp() * ((omega * 2.0).cos() + 0.25 / omega * (omega * 2.0).sin());
+		assert_approx_eq::assert_approx_eq!(position[0], expected, 1e-5);
+	}
+
 	#[test]
 	fn test_add_old_force_system() {
 		let mut test_world = World::new();
@@ -6,6 +6,7 @@ use specs::prelude::*;
 use super::cooling::{CoolingLight, CoolingLightIndex};
 use super::gaussian::GaussianBeam;
 use crate::atom::Velocity;
+use crate::substep::{active_atoms, SubstepMask};
 
 const LASER_CACHE_SIZE: usize = 16;
 
@@ -36,11 +37,17 @@ impl<'a> System<'a> for CalculateDopplerShiftSystem {
         ReadStorage<'a, GaussianBeam>,
         WriteStorage<'a, DopplerShiftSamplers>,
         ReadStorage<'a, Velocity>,
+        Option<Read<'a, SubstepMask>>,
     );
 
-    fn run(&mut self, (cooling, indices, gaussian, mut samplers, velocities): Self::SystemData) {
+    fn run(
+        &mut self,
+        (cooling, indices, gaussian, mut samplers, velocities, substep_mask): Self::SystemData,
+    ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), samplers.mask());
+
         // There are typically only a small number of lasers in a simulation.
         // For a speedup, cache the required components into thread memory,
         // so they can be distributed to parallel workers during the atom loop.
@@ -58,9 +65,9 @@ impl<'a> System<'a> for CalculateDopplerShiftSystem {
             laser_array[..max_index].copy_from_slice(slice);
             let number_in_iteration = slice.len();
 
-            (&mut samplers, &velocities)
+            (&atoms, &mut samplers, &velocities)
                 .par_join()
-                .for_each(|(sampler, vel)| {
+                .for_each(|(_, sampler, vel)| {
                     for i in 0..number_in_iteration {
                         let (cooling, index, gaussian) = laser_array[i];
                         sampler.contents[index.index].doppler_shift = vel
@@ -15,6 +15,8 @@ use specs::prelude::*;
 use crate::atom::Force;
 use crate::constant::HBAR;
 use crate::integrator::Timestep;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use crate::substep::{active_atoms, SubstepMask};
 
 use crate::laser::repump::*;
 
@@ -36,6 +38,7 @@ impl<'a> System<'a> for CalculateAbsorptionForcesSystem {
         WriteStorage<'a, Force>,
         ReadExpect<'a, Timestep>,
         ReadStorage<'a, Dark>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
@@ -48,10 +51,13 @@ impl<'a> System<'a> for CalculateAbsorptionForcesSystem {
             mut forces,
             timestep,
             _dark,
+            substep_mask,
         ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), forces.mask());
+
         // There are typically only a small number of lasers in a simulation.
         // For a speedup, cache the required components into thread memory,
         // so they can be distributed to parallel workers during the atom loop.
@@ -69,9 +75,9 @@ impl<'a> System<'a> for CalculateAbsorptionForcesSystem {
             laser_array[..max_index].copy_from_slice(slice);
             let number_in_iteration = slice.len();
 
-            (&actual_scattered_vector, &mut forces, !&_dark)
+            (&atoms, &actual_scattered_vector, &mut forces, !&_dark)
                 .par_join()
-                .for_each(|(scattered, mut force, _)| {
+                .for_each(|(_, scattered, mut force, _)| {
                     for i in 0..number_in_iteration {
                         let (cooling, index, gaussian) = laser_array[i];
                         let new_force = scattered.contents[index.index].scattered * HBAR
@@ -128,11 +134,23 @@ impl<'a> System<'a> for ApplyEmissionForceSystem {
         ReadStorage<'a, ActualPhotonsScatteredVector>,
         ReadStorage<'a, AtomicTransition>,
         ReadExpect<'a, Timestep>,
+        Entities<'a>,
+        Option<Read<'a, FrameRandomKey>>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (rand_opt, mut force, actual_scattered_vector, atom_info, timestep): Self::SystemData,
+        (
+            rand_opt,
+            mut force,
+            actual_scattered_vector,
+            atom_info,
+            timestep,
+            entities,
+            random_key,
+            substep_mask,
+        ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
@@ -142,38 +160,50 @@ impl<'a> System<'a> for ApplyEmissionForceSystem {
                 match *opt {
                     EmissionForceOption::Off => {}
                     EmissionForceOption::On(configuration) => {
-                        (&mut force, &atom_info, &actual_scattered_vector)
+                        let streams =
+                            RandomStreams::new(random_key.as_deref(), "calculate_emission_forces");
+                        let atoms = active_atoms(substep_mask.as_deref(), force.mask());
+                        (
+                            &atoms,
+                            &entities,
+                            &mut force,
+                            &atom_info,
+                            &actual_scattered_vector,
+                        )
                             .par_join()
-                            .for_each(|(mut force, atom_info, kick)| {
-                                let total: u64 = kick.calculate_total_scattered();
-                                let mut rng = rand::thread_rng();
-                                let omega = 2.0 * constant::PI * atom_info.frequency;
-                                let force_one_kick =
-                                    constant::HBAR * omega / constant::C / timestep.delta;
-                                if total > configuration.explicit_threshold {
-                                    // see HSIUNG, HSIUNG,GORDUS,1960, A Closed General Solution of the Probability Distribution Function for
-                                    //Three-Dimensional Random Walk Processes*
-                                    let normal = Normal::new(
-                                        0.0,
-                                        (total as f64 * force_one_kick.powf(2.0) / 3.0).powf(0.5),
-                                    )
-                                    .unwrap();
-
-                                    let force_n_kicks = Vector3::new(
-                                        normal.sample(&mut rng),
-                                        normal.sample(&mut rng),
-                                        normal.sample(&mut rng),
-                                    );
-                                    force.force = force.force + force_n_kicks;
-                                } else {
-                                    // explicit random walk implementation
-                                    for _i in 0..total {
-                                        let v: [f64; 3] = UnitSphere.sample(&mut rng);
-                                        force.force = force.force
-                                            + force_one_kick * Vector3::new(v[0], v[1], v[2]);
+                            .for_each(
+                                |(_, entity, mut force, atom_info, kick)| {
+                                    let total: u64 = kick.calculate_total_scattered();
+                                    let mut rng = streams.entity_rng(entity);
+                                    let omega = 2.0 * constant::PI * atom_info.frequency;
+                                    let force_one_kick =
+                                        constant::HBAR * omega / constant::C / timestep.delta;
+                                    if total > configuration.explicit_threshold {
+                                        // see HSIUNG, HSIUNG,GORDUS,1960, A Closed General Solution of the Probability Distribution Function for
+                                        //Three-Dimensional Random Walk Processes*
+                                        let normal = Normal::new(
+                                            0.0,
+                                            (total as f64 * force_one_kick.powf(2.0) / 3.0)
+                                                .powf(0.5),
+                                        )
+                                        .unwrap();
+
+                                        let force_n_kicks = Vector3::new(
+                                            normal.sample(&mut rng),
+                                            normal.sample(&mut rng),
+                                            normal.sample(&mut rng),
+                                        );
+                                        force.force = force.force + force_n_kicks;
+                                    } else {
+                                        // explicit random walk implementation
+                                        for _i in 0..total {
+                                            let v: [f64; 3] = UnitSphere.sample(&mut rng);
+                                            force.force = force.force
+                                                + force_one_kick * Vector3::new(v[0], v[1], v[2]);
+                                        }
                                     }
-                                }
-                            });
+                                },
+                            );
                     }
                 }
             }
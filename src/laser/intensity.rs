@@ -11,6 +11,7 @@ use specs::prelude::*;
 use super::cooling::CoolingLightIndex;
 use super::gaussian::{get_gaussian_beam_intensity, CircularMask, GaussianBeam};
 use crate::atom::Position;
+use crate::substep::{active_atoms, SubstepMask};
 
 const LASER_CACHE_SIZE: usize = 16;
 
@@ -44,13 +45,20 @@ impl Component for LaserIntensitySamplers {
 /// It also ensures that the size of the `LaserIntensitySamplers` components match the number of CoolingLight entities in the world.
 pub struct InitialiseLaserIntensitySamplersSystem;
 impl<'a> System<'a> for InitialiseLaserIntensitySamplersSystem {
-    type SystemData = (WriteStorage<'a, LaserIntensitySamplers>,);
-    fn run(&mut self, (mut samplers,): Self::SystemData) {
+    type SystemData = (
+        WriteStorage<'a, LaserIntensitySamplers>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut samplers, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
 
-        (&mut samplers).par_join().for_each(|mut sampler| {
-            sampler.contents = [LaserIntensitySampler::default(); crate::laser::COOLING_BEAM_LIMIT];
-        });
+        let atoms = active_atoms(substep_mask.as_deref(), samplers.mask());
+        (&atoms, &mut samplers)
+            .par_join()
+            .for_each(|(_, mut sampler)| {
+                sampler.contents =
+                    [LaserIntensitySampler::default(); crate::laser::COOLING_BEAM_LIMIT];
+            });
     }
 }
 
@@ -69,14 +77,17 @@ impl<'a> System<'a> for SampleLaserIntensitySystem {
         ReadStorage<'a, CircularMask>,
         ReadStorage<'a, Position>,
         WriteStorage<'a, LaserIntensitySamplers>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (entities, indices, gaussian, masks, position, mut intensity_samplers): Self::SystemData,
+        (entities, indices, gaussian, masks, position, mut intensity_samplers, substep_mask): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), intensity_samplers.mask());
+
         // There are typically only a small number of lasers in a simulation.
         // For a speedup, cache the required components into thread memory,
         // so they can be distributed to parallel workers during the atom loop.
@@ -100,9 +111,9 @@ impl<'a> System<'a> for SampleLaserIntensitySystem {
             laser_array[..max_index].copy_from_slice(slice);
             let number_in_iteration = slice.len();
 
-            (&mut intensity_samplers, &position)
+            (&atoms, &mut intensity_samplers, &position)
                 .par_join()
-                .for_each(|(samplers, pos)| {
+                .for_each(|(_, samplers, pos)| {
                     for i in 0..number_in_iteration {
                         let (index, gaussian, mask) = laser_array[i];
                         samplers.contents[index.index].intensity =
@@ -90,16 +90,27 @@ impl<'a> System<'a> for AttachLaserComponentsToNewlyCreatedAtomsSystem {
 ///
 /// `deps`: any dependencies that must be completed before the systems run.
 pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>, deps: &[&str]) {
-	builder.add(
-		AttachLaserComponentsToNewlyCreatedAtomsSystem,
-		"attach_atom_laser_components",
-		deps,
-	);
-	builder.add(
-		cooling::AttachIndexToCoolingLightSystem,
-		"attach_cooling_index",
-		deps,
-	);
+	add_force_systems_to_dispatch(builder, deps);
+	add_event_systems_to_dispatch(builder, deps);
+}
+
+/// Adds the systems which calculate the optical forces on atoms to the dispatcher.
+///
+/// These systems may be run several times per frame to evaluate the forces on atoms, eg by a multistage integrator.
+///
+/// The laser detuning includes the Stark shift of atoms with a
+/// [StarkShiftSampler](../electric/stark/struct.StarkShiftSampler.html). If the electric systems are added to the
+/// dispatcher, include `"stark_shift"` in `deps` so that the shift is calculated first.
+///
+/// #Arguments
+///
+/// `builder`: the dispatch builder to modify
+///
+/// `deps`: any dependencies that must be completed before the systems run.
+pub fn add_force_systems_to_dispatch(
+	builder: &mut DispatcherBuilder<'static, 'static>,
+	deps: &[&str],
+) {
 	builder.add(
 		cooling::IndexCoolingLightsSystem,
 		"index_cooling_lights",
@@ -187,11 +198,6 @@ pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>
 		"calculate_absorption_forces",
 		&["calculate_actual_photons", INTEGRATE_POSITION_SYSTEM_NAME],
 	);
-	builder.add(
-		repump::RepumpSystem,
-		"repump",
-		&["calculate_absorption_forces"],
-	);
 	builder.add(
 		force::ApplyEmissionForceSystem,
 		"calculate_emission_forces",
@@ -202,6 +208,38 @@ pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>
 	);
 }
 
+/// Adds the laser systems which must only run once per frame to the dispatcher, such as repump losses
+/// and the systems which attach components to new atoms and lights.
+///
+/// These systems depend on the systems added by [add_force_systems_to_dispatch](fn.add_force_systems_to_dispatch.html),
+/// which must be added first.
+///
+/// #Arguments
+///
+/// `builder`: the dispatch builder to modify
+///
+/// `deps`: any dependencies that must be completed before the systems run.
+pub fn add_event_systems_to_dispatch(
+	builder: &mut DispatcherBuilder<'static, 'static>,
+	deps: &[&str],
+) {
+	builder.add(
+		AttachLaserComponentsToNewlyCreatedAtomsSystem,
+		"attach_atom_laser_components",
+		deps,
+	);
+	builder.add(
+		cooling::AttachIndexToCoolingLightSystem,
+		"attach_cooling_index",
+		deps,
+	);
+	builder.add(
+		repump::RepumpSystem,
+		"repump",
+		&["calculate_absorption_forces"],
+	);
+}
+
 /// Registers resources required by magnetics to the ecs world.
 pub fn register_components(world: &mut World) {
 	world.register::<cooling::CoolingLight>();
@@ -209,3 +247,24 @@ pub fn register_components(world: &mut World) {
 	world.register::<gaussian::GaussianBeam>();
 	world.register::<gaussian::CircularMask>();
 }
+
+#[cfg(test)]
+pub mod tests {
+	use super::*;
+	use crate::integrator::IntegrationPlaceholderSystem;
+
+	/// Tests that the laser systems can be added to a dispatcher without the electric systems.
+	#[test]
+	fn test_add_systems_without_electric_fields() {
+		let mut builder = DispatcherBuilder::new();
+		builder.add(
+			IntegrationPlaceholderSystem,
+			INTEGRATE_POSITION_SYSTEM_NAME,
+			&[],
+		);
+		crate::magnetic::add_field_systems_to_dispatch(&mut builder, &[]);
+		add_systems_to_dispatch(&mut builder, &[]);
+		let mut world = World::new();
+		builder.build().setup(&mut world);
+	}
+}
@@ -2,7 +2,6 @@
 
 extern crate rayon;
 
-use rand;
 use rand_distr::{Distribution, Poisson};
 
 use crate::atom::AtomicTransition;
@@ -10,6 +9,8 @@ use crate::integrator::Timestep;
 use crate::laser::rate::RateCoefficients;
 use crate::laser::sampler::LaserSamplerMasks;
 use crate::laser::twolevel::TwoLevelPopulation;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use crate::substep::{active_atoms, SubstepMask};
 use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use std::fmt;
@@ -48,21 +49,30 @@ impl<'a> System<'a> for CalculateMeanTotalPhotonsScatteredSystem {
         ReadStorage<'a, AtomicTransition>,
         ReadStorage<'a, TwoLevelPopulation>,
         WriteStorage<'a, TotalPhotonsScattered>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (timestep, atomic_transition, twolevel_population, mut total_photons_scattered): Self::SystemData,
+        (
+            timestep,
+            atomic_transition,
+            twolevel_population,
+            mut total_photons_scattered,
+            substep_mask,
+        ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), total_photons_scattered.mask());
         (
+            &atoms,
             &atomic_transition,
             &twolevel_population,
             &mut total_photons_scattered,
         )
             .par_join()
-            .for_each(|(atominfo, twolevel, total)| {
+            .for_each(|(_, atominfo, twolevel, total)| {
                 total.total = timestep.delta * atominfo.gamma() * twolevel.excited;
             });
     }
@@ -109,14 +119,20 @@ impl fmt::Display for ExpectedPhotonsScatteredVector {
 /// It also ensures that the size of the ´ExpectedPhotonsScatteredVector´ components match the number of CoolingLight entities in the world.
 pub struct InitialiseExpectedPhotonsScatteredVectorSystem;
 impl<'a> System<'a> for InitialiseExpectedPhotonsScatteredVectorSystem {
-    type SystemData = (WriteStorage<'a, ExpectedPhotonsScatteredVector>,);
-    fn run(&mut self, (mut expected_photons,): Self::SystemData) {
+    type SystemData = (
+        WriteStorage<'a, ExpectedPhotonsScatteredVector>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut expected_photons, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
 
-        (&mut expected_photons).par_join().for_each(|mut expected| {
-            expected.contents =
-                [ExpectedPhotonsScattered::default(); crate::laser::COOLING_BEAM_LIMIT];
-        });
+        let atoms = active_atoms(substep_mask.as_deref(), expected_photons.mask());
+        (&atoms, &mut expected_photons)
+            .par_join()
+            .for_each(|(_, mut expected)| {
+                expected.contents =
+                    [ExpectedPhotonsScattered::default(); crate::laser::COOLING_BEAM_LIMIT];
+            });
     }
 }
 
@@ -131,22 +147,31 @@ impl<'a> System<'a> for CalculateExpectedPhotonsScatteredSystem {
         ReadStorage<'a, TotalPhotonsScattered>,
         ReadStorage<'a, LaserSamplerMasks>,
         WriteStorage<'a, ExpectedPhotonsScatteredVector>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (rate_coefficients, total_photons_scattered, masks, mut expected_photons_vector): Self::SystemData,
+        (
+            rate_coefficients,
+            total_photons_scattered,
+            masks,
+            mut expected_photons_vector,
+            substep_mask,
+        ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), expected_photons_vector.mask());
         (
+            &atoms,
             &rate_coefficients,
             &total_photons_scattered,
             &masks,
             &mut expected_photons_vector,
         )
             .par_join()
-            .for_each(|(rates, total, mask, expected)| {
+            .for_each(|(_, rates, total, mask, expected)| {
                 let mut sum_rates: f64 = 0.;
 
                 for index in 0..rates.contents.len() {
@@ -243,19 +268,31 @@ impl<'a> System<'a> for CalculateActualPhotonsScatteredSystem {
         Option<Read<'a, ScatteringFluctuationsOption>>,
         ReadStorage<'a, ExpectedPhotonsScatteredVector>,
         WriteStorage<'a, ActualPhotonsScatteredVector>,
+        Entities<'a>,
+        Option<Read<'a, FrameRandomKey>>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (fluctuations_option, expected_photons_vector, mut actual_photons_vector): Self::SystemData,
+        (
+            fluctuations_option,
+            expected_photons_vector,
+            mut actual_photons_vector,
+            entities,
+            random_key,
+            substep_mask,
+        ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), actual_photons_vector.mask());
+
         match fluctuations_option {
             None => {
-                (&expected_photons_vector, &mut actual_photons_vector)
+                (&atoms, &expected_photons_vector, &mut actual_photons_vector)
                     .par_join()
-                    .for_each(|(expected, actual)| {
+                    .for_each(|(_, expected, actual)| {
                         for index in 0..expected.contents.len() {
                             actual.contents[index].scattered = expected.contents[index].scattered;
                         }
@@ -263,9 +300,9 @@ impl<'a> System<'a> for CalculateActualPhotonsScatteredSystem {
             }
             Some(rand_option) => match *rand_option {
                 ScatteringFluctuationsOption::Off => {
-                    (&expected_photons_vector, &mut actual_photons_vector)
+                    (&atoms, &expected_photons_vector, &mut actual_photons_vector)
                         .par_join()
-                        .for_each(|(expected, actual)| {
+                        .for_each(|(_, expected, actual)| {
                             for index in 0..expected.contents.len() {
                                 actual.contents[index].scattered =
                                     expected.contents[index].scattered;
@@ -273,9 +310,17 @@ impl<'a> System<'a> for CalculateActualPhotonsScatteredSystem {
                         });
                 }
                 ScatteringFluctuationsOption::On => {
-                    (&expected_photons_vector, &mut actual_photons_vector)
+                    let streams =
+                        RandomStreams::new(random_key.as_deref(), "calculate_actual_photons");
+                    (
+                        &atoms,
+                        &entities,
+                        &expected_photons_vector,
+                        &mut actual_photons_vector,
+                    )
                         .par_join()
-                        .for_each(|(expected, actual)| {
+                        .for_each(|(_, entity, expected, actual)| {
+                            let mut rng = streams.entity_rng(entity);
                             for index in 0..expected.contents.len() {
                                 let lambda = expected.contents[index].scattered;
                                 actual.contents[index].scattered =
@@ -283,7 +328,7 @@ impl<'a> System<'a> for CalculateActualPhotonsScatteredSystem {
                                         0.0
                                     } else {
                                         let poisson = Poisson::new(lambda).unwrap();
-                                        let drawn_number = poisson.sample(&mut rand::thread_rng());
+                                        let drawn_number = poisson.sample(&mut rng);
                                         drawn_number as f64
                                     }
                             }
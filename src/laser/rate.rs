@@ -6,6 +6,7 @@ use crate::laser::gaussian::GaussianBeam;
 use crate::laser::intensity::LaserIntensitySamplers;
 use crate::laser::sampler::LaserDetuningSamplers;
 use crate::magnetic::MagneticFieldSampler;
+use crate::substep::{active_atoms, SubstepMask};
 use specs::prelude::*;
 
 /// Represents the rate coefficient of the atom with respect to a specific CoolingLight entity
@@ -38,13 +39,17 @@ impl Component for RateCoefficients {
 /// It also ensures that the size of the `RateCoefficient` components match the number of CoolingLight entities in the world.
 pub struct InitialiseRateCoefficientsSystem;
 impl<'a> System<'a> for InitialiseRateCoefficientsSystem {
-    type SystemData = (WriteStorage<'a, RateCoefficients>,);
-    fn run(&mut self, (mut rate_coefficients,): Self::SystemData) {
+    type SystemData = (
+        WriteStorage<'a, RateCoefficients>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut rate_coefficients, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
 
-        (&mut rate_coefficients)
+        let atoms = active_atoms(substep_mask.as_deref(), rate_coefficients.mask());
+        (&atoms, &mut rate_coefficients)
             .par_join()
-            .for_each(|mut rate_coefficient| {
+            .for_each(|(_, mut rate_coefficient)| {
                 rate_coefficient.contents =
                     [RateCoefficient::default(); crate::laser::COOLING_BEAM_LIMIT];
             });
@@ -71,6 +76,7 @@ impl<'a> System<'a> for CalculateRateCoefficientsSystem {
         ReadStorage<'a, GaussianBeam>,
         ReadStorage<'a, MagneticFieldSampler>,
         WriteStorage<'a, RateCoefficients>,
+        Option<Read<'a, SubstepMask>>,
     );
     fn run(
         &mut self,
@@ -83,12 +89,15 @@ impl<'a> System<'a> for CalculateRateCoefficientsSystem {
             gaussian_beam,
             magnetic_field_sampler,
             mut rate_coefficients,
+            substep_mask,
         ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), rate_coefficients.mask());
         for (cooling, index, gaussian) in (&cooling_light, &cooling_index, &gaussian_beam).join() {
             (
+                &atoms,
                 &laser_detunings,
                 &laser_intensities,
                 &atomic_transition,
@@ -96,7 +105,7 @@ impl<'a> System<'a> for CalculateRateCoefficientsSystem {
                 &mut rate_coefficients,
             )
                 .par_join()
-                .for_each(|(detunings, intensities, atominfo, bfield, rates)| {
+                .for_each(|(_, detunings, intensities, atominfo, bfield, rates)| {
                     let beam_direction_vector = gaussian.direction.normalize();
                     let costheta = if &bfield.field.norm_squared() < &(10.0 * f64::EPSILON) {
                         0.0
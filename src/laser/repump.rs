@@ -1,8 +1,8 @@
 //! Handling of dark states and repumping
 
-use rand;
 extern crate specs;
 use crate::laser::photons_scattered::TotalPhotonsScattered;
+use crate::rng::{FrameRandomKey, RandomStreams};
 use rand::Rng;
 use specs::{Component, Entities, LazyUpdate, Read, ReadStorage, System, VecStorage};
 
@@ -20,8 +20,7 @@ pub struct RepumpLoss {
 }
 
 impl RepumpLoss {
-    pub fn if_loss(&self, number_scattering_events: f64) -> bool {
-        let mut rng = rand::thread_rng();
+    pub fn if_loss<R: Rng + ?Sized>(&self, number_scattering_events: f64, rng: &mut R) -> bool {
         let result: f64 = rng.gen_range(0.0..1.0);
         return result < (1.0 - self.depump_chance).powf(number_scattering_events);
     }
@@ -37,16 +36,18 @@ impl<'a> System<'a> for RepumpSystem {
         Read<'a, LazyUpdate>,
         ReadStorage<'a, TotalPhotonsScattered>,
         Entities<'a>,
+        Option<Read<'a, FrameRandomKey>>,
     );
-    fn run(&mut self, (repump_opt, lazy, num, ent): Self::SystemData) {
+    fn run(&mut self, (repump_opt, lazy, num, ent, random_key): Self::SystemData) {
         use rayon::prelude::*;
         use specs::ParJoin;
 
         match repump_opt {
             None => (),
             Some(repump) => {
+                let streams = RandomStreams::new(random_key.as_deref(), "repump");
                 (&ent, &num).par_join().for_each(|(ent, num)| {
-                    if repump.if_loss(num.total) {
+                    if repump.if_loss(num.total, &mut streams.entity_rng(ent)) {
                         lazy.insert(ent, Dark {})
                     }
                 });
@@ -2,9 +2,11 @@
 
 use crate::atom::AtomicTransition;
 use crate::constant;
+use crate::electric::stark::StarkShiftSampler;
 use crate::laser::cooling::{CoolingLight, CoolingLightIndex};
 use crate::laser::doppler::DopplerShiftSamplers;
 use crate::magnetic::zeeman::ZeemanShiftSampler;
+use crate::substep::{active_atoms, SubstepMask};
 use specs::prelude::*;
 use std::f64;
 extern crate nalgebra;
@@ -34,12 +36,16 @@ impl Component for LaserSamplerMasks {
 /// Marks all laser sampler mask slots as empty.
 pub struct InitialiseLaserSamplerMasksSystem;
 impl<'a> System<'a> for InitialiseLaserSamplerMasksSystem {
-    type SystemData = (WriteStorage<'a, LaserSamplerMasks>,);
+    type SystemData = (
+        WriteStorage<'a, LaserSamplerMasks>,
+        Option<Read<'a, SubstepMask>>,
+    );
 
-    fn run(&mut self, (mut masks,): Self::SystemData) {
+    fn run(&mut self, (mut masks, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
 
-        (&mut masks).par_join().for_each(|mask| {
+        let atoms = active_atoms(substep_mask.as_deref(), masks.mask());
+        (&atoms, &mut masks).par_join().for_each(|(_, mask)| {
             mask.contents = [LaserSamplerMask::default(); crate::laser::COOLING_BEAM_LIMIT];
         });
     }
@@ -51,12 +57,14 @@ impl<'a> System<'a> for FillLaserSamplerMasksSystem {
     type SystemData = (
         ReadStorage<'a, CoolingLightIndex>,
         WriteStorage<'a, LaserSamplerMasks>,
+        Option<Read<'a, SubstepMask>>,
     );
-    fn run(&mut self, (light_index, mut masks): Self::SystemData) {
+    fn run(&mut self, (light_index, mut masks, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), masks.mask());
         for light_index in (&light_index).join() {
-            (&mut masks).par_join().for_each(|masks| {
+            (&atoms, &mut masks).par_join().for_each(|(_, masks)| {
                 masks.contents[light_index.index] = LaserSamplerMask { filled: true };
             });
         }
@@ -110,6 +118,8 @@ impl<'a> System<'a> for InitialiseLaserDetuningSamplersSystem {
 
 /// This system calculates the total Laser Detuning for each atom with respect to
 /// each CoolingLight entities.
+///
+/// The Stark shift is included for atoms which have a [StarkShiftSampler](../../electric/stark/struct.StarkShiftSampler.html).
 pub struct CalculateLaserDetuningSystem;
 impl<'a> System<'a> for CalculateLaserDetuningSystem {
     type SystemData = (
@@ -118,7 +128,9 @@ impl<'a> System<'a> for CalculateLaserDetuningSystem {
         ReadStorage<'a, CoolingLight>,
         ReadStorage<'a, DopplerShiftSamplers>,
         ReadStorage<'a, ZeemanShiftSampler>,
+        ReadStorage<'a, StarkShiftSampler>,
         WriteStorage<'a, LaserDetuningSamplers>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
@@ -129,11 +141,15 @@ impl<'a> System<'a> for CalculateLaserDetuningSystem {
             cooling_light,
             doppler_samplers,
             zeeman_sampler,
+            stark_sampler,
             mut detuning_samplers,
+            substep_mask,
         ): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), detuning_samplers.mask());
+
         // There are typically only a small number of lasers in a simulation.
         // For a speedup, cache the required components into thread memory,
         // so they can be distributed to parallel workers during the atom loop.
@@ -152,20 +168,34 @@ impl<'a> System<'a> for CalculateLaserDetuningSystem {
             let number_in_iteration = slice.len();
 
             (
+                &atoms,
                 &mut detuning_samplers,
                 &doppler_samplers,
                 &zeeman_sampler,
+                stark_sampler.maybe(),
                 &atom_info,
             )
                 .par_join()
                 .for_each(
-                    |(detuning_sampler, doppler_samplers, zeeman_sampler, atom_info)| {
+                    |(
+                        _,
+                        detuning_sampler,
+                        doppler_samplers,
+                        zeeman_sampler,
+                        stark_sampler,
+                        atom_info,
+                    )| {
+                        let stark_shift = match stark_sampler {
+                            Some(stark) => stark.shift,
+                            None => 0.0,
+                        };
                         for i in 0..number_in_iteration {
                             let (index, cooling) = laser_array[i];
                             let without_zeeman = 2.0
                                 * constant::PI
                                 * (constant::C / cooling.wavelength - atom_info.frequency)
-                                - doppler_samplers.contents[index.index].doppler_shift;
+                                - doppler_samplers.contents[index.index].doppler_shift
+                                - stark_shift;
 
                             detuning_sampler.contents[index.index].detuning_sigma_plus =
                                 without_zeeman.clone() - zeeman_sampler.sigma_plus;
@@ -196,6 +226,7 @@ pub mod tests {
         test_world.register::<LaserDetuningSamplers>();
         test_world.register::<AtomicTransition>();
         test_world.register::<ZeemanShiftSampler>();
+        test_world.register::<StarkShiftSampler>();
 
         let wavelength = constant::C / AtomicTransition::strontium().frequency;
         test_world
@@ -228,6 +259,25 @@ pub mod tests {
             })
             .build();
 
+        let atom2 = test_world
+            .create_entity()
+            .with(DopplerShiftSamplers {
+                contents: [crate::laser::doppler::DopplerShiftSampler {
+                    doppler_shift: 10.0e6, //rad/s
+                }; crate::laser::COOLING_BEAM_LIMIT],
+            })
+            .with(AtomicTransition::strontium())
+            .with(ZeemanShiftSampler {
+                sigma_plus: 0.0,
+                sigma_minus: 0.0,
+                sigma_pi: 0.0,
+            })
+            .with(StarkShiftSampler { shift: 5.0e6 })
+            .with(LaserDetuningSamplers {
+                contents: [LaserDetuningSampler::default(); crate::laser::COOLING_BEAM_LIMIT],
+            })
+            .build();
+
         let mut system = CalculateLaserDetuningSystem;
         system.run_now(&test_world);
         test_world.maintain();
@@ -261,5 +311,14 @@ pub mod tests {
             -10.0e6,
             1e-2_f64
         );
+        assert_approx_eq!(
+            sampler_storage
+                .get(atom2)
+                .expect("entity not found")
+                .contents[0]
+                .detuning_pi,
+            -10.0e6 - 5.0e6,
+            1e-2_f64
+        );
     }
 }
@@ -5,6 +5,7 @@ extern crate rayon;
 use crate::atom::AtomicTransition;
 use crate::laser::rate::RateCoefficients;
 use crate::laser::sampler::LaserSamplerMasks;
+use crate::substep::{active_atoms, SubstepMask};
 use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use std::fmt;
@@ -58,22 +59,25 @@ impl<'a> System<'a> for CalculateTwoLevelPopulationSystem {
         ReadStorage<'a, RateCoefficients>,
         ReadStorage<'a, LaserSamplerMasks>,
         WriteStorage<'a, TwoLevelPopulation>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (atomic_transition, rate_coefficients, masks, mut twolevel_population): Self::SystemData,
+        (atomic_transition, rate_coefficients, masks, mut twolevel_population, substep_mask): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), twolevel_population.mask());
         (
+            &atoms,
             &atomic_transition,
             &rate_coefficients,
             &masks,
             &mut twolevel_population,
         )
             .par_join()
-            .for_each(|(atominfo, rates, mask, twolevel)| {
+            .for_each(|(_, atominfo, rates, mask, twolevel)| {
                 let mut sum_rates: f64 = 0.;
 
                 for count in 0..rates.contents.len() {
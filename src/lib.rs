@@ -8,9 +8,12 @@ extern crate atomecs_derive;
 
 pub mod atom;
 pub mod atom_sources;
+pub mod background;
 pub mod constant;
 pub mod destructor;
 pub mod ecs;
+pub mod electric;
+pub mod field_grid;
 pub mod fileinput;
 pub mod gravity;
 pub mod initiate;
@@ -21,5 +24,8 @@ pub mod magnetic;
 pub mod maths;
 pub mod output;
 pub mod ramp;
+pub mod rng;
+pub mod run;
 pub mod shapes;
 pub mod sim_region;
+pub mod substep;
@@ -0,0 +1,574 @@
+//! Magnetic fields of current-carrying coils and wires.
+//!
+//! Each coil geometry is a component which is placed on an entity together with a `Position`,
+//! which defines the centre of the coil, and a [CoilCurrent](struct.CoilCurrent.html). The field
+//! of each coil is calculated exactly, using elliptic integrals for circular loops and solenoids
+//! and the Biot-Savart law for straight wire segments.
+//!
+//! The `CoilCurrent` implements `Lerp`, so currents can be changed during a simulation by adding
+//! a `Ramp<CoilCurrent>` to the coil entity.
+
+extern crate nalgebra;
+use crate::atom::Position;
+use crate::constant::{MU0, PI};
+use crate::magnetic::envelope::{envelope_scale, FieldEnvelope};
+use crate::magnetic::gradient::{finite_difference_jacobian, MagneticGradientSampler};
+use crate::magnetic::region::{in_region, FieldRegion};
+use crate::magnetic::MagneticFieldSampler;
+use crate::maths;
+use crate::ramp::Lerp;
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::{Matrix3, Vector3};
+use specs::{Component, HashMapStorage, Join, Read, ReadStorage, System, WriteStorage};
+use std::marker::PhantomData;
+
+/// The current flowing through the wires of a coil entity.
+#[derive(Clone, Lerp)]
+pub struct CoilCurrent {
+    /// Current in each turn of the coil, in units of Ampere.
+    ///
+    /// Positive currents circulate anticlockwise about the coil's normal, producing a field
+    /// along the normal at the coil centre.
+    pub amperes: f64,
+}
+impl Component for CoilCurrent {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// A geometry of current-carrying wire.
+pub trait Coil {
+    /// Calculates the magnetic field per unit current, in units of Tesla/Ampere.
+    ///
+    /// # Arguments
+    ///
+    /// `coil_position`: position of the coil entity, m
+    ///
+    /// `pos`: position at which to calculate the field, m
+    fn field_per_ampere(&self, coil_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64>;
+
+    /// Calculates the Jacobian of the magnetic field per unit current, `J_ij = dB_i/dx_j`, in units of Tesla/m/Ampere.
+    ///
+    /// The default implementation uses finite differences of `field_per_ampere`.
+    fn jacobian_per_ampere(
+        &self,
+        coil_position: &Vector3<f64>,
+        pos: &Vector3<f64>,
+    ) -> Matrix3<f64> {
+        finite_difference_jacobian(|p| self.field_per_ampere(coil_position, p), pos)
+    }
+}
+
+/// Splits the displacement `delta` into a component along the unit vector `axis` and
+/// the cylindrical radius and radial unit vector about it.
+fn cylindrical(delta: &Vector3<f64>, axis: &Vector3<f64>) -> (f64, f64, Vector3<f64>) {
+    let z = delta.dot(axis);
+    let radial = delta - z * axis;
+    let rho = radial.norm();
+    let e_rho = if rho > 0.0 {
+        radial / rho
+    } else {
+        Vector3::new(0.0, 0.0, 0.0)
+    };
+    (z, rho, e_rho)
+}
+
+/// Field of a single circular current loop of `radius`, per unit current.
+///
+/// The loop lies in the plane `z=0` of the cylindrical coordinates `(rho, z)`.
+/// Returns the components `(B_rho, B_z)`.
+///
+/// See Simpson et al., _Simple Analytic Expressions for the Magnetic Field of a Circular Current Loop_, NASA/TM-2013-217919.
+fn loop_field(radius: f64, rho: f64, z: f64) -> (f64, f64) {
+    let r_squared = radius.powi(2) + rho.powi(2) + z.powi(2);
+    let alpha_squared = r_squared - 2.0 * radius * rho;
+    let beta_squared = r_squared + 2.0 * radius * rho;
+    if alpha_squared <= 0.0 {
+        // on the wire, the field is singular.
+        return (0.0, 0.0);
+    }
+    let beta = beta_squared.sqrt();
+    let m = 1.0 - alpha_squared / beta_squared;
+    let k = maths::elliptic_k(m);
+    let e = maths::elliptic_e(m);
+    let prefactor = MU0 / PI / (2.0 * alpha_squared * beta);
+    let b_z = prefactor * ((radius.powi(2) - rho.powi(2) - z.powi(2)) * e + alpha_squared * k);
+    let b_rho = if rho > 0.0 {
+        prefactor * z / rho * (r_squared * e - alpha_squared * k)
+    } else {
+        0.0
+    };
+    (b_rho, b_z)
+}
+
+/// Field of a straight wire segment from `start` to `end`, per unit current flowing from `start` to `end`.
+fn segment_field(start: &Vector3<f64>, end: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+    let r1 = pos - start;
+    let r2 = pos - end;
+    let n1 = r1.norm();
+    let n2 = r2.norm();
+    let denominator = n1 * n2 * (n1 * n2 + r1.dot(&r2));
+    if denominator.abs() < std::f64::EPSILON * (n1 * n2).powi(2) {
+        // on the line of the segment, the field is zero (or singular).
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+    MU0 / (4.0 * PI) * (n1 + n2) / denominator * r1.cross(&r2)
+}
+
+/// A circular coil of wire.
+pub struct CircularCoil {
+    /// Radius of the coil, in m.
+    pub radius: f64,
+    /// A unit vector normal to the plane of the coil.
+    pub normal: Vector3<f64>,
+    /// Number of turns of wire in the coil.
+    pub turns: f64,
+}
+impl CircularCoil {
+    pub fn new(radius: f64, normal: Vector3<f64>, turns: f64) -> Self {
+        CircularCoil {
+            radius: radius,
+            normal: normal.normalize(),
+            turns: turns,
+        }
+    }
+}
+impl Component for CircularCoil {
+    type Storage = HashMapStorage<Self>;
+}
+impl Coil for CircularCoil {
+    fn field_per_ampere(&self, coil_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let (z, rho, e_rho) = cylindrical(&(pos - coil_position), &self.normal);
+        let (b_rho, b_z) = loop_field(self.radius, rho, z);
+        self.turns * (b_rho * e_rho + b_z * self.normal)
+    }
+}
+
+/// A finite solenoid, modelled as a uniform cylindrical current sheet.
+pub struct Solenoid {
+    /// Radius of the solenoid, in m.
+    pub radius: f64,
+    /// Length of the solenoid, in m.
+    pub length: f64,
+    /// A unit vector along the axis of the solenoid.
+    pub normal: Vector3<f64>,
+    /// Total number of turns of wire along the solenoid.
+    pub turns: f64,
+}
+impl Solenoid {
+    pub fn new(radius: f64, length: f64, normal: Vector3<f64>, turns: f64) -> Self {
+        Solenoid {
+            radius: radius,
+            length: length,
+            normal: normal.normalize(),
+            turns: turns,
+        }
+    }
+}
+impl Component for Solenoid {
+    type Storage = HashMapStorage<Self>;
+}
+impl Coil for Solenoid {
+    fn field_per_ampere(&self, coil_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        self.turns / self.length
+            * current_sheet_field(
+                self.radius,
+                self.length,
+                &self.normal,
+                &(pos - coil_position),
+            )
+    }
+}
+
+/// Field of a cylindrical sheet of azimuthal current, per unit surface current density (A/m).
+///
+/// The sheet is centred on the origin of `delta`, with its axis along the unit vector `axis`.
+/// The field is calculated using the expressions of Derby and Olbert, Am. J. Phys. 78, 229 (2010).
+pub(crate) fn current_sheet_field(
+    radius: f64,
+    length: f64,
+    axis: &Vector3<f64>,
+    delta: &Vector3<f64>,
+) -> Vector3<f64> {
+    let (z, rho, e_rho) = cylindrical(delta, axis);
+    let a = radius;
+    let gamma = (a - rho) / (a + rho);
+
+    let mut b_rho = 0.0;
+    let mut b_z = 0.0;
+    for (sign, z_end) in &[(1.0, z + length / 2.0), (-1.0, z - length / 2.0)] {
+        let denominator = (z_end.powi(2) + (rho + a).powi(2)).sqrt();
+        let alpha = a / denominator;
+        let beta = z_end / denominator;
+        let kc = ((z_end.powi(2) + (rho - a).powi(2)) / (z_end.powi(2) + (rho + a).powi(2))).sqrt();
+        b_rho = b_rho + sign * alpha * maths::elliptic_cel(kc, 1.0, 1.0, -1.0);
+        b_z =
+            b_z + sign * a / (a + rho) * beta * maths::elliptic_cel(kc, gamma.powi(2), 1.0, gamma);
+    }
+    MU0 / PI * (b_rho * e_rho + b_z * axis)
+}
+
+/// A rectangular coil of wire.
+pub struct RectangularCoil {
+    /// Length of the sides parallel to `width_direction`, in m.
+    pub width: f64,
+    /// Length of the sides perpendicular to `width_direction`, in m.
+    pub height: f64,
+    /// A unit vector normal to the plane of the coil.
+    pub normal: Vector3<f64>,
+    /// A unit vector in the plane of the coil, parallel to the sides of length `width`.
+    pub width_direction: Vector3<f64>,
+    /// Number of turns of wire in the coil.
+    pub turns: f64,
+}
+impl RectangularCoil {
+    pub fn new(
+        width: f64,
+        height: f64,
+        normal: Vector3<f64>,
+        width_direction: Vector3<f64>,
+        turns: f64,
+    ) -> Self {
+        let normal = normal.normalize();
+        RectangularCoil {
+            width: width,
+            height: height,
+            normal: normal,
+            width_direction: (width_direction - normal * normal.dot(&width_direction)).normalize(),
+            turns: turns,
+        }
+    }
+}
+impl Component for RectangularCoil {
+    type Storage = HashMapStorage<Self>;
+}
+impl Coil for RectangularCoil {
+    fn field_per_ampere(&self, coil_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let u = self.width_direction * self.width / 2.0;
+        let v = self.normal.cross(&self.width_direction) * self.height / 2.0;
+        let corners = [
+            coil_position - u - v,
+            coil_position + u - v,
+            coil_position + u + v,
+            coil_position - u + v,
+        ];
+        let mut field = Vector3::new(0.0, 0.0, 0.0);
+        for i in 0..4 {
+            field = field + segment_field(&corners[i], &corners[(i + 1) % 4], pos);
+        }
+        self.turns * field
+    }
+}
+
+/// A wire following an arbitrary path of straight segments.
+///
+/// Current flows from the first point towards the last. To form a closed loop, repeat the first point at the end.
+pub struct PolylineWire {
+    /// Points along the wire, relative to the position of the wire entity, in m.
+    pub points: Vec<Vector3<f64>>,
+}
+impl Component for PolylineWire {
+    type Storage = HashMapStorage<Self>;
+}
+impl Coil for PolylineWire {
+    fn field_per_ampere(&self, coil_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let mut field = Vector3::new(0.0, 0.0, 0.0);
+        for segment in self.points.windows(2) {
+            field = field
+                + segment_field(
+                    &(coil_position + segment[0]),
+                    &(coil_position + segment[1]),
+                    pos,
+                );
+        }
+        field
+    }
+}
+
+/// Whether the currents in a [CoilPair](struct.CoilPair.html) flow in the same or opposite directions.
+#[derive(Clone, Copy)]
+pub enum CoilPairConfiguration {
+    /// Currents circulate in the same direction, producing a uniform field near the centre.
+    Helmholtz,
+    /// Currents circulate in opposite directions, producing a quadrupole field near the centre.
+    AntiHelmholtz,
+}
+
+/// A pair of identical, coaxial circular coils, placed symmetrically about the entity position.
+///
+/// For the `AntiHelmholtz` configuration, the coil displaced along `+normal` carries the current
+/// `CoilCurrent`, and the coil displaced along `-normal` carries the opposite current.
+pub struct CoilPair {
+    /// Radius of each coil, in m.
+    pub radius: f64,
+    /// Distance between the planes of the two coils, in m.
+    pub separation: f64,
+    /// A unit vector along the common axis of the coils.
+    pub normal: Vector3<f64>,
+    /// Number of turns of wire in each coil.
+    pub turns: f64,
+    pub configuration: CoilPairConfiguration,
+}
+impl CoilPair {
+    /// Creates a Helmholtz pair, with coil separation equal to the coil radius.
+    pub fn helmholtz(radius: f64, normal: Vector3<f64>, turns: f64) -> Self {
+        CoilPair {
+            radius: radius,
+            separation: radius,
+            normal: normal.normalize(),
+            turns: turns,
+            configuration: CoilPairConfiguration::Helmholtz,
+        }
+    }
+
+    /// Creates an anti-Helmholtz pair with the given separation.
+    ///
+    /// A separation of `sqrt(3) * radius` gives the most uniform field gradient at the centre.
+    pub fn anti_helmholtz(radius: f64, separation: f64, normal: Vector3<f64>, turns: f64) -> Self {
+        CoilPair {
+            radius: radius,
+            separation: separation,
+            normal: normal.normalize(),
+            turns: turns,
+            configuration: CoilPairConfiguration::AntiHelmholtz,
+        }
+    }
+}
+impl Component for CoilPair {
+    type Storage = HashMapStorage<Self>;
+}
+impl Coil for CoilPair {
+    fn field_per_ampere(&self, coil_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let (z, rho, e_rho) = cylindrical(&(pos - coil_position), &self.normal);
+        let (b_rho_1, b_z_1) = loop_field(self.radius, rho, z - self.separation / 2.0);
+        let (b_rho_2, b_z_2) = loop_field(self.radius, rho, z + self.separation / 2.0);
+        let sign = match self.configuration {
+            CoilPairConfiguration::Helmholtz => 1.0,
+            CoilPairConfiguration::AntiHelmholtz => -1.0,
+        };
+        self.turns * ((b_rho_1 + sign * b_rho_2) * e_rho + (b_z_1 + sign * b_z_2) * self.normal)
+    }
+}
+
+/// Updates the values of magnetic field samplers to include the fields of coils of type `T`.
+pub struct SampleCoilFieldSystem<T: Coil> {
+    pub marker: PhantomData<T>,
+}
+impl<T: Coil> Default for SampleCoilFieldSystem<T> {
+    fn default() -> Self {
+        SampleCoilFieldSystem {
+            marker: PhantomData,
+        }
+    }
+}
+impl<'a, T> System<'a> for SampleCoilFieldSystem<T>
+where
+    T: Coil + Component + Sync,
+{
+    type SystemData = (
+        WriteStorage<'a, MagneticFieldSampler>,
+        WriteStorage<'a, MagneticGradientSampler>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, T>,
+        ReadStorage<'a, CoilCurrent>,
+        ReadStorage<'a, FieldRegion>,
+        ReadStorage<'a, FieldEnvelope>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(
+        &mut self,
+        (mut sampler, mut gradient_sampler, pos, coils, currents, regions, envelopes, substep_mask): Self::SystemData,
+    ) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for (centre, coil, current, region, envelope) in
+            (&pos, &coils, &currents, regions.maybe(), envelopes.maybe()).join()
+        {
+            let scale = envelope_scale(envelope);
+            (&atoms, &pos, &mut sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    sampler.field = sampler.field
+                        + scale * current.amperes * coil.field_per_ampere(&centre.pos, &pos.pos);
+                });
+            (&atoms, &pos, &mut gradient_sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    sampler.jacobian = sampler.jacobian
+                        + scale * current.amperes * coil.jacobian_per_ampere(&centre.pos, &pos.pos);
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use specs::{Builder, RunNow, World, WorldExt};
+
+    fn assert_vectors_close(a: Vector3<f64>, b: Vector3<f64>, tolerance: f64) {
+        assert!((a - b).norm() <= tolerance, "{:?} differs from {:?}", a, b);
+    }
+
+    /// Approximates a circular loop by a polygon with many sides.
+    fn polygon_loop(radius: f64, sides: usize) -> PolylineWire {
+        let points = (0..=sides)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f64 / sides as f64;
+                Vector3::new(radius * angle.cos(), radius * angle.sin(), 0.0)
+            })
+            .collect();
+        PolylineWire { points: points }
+    }
+
+    #[test]
+    fn test_circular_coil_field() {
+        let radius = 0.1;
+        let coil = CircularCoil::new(radius, Vector3::z(), 1.0);
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+
+        // On axis, B = mu0 I a^2 / (2 (a^2+z^2)^1.5)
+        let z = 0.05;
+        let field = coil.field_per_ampere(&centre, &Vector3::new(0.0, 0.0, z));
+        let expected = MU0 * radius.powi(2) / (2.0 * (radius.powi(2) + z.powi(2)).powf(1.5));
+        assert_vectors_close(field, Vector3::new(0.0, 0.0, expected), 1e-12 * expected);
+
+        // Off axis, compare against the Biot-Savart law for a finely discretised loop.
+        let polygon = polygon_loop(radius, 20_000);
+        for pos in &[
+            Vector3::new(0.03, 0.02, 0.04),
+            Vector3::new(0.15, -0.02, -0.03),
+            Vector3::new(-0.05, 0.0, 0.0),
+        ] {
+            let exact = coil.field_per_ampere(&centre, pos);
+            let discrete = polygon.field_per_ampere(&centre, pos);
+            assert_vectors_close(exact, discrete, 1e-6 * exact.norm());
+        }
+    }
+
+    #[test]
+    fn test_solenoid_field() {
+        let radius = 0.02;
+        let length = 0.1;
+        let turns = 200.0;
+        let solenoid = Solenoid::new(radius, length, Vector3::x(), turns);
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+
+        // On axis, B = mu0 n I / 2 * (cos(theta1) - cos(theta2))
+        let x = 0.03;
+        let field = solenoid.field_per_ampere(&centre, &Vector3::new(x, 0.0, 0.0));
+        let n = turns / length;
+        let end = |z: f64| z / (z.powi(2) + radius.powi(2)).sqrt();
+        let expected = MU0 * n / 2.0 * (end(x + length / 2.0) - end(x - length / 2.0));
+        assert_vectors_close(field, Vector3::new(expected, 0.0, 0.0), 1e-9 * expected);
+
+        // Off axis, compare against a stack of closely spaced loops.
+        let loops = 4000;
+        let pos = Vector3::new(0.01, 0.03, 0.005);
+        let mut stacked = Vector3::new(0.0, 0.0, 0.0);
+        let coil = CircularCoil::new(radius, Vector3::x(), turns / loops as f64);
+        for i in 0..loops {
+            let offset = ((i as f64 + 0.5) / loops as f64 - 0.5) * length;
+            stacked = stacked + coil.field_per_ampere(&Vector3::new(offset, 0.0, 0.0), &pos);
+        }
+        let exact = solenoid.field_per_ampere(&centre, &pos);
+        assert_vectors_close(exact, stacked, 1e-5 * exact.norm());
+    }
+
+    #[test]
+    fn test_solenoid_field_on_rim() {
+        let radius = 0.02;
+        let length = 0.1;
+        let solenoid = Solenoid::new(radius, length, Vector3::x(), 200.0);
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+
+        // The field diverges at the edge of the current sheet, but should remain finite.
+        for pos in &[
+            Vector3::new(length / 2.0, radius, 0.0),
+            Vector3::new(-length / 2.0, 0.0, -radius),
+        ] {
+            let field = solenoid.field_per_ampere(&centre, pos);
+            assert!(field.iter().all(|component| component.is_finite()));
+        }
+    }
+
+    #[test]
+    fn test_rectangular_coil_field() {
+        // At the centre of a square loop of side L, B = 2 sqrt(2) mu0 I / (pi L).
+        let side = 0.2;
+        let coil = RectangularCoil::new(side, side, Vector3::y(), Vector3::z(), 1.0);
+        let field =
+            coil.field_per_ampere(&Vector3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 0.0));
+        let expected = 2.0 * 2.0_f64.sqrt() * MU0 / (PI * side);
+        assert_vectors_close(field, Vector3::new(0.0, expected, 0.0), 1e-12 * expected);
+    }
+
+    #[test]
+    fn test_coil_pairs() {
+        let radius = 0.1;
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+
+        // Field at the centre of a Helmholtz pair is (4/5)^1.5 mu0 N I / R.
+        let helmholtz = CoilPair::helmholtz(radius, Vector3::z(), 10.0);
+        let field = helmholtz.field_per_ampere(&centre, &centre);
+        let expected = 0.8_f64.powf(1.5) * MU0 * 10.0 / radius;
+        assert_vectors_close(field, Vector3::new(0.0, 0.0, expected), 1e-12 * expected);
+
+        // The field of an anti-Helmholtz pair vanishes at the centre, and has a quadrupole
+        // form nearby with the axial gradient twice the radial gradient.
+        let anti = CoilPair::anti_helmholtz(radius, 3.0_f64.sqrt() * radius, Vector3::z(), 10.0);
+        let field = anti.field_per_ampere(&centre, &centre);
+        assert_approx_eq!(field.norm(), 0.0, 1e-18);
+        let delta = 1e-4;
+        let axial = anti.field_per_ampere(&centre, &Vector3::new(0.0, 0.0, delta))[2] / delta;
+        let radial = anti.field_per_ampere(&centre, &Vector3::new(delta, 0.0, 0.0))[0] / delta;
+        assert_approx_eq!(axial, -2.0 * radial, 1e-6 * axial.abs());
+    }
+
+    #[test]
+    fn test_sample_coil_field_system() {
+        let mut test_world = World::new();
+        test_world.register::<Position>();
+        test_world.register::<MagneticFieldSampler>();
+        test_world.register::<MagneticGradientSampler>();
+        test_world.register::<FieldRegion>();
+        test_world.register::<FieldEnvelope>();
+        test_world.register::<CircularCoil>();
+        test_world.register::<CoilCurrent>();
+
+        let radius = 0.1;
+        let current = 5.0;
+        test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 1.0),
+            })
+            .with(CircularCoil::new(radius, Vector3::z(), 2.0))
+            .with(CoilCurrent { amperes: current })
+            .build();
+
+        let sampler_entity = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 1.0),
+            })
+            .with(MagneticFieldSampler::default())
+            .build();
+
+        let mut system = SampleCoilFieldSystem::<CircularCoil>::default();
+        system.run_now(&test_world);
+
+        let samplers = test_world.read_storage::<MagneticFieldSampler>();
+        let field = samplers
+            .get(sampler_entity)
+            .expect("entity not found")
+            .field;
+        let expected = MU0 * 2.0 * current / (2.0 * radius);
+        assert_vectors_close(field, Vector3::new(0.0, 0.0, expected), 1e-12 * expected);
+    }
+}
@@ -0,0 +1,234 @@
+//! Time-dependent envelopes for magnetic field sources.
+//!
+//! A [FieldEnvelope](struct.FieldEnvelope.html) added to a field source entity scales the field
+//! of that source by a factor which varies with the simulation time. Envelopes can be used to
+//! model the switching transients of real coils, such as the exponential decay of a MOT
+//! quadrupole field when released, or to modulate a field sinusoidally.
+//!
+//! Envelopes scale every type of magnetic field source, including grids. Unlike a `Ramp`, the
+//! source component itself is unchanged; the envelope scale is applied as the field is sampled.
+
+use crate::constant::PI;
+use crate::integrator::SimulationTime;
+use specs::prelude::*;
+
+/// The time dependence of a [FieldEnvelope](struct.FieldEnvelope.html).
+///
+/// All times are in units of s.
+pub enum Envelope {
+    /// The field is switched off at `time`, and decays exponentially after a further `delay`.
+    ///
+    /// The `delay` models the finite response of the coil driver, while `time_constant` models
+    /// the decay of the field, eg due to coil inductance or eddy currents.
+    ExponentialSwitchOff {
+        time: f64,
+        delay: f64,
+        time_constant: f64,
+    },
+    /// The field is switched on at `time`, and rises exponentially after a further `delay`.
+    ExponentialSwitchOn {
+        time: f64,
+        delay: f64,
+        time_constant: f64,
+    },
+    /// The field is modulated as `offset + amplitude * sin(2 pi frequency t + phase)`.
+    ///
+    /// `frequency` is in units of Hz, and `phase` in radians.
+    Sinusoidal {
+        offset: f64,
+        amplitude: f64,
+        frequency: f64,
+        phase: f64,
+    },
+    /// The field is scaled by a user-defined function of time.
+    Function(Box<dyn Fn(f64) -> f64 + Send + Sync>),
+}
+impl Envelope {
+    /// Gets the value of the envelope at the given time, in s.
+    pub fn get_value(&self, time: f64) -> f64 {
+        match self {
+            Envelope::ExponentialSwitchOff {
+                time: switch_time,
+                delay,
+                time_constant,
+            } => {
+                let elapsed = time - switch_time - delay;
+                if elapsed < 0.0 {
+                    1.0
+                } else {
+                    (-elapsed / time_constant).exp()
+                }
+            }
+            Envelope::ExponentialSwitchOn {
+                time: switch_time,
+                delay,
+                time_constant,
+            } => {
+                let elapsed = time - switch_time - delay;
+                if elapsed < 0.0 {
+                    0.0
+                } else {
+                    1.0 - (-elapsed / time_constant).exp()
+                }
+            }
+            Envelope::Sinusoidal {
+                offset,
+                amplitude,
+                frequency,
+                phase,
+            } => offset + amplitude * (2.0 * PI * frequency * time + phase).sin(),
+            Envelope::Function(function) => function(time),
+        }
+    }
+}
+
+/// A component that scales the field of a magnetic field source with time.
+pub struct FieldEnvelope {
+    pub envelope: Envelope,
+    /// The current value of the envelope, which multiplies the field of the source.
+    pub scale: f64,
+}
+impl FieldEnvelope {
+    pub fn new(envelope: Envelope) -> Self {
+        FieldEnvelope {
+            scale: envelope.get_value(0.0),
+            envelope: envelope,
+        }
+    }
+}
+impl Component for FieldEnvelope {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Returns the scale factor of a field source with an optional envelope.
+pub fn envelope_scale(envelope: Option<&FieldEnvelope>) -> f64 {
+    match envelope {
+        Some(envelope) => envelope.scale,
+        None => 1.0,
+    }
+}
+
+/// Updates the scale of each [FieldEnvelope](struct.FieldEnvelope.html) to the current simulation time.
+pub struct UpdateFieldEnvelopeSystem;
+impl<'a> System<'a> for UpdateFieldEnvelopeSystem {
+    type SystemData = (WriteStorage<'a, FieldEnvelope>, Read<'a, SimulationTime>);
+
+    fn run(&mut self, (mut envelopes, time): Self::SystemData) {
+        let current_time = time.time;
+        for envelope in (&mut envelopes).join() {
+            envelope.scale = envelope.envelope.get_value(current_time);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_envelope_values() {
+        let off = Envelope::ExponentialSwitchOff {
+            time: 1e-3,
+            delay: 1e-4,
+            time_constant: 2e-4,
+        };
+        assert_eq!(off.get_value(0.0), 1.0);
+        assert_eq!(off.get_value(1.05e-3), 1.0);
+        assert_approx_eq!(off.get_value(1.3e-3), (-1.0_f64).exp(), 1e-12);
+
+        let on = Envelope::ExponentialSwitchOn {
+            time: 1e-3,
+            delay: 0.0,
+            time_constant: 2e-4,
+        };
+        assert_eq!(on.get_value(0.0), 0.0);
+        assert_approx_eq!(on.get_value(1.2e-3), 1.0 - (-1.0_f64).exp(), 1e-12);
+
+        let sinusoidal = Envelope::Sinusoidal {
+            offset: 1.0,
+            amplitude: 0.5,
+            frequency: 1e3,
+            phase: 0.0,
+        };
+        assert_approx_eq!(sinusoidal.get_value(0.25e-3), 1.5, 1e-12);
+        assert_approx_eq!(sinusoidal.get_value(0.75e-3), 0.5, 1e-12);
+
+        let function = Envelope::Function(Box::new(|t| 2.0 * t));
+        assert_eq!(function.get_value(3.0), 6.0);
+    }
+
+    #[test]
+    fn test_update_field_envelope_system() {
+        let mut test_world = World::new();
+        test_world.register::<FieldEnvelope>();
+        test_world.insert(SimulationTime { time: 5e-4 });
+
+        let source = test_world
+            .create_entity()
+            .with(FieldEnvelope::new(Envelope::Function(Box::new(|t| t))))
+            .build();
+
+        let mut system = UpdateFieldEnvelopeSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let envelopes = test_world.read_storage::<FieldEnvelope>();
+        assert_approx_eq!(
+            envelopes.get(source).expect("entity not found").scale,
+            5e-4,
+            1e-12
+        );
+    }
+
+    #[test]
+    fn test_envelope_scales_grid_field() {
+        use crate::atom::Position;
+        use crate::magnetic::gradient::MagneticGradientSampler;
+        use crate::magnetic::grid::{PrecalculatedMagneticFieldGrid, SampleMagneticGridSystem};
+        use crate::magnetic::region::FieldRegion;
+        use crate::magnetic::MagneticFieldSampler;
+        use nalgebra::Vector3;
+
+        let mut test_world = World::new();
+        test_world.register::<Position>();
+        test_world.register::<PrecalculatedMagneticFieldGrid>();
+        test_world.register::<FieldRegion>();
+        test_world.register::<FieldEnvelope>();
+        test_world.register::<MagneticFieldSampler>();
+        test_world.register::<MagneticGradientSampler>();
+
+        let mut envelope = FieldEnvelope::new(Envelope::ExponentialSwitchOff {
+            time: 0.0,
+            delay: 0.0,
+            time_constant: 1e-3,
+        });
+        envelope.scale = 0.5;
+        test_world
+            .create_entity()
+            .with(PrecalculatedMagneticFieldGrid::new(
+                Vector3::new(1.0, 1.0, 1.0),
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1, 1, 1),
+                vec![Vector3::new(1e-3, 0.0, 0.0)],
+            ))
+            .with(envelope)
+            .build();
+        let atom = test_world
+            .create_entity()
+            .with(Position::new())
+            .with(MagneticFieldSampler::default())
+            .build();
+
+        let mut system = SampleMagneticGridSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let samplers = test_world.read_storage::<MagneticFieldSampler>();
+        assert_eq!(
+            samplers.get(atom).expect("entity not found").field,
+            Vector3::new(0.5e-3, 0.0, 0.0)
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Forces on atoms due to magnetic field gradients.
+//!
+//! An atom in the magnetic sublevel `m_F` has potential energy `U = g_F m_F mu_B |B|` in the
+//! magnetic field, and so experiences a force `F = -g_F m_F mu_B grad |B|`. This force enables
+//! simulations of magnetic traps, magnetic transport and Stern-Gerlach separation.
+//!
+//! Only atoms with a [MagneticDipole](struct.MagneticDipole.html) component experience the force.
+//! A [MagneticGradientSampler](../gradient/struct.MagneticGradientSampler.html) is attached to
+//! these atoms so that the field gradient is calculated at their location.
+
+extern crate nalgebra;
+use crate::atom::Force;
+use crate::constant;
+use crate::magnetic::gradient::MagneticGradientSampler;
+use crate::substep::{active_atoms, SubstepMask};
+use specs::prelude::*;
+
+/// A component that describes the magnetic moment of an atom in a particular magnetic sublevel.
+#[derive(Clone, Copy)]
+pub struct MagneticDipole {
+    /// The product of the Landé g-factor and magnetic quantum number, `g_F m_F`.
+    ///
+    /// Atoms with positive values are low-field seekers, which can be trapped at a field minimum.
+    pub mf_gf: f64,
+
+    /// The Landé g-factor `g_F`, which determines the Larmor frequency of the atom.
+    pub gf: f64,
+}
+impl Component for MagneticDipole {
+    type Storage = VecStorage<Self>;
+}
+
+/// Attaches a [MagneticGradientSampler](../gradient/struct.MagneticGradientSampler.html) to all entities
+/// which have a [MagneticDipole](struct.MagneticDipole.html) but no gradient sampler.
+pub struct AttachGradientSamplersToMagneticDipolesSystem;
+impl<'a> System<'a> for AttachGradientSamplersToMagneticDipolesSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, MagneticDipole>,
+        ReadStorage<'a, MagneticGradientSampler>,
+        Read<'a, LazyUpdate>,
+    );
+    fn run(&mut self, (ent, dipoles, samplers, updater): Self::SystemData) {
+        for (ent, _dipole, _) in (&ent, &dipoles, !&samplers).join() {
+            updater.insert(ent, MagneticGradientSampler::default());
+        }
+    }
+}
+
+/// This system adds the magnetic dipole force to all entities with a [MagneticDipole](struct.MagneticDipole.html).
+pub struct ApplyMagneticForceSystem;
+impl<'a> System<'a> for ApplyMagneticForceSystem {
+    type SystemData = (
+        WriteStorage<'a, Force>,
+        ReadStorage<'a, MagneticDipole>,
+        ReadStorage<'a, MagneticGradientSampler>,
+        Option<Read<'a, SubstepMask>>,
+    );
+
+    fn run(&mut self, (mut force, dipoles, gradients, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+
+        let atoms = active_atoms(substep_mask.as_deref(), force.mask());
+        (&atoms, &mut force, &dipoles, &gradients)
+            .par_join()
+            .for_each(|(_, force, dipole, gradient)| {
+                force.force =
+                    force.force - dipole.mf_gf * constant::BOHRMAG * gradient.magnitude_gradient;
+            });
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::atom::Position;
+    use crate::magnetic::quadrupole::QuadrupoleField3D;
+    use crate::magnetic::MagneticFieldSampler;
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_apply_magnetic_force_system() {
+        let mut test_world = World::new();
+        test_world.register::<Force>();
+        test_world.register::<MagneticDipole>();
+        test_world.register::<MagneticGradientSampler>();
+
+        let atom = test_world
+            .create_entity()
+            .with(Force::new())
+            .with(MagneticDipole {
+                mf_gf: 0.5,
+                gf: 0.5,
+            })
+            .with(MagneticGradientSampler {
+                jacobian: nalgebra::Matrix3::zeros(),
+                magnitude_gradient: Vector3::new(0.0, 0.2, 0.0),
+            })
+            .build();
+
+        let mut system = ApplyMagneticForceSystem;
+        system.run_now(&test_world);
+
+        let forces = test_world.read_storage::<Force>();
+        let force = forces.get(atom).expect("entity not found").force;
+        assert_approx_eq!(force[1], -0.1 * constant::BOHRMAG, 1e-30);
+        assert_eq!(force[0], 0.0);
+        assert_eq!(force[2], 0.0);
+    }
+
+    /// A low-field seeking atom displaced from the centre of a quadrupole trap is pushed back towards the centre.
+    #[test]
+    fn test_force_in_quadrupole_trap() {
+        use crate::initiate::NewlyCreated;
+        use crate::integrator::{Step, Timestep, VelocityVerletIntegratePositionSystem};
+        use crate::magnetic;
+
+        let mut test_world = World::new();
+        magnetic::register_components(&mut test_world);
+        test_world.register::<NewlyCreated>();
+        test_world.register::<Force>();
+        test_world.register::<magnetic::zeeman::ZeemanShiftSampler>();
+        let mut builder = DispatcherBuilder::new();
+        builder.add(
+            VelocityVerletIntegratePositionSystem {},
+            crate::integrator::INTEGRATE_POSITION_SYSTEM_NAME,
+            &[],
+        );
+        magnetic::add_systems_to_dispatch(&mut builder, &[]);
+        builder.add(
+            ApplyMagneticForceSystem,
+            "magnetic_force",
+            &["magnetics_magnitude_gradient"],
+        );
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut test_world);
+        test_world.insert(Step { n: 0 });
+        test_world.insert(Timestep { delta: 1.0e-6 });
+
+        let gradient = 20.0;
+        test_world
+            .create_entity()
+            .with(QuadrupoleField3D::gauss_per_cm(gradient, Vector3::z()))
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 0.0),
+            })
+            .build();
+
+        let mf_gf = 1.0;
+        let atom = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 1.0e-3),
+            })
+            .with(Force::new())
+            .with(MagneticDipole {
+                mf_gf: mf_gf,
+                gf: 0.5,
+            })
+            .with(MagneticFieldSampler::default())
+            .build();
+
+        // The first dispatch attaches the gradient sampler, the second calculates the force.
+        dispatcher.dispatch(&mut test_world);
+        test_world.maintain();
+        dispatcher.dispatch(&mut test_world);
+        test_world.maintain();
+
+        let forces = test_world.read_storage::<Force>();
+        let force = forces.get(atom).expect("entity not found").force;
+        let expected = -mf_gf * constant::BOHRMAG * 2.0 * gradient * 0.01;
+        assert_approx_eq!(force[2], expected, 1e-6 * expected.abs());
+        assert_approx_eq!(force[0], 0.0, 1e-30);
+    }
+}
@@ -0,0 +1,193 @@
+//! Spatial derivatives of the magnetic field.
+//!
+//! The [MagneticGradientSampler](struct.MagneticGradientSampler.html) stores the Jacobian of the
+//! magnetic field at an entity's location. Each field system adds its contribution to the
+//! Jacobian alongside its contribution to the field, in the same way as for the
+//! [MagneticFieldSampler](../struct.MagneticFieldSampler.html). Uniform fields do not contribute.
+//!
+//! Gradient samplers are optional, because calculating the Jacobian adds to the cost of each step.
+//! They are attached automatically to atoms that experience a magnetic force. To sample the
+//! gradient for all atoms, _eg_ for diagnostics, add the
+//! [SampleMagneticGradientOption](struct.SampleMagneticGradientOption.html) resource to the world.
+
+extern crate nalgebra;
+use crate::initiate::NewlyCreated;
+use crate::magnetic::MagneticFieldSampler;
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::{Matrix3, Vector3};
+use specs::{
+    Component, Entities, Join, LazyUpdate, Read, ReadStorage, System, VecStorage, WriteStorage,
+};
+
+/// Step size used to calculate Jacobians by finite differences, in m.
+pub const FINITE_DIFFERENCE_STEP: f64 = 1.0e-6;
+
+/// A component that stores the spatial derivatives of the magnetic field at an entity's location.
+#[derive(Copy, Clone)]
+pub struct MagneticGradientSampler {
+    /// Jacobian of the magnetic field, `J_ij = dB_i/dx_j`, in units of Tesla/m.
+    pub jacobian: Matrix3<f64>,
+
+    /// Gradient of the magnitude of the magnetic field, in units of Tesla/m.
+    pub magnitude_gradient: Vector3<f64>,
+}
+impl Default for MagneticGradientSampler {
+    fn default() -> Self {
+        MagneticGradientSampler {
+            jacobian: Matrix3::zeros(),
+            magnitude_gradient: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+impl Component for MagneticGradientSampler {
+    type Storage = VecStorage<Self>;
+}
+
+/// Calculates the Jacobian `J_ij = dB_i/dx_j` of the field `field` at `pos` using central differences.
+pub fn finite_difference_jacobian<F>(field: F, pos: &Vector3<f64>) -> Matrix3<f64>
+where
+    F: Fn(&Vector3<f64>) -> Vector3<f64>,
+{
+    let mut jacobian = Matrix3::zeros();
+    for j in 0..3 {
+        let mut step = Vector3::new(0.0, 0.0, 0.0);
+        step[j] = FINITE_DIFFERENCE_STEP;
+        let derivative =
+            (field(&(pos + step)) - field(&(pos - step))) / (2.0 * FINITE_DIFFERENCE_STEP);
+        jacobian.set_column(j, &derivative);
+    }
+    jacobian
+}
+
+/// A resource that indicates that magnetic gradient samplers should be attached to all newly created atoms.
+pub struct SampleMagneticGradientOption;
+
+/// Attaches the [MagneticGradientSampler](struct.MagneticGradientSampler.html) component to newly created atoms,
+/// if the [SampleMagneticGradientOption](struct.SampleMagneticGradientOption.html) resource is present.
+pub struct AttachGradientSamplersToNewlyCreatedAtomsSystem;
+impl<'a> System<'a> for AttachGradientSamplersToNewlyCreatedAtomsSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, NewlyCreated>,
+        Option<Read<'a, SampleMagneticGradientOption>>,
+        Read<'a, LazyUpdate>,
+    );
+    fn run(&mut self, (ent, newly_created, option, updater): Self::SystemData) {
+        match option {
+            None => (),
+            Some(_) => {
+                for (ent, _nc) in (&ent, &newly_created).join() {
+                    updater.insert(ent, MagneticGradientSampler::default());
+                }
+            }
+        }
+    }
+}
+
+/// System that clears the magnetic gradient samplers each frame.
+pub struct ClearMagneticGradientSamplerSystem;
+impl<'a> System<'a> for ClearMagneticGradientSamplerSystem {
+    type SystemData = (
+        WriteStorage<'a, MagneticGradientSampler>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut sampler, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), sampler.mask());
+        (&atoms, &mut sampler).par_join().for_each(|(_, sampler)| {
+            sampler.jacobian = Matrix3::zeros();
+            sampler.magnitude_gradient = Vector3::new(0.0, 0.0, 0.0);
+        });
+    }
+}
+
+/// System that calculates the gradient of the magnetic field magnitude, `grad |B| = J^T B / |B|`.
+///
+/// This system runs after the field magnitude has been calculated.
+pub struct CalculateMagnitudeGradientSystem;
+impl<'a> System<'a> for CalculateMagnitudeGradientSystem {
+    type SystemData = (
+        WriteStorage<'a, MagneticGradientSampler>,
+        ReadStorage<'a, MagneticFieldSampler>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(&mut self, (mut gradients, fields, substep_mask): Self::SystemData) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), gradients.mask());
+        (&atoms, &mut gradients, &fields)
+            .par_join()
+            .for_each(|(_, gradient, field)| {
+                gradient.magnitude_gradient = if field.magnitude > 0.0 {
+                    gradient.jacobian.transpose() * field.field / field.magnitude
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                };
+            });
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use specs::{Builder, RunNow, World, WorldExt};
+
+    #[test]
+    fn test_finite_difference_jacobian() {
+        let field =
+            |pos: &Vector3<f64>| Vector3::new(pos[0] * pos[1], pos[2].powi(2), 3.0 * pos[0]);
+        let jacobian = finite_difference_jacobian(field, &Vector3::new(1.0, 2.0, 3.0));
+        let expected = Matrix3::new(2.0, 1.0, 0.0, 0.0, 0.0, 6.0, 3.0, 0.0, 0.0);
+        assert!((jacobian - expected).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_magnitude_gradient() {
+        let mut test_world = World::new();
+        test_world.register::<MagneticGradientSampler>();
+        test_world.register::<MagneticFieldSampler>();
+
+        // For B = (x, 0, 0) evaluated at x = -2, |B| decreases with x.
+        let entity = test_world
+            .create_entity()
+            .with(MagneticFieldSampler::tesla(Vector3::new(-2.0, 0.0, 0.0)))
+            .with(MagneticGradientSampler {
+                jacobian: Matrix3::new(1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0),
+                magnitude_gradient: Vector3::new(0.0, 0.0, 0.0),
+            })
+            .build();
+
+        let mut system = CalculateMagnitudeGradientSystem;
+        system.run_now(&test_world);
+
+        let samplers = test_world.read_storage::<MagneticGradientSampler>();
+        let gradient = samplers.get(entity).expect("entity not found");
+        assert_eq!(gradient.magnitude_gradient, Vector3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_gradient_samplers_are_optional() {
+        let mut test_world = World::new();
+        test_world.register::<MagneticGradientSampler>();
+        test_world.register::<NewlyCreated>();
+
+        let atom = test_world.create_entity().with(NewlyCreated).build();
+        let mut system = AttachGradientSamplersToNewlyCreatedAtomsSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+        assert!(!test_world
+            .read_storage::<MagneticGradientSampler>()
+            .contains(atom));
+
+        test_world.insert(SampleMagneticGradientOption);
+        system.run_now(&test_world);
+        test_world.maintain();
+        assert!(test_world
+            .read_storage::<MagneticGradientSampler>()
+            .contains(atom));
+    }
+}
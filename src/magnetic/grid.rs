@@ -2,80 +2,61 @@
 
 extern crate nalgebra;
 use crate::atom::Position;
+use crate::field_grid::FieldGrid;
+pub use crate::field_grid::GridInterpolation;
+use crate::magnetic::envelope::{envelope_scale, FieldEnvelope};
+use crate::magnetic::gradient::MagneticGradientSampler;
+use crate::magnetic::region::{in_region, FieldRegion};
 use crate::magnetic::MagneticFieldSampler;
-use nalgebra::Vector3;
-use specs::{Component, HashMapStorage, Join, ReadStorage, System, WriteStorage};
-extern crate serde;
-use serde::{Deserialize, Serialize};
+use crate::substep::{active_atoms, SubstepMask};
+use specs::{Join, Read, ReadStorage, System, WriteStorage};
 
-/// Defines a magnetic field using a grid-based representation.
-///
-/// The grid is ordered as a linear array, with elements ordered in priority z,y,x;
-/// items with dz=1 are adjacent in memory.
-///
-/// # Fields
-///
-/// `extent_spatial`: Size of the grid, in units of m.
-///
-/// `position`: Position of the grid center, in units of m.
-///
-/// `extent_cells`: Size of the grid in cells, along the (x,y,z) axes.
-///
-/// `grid`: `Vec<Vector3<f64>>` containing the field at each grid cell.
-#[derive(Serialize, Deserialize)]
-pub struct PrecalculatedMagneticFieldGrid {
-    pub extent_spatial: Vector3<f64>,
-    pub position: Vector3<f64>,
-    pub extent_cells: Vector3<i32>,
-    pub grid: Vec<Vector3<f64>>,
-}
-
-impl PrecalculatedMagneticFieldGrid {
-    pub fn position_to_grid_index(&self, pos: &Vector3<f64>) -> i32 {
-        let delta = pos - (self.position - self.extent_spatial / 2.0);
-        let fraction = delta.component_div(&self.extent_spatial);
-        // calculate cell ids
-        let cell_id = Vector3::new(
-            ((fraction[0] * self.extent_cells[0] as f64) as i32)
-                .max(0)
-                .min(self.extent_cells[0] - 1),
-            ((fraction[1] * self.extent_cells[1] as f64) as i32)
-                .max(0)
-                .min(self.extent_cells[1] - 1),
-            ((fraction[2] * self.extent_cells[2] as f64) as i32)
-                .max(0)
-                .min(self.extent_cells[2] - 1),
-        );
-        return self.extent_cells[2] as i32
-            * (self.extent_cells[1] as i32 * (cell_id[0] as i32) + (cell_id[1] as i32))
-            + (cell_id[2] as i32);
-    }
-
-    pub fn get_field(&self, pos: &Vector3<f64>) -> Vector3<f64> {
-        let index = self.position_to_grid_index(&pos);
-        return self.grid[index as usize];
-    }
-}
+/// Marks a [FieldGrid](../../field_grid/struct.FieldGrid.html) which holds a magnetic field.
+pub struct Magnetic;
 
-impl Component for PrecalculatedMagneticFieldGrid {
-    type Storage = HashMapStorage<Self>;
-}
+/// Defines a magnetic field using a grid-based representation, with the field in each cell in units of T.
+///
+/// See [FieldGrid](../../field_grid/struct.FieldGrid.html) for the layout of the grid.
+pub type PrecalculatedMagneticFieldGrid = FieldGrid<Magnetic>;
 
 /// Samples from the MagneticFieldGrid at a `Position` and stores
 /// result in `MagneticFieldSampler`
+///
+/// Grids with a [FieldRegion](../region/struct.FieldRegion.html) are only sampled for atoms
+/// within the region, which is centred on the grid's `position`.
 pub struct SampleMagneticGridSystem;
 impl<'a> System<'a> for SampleMagneticGridSystem {
     type SystemData = (
         WriteStorage<'a, MagneticFieldSampler>,
+        WriteStorage<'a, MagneticGradientSampler>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, PrecalculatedMagneticFieldGrid>,
+        ReadStorage<'a, FieldRegion>,
+        ReadStorage<'a, FieldEnvelope>,
+        Option<Read<'a, SubstepMask>>,
     );
-    fn run(&mut self, (mut sampler, pos, grids): Self::SystemData) {
-        for grid in (&grids).join() {
-            for (pos, mut sampler) in (&pos, &mut sampler).join() {
-                let field = grid.get_field(&pos.pos);
-                sampler.field = sampler.field + field;
-            }
+    fn run(
+        &mut self,
+        (mut sampler, mut gradient_sampler, pos, grids, regions, envelopes, substep_mask): Self::SystemData,
+    ) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for (grid, region, envelope) in (&grids, regions.maybe(), envelopes.maybe()).join() {
+            let scale = envelope_scale(envelope);
+            (&atoms, &pos, &mut sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &grid.position, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    sampler.field = sampler.field + scale * grid.get_field(&pos.pos);
+                });
+            (&atoms, &pos, &mut gradient_sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &grid.position, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    sampler.jacobian = sampler.jacobian + scale * grid.get_jacobian(&pos.pos);
+                });
         }
     }
 }
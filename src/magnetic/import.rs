@@ -0,0 +1,659 @@
+//! Import magnetic field maps exported from field solvers.
+//!
+//! Coil and magnet design tools (eg COMSOL, Radia, Magpylib) can export fields as tables of
+//! `x, y, z, Bx, By, Bz` values. This module reads such tables and converts them into a
+//! [PrecalculatedMagneticFieldGrid](../grid/struct.PrecalculatedMagneticFieldGrid.html).
+//!
+//! Tables may be comma, semicolon, tab or whitespace delimited. Lines beginning with `%`, `#`
+//! or `//` are treated as comments. The columns may appear in any order and use any length
+//! and field units, see [FieldMapReader](struct.FieldMapReader.html).
+//!
+//! If the points form a regular grid they are used directly. Otherwise the points are
+//! resampled onto a regular grid using inverse-distance weighting, which requires the
+//! resolution of the grid to be given with `FieldMapReader::with_resampling`.
+
+extern crate nalgebra;
+use crate::magnetic::grid::PrecalculatedMagneticFieldGrid;
+use nalgebra::Vector3;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Identifies a column of the field map table.
+#[derive(Clone, Debug)]
+pub enum ColumnSelector {
+    /// Zero-based index of the column.
+    Index(usize),
+    /// Name of the column as it appears in the table header. Matching is case-insensitive.
+    Name(String),
+}
+
+/// Locations of the position and field components in the field map table.
+#[derive(Clone, Debug)]
+pub struct FieldMapColumns {
+    pub x: ColumnSelector,
+    pub y: ColumnSelector,
+    pub z: ColumnSelector,
+    pub bx: ColumnSelector,
+    pub by: ColumnSelector,
+    pub bz: ColumnSelector,
+}
+impl FieldMapColumns {
+    /// Selects columns by their index, in the order `[x, y, z, Bx, By, Bz]`.
+    pub fn indices(indices: [usize; 6]) -> Self {
+        FieldMapColumns {
+            x: ColumnSelector::Index(indices[0]),
+            y: ColumnSelector::Index(indices[1]),
+            z: ColumnSelector::Index(indices[2]),
+            bx: ColumnSelector::Index(indices[3]),
+            by: ColumnSelector::Index(indices[4]),
+            bz: ColumnSelector::Index(indices[5]),
+        }
+    }
+
+    /// Selects columns by their header names, in the order `[x, y, z, Bx, By, Bz]`.
+    pub fn named(names: [&str; 6]) -> Self {
+        FieldMapColumns {
+            x: ColumnSelector::Name(names[0].to_string()),
+            y: ColumnSelector::Name(names[1].to_string()),
+            z: ColumnSelector::Name(names[2].to_string()),
+            bx: ColumnSelector::Name(names[3].to_string()),
+            by: ColumnSelector::Name(names[4].to_string()),
+            bz: ColumnSelector::Name(names[5].to_string()),
+        }
+    }
+
+    fn selectors(&self) -> [&ColumnSelector; 6] {
+        [&self.x, &self.y, &self.z, &self.bx, &self.by, &self.bz]
+    }
+}
+impl Default for FieldMapColumns {
+    /// Columns ordered `x, y, z, Bx, By, Bz`.
+    fn default() -> Self {
+        FieldMapColumns::indices([0, 1, 2, 3, 4, 5])
+    }
+}
+
+/// Unit of length used for positions in the field map.
+#[derive(Clone, Copy, Debug)]
+pub enum LengthUnit {
+    Metre,
+    Centimetre,
+    Millimetre,
+    /// Length of one unit, in metres.
+    Custom(f64),
+}
+impl LengthUnit {
+    /// Length of one unit, in metres.
+    pub fn in_metres(&self) -> f64 {
+        match self {
+            LengthUnit::Metre => 1.0,
+            LengthUnit::Centimetre => 1.0e-2,
+            LengthUnit::Millimetre => 1.0e-3,
+            LengthUnit::Custom(scale) => *scale,
+        }
+    }
+}
+
+/// Unit of magnetic field used in the field map.
+#[derive(Clone, Copy, Debug)]
+pub enum FieldUnit {
+    Tesla,
+    Millitesla,
+    Gauss,
+    /// Field of one unit, in Tesla.
+    Custom(f64),
+}
+impl FieldUnit {
+    /// Field of one unit, in Tesla.
+    pub fn in_tesla(&self) -> f64 {
+        match self {
+            FieldUnit::Tesla => 1.0,
+            FieldUnit::Millitesla => 1.0e-3,
+            FieldUnit::Gauss => 1.0e-4,
+            FieldUnit::Custom(scale) => *scale,
+        }
+    }
+}
+
+/// Errors that can occur when importing a field map.
+#[derive(Debug)]
+pub enum FieldMapError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A line of the table could not be parsed.
+    Parse { line: usize, message: String },
+    /// A named column could not be found in the table header.
+    MissingColumn(String),
+    /// The table contains no data points.
+    Empty,
+    /// The points do not form a regular grid, and no resampling resolution was specified.
+    NotAGrid,
+}
+impl fmt::Display for FieldMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldMapError::Io(error) => write!(f, "could not read field map: {}", error),
+            FieldMapError::Parse { line, message } => {
+                write!(f, "could not parse field map at line {}: {}", line, message)
+            }
+            FieldMapError::MissingColumn(name) => {
+                write!(f, "column '{}' not found in field map header", name)
+            }
+            FieldMapError::Empty => write!(f, "field map contains no data"),
+            FieldMapError::NotAGrid => write!(
+                f,
+                "field map points do not form a regular grid; specify a resampling resolution"
+            ),
+        }
+    }
+}
+impl Error for FieldMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FieldMapError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for FieldMapError {
+    fn from(error: io::Error) -> Self {
+        FieldMapError::Io(error)
+    }
+}
+
+/// A single tabulated sample of the field, in SI units.
+#[derive(Clone, Copy)]
+pub struct FieldMapPoint {
+    /// Position of the sample, in m.
+    pub position: Vector3<f64>,
+    /// Magnetic field at the sample position, in T.
+    pub field: Vector3<f64>,
+}
+
+/// Reads tabulated field maps and converts them to a
+/// [PrecalculatedMagneticFieldGrid](../grid/struct.PrecalculatedMagneticFieldGrid.html).
+///
+/// For example, to read a COMSOL export with positions in mm and field in Gauss:
+/// ```no_run
+/// # use atomecs::magnetic::import::*;
+/// let grid = FieldMapReader::new()
+///     .with_columns(FieldMapColumns::named(["x", "y", "z", "mf.Bx", "mf.By", "mf.Bz"]))
+///     .with_length_unit(LengthUnit::Millimetre)
+///     .with_field_unit(FieldUnit::Gauss)
+///     .read_file("coils.txt")
+///     .expect("Could not import field map");
+/// ```
+pub struct FieldMapReader {
+    columns: FieldMapColumns,
+    length_unit: LengthUnit,
+    field_unit: FieldUnit,
+    resample_cells: Option<Vector3<i32>>,
+}
+impl FieldMapReader {
+    pub fn new() -> Self {
+        FieldMapReader {
+            columns: FieldMapColumns::default(),
+            length_unit: LengthUnit::Metre,
+            field_unit: FieldUnit::Tesla,
+            resample_cells: None,
+        }
+    }
+
+    pub fn with_columns(&mut self, columns: FieldMapColumns) -> &mut Self {
+        self.columns = columns;
+        self
+    }
+
+    pub fn with_length_unit(&mut self, unit: LengthUnit) -> &mut Self {
+        self.length_unit = unit;
+        self
+    }
+
+    pub fn with_field_unit(&mut self, unit: FieldUnit) -> &mut Self {
+        self.field_unit = unit;
+        self
+    }
+
+    /// Resample points that do not lie on a regular grid onto a grid with the given number of cells along x,y,z.
+    pub fn with_resampling(&mut self, cells: Vector3<i32>) -> &mut Self {
+        self.resample_cells = Some(cells);
+        self
+    }
+
+    /// Reads a field map from the file at `path`.
+    pub fn read_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<PrecalculatedMagneticFieldGrid, FieldMapError> {
+        let file = File::open(path)?;
+        self.read(BufReader::new(file))
+    }
+
+    /// Reads a field map from the given `reader`.
+    pub fn read<R: BufRead>(
+        &self,
+        reader: R,
+    ) -> Result<PrecalculatedMagneticFieldGrid, FieldMapError> {
+        let points = self.read_points(reader)?;
+        match regular_grid(&points) {
+            Some(grid) => Ok(grid),
+            None => match self.resample_cells {
+                Some(cells) => Ok(resample(&points, cells)),
+                None => Err(FieldMapError::NotAGrid),
+            },
+        }
+    }
+
+    /// Reads the tabulated points from the given `reader`, converted to SI units.
+    pub fn read_points<R: BufRead>(&self, reader: R) -> Result<Vec<FieldMapPoint>, FieldMapError> {
+        let length_scale = self.length_unit.in_metres();
+        let field_scale = self.field_unit.in_tesla();
+
+        let mut header: Option<Vec<String>> = None;
+        let mut indices: Option<[usize; 6]> = None;
+        let mut points = Vec::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = i + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            // Comment lines may hold the header, eg for COMSOL exports.
+            if let Some(comment) = strip_comment(trimmed) {
+                if points.is_empty() && !comment.trim().is_empty() {
+                    header = Some(split_header(comment));
+                }
+                continue;
+            }
+
+            let tokens = split_line(trimmed);
+            let values: Result<Vec<f64>, _> = tokens.iter().map(|t| t.parse::<f64>()).collect();
+            let values = match values {
+                Ok(values) => values,
+                Err(_) => {
+                    if points.is_empty() && indices.is_none() {
+                        header = Some(split_header(trimmed));
+                        continue;
+                    }
+                    return Err(FieldMapError::Parse {
+                        line: line_number,
+                        message: format!("expected numeric values, found '{}'", trimmed),
+                    });
+                }
+            };
+
+            if indices.is_none() {
+                indices = Some(self.resolve_columns(&header)?);
+            }
+            let indices = indices.unwrap();
+            let get = |index: usize| -> Result<f64, FieldMapError> {
+                values.get(index).copied().ok_or(FieldMapError::Parse {
+                    line: line_number,
+                    message: format!(
+                        "expected at least {} columns, found {}",
+                        index + 1,
+                        values.len()
+                    ),
+                })
+            };
+
+            let position = Vector3::new(get(indices[0])?, get(indices[1])?, get(indices[2])?);
+            if !position.iter().all(|coordinate| coordinate.is_finite()) {
+                return Err(FieldMapError::Parse {
+                    line: line_number,
+                    message: format!("coordinates must be finite, found '{}'", trimmed),
+                });
+            }
+            points.push(FieldMapPoint {
+                position: position * length_scale,
+                field: Vector3::new(get(indices[3])?, get(indices[4])?, get(indices[5])?)
+                    * field_scale,
+            });
+        }
+
+        if points.is_empty() {
+            return Err(FieldMapError::Empty);
+        }
+        Ok(points)
+    }
+
+    /// Converts the column selectors into indices, using the header if required.
+    fn resolve_columns(&self, header: &Option<Vec<String>>) -> Result<[usize; 6], FieldMapError> {
+        let mut indices = [0; 6];
+        for (i, selector) in self.columns.selectors().iter().enumerate() {
+            indices[i] = match selector {
+                ColumnSelector::Index(index) => *index,
+                ColumnSelector::Name(name) => header
+                    .as_ref()
+                    .and_then(|h| h.iter().position(|c| c.eq_ignore_ascii_case(name)))
+                    .ok_or(FieldMapError::MissingColumn(name.clone()))?,
+            };
+        }
+        Ok(indices)
+    }
+}
+impl Default for FieldMapReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn strip_comment(line: &str) -> Option<&str> {
+    for prefix in &["%", "#", "//"] {
+        if line.starts_with(prefix) {
+            return Some(&line[prefix.len()..]);
+        }
+    }
+    None
+}
+
+fn split_line(line: &str) -> Vec<&str> {
+    line.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Splits a header line into column names.
+///
+/// Unit annotations such as `(T)` or `[mm]` that follow a column name are discarded.
+fn split_header(line: &str) -> Vec<String> {
+    split_line(line)
+        .into_iter()
+        .filter(|s| !(s.starts_with('(') || s.starts_with('[')))
+        .map(|s| s.trim_matches('"').to_string())
+        .collect()
+}
+
+/// Sorted, distinct coordinates of the points along the given axis.
+fn distinct_coordinates(points: &[FieldMapPoint], axis: usize) -> Vec<f64> {
+    let mut values: Vec<f64> = points.iter().map(|p| p.position[axis]).collect();
+    values.sort_by(|a, b| a.total_cmp(b));
+    let tolerance = 1.0e-9 * (values[values.len() - 1] - values[0]).abs().max(1.0e-12);
+    let mut distinct: Vec<f64> = Vec::new();
+    for value in values {
+        match distinct.last() {
+            Some(last) if (value - last).abs() <= tolerance => (),
+            _ => distinct.push(value),
+        }
+    }
+    distinct
+}
+
+/// Attempts to interpret the points as a complete, evenly spaced grid.
+fn regular_grid(points: &[FieldMapPoint]) -> Option<PrecalculatedMagneticFieldGrid> {
+    let axes: Vec<Vec<f64>> = (0..3)
+        .map(|axis| distinct_coordinates(points, axis))
+        .collect();
+    let cells = Vector3::new(axes[0].len(), axes[1].len(), axes[2].len());
+    if cells[0] * cells[1] * cells[2] != points.len() {
+        return None;
+    }
+
+    let mut spacing = Vector3::new(0.0, 0.0, 0.0);
+    for axis in 0..3 {
+        let values = &axes[axis];
+        if values.len() < 2 {
+            continue;
+        }
+        let step = (values[values.len() - 1] - values[0]) / (values.len() - 1) as f64;
+        for pair in values.windows(2) {
+            if ((pair[1] - pair[0]) - step).abs() > 1.0e-6 * step {
+                return None;
+            }
+        }
+        spacing[axis] = step;
+    }
+    // Axes with a single plane of points take the spacing of the other axes; the field is
+    // constant along them anyway.
+    let fallback = spacing.max();
+    let fallback = if fallback > 0.0 { fallback } else { 1.0 };
+    for axis in 0..3 {
+        if spacing[axis] == 0.0 {
+            spacing[axis] = fallback;
+        }
+    }
+
+    let minimum = Vector3::new(axes[0][0], axes[1][0], axes[2][0]);
+    let extent_cells = Vector3::new(cells[0] as i32, cells[1] as i32, cells[2] as i32);
+    let extent_spatial = Vector3::new(
+        spacing[0] * cells[0] as f64,
+        spacing[1] * cells[1] as f64,
+        spacing[2] * cells[2] as f64,
+    );
+    let mut grid = PrecalculatedMagneticFieldGrid::new(
+        extent_spatial,
+        minimum - spacing / 2.0 + extent_spatial / 2.0,
+        extent_cells,
+        vec![Vector3::new(f64::NAN, f64::NAN, f64::NAN); points.len()],
+    );
+    for point in points {
+        let index = grid.position_to_grid_index(&point.position) as usize;
+        if !grid.grid[index][0].is_nan() {
+            // Two points fall into the same cell, so the points are not a grid.
+            return None;
+        }
+        grid.grid[index] = point.field;
+    }
+    Some(grid)
+}
+
+/// Resamples scattered points onto a regular grid spanning their bounding box.
+///
+/// The field in each cell is the inverse-distance-weighted average of the points within that cell.
+/// Cells which contain no points use the points in the nearest shell of surrounding cells that contains any.
+fn resample(points: &[FieldMapPoint], cells: Vector3<i32>) -> PrecalculatedMagneticFieldGrid {
+    let cells = cells.map(|n| n.max(1));
+    let mut minimum = points[0].position;
+    let mut maximum = points[0].position;
+    for point in points {
+        minimum = minimum.zip_map(&point.position, |a, b| a.min(b));
+        maximum = maximum.zip_map(&point.position, |a, b| a.max(b));
+    }
+    let mut extent_spatial = maximum - minimum;
+    let fallback = extent_spatial.max();
+    let fallback = if fallback > 0.0 { fallback } else { 1.0 };
+    for axis in 0..3 {
+        if extent_spatial[axis] == 0.0 {
+            extent_spatial[axis] = fallback;
+        }
+    }
+
+    let mut grid = PrecalculatedMagneticFieldGrid::new(
+        extent_spatial,
+        (minimum + maximum) / 2.0,
+        cells,
+        vec![Vector3::new(0.0, 0.0, 0.0); (cells[0] * cells[1] * cells[2]) as usize],
+    );
+
+    // Bin the points into their cells.
+    let mut bins: Vec<Vec<usize>> = vec![Vec::new(); grid.grid.len()];
+    for (i, point) in points.iter().enumerate() {
+        bins[grid.position_to_grid_index(&point.position) as usize].push(i);
+    }
+
+    let cell_size = extent_spatial.component_div(&cells.map(|n| n as f64));
+    let corner = grid.position - extent_spatial / 2.0;
+    let max_shell = cells.max();
+    for ix in 0..cells[0] {
+        for iy in 0..cells[1] {
+            for iz in 0..cells[2] {
+                let centre = corner
+                    + Vector3::new(
+                        (ix as f64 + 0.5) * cell_size[0],
+                        (iy as f64 + 0.5) * cell_size[1],
+                        (iz as f64 + 0.5) * cell_size[2],
+                    );
+                let mut field = None;
+                for shell in 0..=max_shell {
+                    let mut neighbours = Vec::new();
+                    for jx in (ix - shell).max(0)..=(ix + shell).min(cells[0] - 1) {
+                        for jy in (iy - shell).max(0)..=(iy + shell).min(cells[1] - 1) {
+                            for jz in (iz - shell).max(0)..=(iz + shell).min(cells[2] - 1) {
+                                let on_shell = (jx - ix).abs() == shell
+                                    || (jy - iy).abs() == shell
+                                    || (jz - iz).abs() == shell;
+                                if on_shell {
+                                    let index = cells[2] * (cells[1] * jx + jy) + jz;
+                                    neighbours.extend(bins[index as usize].iter());
+                                }
+                            }
+                        }
+                    }
+                    if !neighbours.is_empty() {
+                        field = Some(inverse_distance_weighted(points, &neighbours, &centre));
+                        break;
+                    }
+                }
+                let index = cells[2] * (cells[1] * ix + iy) + iz;
+                grid.grid[index as usize] = field.unwrap_or(Vector3::new(0.0, 0.0, 0.0));
+            }
+        }
+    }
+    grid
+}
+
+fn inverse_distance_weighted(
+    points: &[FieldMapPoint],
+    neighbours: &[usize],
+    position: &Vector3<f64>,
+) -> Vector3<f64> {
+    let mut total_weight = 0.0;
+    let mut field = Vector3::new(0.0, 0.0, 0.0);
+    for &i in neighbours {
+        let distance_squared = (points[i].position - position).norm_squared();
+        if distance_squared == 0.0 {
+            return points[i].field;
+        }
+        let weight = 1.0 / distance_squared;
+        total_weight = total_weight + weight;
+        field = field + weight * points[i].field;
+    }
+    field / total_weight
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use std::io::Cursor;
+
+    /// Tests that a regular grid is imported with the correct geometry and ordering.
+    #[test]
+    fn test_import_regular_grid() {
+        let mut table = String::from("x,y,z,Bx,By,Bz\n");
+        for i in 0..3 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    table.push_str(&format!("{},{},{},{},{},{}\n", i, j, k, i, j, k));
+                }
+            }
+        }
+        let grid = FieldMapReader::new()
+            .read(Cursor::new(table))
+            .expect("Could not import grid");
+
+        assert_eq!(grid.extent_cells, Vector3::new(3, 2, 2));
+        assert_eq!(grid.extent_spatial, Vector3::new(3.0, 2.0, 2.0));
+        assert_eq!(grid.position, Vector3::new(1.0, 0.5, 0.5));
+        for i in 0..3 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let pos = Vector3::new(i as f64, j as f64, k as f64);
+                    assert_eq!(grid.get_field(&pos), pos);
+                }
+            }
+        }
+    }
+
+    /// Tests that columns can be reordered, selected by name, and converted from other units.
+    #[test]
+    fn test_import_named_columns_and_units() {
+        let table = "% Model exported from a field solver\n\
+                     % Bz (G) x [mm] By (G) y [mm] Bx (G) z [mm]\n\
+                     3 0 2 0 1 0\n\
+                     3 10 2 0 1 0\n";
+        let grid = FieldMapReader::new()
+            .with_columns(FieldMapColumns::named(["x", "y", "z", "Bx", "By", "Bz"]))
+            .with_length_unit(LengthUnit::Millimetre)
+            .with_field_unit(FieldUnit::Gauss)
+            .read(Cursor::new(table))
+            .expect("Could not import grid");
+
+        assert_eq!(grid.extent_cells, Vector3::new(2, 1, 1));
+        assert_approx_eq!(grid.extent_spatial[0], 0.02, 1e-12);
+        let field = grid.get_field(&Vector3::new(0.01, 0.0, 0.0));
+        assert_approx_eq!(field[0], 1.0e-4, 1e-12);
+        assert_approx_eq!(field[1], 2.0e-4, 1e-12);
+        assert_approx_eq!(field[2], 3.0e-4, 1e-12);
+    }
+
+    /// Tests that scattered points are resampled onto a grid.
+    #[test]
+    fn test_import_scattered_points() {
+        let table = "0.0 0.0 0.0 1 0 0\n\
+                     0.9 0.1 0.0 2 0 0\n\
+                     0.2 0.8 0.0 3 0 0\n";
+
+        let result = FieldMapReader::new().read(Cursor::new(table));
+        match result {
+            Err(FieldMapError::NotAGrid) => (),
+            _ => panic!("Scattered points should not be read as a grid."),
+        }
+
+        let grid = FieldMapReader::new()
+            .with_resampling(Vector3::new(2, 2, 1))
+            .read(Cursor::new(table))
+            .expect("Could not resample points");
+        assert_eq!(grid.grid.len(), 4);
+        assert_eq!(grid.get_field(&Vector3::new(0.0, 0.0, 0.0))[0], 1.0);
+        assert_eq!(grid.get_field(&Vector3::new(0.9, 0.0, 0.0))[0], 2.0);
+        assert_eq!(grid.get_field(&Vector3::new(0.0, 0.8, 0.0))[0], 3.0);
+        // The empty cell takes a weighted average of its neighbours.
+        let empty = grid.get_field(&Vector3::new(0.9, 0.8, 0.0))[0];
+        assert!(empty > 1.0 && empty < 3.0);
+    }
+
+    /// Tests that malformed files produce errors.
+    #[test]
+    fn test_import_errors() {
+        let result = FieldMapReader::new().read(Cursor::new("0 0 0 1 1 1\n0 0 x 1 1 1\n"));
+        match result {
+            Err(FieldMapError::Parse { line: 2, .. }) => (),
+            _ => panic!("Expected a parse error on line 2."),
+        }
+
+        let result = FieldMapReader::new().read(Cursor::new("0 0 0 1 1 1\nNaN 0 0 1 1 1\n"));
+        match result {
+            Err(FieldMapError::Parse { line: 2, .. }) => (),
+            _ => panic!("Expected a parse error for the non-finite coordinate."),
+        }
+
+        let result = FieldMapReader::new().read(Cursor::new("0 0 0 1 1\n"));
+        match result {
+            Err(FieldMapError::Parse { line: 1, .. }) => (),
+            _ => panic!("Expected a parse error for the missing column."),
+        }
+
+        let result = FieldMapReader::new()
+            .with_columns(FieldMapColumns::named(["x", "y", "z", "Bx", "By", "Bz"]))
+            .read(Cursor::new("x y z Bx By\n0 0 0 1 1 1\n"));
+        match result {
+            Err(FieldMapError::MissingColumn(name)) => assert_eq!(name, "Bz"),
+            _ => panic!("Expected a missing column error."),
+        }
+
+        let result = FieldMapReader::new().read(Cursor::new("% no data\n"));
+        match result {
+            Err(FieldMapError::Empty) => (),
+            _ => panic!("Expected an empty file error."),
+        }
+    }
+}
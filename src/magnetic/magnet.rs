@@ -0,0 +1,511 @@
+//! Magnetic fields of permanent magnets.
+//!
+//! Each magnet is a component which is placed on an entity together with a `Position`, which
+//! defines the centre of the magnet. Magnets are assumed to be uniformly magnetised, with the
+//! magnetisation given by the remanence `mu0 M` in units of Tesla.
+
+extern crate nalgebra;
+use crate::atom::Position;
+use crate::constant::{MU0, PI};
+use crate::magnetic::coil::current_sheet_field;
+use crate::magnetic::envelope::{envelope_scale, FieldEnvelope};
+use crate::magnetic::gradient::{finite_difference_jacobian, MagneticGradientSampler};
+use crate::magnetic::region::{in_region, FieldRegion};
+use crate::magnetic::MagneticFieldSampler;
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::{Matrix3, Vector3};
+use specs::{Component, HashMapStorage, Join, Read, ReadStorage, System, WriteStorage};
+use std::marker::PhantomData;
+
+/// A permanent magnet, or arrangement of permanent magnets.
+pub trait Magnet {
+    /// Calculates the magnetic field of the magnet, in units of Tesla.
+    ///
+    /// # Arguments
+    ///
+    /// `magnet_position`: position of the magnet entity, m
+    ///
+    /// `pos`: position at which to calculate the field, m
+    fn field(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64>;
+
+    /// Calculates the Jacobian of the magnetic field, `J_ij = dB_i/dx_j`, in units of Tesla/m.
+    ///
+    /// The default implementation uses finite differences of `field`.
+    fn jacobian(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Matrix3<f64> {
+        finite_difference_jacobian(|p| self.field(magnet_position, p), pos)
+    }
+}
+
+/// A point magnetic dipole.
+///
+/// This is a good approximation for any small magnet when the field is evaluated far away from it.
+pub struct DipoleMagnet {
+    /// Magnetic moment of the dipole, in units of A m^2.
+    pub moment: Vector3<f64>,
+}
+impl Component for DipoleMagnet {
+    type Storage = HashMapStorage<Self>;
+}
+impl Magnet for DipoleMagnet {
+    fn field(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let delta = pos - magnet_position;
+        let distance = delta.norm();
+        if distance == 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let direction = delta / distance;
+        MU0 / (4.0 * PI) * (3.0 * direction * direction.dot(&self.moment) - self.moment)
+            / distance.powi(3)
+    }
+}
+
+/// Contribution to `ln(y + r)` which remains accurate when `y` is large and negative.
+fn log_plus(y: f64, r: f64, perpendicular_squared: f64) -> f64 {
+    if y >= 0.0 {
+        (y + r).ln()
+    } else {
+        (perpendicular_squared / (r - y)).ln()
+    }
+}
+
+/// Field of a cuboid magnet magnetised along the z axis, per unit remanence.
+///
+/// The field is calculated from the magnetic surface charges on the two faces normal to z,
+/// see _eg_ Furlani, _Permanent Magnet and Electromechanical Devices_ (2001).
+fn cuboid_field_along_z(half_width: &Vector3<f64>, delta: &Vector3<f64>) -> Vector3<f64> {
+    let mut field = Vector3::new(0.0, 0.0, 0.0);
+    for (face_sign, z_face) in &[(1.0, half_width[2]), (-1.0, -half_width[2])] {
+        let z = delta[2] - z_face;
+        for (i, x_corner) in [-half_width[0], half_width[0]].iter().enumerate() {
+            for (j, y_corner) in [-half_width[1], half_width[1]].iter().enumerate() {
+                let sign = face_sign * if (i + j) % 2 == 0 { 1.0 } else { -1.0 };
+                let x = delta[0] - x_corner;
+                let y = delta[1] - y_corner;
+                let r = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
+                let angle = (x * y / (z * r)).atan();
+                if !angle.is_nan() {
+                    field[2] = field[2] + sign * angle;
+                }
+                field[0] = field[0] - sign * log_plus(y, r, x.powi(2) + z.powi(2));
+                field[1] = field[1] - sign * log_plus(x, r, y.powi(2) + z.powi(2));
+            }
+        }
+    }
+    let mut field = field / (4.0 * PI);
+
+    // Inside the magnet, B = mu0 (H + M).
+    if delta[0].abs() < half_width[0]
+        && delta[1].abs() < half_width[1]
+        && delta[2].abs() < half_width[2]
+    {
+        field[2] = field[2] + 1.0;
+    }
+    field
+}
+
+/// Field of a uniformly magnetised cuboid, with faces normal to the axes of `delta`.
+fn cuboid_field(
+    half_width: &Vector3<f64>,
+    remanence: &Vector3<f64>,
+    delta: &Vector3<f64>,
+) -> Vector3<f64> {
+    let mut field = Vector3::new(0.0, 0.0, 0.0);
+    for axis in 0..3 {
+        if remanence[axis] == 0.0 {
+            continue;
+        }
+        // Cyclically permute coordinates so the magnetisation lies along the local z axis.
+        let permute =
+            |v: &Vector3<f64>| Vector3::new(v[(axis + 1) % 3], v[(axis + 2) % 3], v[axis]);
+        let local = cuboid_field_along_z(&permute(half_width), &permute(delta));
+        for i in 0..3 {
+            field[(axis + 1 + i) % 3] = field[(axis + 1 + i) % 3] + remanence[axis] * local[i];
+        }
+    }
+    field
+}
+
+/// A uniformly magnetised cuboid magnet, with faces normal to the x, y and z axes.
+pub struct CuboidMagnet {
+    /// Half the side lengths of the cuboid along x, y and z, in m.
+    pub half_width: Vector3<f64>,
+    /// Remanent magnetisation `mu0 M`, in units of Tesla.
+    pub remanence: Vector3<f64>,
+}
+impl Component for CuboidMagnet {
+    type Storage = HashMapStorage<Self>;
+}
+impl Magnet for CuboidMagnet {
+    fn field(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        cuboid_field(&self.half_width, &self.remanence, &(pos - magnet_position))
+    }
+}
+
+/// A cylindrical magnet, uniformly magnetised along its axis.
+pub struct CylindricalMagnet {
+    /// Radius of the cylinder, in m.
+    pub radius: f64,
+    /// Length of the cylinder, in m.
+    pub length: f64,
+    /// A unit vector along the axis of the cylinder, which is also the direction of magnetisation.
+    pub direction: Vector3<f64>,
+    /// Remanent magnetisation `mu0 M`, in units of Tesla.
+    pub remanence: f64,
+}
+impl CylindricalMagnet {
+    pub fn new(radius: f64, length: f64, direction: Vector3<f64>, remanence: f64) -> Self {
+        CylindricalMagnet {
+            radius: radius,
+            length: length,
+            direction: direction.normalize(),
+            remanence: remanence,
+        }
+    }
+}
+impl Component for CylindricalMagnet {
+    type Storage = HashMapStorage<Self>;
+}
+impl Magnet for CylindricalMagnet {
+    fn field(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        // The magnetisation is equivalent to an azimuthal surface current density M.
+        self.remanence / MU0
+            * current_sheet_field(
+                self.radius,
+                self.length,
+                &self.direction,
+                &(pos - magnet_position),
+            )
+    }
+}
+
+/// A stack of identical cuboid magnets, spaced at regular intervals.
+///
+/// The stack is centred on the position of the entity. This arrangement is commonly used
+/// to produce the quadrupole field of a 2D MOT.
+pub struct MagnetStack {
+    /// Half the side lengths of each magnet along x, y and z, in m.
+    pub half_width: Vector3<f64>,
+    /// Remanent magnetisation `mu0 M` of each magnet, in units of Tesla.
+    pub remanence: Vector3<f64>,
+    /// Number of magnets in the stack.
+    pub count: usize,
+    /// Displacement between the centres of neighbouring magnets, in m.
+    pub spacing: Vector3<f64>,
+}
+impl Component for MagnetStack {
+    type Storage = HashMapStorage<Self>;
+}
+impl Magnet for MagnetStack {
+    fn field(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let mut field = Vector3::new(0.0, 0.0, 0.0);
+        for i in 0..self.count {
+            let offset = (i as f64 - (self.count as f64 - 1.0) / 2.0) * self.spacing;
+            field = field
+                + cuboid_field(
+                    &self.half_width,
+                    &self.remanence,
+                    &(pos - magnet_position - offset),
+                );
+        }
+        field
+    }
+}
+
+/// A ring of cuboid magnets in a Halbach arrangement.
+///
+/// The magnets are placed at angles `theta_i = 2 pi i / count` about the axis, measured from
+/// `reference`. The magnetisation of each magnet lies in the plane of the ring and makes an angle
+/// `order * theta_i` to the radial direction, so the magnetisation rotates through
+/// `(1 + order) theta_i` relative to `reference`. An `order` of 1 produces a uniform dipole field
+/// inside the ring, and an `order` of 2 produces a 2D quadrupole field.
+pub struct HalbachArray {
+    /// Distance from the axis to the centre of each magnet, in m.
+    pub radius: f64,
+    /// A unit vector along the axis of the ring.
+    pub axis: Vector3<f64>,
+    /// A unit vector perpendicular to `axis`, which points towards the first magnet.
+    pub reference: Vector3<f64>,
+    /// Number of magnets in the ring.
+    pub count: usize,
+    /// Multipole order of the field produced inside the ring.
+    pub order: i32,
+    /// Half the side lengths of each magnet along the radial, tangential and axial directions, in m.
+    pub half_width: Vector3<f64>,
+    /// Magnitude of the remanent magnetisation `mu0 M` of each magnet, in units of Tesla.
+    pub remanence: f64,
+}
+impl HalbachArray {
+    pub fn new(
+        radius: f64,
+        axis: Vector3<f64>,
+        reference: Vector3<f64>,
+        count: usize,
+        order: i32,
+        half_width: Vector3<f64>,
+        remanence: f64,
+    ) -> Self {
+        let axis = axis.normalize();
+        HalbachArray {
+            radius: radius,
+            axis: axis,
+            reference: (reference - axis * axis.dot(&reference)).normalize(),
+            count: count,
+            order: order,
+            half_width: half_width,
+            remanence: remanence,
+        }
+    }
+}
+impl Component for HalbachArray {
+    type Storage = HashMapStorage<Self>;
+}
+impl Magnet for HalbachArray {
+    fn field(&self, magnet_position: &Vector3<f64>, pos: &Vector3<f64>) -> Vector3<f64> {
+        let other = self.axis.cross(&self.reference);
+        let mut field = Vector3::new(0.0, 0.0, 0.0);
+        for i in 0..self.count {
+            let theta = 2.0 * PI * i as f64 / self.count as f64;
+            let radial = theta.cos() * self.reference + theta.sin() * other;
+            let tangential = self.axis.cross(&radial);
+            let delta = pos - magnet_position - self.radius * radial;
+            let local_delta = Vector3::new(
+                delta.dot(&radial),
+                delta.dot(&tangential),
+                delta.dot(&self.axis),
+            );
+            let angle = self.order as f64 * theta;
+            let local_remanence = self.remanence * Vector3::new(angle.cos(), angle.sin(), 0.0);
+            let local = cuboid_field(&self.half_width, &local_remanence, &local_delta);
+            field = field + local[0] * radial + local[1] * tangential + local[2] * self.axis;
+        }
+        field
+    }
+}
+
+/// Updates the values of magnetic field samplers to include the fields of magnets of type `T`.
+pub struct SampleMagnetFieldSystem<T: Magnet> {
+    pub marker: PhantomData<T>,
+}
+impl<T: Magnet> Default for SampleMagnetFieldSystem<T> {
+    fn default() -> Self {
+        SampleMagnetFieldSystem {
+            marker: PhantomData,
+        }
+    }
+}
+impl<'a, T> System<'a> for SampleMagnetFieldSystem<T>
+where
+    T: Magnet + Component + Sync,
+{
+    type SystemData = (
+        WriteStorage<'a, MagneticFieldSampler>,
+        WriteStorage<'a, MagneticGradientSampler>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, T>,
+        ReadStorage<'a, FieldRegion>,
+        ReadStorage<'a, FieldEnvelope>,
+        Option<Read<'a, SubstepMask>>,
+    );
+    fn run(
+        &mut self,
+        (mut sampler, mut gradient_sampler, pos, magnets, regions, envelopes, substep_mask): Self::SystemData,
+    ) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for (centre, magnet, region, envelope) in
+            (&pos, &magnets, regions.maybe(), envelopes.maybe()).join()
+        {
+            let scale = envelope_scale(envelope);
+            (&atoms, &pos, &mut sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    sampler.field = sampler.field + scale * magnet.field(&centre.pos, &pos.pos);
+                });
+            (&atoms, &pos, &mut gradient_sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    sampler.jacobian =
+                        sampler.jacobian + scale * magnet.jacobian(&centre.pos, &pos.pos);
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use specs::{Builder, RunNow, World, WorldExt};
+
+    fn assert_vectors_close(a: Vector3<f64>, b: Vector3<f64>, tolerance: f64) {
+        assert!((a - b).norm() <= tolerance, "{:?} differs from {:?}", a, b);
+    }
+
+    #[test]
+    fn test_cuboid_magnet_field() {
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+        let half_width = Vector3::new(0.01, 0.015, 0.02);
+        let remanence = 1.2;
+
+        // On the magnetisation axis, B = Br/pi [atan(ab / (z_- r_-)) - atan(ab / (z_+ r_+))]
+        let magnet = CuboidMagnet {
+            half_width: half_width,
+            remanence: Vector3::new(0.0, 0.0, remanence),
+        };
+        let (a, b, c) = (half_width[0], half_width[1], half_width[2]);
+        let on_axis = |z: f64| {
+            let term = |d: f64| (a * b / (d * (a.powi(2) + b.powi(2) + d.powi(2)).sqrt())).atan();
+            remanence / PI * (term(z - c) - term(z + c))
+        };
+        for z in &[0.03, 0.1, -0.05] {
+            let field = magnet.field(&centre, &Vector3::new(0.0, 0.0, *z));
+            let expected = on_axis(*z);
+            assert_vectors_close(
+                field,
+                Vector3::new(0.0, 0.0, expected),
+                1e-9 * expected.abs(),
+            );
+        }
+
+        // Far away, the field of any magnetised cuboid is that of a dipole with m = M V.
+        let remanence = Vector3::new(0.3, -0.8, 0.5);
+        let magnet = CuboidMagnet {
+            half_width: half_width,
+            remanence: remanence,
+        };
+        let volume = 8.0 * half_width[0] * half_width[1] * half_width[2];
+        let dipole = DipoleMagnet {
+            moment: remanence * volume / MU0,
+        };
+        for pos in &[
+            Vector3::new(1.0, 0.5, -0.7),
+            Vector3::new(-0.2, 1.5, 0.1),
+            Vector3::new(0.0, 0.0, 2.0),
+        ] {
+            let expected = dipole.field(&centre, pos);
+            assert_vectors_close(magnet.field(&centre, pos), expected, 1e-3 * expected.norm());
+        }
+
+        // The normal component of B is continuous across the face of the magnet.
+        let inside = magnet.field(&centre, &Vector3::new(0.001, 0.002, c - 1e-9));
+        let outside = magnet.field(&centre, &Vector3::new(0.001, 0.002, c + 1e-9));
+        assert!((inside[2] - outside[2]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cylindrical_magnet_field() {
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+        let (radius, length, remanence) = (0.005, 0.01, 1.3);
+        let magnet = CylindricalMagnet::new(radius, length, Vector3::y(), remanence);
+
+        // On axis, B = Br/2 [(z + L/2) / sqrt((z + L/2)^2 + R^2) - (z - L/2) / sqrt((z - L/2)^2 + R^2)]
+        let end = |z: f64| z / (z.powi(2) + radius.powi(2)).sqrt();
+        for y in &[0.0, 0.02, -0.01] {
+            let field = magnet.field(&centre, &Vector3::new(0.0, *y, 0.0));
+            let expected = remanence / 2.0 * (end(y + length / 2.0) - end(y - length / 2.0));
+            assert_vectors_close(field, Vector3::new(0.0, expected, 0.0), 1e-9 * expected);
+        }
+
+        // Far away, the field is that of a dipole with m = M V.
+        let dipole = DipoleMagnet {
+            moment: Vector3::y() * remanence * PI * radius.powi(2) * length / MU0,
+        };
+        let pos = Vector3::new(0.3, 0.4, -0.2);
+        let expected = dipole.field(&centre, &pos);
+        assert_vectors_close(
+            magnet.field(&centre, &pos),
+            expected,
+            1e-3 * expected.norm(),
+        );
+    }
+
+    #[test]
+    fn test_magnet_stack_field() {
+        let centre = Vector3::new(0.1, 0.0, 0.0);
+        let half_width = Vector3::new(0.005, 0.0025, 0.001);
+        let remanence = Vector3::new(1.0, 0.0, 0.0);
+        let stack = MagnetStack {
+            half_width: half_width,
+            remanence: remanence,
+            count: 3,
+            spacing: Vector3::new(0.0, 0.0, 0.004),
+        };
+        let pos = Vector3::new(0.0, 0.01, 0.002);
+        let mut expected = Vector3::new(0.0, 0.0, 0.0);
+        for z in &[-0.004, 0.0, 0.004] {
+            let magnet = CuboidMagnet {
+                half_width: half_width,
+                remanence: remanence,
+            };
+            expected = expected + magnet.field(&(centre + Vector3::new(0.0, 0.0, *z)), &pos);
+        }
+        assert_vectors_close(stack.field(&centre, &pos), expected, 1e-12);
+    }
+
+    #[test]
+    fn test_halbach_array_field() {
+        let centre = Vector3::new(0.0, 0.0, 0.0);
+        let half_width = Vector3::new(0.005, 0.005, 0.02);
+
+        // A dipole ring produces a uniform field inside, perpendicular to the axis.
+        let dipole_ring =
+            HalbachArray::new(0.03, Vector3::z(), Vector3::x(), 16, 1, half_width, 1.0);
+        let field = dipole_ring.field(&centre, &centre);
+        assert!(field.norm() > 0.01);
+        assert!(field[2].abs() < 1e-12 * field.norm());
+        let offset = dipole_ring.field(&centre, &Vector3::new(0.002, 0.001, 0.0));
+        assert_vectors_close(offset, field, 1e-2 * field.norm());
+
+        // A quadrupole ring produces zero field at the centre, increasing linearly away from it.
+        let quadrupole_ring =
+            HalbachArray::new(0.03, Vector3::z(), Vector3::x(), 16, 2, half_width, 1.0);
+        assert!(quadrupole_ring.field(&centre, &centre).norm() < 1e-12);
+        let near = quadrupole_ring.field(&centre, &Vector3::new(0.001, 0.0, 0.0));
+        let far = quadrupole_ring.field(&centre, &Vector3::new(0.002, 0.0, 0.0));
+        assert!(near.norm() > 1e-4);
+        assert_vectors_close(far, 2.0 * near, 1e-2 * far.norm());
+    }
+
+    #[test]
+    fn test_sample_magnet_field_system() {
+        let mut test_world = World::new();
+        test_world.register::<Position>();
+        test_world.register::<MagneticFieldSampler>();
+        test_world.register::<MagneticGradientSampler>();
+        test_world.register::<FieldRegion>();
+        test_world.register::<FieldEnvelope>();
+        test_world.register::<DipoleMagnet>();
+
+        let moment = Vector3::new(0.0, 0.0, 2.0);
+        test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 0.0),
+            })
+            .with(DipoleMagnet { moment: moment })
+            .build();
+
+        let sampler_entity = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 0.1),
+            })
+            .with(MagneticFieldSampler::default())
+            .build();
+
+        let mut system = SampleMagnetFieldSystem::<DipoleMagnet>::default();
+        system.run_now(&test_world);
+
+        let samplers = test_world.read_storage::<MagneticFieldSampler>();
+        let field = samplers
+            .get(sampler_entity)
+            .expect("entity not found")
+            .field;
+        let expected = MU0 / (4.0 * PI) * 2.0 * moment / 0.1_f64.powi(3);
+        assert_vectors_close(field, expected, 1e-12 * expected.norm());
+    }
+}
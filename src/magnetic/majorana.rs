@@ -0,0 +1,272 @@
+//! Majorana spin-flip losses near zeros of the magnetic field.
+//!
+//! The magnetic moment of an atom follows the direction of the magnetic field adiabatically
+//! provided the field direction rotates slowly compared to the Larmor frequency,
+//! `omega_L = |g_F| mu_B |B| / hbar`. Close to a field zero, such as the centre of a quadrupole
+//! trap, this condition fails and the atom may undergo a non-adiabatic transition to another
+//! magnetic sublevel.
+//!
+//! The rate of change of field direction seen by a moving atom is `omega_dir = |d(B/|B|)/dt|`,
+//! calculated from the Jacobian of the field and the atom's velocity. The spin-flip rate is
+//! estimated as `omega_dir exp(-pi omega_L / (2 omega_dir))`, which has the Landau-Zener form:
+//! spin flips occur at the rate the field rotates, but are exponentially suppressed when the
+//! atom's motion is adiabatic.
+//!
+//! Spin flips are only simulated when the [MajoranaLoss](struct.MajoranaLoss.html) resource is
+//! added to the world, and only for atoms with a [MagneticDipole](../force/struct.MagneticDipole.html).
+
+extern crate nalgebra;
+use crate::atom::Velocity;
+use crate::constant;
+use crate::destructor::ToBeDestroyed;
+use crate::integrator::Timestep;
+use crate::magnetic::force::MagneticDipole;
+use crate::magnetic::gradient::MagneticGradientSampler;
+use crate::magnetic::MagneticFieldSampler;
+use crate::rng::{FrameRandomKey, RandomStreams};
+use nalgebra::{Matrix3, Vector3};
+use rand::Rng;
+use specs::prelude::*;
+
+/// Determines what happens to an atom which undergoes a Majorana spin flip.
+#[derive(Clone, Copy)]
+pub enum MajoranaLossOutcome {
+    /// The atom is removed from the simulation.
+    Remove,
+    /// The sign of the atom's magnetic moment is reversed, so that trapped atoms become anti-trapped.
+    Flip,
+}
+
+/// A resource that enables Majorana spin-flip losses.
+pub struct MajoranaLoss {
+    pub outcome: MajoranaLossOutcome,
+}
+
+/// Calculates the probability that an atom undergoes a Majorana spin flip during a step.
+///
+/// # Arguments
+///
+/// `gf`: Landé g-factor of the atom.
+///
+/// `field`: magnetic field at the atom's position, in T.
+///
+/// `jacobian`: Jacobian of the magnetic field at the atom's position, in T/m.
+///
+/// `velocity`: velocity of the atom, in m/s.
+///
+/// `dt`: duration of the step, in s.
+///
+/// Where the field is zero there is no axis for the atom's moment to follow. A spin flip is only certain
+/// if the atom is moving through a true zero of the field, ie the field seen by the atom is changing.
+/// Otherwise, such as in a world without magnetic fields or where the field has been ramped to zero,
+/// the probability is zero.
+pub fn spin_flip_probability(
+    gf: f64,
+    field: &Vector3<f64>,
+    jacobian: &Matrix3<f64>,
+    velocity: &Vector3<f64>,
+    dt: f64,
+) -> f64 {
+    let magnitude = field.norm();
+    let field_rate = jacobian * velocity;
+    if magnitude == 0.0 {
+        return if field_rate.norm() > 0.0 { 1.0 } else { 0.0 };
+    }
+    let direction = field / magnitude;
+    let direction_rate = ((field_rate - direction * direction.dot(&field_rate)) / magnitude).norm();
+    if direction_rate == 0.0 {
+        return 0.0;
+    }
+    let larmor = gf.abs() * constant::BOHRMAG * magnitude / constant::HBAR;
+    let rate = direction_rate * (-constant::PI * larmor / (2.0 * direction_rate)).exp();
+    1.0 - (-rate * dt).exp()
+}
+
+/// Checks if atoms undergo a Majorana spin flip during the current simulation step,
+/// if the [MajoranaLoss](struct.MajoranaLoss.html) resource has been added to the world.
+pub struct MajoranaSpinFlipSystem;
+impl<'a> System<'a> for MajoranaSpinFlipSystem {
+    type SystemData = (
+        Entities<'a>,
+        Option<Read<'a, MajoranaLoss>>,
+        ReadExpect<'a, Timestep>,
+        WriteStorage<'a, MagneticDipole>,
+        ReadStorage<'a, MagneticFieldSampler>,
+        ReadStorage<'a, MagneticGradientSampler>,
+        ReadStorage<'a, Velocity>,
+        Read<'a, LazyUpdate>,
+        Option<Read<'a, FrameRandomKey>>,
+    );
+    fn run(
+        &mut self,
+        (ent, loss, timestep, mut dipoles, fields, gradients, velocities, updater, random_key): Self::SystemData,
+    ) {
+        use rayon::prelude::*;
+
+        match loss {
+            None => (),
+            Some(loss) => {
+                let streams = RandomStreams::new(random_key.as_deref(), "magnetics_majorana");
+                (&ent, &mut dipoles, &fields, &gradients, &velocities)
+                    .par_join()
+                    .for_each(|(ent, dipole, field, gradient, velocity)| {
+                        let probability = spin_flip_probability(
+                            dipole.gf,
+                            &field.field,
+                            &gradient.jacobian,
+                            &velocity.vel,
+                            timestep.delta,
+                        );
+                        let mut rng = streams.entity_rng(ent);
+                        if rng.gen_range(0.0..1.0) < probability {
+                            match loss.outcome {
+                                MajoranaLossOutcome::Remove => updater.insert(ent, ToBeDestroyed),
+                                MajoranaLossOutcome::Flip => dipole.mf_gf = -dipole.mf_gf,
+                            }
+                        }
+                    });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::magnetic::quadrupole::Sample3DQuadrupoleFieldSystem;
+
+    #[test]
+    fn test_spin_flip_probability() {
+        let gradient = 0.2;
+        let jacobian = Sample3DQuadrupoleFieldSystem::calculate_jacobian(gradient, Vector3::z());
+        let field_at = |pos: Vector3<f64>| {
+            Sample3DQuadrupoleFieldSystem::calculate_field(
+                pos,
+                Vector3::new(0.0, 0.0, 0.0),
+                gradient,
+                Vector3::z(),
+            )
+        };
+        let velocity = Vector3::new(0.0, 0.1, 0.0);
+        let dt = 1e-6;
+
+        // Moving along a field line, the field direction does not change.
+        let along = spin_flip_probability(
+            0.5,
+            &field_at(Vector3::new(0.0, 1e-3, 0.0)),
+            &jacobian,
+            &velocity,
+            dt,
+        );
+        assert_eq!(along, 0.0);
+
+        // Passing close to the field zero is non-adiabatic, passing far away is adiabatic.
+        let near = spin_flip_probability(
+            0.5,
+            &field_at(Vector3::new(1e-7, 0.0, 0.0)),
+            &jacobian,
+            &velocity,
+            dt,
+        );
+        let far = spin_flip_probability(
+            0.5,
+            &field_at(Vector3::new(1e-3, 0.0, 0.0)),
+            &jacobian,
+            &velocity,
+            dt,
+        );
+        assert!(near > 0.1);
+        assert!(far < 1e-12);
+
+        // Moving through the field zero, a spin flip is certain.
+        let zero =
+            spin_flip_probability(0.5, &Vector3::new(0.0, 0.0, 0.0), &jacobian, &velocity, dt);
+        assert_eq!(zero, 1.0);
+
+        // In a uniformly zero field there is no adiabatic axis, and so no spin flips.
+        let no_field = spin_flip_probability(
+            0.5,
+            &Vector3::new(0.0, 0.0, 0.0),
+            &Matrix3::zeros(),
+            &velocity,
+            dt,
+        );
+        assert_eq!(no_field, 0.0);
+
+        // A stationary atom at the field zero does not see the field change.
+        let stationary = spin_flip_probability(
+            0.5,
+            &Vector3::new(0.0, 0.0, 0.0),
+            &jacobian,
+            &Vector3::new(0.0, 0.0, 0.0),
+            dt,
+        );
+        assert_eq!(stationary, 0.0);
+    }
+
+    #[test]
+    fn test_majorana_spin_flip_system() {
+        let mut test_world = World::new();
+        test_world.register::<MagneticDipole>();
+        test_world.register::<MagneticFieldSampler>();
+        test_world.register::<MagneticGradientSampler>();
+        test_world.register::<Velocity>();
+        test_world.register::<ToBeDestroyed>();
+        test_world.insert(Timestep { delta: 1e-6 });
+        test_world.insert(MajoranaLoss {
+            outcome: MajoranaLossOutcome::Flip,
+        });
+
+        let atom = test_world
+            .create_entity()
+            .with(MagneticDipole {
+                mf_gf: 1.0,
+                gf: 0.5,
+            })
+            .with(MagneticFieldSampler::tesla(Vector3::new(0.0, 0.0, 0.0)))
+            .with(MagneticGradientSampler {
+                jacobian: Sample3DQuadrupoleFieldSystem::calculate_jacobian(0.2, Vector3::z()),
+                magnitude_gradient: Vector3::new(0.0, 0.0, 0.0),
+            })
+            .with(Velocity {
+                vel: Vector3::new(1.0, 0.0, 0.0),
+            })
+            .build();
+        // An atom in a world without magnetic fields.
+        let unaffected = test_world
+            .create_entity()
+            .with(MagneticDipole {
+                mf_gf: 1.0,
+                gf: 0.5,
+            })
+            .with(MagneticFieldSampler::tesla(Vector3::new(0.0, 0.0, 0.0)))
+            .with(MagneticGradientSampler::default())
+            .with(Velocity {
+                vel: Vector3::new(1.0, 0.0, 0.0),
+            })
+            .build();
+
+        let mut system = MajoranaSpinFlipSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+        {
+            let dipoles = test_world.read_storage::<MagneticDipole>();
+            assert_eq!(dipoles.get(atom).expect("entity not found").mf_gf, -1.0);
+            assert_eq!(
+                dipoles.get(unaffected).expect("entity not found").mf_gf,
+                1.0
+            );
+        }
+
+        test_world.insert(MajoranaLoss {
+            outcome: MajoranaLossOutcome::Remove,
+        });
+        system.run_now(&test_world);
+        test_world.maintain();
+        assert!(test_world.read_storage::<ToBeDestroyed>().contains(atom));
+        assert!(!test_world
+            .read_storage::<ToBeDestroyed>()
+            .contains(unaffected));
+    }
+}
@@ -6,14 +6,23 @@ use specs::prelude::*;
 
 use crate::initiate::NewlyCreated;
 use crate::integrator::INTEGRATE_POSITION_SYSTEM_NAME;
+use crate::substep::{active_atoms, SubstepMask};
 use nalgebra::Vector3;
 use specs::{
 	Component, DispatcherBuilder, Entities, Join, LazyUpdate, Read, ReadStorage, System,
 	VecStorage, World, WriteStorage,
 };
 
+pub mod coil;
+pub mod envelope;
+pub mod force;
+pub mod gradient;
 pub mod grid;
+pub mod import;
+pub mod magnet;
+pub mod majorana;
 pub mod quadrupole;
+pub mod region;
 pub mod uniform;
 pub mod zeeman;
 use std::fmt;
@@ -61,11 +70,15 @@ impl Default for MagneticFieldSampler {
 pub struct ClearMagneticFieldSamplerSystem;
 
 impl<'a> System<'a> for ClearMagneticFieldSamplerSystem {
-	type SystemData = WriteStorage<'a, MagneticFieldSampler>;
-	fn run(&mut self, mut sampler: Self::SystemData) {
+	type SystemData = (
+		WriteStorage<'a, MagneticFieldSampler>,
+		Option<Read<'a, SubstepMask>>,
+	);
+	fn run(&mut self, (mut sampler, substep_mask): Self::SystemData) {
 		use rayon::prelude::*;
 
-		(&mut sampler).par_join().for_each(|mut sampler| {
+		let atoms = active_atoms(substep_mask.as_deref(), sampler.mask());
+		(&atoms, &mut sampler).par_join().for_each(|(_, mut sampler)| {
 			sampler.magnitude = 0.;
 			sampler.field = Vector3::new(0.0, 0.0, 0.0)
 		});
@@ -79,11 +92,15 @@ impl<'a> System<'a> for ClearMagneticFieldSamplerSystem {
 pub struct CalculateMagneticFieldMagnitudeSystem;
 
 impl<'a> System<'a> for CalculateMagneticFieldMagnitudeSystem {
-	type SystemData = WriteStorage<'a, MagneticFieldSampler>;
-	fn run(&mut self, mut sampler: Self::SystemData) {
+	type SystemData = (
+		WriteStorage<'a, MagneticFieldSampler>,
+		Option<Read<'a, SubstepMask>>,
+	);
+	fn run(&mut self, (mut sampler, substep_mask): Self::SystemData) {
 		use rayon::prelude::*;
 
-		(&mut sampler).par_join().for_each(|mut sampler| {
+		let atoms = active_atoms(substep_mask.as_deref(), sampler.mask());
+		(&atoms, &mut sampler).par_join().for_each(|(_, mut sampler)| {
 			sampler.magnitude = sampler.field.norm();
 			if sampler.magnitude.is_nan() {
 				sampler.magnitude = 0.0;
@@ -117,12 +134,41 @@ impl<'a> System<'a> for AttachFieldSamplersToNewlyCreatedAtomsSystem {
 ///
 /// `deps`: any dependencies that must be completed before the magnetics systems run.
 pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>, deps: &[&str]) {
+	add_field_systems_to_dispatch(builder, deps);
+	add_event_systems_to_dispatch(builder, deps);
+}
+
+/// Adds the systems which sample the magnetic field and its derivatives to the dispatcher.
+///
+/// These systems may be run several times per frame to evaluate the forces on atoms, eg by a multistage integrator.
+///
+/// #Arguments
+///
+/// `builder`: the dispatch builder to modify
+///
+/// `deps`: any dependencies that must be completed before the magnetics systems run.
+pub fn add_field_systems_to_dispatch(
+	builder: &mut DispatcherBuilder<'static, 'static>,
+	deps: &[&str],
+) {
 	builder.add(ClearMagneticFieldSamplerSystem, "magnetics_clear", deps);
+	builder.add(
+		gradient::ClearMagneticGradientSamplerSystem,
+		"magnetics_gradient_clear",
+		deps,
+	);
+	builder.add(
+		envelope::UpdateFieldEnvelopeSystem,
+		"magnetics_envelope",
+		deps,
+	);
 	builder.add(
 		quadrupole::Sample3DQuadrupoleFieldSystem,
 		"magnetics_quadrupole",
 		&[
 			"magnetics_clear",
+			"magnetics_gradient_clear",
+			"magnetics_envelope",
 			crate::integrator::INTEGRATE_POSITION_SYSTEM_NAME,
 		],
 	);
@@ -141,10 +187,107 @@ pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>
 		"magnetics_grid",
 		&["magnetics_uniform", INTEGRATE_POSITION_SYSTEM_NAME],
 	);
+	builder.add(
+		crate::ramp::RampUpdateSystem::<coil::CoilCurrent>::default(),
+		"magnetics_coil_current_ramp",
+		deps,
+	);
+	builder.add(
+		coil::SampleCoilFieldSystem::<coil::CircularCoil>::default(),
+		"magnetics_circular_coil",
+		&["magnetics_grid", "magnetics_coil_current_ramp"],
+	);
+	builder.add(
+		coil::SampleCoilFieldSystem::<coil::Solenoid>::default(),
+		"magnetics_solenoid",
+		&["magnetics_circular_coil"],
+	);
+	builder.add(
+		coil::SampleCoilFieldSystem::<coil::RectangularCoil>::default(),
+		"magnetics_rectangular_coil",
+		&["magnetics_solenoid"],
+	);
+	builder.add(
+		coil::SampleCoilFieldSystem::<coil::PolylineWire>::default(),
+		"magnetics_polyline_wire",
+		&["magnetics_rectangular_coil"],
+	);
+	builder.add(
+		coil::SampleCoilFieldSystem::<coil::CoilPair>::default(),
+		"magnetics_coil_pair",
+		&["magnetics_polyline_wire"],
+	);
+	builder.add(
+		magnet::SampleMagnetFieldSystem::<magnet::DipoleMagnet>::default(),
+		"magnetics_dipole_magnet",
+		&["magnetics_coil_pair"],
+	);
+	builder.add(
+		magnet::SampleMagnetFieldSystem::<magnet::CuboidMagnet>::default(),
+		"magnetics_cuboid_magnet",
+		&["magnetics_dipole_magnet"],
+	);
+	builder.add(
+		magnet::SampleMagnetFieldSystem::<magnet::CylindricalMagnet>::default(),
+		"magnetics_cylindrical_magnet",
+		&["magnetics_cuboid_magnet"],
+	);
+	builder.add(
+		magnet::SampleMagnetFieldSystem::<magnet::MagnetStack>::default(),
+		"magnetics_magnet_stack",
+		&["magnetics_cylindrical_magnet"],
+	);
+	builder.add(
+		magnet::SampleMagnetFieldSystem::<magnet::HalbachArray>::default(),
+		"magnetics_halbach_array",
+		&["magnetics_magnet_stack"],
+	);
 	builder.add(
 		CalculateMagneticFieldMagnitudeSystem,
 		"magnetics_magnitude",
-		&["magnetics_grid"],
+		&["magnetics_halbach_array"],
+	);
+	builder.add(
+		gradient::CalculateMagnitudeGradientSystem,
+		"magnetics_magnitude_gradient",
+		&["magnetics_magnitude"],
+	);
+	builder.add(
+		zeeman::CalculateZeemanShiftSystem,
+		"zeeman_shift",
+		&["magnetics_magnitude"],
+	);
+}
+
+/// Adds the magnetics systems which must only run once per frame to the dispatcher, such as Majorana spin flips
+/// and the systems which attach components to new atoms.
+///
+/// These systems depend on the systems added by [add_field_systems_to_dispatch](fn.add_field_systems_to_dispatch.html),
+/// which must be added first.
+///
+/// #Arguments
+///
+/// `builder`: the dispatch builder to modify
+///
+/// `deps`: any dependencies that must be completed before the magnetics systems run.
+pub fn add_event_systems_to_dispatch(
+	builder: &mut DispatcherBuilder<'static, 'static>,
+	_deps: &[&str],
+) {
+	builder.add(
+		majorana::MajoranaSpinFlipSystem,
+		"magnetics_majorana",
+		&["magnetics_magnitude_gradient"],
+	);
+	builder.add(
+		gradient::AttachGradientSamplersToNewlyCreatedAtomsSystem,
+		"attach_magnetic_gradient_samplers_to_newly_created",
+		&[],
+	);
+	builder.add(
+		force::AttachGradientSamplersToMagneticDipolesSystem,
+		"attach_magnetic_gradient_samplers",
+		&[],
 	);
 	builder.add(
 		AttachFieldSamplersToNewlyCreatedAtomsSystem,
@@ -156,11 +299,6 @@ pub fn add_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>
 		"attach_zeeman_shift_samplers",
 		&[],
 	);
-	builder.add(
-		zeeman::CalculateZeemanShiftSystem,
-		"zeeman_shift",
-		&["magnetics_magnitude"],
-	);
 }
 
 /// Registers resources required by magnetics to the ecs world.
@@ -169,7 +307,23 @@ pub fn register_components(world: &mut World) {
 	world.register::<quadrupole::QuadrupoleField3D>();
 	world.register::<quadrupole::QuadrupoleField2D>();
 	world.register::<MagneticFieldSampler>();
+	world.register::<gradient::MagneticGradientSampler>();
+	world.register::<force::MagneticDipole>();
 	world.register::<grid::PrecalculatedMagneticFieldGrid>();
+	world.register::<coil::CoilCurrent>();
+	world.register::<crate::ramp::Ramp<coil::CoilCurrent>>();
+	world.register::<coil::CircularCoil>();
+	world.register::<coil::Solenoid>();
+	world.register::<coil::RectangularCoil>();
+	world.register::<coil::PolylineWire>();
+	world.register::<coil::CoilPair>();
+	world.register::<magnet::DipoleMagnet>();
+	world.register::<magnet::CuboidMagnet>();
+	world.register::<magnet::CylindricalMagnet>();
+	world.register::<magnet::MagnetStack>();
+	world.register::<magnet::HalbachArray>();
+	world.register::<region::FieldRegion>();
+	world.register::<envelope::FieldEnvelope>();
 }
 
 #[cfg(test)]
@@ -4,10 +4,14 @@ extern crate nalgebra;
 extern crate specs;
 use crate::atom::Position;
 
+use crate::magnetic::envelope::{envelope_scale, FieldEnvelope};
+use crate::magnetic::gradient::MagneticGradientSampler;
+use crate::magnetic::region::{in_region, FieldRegion};
 use crate::magnetic::MagneticFieldSampler;
 use crate::ramp::Lerp;
-use nalgebra::{Unit, Vector3};
-use specs::{Component, HashMapStorage, Join, ReadStorage, System, WriteStorage};
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::{Matrix3, Unit, Vector3};
+use specs::{Component, HashMapStorage, Join, Read, ReadStorage, System, WriteStorage};
 
 /// A component representing a 3D quadrupole field.
 #[derive(Clone, Lerp)]
@@ -58,29 +62,62 @@ impl Sample3DQuadrupoleFieldSystem {
         let r_comp = delta - z_comp;
         gradient * (r_comp - 2.0 * z_comp)
     }
+
+    /// Calculates the Jacobian of the quadrupole magnetic field, `J_ij = dB_i/dx_j`.
+    ///
+    /// # Arguments
+    ///
+    /// `gradient`: quadrupole gradient, in Tesla/m
+    ///
+    /// `direction`: A _normalized_ vector pointing in the direction of the quadrupole's symmetry axis.
+    pub fn calculate_jacobian(gradient: f64, direction: Vector3<f64>) -> Matrix3<f64> {
+        gradient * (Matrix3::identity() - 3.0 * direction * direction.transpose())
+    }
 }
 
 impl<'a> System<'a> for Sample3DQuadrupoleFieldSystem {
     type SystemData = (
         WriteStorage<'a, MagneticFieldSampler>,
+        WriteStorage<'a, MagneticGradientSampler>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, QuadrupoleField3D>,
+        ReadStorage<'a, FieldRegion>,
+        ReadStorage<'a, FieldEnvelope>,
+        Option<Read<'a, SubstepMask>>,
     );
-    fn run(&mut self, (mut sampler, pos, quadrupole): Self::SystemData) {
+    fn run(
+        &mut self,
+        (mut sampler, mut gradient_sampler, pos, quadrupole, regions, envelopes, substep_mask): Self::SystemData,
+    ) {
         use rayon::prelude::*;
         use specs::ParJoin;
 
-        for (centre, quadrupole) in (&pos, &quadrupole).join() {
-            (&pos, &mut sampler)
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for (centre, quadrupole, region, envelope) in
+            (&pos, &quadrupole, regions.maybe(), envelopes.maybe()).join()
+        {
+            let scale = envelope_scale(envelope);
+            (&atoms, &pos, &mut sampler)
                 .par_join()
-                .for_each(|(pos, mut sampler)| {
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
                     let quad_field = Sample3DQuadrupoleFieldSystem::calculate_field(
                         pos.pos,
                         centre.pos,
                         quadrupole.gradient,
                         quadrupole.direction,
                     );
-                    sampler.field = sampler.field + quad_field;
+                    sampler.field = sampler.field + scale * quad_field;
+                });
+            let jacobian = Sample3DQuadrupoleFieldSystem::calculate_jacobian(
+                quadrupole.gradient,
+                quadrupole.direction,
+            );
+            (&atoms, &pos, &mut gradient_sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, _, sampler)| {
+                    sampler.jacobian = sampler.jacobian + scale * jacobian;
                 });
         }
     }
@@ -92,6 +129,7 @@ impl<'a> System<'a> for Sample3DQuadrupoleFieldSystem {
 /// The coordinate system is aligned such that:
 ///  * `e_x` is in the direction `direction_out`
 ///  * `e_y` is in the direction `direction_in`.
+#[derive(Clone, Lerp)]
 pub struct QuadrupoleField2D {
     /// Gradient of the quadrupole field, `B'`, in units of Tesla/m
     gradient: f64,
@@ -150,26 +188,72 @@ impl Sample2DQuadrupoleFieldSystem {
         let out_comp = direction_out.dot(&delta) * direction_out;
         gradient * (out_comp - in_comp)
     }
+
+    /// Calculates the Jacobian of the 2D quadrupole magnetic field, `J_ij = dB_i/dx_j`.
+    ///
+    /// # Arguments
+    ///
+    /// `gradient`: quadrupole gradient, in Tesla/m
+    ///
+    /// `direction_in`: A unit vector in the direction for the field lines point in to the node.
+    ///
+    /// `direction_out`: A unit vector in the direction for the field lines point away from the node.
+    pub fn calculate_jacobian(
+        gradient: f64,
+        direction_in: Vector3<f64>,
+        direction_out: Vector3<f64>,
+    ) -> Matrix3<f64> {
+        gradient
+            * (direction_out * direction_out.transpose() - direction_in * direction_in.transpose())
+    }
 }
 
 impl<'a> System<'a> for Sample2DQuadrupoleFieldSystem {
     type SystemData = (
         WriteStorage<'a, MagneticFieldSampler>,
+        WriteStorage<'a, MagneticGradientSampler>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, QuadrupoleField2D>,
+        ReadStorage<'a, FieldRegion>,
+        ReadStorage<'a, FieldEnvelope>,
+        Option<Read<'a, SubstepMask>>,
     );
-    fn run(&mut self, (mut sampler, pos, quadrupole): Self::SystemData) {
-        for (centre, quadrupole) in (&pos, &quadrupole).join() {
-            for (pos, mut sampler) in (&pos, &mut sampler).join() {
-                let quad_field = Self::calculate_field(
-                    pos.pos,
-                    centre.pos,
-                    quadrupole.gradient,
-                    quadrupole.direction_in,
-                    quadrupole.direction_out,
-                );
-                sampler.field = sampler.field + quad_field;
-            }
+    fn run(
+        &mut self,
+        (mut sampler, mut gradient_sampler, pos, quadrupole, regions, envelopes, substep_mask): Self::SystemData,
+    ) {
+        use rayon::prelude::*;
+        use specs::ParJoin;
+
+        let atoms = active_atoms(substep_mask.as_deref(), pos.mask());
+        for (centre, quadrupole, region, envelope) in
+            (&pos, &quadrupole, regions.maybe(), envelopes.maybe()).join()
+        {
+            let scale = envelope_scale(envelope);
+            (&atoms, &pos, &mut sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, pos, sampler)| {
+                    let quad_field = Self::calculate_field(
+                        pos.pos,
+                        centre.pos,
+                        quadrupole.gradient,
+                        quadrupole.direction_in,
+                        quadrupole.direction_out,
+                    );
+                    sampler.field = sampler.field + scale * quad_field;
+                });
+            let jacobian = Self::calculate_jacobian(
+                quadrupole.gradient,
+                quadrupole.direction_in,
+                quadrupole.direction_out,
+            );
+            (&atoms, &pos, &mut gradient_sampler)
+                .par_join()
+                .filter(|(_, pos, _)| in_region(region, &centre.pos, &pos.pos))
+                .for_each(|(_, _, sampler)| {
+                    sampler.jacobian = sampler.jacobian + scale * jacobian;
+                });
         }
     }
 }
@@ -206,4 +290,35 @@ pub mod tests {
         );
         assert_eq!(field, Vector3::new(-1., 0.5, 0.));
     }
+
+    #[test]
+    fn test_quadrupole_jacobians() {
+        use crate::magnetic::gradient::finite_difference_jacobian;
+
+        let pos = Vector3::new(0.3, -0.2, 0.5);
+        let centre = Vector3::new(0.1, 0.0, -0.1);
+        let direction = Vector3::new(1.0, 2.0, 2.0).normalize();
+        let jacobian = Sample3DQuadrupoleFieldSystem::calculate_jacobian(0.7, direction);
+        let expected = finite_difference_jacobian(
+            |p| Sample3DQuadrupoleFieldSystem::calculate_field(*p, centre, 0.7, direction),
+            &pos,
+        );
+        assert!((jacobian - expected).norm() < 1e-9);
+
+        let jacobian =
+            Sample2DQuadrupoleFieldSystem::calculate_jacobian(0.7, Vector3::x(), Vector3::y());
+        let expected = finite_difference_jacobian(
+            |p| {
+                Sample2DQuadrupoleFieldSystem::calculate_field(
+                    *p,
+                    centre,
+                    0.7,
+                    Vector3::x(),
+                    Vector3::y(),
+                )
+            },
+            &pos,
+        );
+        assert!((jacobian - expected).norm() < 1e-9);
+    }
 }
@@ -0,0 +1,179 @@
+//! Bounding regions for localised magnetic field sources.
+//!
+//! Adding a [FieldRegion](struct.FieldRegion.html) to a field source entity restricts the
+//! source to atoms inside the region. The field of the source is not evaluated for atoms
+//! outside the region, which is treated as zero. This keeps simulations with many localised
+//! sources fast, because each source is only evaluated for the atoms near it.
+//!
+//! Sources without a `FieldRegion` are evaluated for all atoms.
+
+extern crate nalgebra;
+use crate::shapes::{Cuboid, Cylinder, Sphere, Volume};
+use nalgebra::Vector3;
+use specs::{Component, HashMapStorage};
+
+/// The shape of a [FieldRegion](struct.FieldRegion.html).
+pub enum RegionShape {
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+}
+
+/// A component that restricts a field source to atoms within a region.
+///
+/// The region is centred on the `Position` of the source entity. For a
+/// [PrecalculatedMagneticFieldGrid](../grid/struct.PrecalculatedMagneticFieldGrid.html),
+/// the region is centred on the grid's `position`.
+pub struct FieldRegion {
+    pub shape: RegionShape,
+}
+impl FieldRegion {
+    /// Creates a spherical region, with radius in m.
+    pub fn sphere(radius: f64) -> Self {
+        FieldRegion {
+            shape: RegionShape::Sphere(Sphere { radius: radius }),
+        }
+    }
+
+    /// Creates a cuboid region, with the half width along each axis in m.
+    pub fn cuboid(half_width: Vector3<f64>) -> Self {
+        FieldRegion {
+            shape: RegionShape::Cuboid(Cuboid {
+                half_width: half_width,
+            }),
+        }
+    }
+
+    /// Creates a cylindrical region, with radius and length in m.
+    pub fn cylinder(radius: f64, length: f64, direction: Vector3<f64>) -> Self {
+        FieldRegion {
+            shape: RegionShape::Cylinder(Cylinder::new(radius, length, direction)),
+        }
+    }
+
+    /// Returns true if `pos` lies within the region.
+    ///
+    /// # Arguments
+    ///
+    /// `centre`: position of the field source, m
+    ///
+    /// `pos`: position to test, m
+    pub fn contains(&self, centre: &Vector3<f64>, pos: &Vector3<f64>) -> bool {
+        match &self.shape {
+            RegionShape::Sphere(sphere) => sphere.contains(centre, pos),
+            RegionShape::Cuboid(cuboid) => cuboid.contains(centre, pos),
+            RegionShape::Cylinder(cylinder) => cylinder.contains(centre, pos),
+        }
+    }
+}
+impl Component for FieldRegion {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Returns true if a field source with an optional region should be evaluated at `pos`.
+pub fn in_region(region: Option<&FieldRegion>, centre: &Vector3<f64>, pos: &Vector3<f64>) -> bool {
+    match region {
+        Some(region) => region.contains(centre, pos),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_field_region_contains() {
+        let centre = Vector3::new(1.0, 0.0, 0.0);
+        let sphere = FieldRegion::sphere(0.1);
+        assert!(sphere.contains(&centre, &Vector3::new(1.05, 0.0, 0.0)));
+        assert!(!sphere.contains(&centre, &Vector3::new(0.0, 0.0, 0.0)));
+
+        let cuboid = FieldRegion::cuboid(Vector3::new(0.1, 0.2, 0.3));
+        assert!(cuboid.contains(&centre, &Vector3::new(1.05, 0.15, -0.25)));
+        assert!(!cuboid.contains(&centre, &Vector3::new(1.05, 0.25, 0.0)));
+
+        let cylinder = FieldRegion::cylinder(0.1, 1.0, Vector3::z());
+        assert!(cylinder.contains(&centre, &Vector3::new(1.05, 0.0, 0.45)));
+        assert!(!cylinder.contains(&centre, &Vector3::new(1.15, 0.0, 0.0)));
+
+        assert!(in_region(None, &centre, &Vector3::new(100.0, 0.0, 0.0)));
+        assert!(!in_region(
+            Some(&sphere),
+            &centre,
+            &Vector3::new(100.0, 0.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn test_sources_are_culled_outside_region() {
+        use crate::atom::Position;
+        use crate::magnetic::gradient::MagneticGradientSampler;
+        use crate::magnetic::quadrupole::{QuadrupoleField3D, Sample3DQuadrupoleFieldSystem};
+        use crate::magnetic::MagneticFieldSampler;
+        use specs::{Builder, RunNow, World, WorldExt};
+
+        let mut test_world = World::new();
+        test_world.register::<Position>();
+        test_world.register::<QuadrupoleField3D>();
+        test_world.register::<FieldRegion>();
+        test_world.register::<crate::magnetic::envelope::FieldEnvelope>();
+        test_world.register::<MagneticFieldSampler>();
+        test_world.register::<MagneticGradientSampler>();
+
+        test_world
+            .create_entity()
+            .with(QuadrupoleField3D::gauss_per_cm(100.0, Vector3::z()))
+            .with(FieldRegion::sphere(0.1))
+            .with(Position::new())
+            .build();
+        let inside = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.05, 0.0, 0.0),
+            })
+            .with(MagneticFieldSampler::default())
+            .with(MagneticGradientSampler::default())
+            .build();
+        let outside = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.5, 0.0, 0.0),
+            })
+            .with(MagneticFieldSampler::default())
+            .with(MagneticGradientSampler::default())
+            .build();
+
+        let mut system = Sample3DQuadrupoleFieldSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let samplers = test_world.read_storage::<MagneticFieldSampler>();
+        let gradients = test_world.read_storage::<MagneticGradientSampler>();
+        assert_eq!(
+            samplers.get(inside).expect("entity not found").field,
+            Vector3::new(0.05, 0.0, 0.0)
+        );
+        assert_eq!(
+            samplers.get(outside).expect("entity not found").field,
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+        assert!(
+            gradients
+                .get(inside)
+                .expect("entity not found")
+                .jacobian
+                .norm()
+                > 0.0
+        );
+        assert_eq!(
+            gradients
+                .get(outside)
+                .expect("entity not found")
+                .jacobian
+                .norm(),
+            0.0
+        );
+    }
+}
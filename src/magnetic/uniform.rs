@@ -3,9 +3,11 @@
 extern crate nalgebra;
 extern crate specs;
 use super::MagneticFieldSampler;
+use crate::magnetic::envelope::{envelope_scale, FieldEnvelope};
 use crate::ramp::Lerp;
+use crate::substep::{active_atoms, SubstepMask};
 use nalgebra::Vector3;
-use specs::{Component, HashMapStorage, Join, ReadStorage, System, WriteStorage};
+use specs::{Component, HashMapStorage, Join, Read, ReadStorage, System, WriteStorage};
 
 /// A component representing a uniform bias field, of the form `B = [ B_x, B_y, B_z ]`
 #[derive(Clone, Lerp)]
@@ -39,14 +41,18 @@ impl<'a> System<'a> for UniformMagneticFieldSystem {
     type SystemData = (
         WriteStorage<'a, MagneticFieldSampler>,
         ReadStorage<'a, UniformMagneticField>,
+        ReadStorage<'a, FieldEnvelope>,
+        Option<Read<'a, SubstepMask>>,
     );
-    fn run(&mut self, (mut samplers, fields): Self::SystemData) {
+    fn run(&mut self, (mut samplers, fields, envelopes, substep_mask): Self::SystemData) {
         use rayon::prelude::*;
         use specs::ParJoin;
 
-        for field in (&fields).join() {
-            (&mut samplers).par_join().for_each(|sampler| {
-                sampler.field = sampler.field + field.field;
+        let atoms = active_atoms(substep_mask.as_deref(), samplers.mask());
+        for (field, envelope) in (&fields, envelopes.maybe()).join() {
+            let scaled = envelope_scale(envelope) * field.field;
+            (&atoms, &mut samplers).par_join().for_each(|(_, sampler)| {
+                sampler.field = sampler.field + scaled;
             });
         }
     }
@@ -1,10 +1,385 @@
 //! Shift in an atom's transition frequency due to a magnetic field (zeeman effect)
+//!
+//! The shift is calculated according to the [ZeemanModel](enum.ZeemanModel.html) of each atom's
+//! `AtomicTransition`. The `Linear` model is accurate for weak fields. The `BreitRabi` model
+//! calculates the energies of the hyperfine sublevels exactly, which is required for strong fields
+//! such as those in Zeeman slowers, where the hyperfine structure is decoupled.
 
 use super::MagneticFieldSampler;
 use crate::atom::AtomicTransition;
-use crate::constant::HBAR;
+use crate::constant::{BOHRMAG, HBAR, PI};
 use crate::initiate::NewlyCreated;
+use crate::substep::{active_atoms, SubstepMask};
+use nalgebra::DMatrix;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
+use std::sync::OnceLock;
+
+/// Describes how the transition frequencies of an atom depend on the magnetic field.
+#[derive(Deserialize, Serialize, Clone)]
+pub enum ZeemanModel {
+    /// Transitions are shifted linearly with field, according to the `mup`, `mum` and `muz` of the `AtomicTransition`.
+    Linear,
+    /// Transitions are shifted according to the energies of the hyperfine sublevels, calculated
+    /// including the hyperfine interaction.
+    BreitRabi(BreitRabiParameters),
+}
+impl Default for ZeemanModel {
+    fn default() -> Self {
+        ZeemanModel::Linear
+    }
+}
+
+/// Fine-structure level of an atom, with its hyperfine constants.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HyperfineLevel {
+    /// Total electronic angular momentum, `J`.
+    pub j: f64,
+    /// Landé g-factor of the fine-structure level, `g_J`.
+    pub g_j: f64,
+    /// Magnetic dipole hyperfine constant, `A`, in Hz.
+    pub a: f64,
+    /// Electric quadrupole hyperfine constant, `B`, in Hz.
+    pub b: f64,
+}
+
+/// Parameters required to calculate the shifts of a cooling transition between hyperfine sublevels.
+///
+/// The cooling transition is driven from the ground sublevel `|F, m_F>` to the excited sublevels
+/// `|F', m_F + q>`, where `q = +1, -1, 0` for the sigma plus, sigma minus and pi transitions.
+/// Transitions to sublevels that do not exist are given zero shift.
+///
+/// The field-independent parts of the calculation are computed the first time the shifts are calculated,
+/// so the parameters should not be modified afterwards.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BreitRabiParameters {
+    /// Nuclear spin, `I`.
+    pub nuclear_spin: f64,
+    /// Nuclear g-factor, `g_I`, using the sign convention where the Zeeman Hamiltonian is `mu_B (g_J J_z + g_I I_z) B`.
+    pub g_i: f64,
+    /// The ground state, which must have `J = 1/2`.
+    pub ground: HyperfineLevel,
+    /// The excited state.
+    pub excited: HyperfineLevel,
+    /// Total angular momentum `F` of the ground sublevel.
+    pub ground_f: f64,
+    /// Magnetic quantum number `m_F` of the ground sublevel.
+    pub ground_mf: f64,
+    /// Total angular momentum `F'` of the excited sublevels.
+    pub excited_f: f64,
+    /// Sublevels of the transition, computed on first use.
+    #[serde(skip)]
+    sublevels: OnceLock<BreitRabiSublevels>,
+}
+
+/// The sublevels of a [BreitRabiParameters](struct.BreitRabiParameters.html) transition.
+#[derive(Clone)]
+struct BreitRabiSublevels {
+    /// Energy of the ground sublevel at zero field, in Joules.
+    ground_zero_field_energy: f64,
+    /// The excited sublevels of the sigma plus, sigma minus and pi transitions, if they exist.
+    excited: [Option<HyperfineSublevel>; 3],
+}
+
+impl BreitRabiParameters {
+    /// Creates the parameters of a cooling transition from the ground sublevel `|F=ground_f, m_F=ground_mf>`
+    /// to the excited sublevels with `F'=excited_f`.
+    pub fn new(
+        nuclear_spin: f64,
+        g_i: f64,
+        ground: HyperfineLevel,
+        excited: HyperfineLevel,
+        ground_f: f64,
+        ground_mf: f64,
+        excited_f: f64,
+    ) -> Self {
+        BreitRabiParameters {
+            nuclear_spin: nuclear_spin,
+            g_i: g_i,
+            ground: ground,
+            excited: excited,
+            ground_f: ground_f,
+            ground_mf: ground_mf,
+            excited_f: excited_f,
+            sublevels: OnceLock::new(),
+        }
+    }
+
+    /// Parameters for the `|F=2, m_F=2> -> |F'=3>` D2 cycling transition of rubidium 87.
+    /// The parameters are taken from Daniel Steck's Data sheet on Rubidium-87.
+    pub fn rubidium87_d2() -> Self {
+        BreitRabiParameters::new(
+            1.5,
+            -0.0009951414,
+            HyperfineLevel {
+                j: 0.5,
+                g_j: 2.00233113,
+                a: 3.417341305452145e9,
+                b: 0.0,
+            },
+            HyperfineLevel {
+                j: 1.5,
+                g_j: 1.3362,
+                a: 84.7185e6,
+                b: 12.4965e6,
+            },
+            2.0,
+            2.0,
+            3.0,
+        )
+    }
+
+    fn sublevels(&self) -> &BreitRabiSublevels {
+        self.sublevels.get_or_init(|| {
+            let excited = |q: f64| {
+                let mf = self.ground_mf + q;
+                if mf.abs() > self.excited_f {
+                    return None;
+                }
+                Some(HyperfineSublevel::new(
+                    &self.excited,
+                    self.nuclear_spin,
+                    self.g_i,
+                    self.excited_f,
+                    mf,
+                ))
+            };
+            BreitRabiSublevels {
+                ground_zero_field_energy: breit_rabi_energy(
+                    &self.ground,
+                    self.nuclear_spin,
+                    self.g_i,
+                    self.ground_f,
+                    self.ground_mf,
+                    0.0,
+                ),
+                excited: [excited(1.0), excited(-1.0), excited(0.0)],
+            }
+        })
+    }
+
+    /// Calculates the Zeeman shifts of the sigma plus, sigma minus and pi transitions, in rad/s.
+    pub fn transition_shifts(&self, field: f64) -> (f64, f64, f64) {
+        let sublevels = self.sublevels();
+        let ground = breit_rabi_energy(
+            &self.ground,
+            self.nuclear_spin,
+            self.g_i,
+            self.ground_f,
+            self.ground_mf,
+            field,
+        ) - sublevels.ground_zero_field_energy;
+        let shift = |excited: &Option<HyperfineSublevel>| match excited {
+            Some(excited) => (excited.shift(field) - ground) / HBAR,
+            None => 0.0,
+        };
+        (
+            shift(&sublevels.excited[0]),
+            shift(&sublevels.excited[1]),
+            shift(&sublevels.excited[2]),
+        )
+    }
+}
+
+/// Energy of the sublevel `|F, m_F>` of a `J = 1/2` level in a magnetic field, in Joules,
+/// calculated using the Breit-Rabi formula.
+///
+/// # Arguments
+///
+/// `level`: the fine-structure level, which must have `J = 1/2`.
+///
+/// `nuclear_spin`: nuclear spin, `I`.
+///
+/// `g_i`: nuclear g-factor.
+///
+/// `f`: total angular momentum of the sublevel, either `I + 1/2` or `I - 1/2`.
+///
+/// `mf`: magnetic quantum number of the sublevel.
+///
+/// `field`: magnitude of the magnetic field, in Tesla.
+pub fn breit_rabi_energy(
+    level: &HyperfineLevel,
+    nuclear_spin: f64,
+    g_i: f64,
+    f: f64,
+    mf: f64,
+    field: f64,
+) -> f64 {
+    let splitting = 2.0 * PI * HBAR * level.a * (nuclear_spin + 0.5);
+    let x = (level.g_j - g_i) * BOHRMAG * field / splitting;
+    let offset = -splitting / (2.0 * (2.0 * nuclear_spin + 1.0)) + g_i * BOHRMAG * mf * field;
+    if mf.abs() == nuclear_spin + 0.5 {
+        // stretched states, for which the square root must not be taken.
+        return offset + splitting / 2.0 * (1.0 + 2.0 * mf * x / (2.0 * nuclear_spin + 1.0));
+    }
+    let sign = if f > nuclear_spin { 1.0 } else { -1.0 };
+    let root = (1.0 + 4.0 * mf * x / (2.0 * nuclear_spin + 1.0) + x.powi(2)).sqrt();
+    offset + sign * splitting / 2.0 * root
+}
+
+/// Energy of the sublevel `|F, m_F>` of a fine-structure level in a magnetic field, in Joules.
+///
+/// See [HyperfineSublevel](struct.HyperfineSublevel.html), which should be used to calculate the energy
+/// at many different fields.
+///
+/// # Arguments
+///
+/// `level`: the fine-structure level.
+///
+/// `nuclear_spin`: nuclear spin, `I`.
+///
+/// `g_i`: nuclear g-factor.
+///
+/// `f`: total angular momentum of the sublevel.
+///
+/// `mf`: magnetic quantum number of the sublevel.
+///
+/// `field`: magnitude of the magnetic field, in Tesla.
+pub fn hyperfine_energy(
+    level: &HyperfineLevel,
+    nuclear_spin: f64,
+    g_i: f64,
+    f: f64,
+    mf: f64,
+    field: f64,
+) -> f64 {
+    HyperfineSublevel::new(level, nuclear_spin, g_i, f, mf).energy(field)
+}
+
+/// A sublevel `|F, m_F>` of a fine-structure level, used to calculate its energy in a magnetic field.
+///
+/// The hyperfine and Zeeman Hamiltonian is diagonalised in the `|m_J, m_I>` basis for the given `m_F`.
+/// Sublevels are identified with eigenvalues by their order at zero field, which is preserved
+/// because levels with the same `m_F` do not cross. The basis, the field-independent hyperfine
+/// Hamiltonian and the order of the sublevel are calculated once, when the sublevel is created.
+#[derive(Clone)]
+pub struct HyperfineSublevel {
+    /// Hyperfine Hamiltonian in the `|m_J, m_I>` basis, in Joules.
+    hyperfine: DMatrix<f64>,
+    /// Zeeman energy of each basis state per unit field, in J/T.
+    zeeman: Vec<f64>,
+    /// Position of the sublevel among the eigenvalues, in increasing order of energy.
+    rank: usize,
+    /// Energy of the sublevel at zero field, in Joules.
+    zero_field_energy: f64,
+}
+
+impl HyperfineSublevel {
+    /// Creates the sublevel `|F, m_F>` of the given fine-structure level.
+    ///
+    /// # Arguments
+    ///
+    /// `level`: the fine-structure level.
+    ///
+    /// `nuclear_spin`: nuclear spin, `I`.
+    ///
+    /// `g_i`: nuclear g-factor.
+    ///
+    /// `f`: total angular momentum of the sublevel.
+    ///
+    /// `mf`: magnetic quantum number of the sublevel.
+    pub fn new(level: &HyperfineLevel, nuclear_spin: f64, g_i: f64, f: f64, mf: f64) -> Self {
+        let (i, j) = (nuclear_spin, level.j);
+        let h = 2.0 * PI * HBAR;
+
+        // basis states |m_J, m_I> with m_J + m_I = m_F
+        let basis: Vec<(f64, f64)> = (0..=(2.0 * j).round() as usize)
+            .map(|n| -j + n as f64)
+            .map(|mj| (mj, mf - mj))
+            .filter(|(_, mi)| mi.abs() <= i + 1e-9)
+            .collect();
+        let size = basis.len();
+
+        // matrix of I.J, from I_z J_z + (I_+ J_- + I_- J_+) / 2
+        let ladder = |jm: f64, m: f64| (jm * (jm + 1.0) - m * (m + 1.0)).max(0.0).sqrt();
+        let mut i_dot_j = DMatrix::<f64>::zeros(size, size);
+        for (row, (mj, mi)) in basis.iter().enumerate() {
+            i_dot_j[(row, row)] = mj * mi;
+            for (column, (mj2, mi2)) in basis.iter().enumerate() {
+                if (mj2 - (mj + 1.0)).abs() < 1e-9 && (mi2 - (mi - 1.0)).abs() < 1e-9 {
+                    let element = 0.5 * ladder(j, *mj) * ladder(i, mi - 1.0);
+                    i_dot_j[(row, column)] = element;
+                    i_dot_j[(column, row)] = element;
+                }
+            }
+        }
+
+        let quadrupole_denominator = 2.0 * i * (2.0 * i - 1.0) * j * (2.0 * j - 1.0);
+        let quadrupole = |k: f64| {
+            if quadrupole_denominator > 0.0 {
+                (3.0 * k.powi(2) + 1.5 * k - i * (i + 1.0) * j * (j + 1.0)) / quadrupole_denominator
+            } else {
+                0.0
+            }
+        };
+
+        let mut hamiltonian = h * level.a * &i_dot_j;
+        if quadrupole_denominator > 0.0 {
+            let identity = DMatrix::<f64>::identity(size, size);
+            hamiltonian += h
+                * level.b
+                * (3.0 * &i_dot_j * &i_dot_j + 1.5 * &i_dot_j
+                    - i * (i + 1.0) * j * (j + 1.0) * identity)
+                / quadrupole_denominator;
+        }
+        let zeeman = basis
+            .iter()
+            .map(|(mj, mi)| BOHRMAG * (level.g_j * mj + g_i * mi))
+            .collect();
+
+        // zero-field energies of the hyperfine levels with this m_F
+        let zero_field_energy = |f: f64| {
+            let k = 0.5 * (f * (f + 1.0) - i * (i + 1.0) - j * (j + 1.0));
+            h * (level.a * k + level.b * quadrupole(k))
+        };
+        let mut levels: Vec<f64> = (0..=(2.0 * j.min(i)).round() as usize)
+            .map(|n| (i - j).abs() + n as f64)
+            .filter(|level_f| *level_f >= mf.abs() - 1e-9)
+            .collect();
+        levels.sort_by(|a, b| {
+            zero_field_energy(*a)
+                .total_cmp(&zero_field_energy(*b))
+                .then(a.total_cmp(b))
+        });
+        let rank = levels
+            .iter()
+            .position(|level_f| (level_f - f).abs() < 1e-9)
+            .expect("No hyperfine sublevel with the given F and m_F.");
+
+        let mut sublevel = HyperfineSublevel {
+            hyperfine: hamiltonian,
+            zeeman: zeeman,
+            rank: rank,
+            zero_field_energy: 0.0,
+        };
+        sublevel.zero_field_energy = sublevel.energy(0.0);
+        sublevel
+    }
+
+    /// Energy of the sublevel in a magnetic field of the given magnitude, in Tesla, in Joules.
+    pub fn energy(&self, field: f64) -> f64 {
+        if self.zeeman.len() == 1 {
+            // stretched states, which are not mixed by the field.
+            return self.hyperfine[(0, 0)] + self.zeeman[0] * field;
+        }
+        let mut hamiltonian = self.hyperfine.clone();
+        for (n, zeeman) in self.zeeman.iter().enumerate() {
+            hamiltonian[(n, n)] += zeeman * field;
+        }
+        let mut energies: Vec<f64> = hamiltonian
+            .symmetric_eigenvalues()
+            .iter()
+            .cloned()
+            .collect();
+        energies.sort_by(|a, b| a.total_cmp(b));
+        energies[self.rank]
+    }
+
+    /// Shift of the energy of the sublevel in a magnetic field of the given magnitude, in Tesla, in Joules.
+    pub fn shift(&self, field: f64) -> f64 {
+        self.energy(field) - self.zero_field_energy
+    }
+}
 
 /// Represents the (angular) Zeemanshift of the atom depending on the magnetic field it experiences
 #[derive(Clone)]
@@ -58,25 +433,39 @@ impl<'a> System<'a> for CalculateZeemanShiftSystem {
         WriteStorage<'a, ZeemanShiftSampler>,
         ReadStorage<'a, MagneticFieldSampler>,
         ReadStorage<'a, AtomicTransition>,
+        Option<Read<'a, SubstepMask>>,
     );
 
     fn run(
         &mut self,
-        (mut zeeman_sampler, magnetic_field_sampler, atomic_transition): Self::SystemData,
+        (mut zeeman_sampler, magnetic_field_sampler, atomic_transition, substep_mask): Self::SystemData,
     ) {
         use rayon::prelude::*;
 
+        let atoms = active_atoms(substep_mask.as_deref(), zeeman_sampler.mask());
         (
+            &atoms,
             &mut zeeman_sampler,
             &magnetic_field_sampler,
             &atomic_transition,
         )
             .par_join()
-            .for_each(|(zeeman, magnetic_field, atom_info)| {
-                zeeman.sigma_plus = atom_info.mup / HBAR * magnetic_field.magnitude;
-                zeeman.sigma_minus = atom_info.mum / HBAR * magnetic_field.magnitude;
-                zeeman.sigma_pi = atom_info.muz / HBAR * magnetic_field.magnitude;
-            });
+            .for_each(
+                |(_, zeeman, magnetic_field, atom_info)| match &atom_info.zeeman_model {
+                    ZeemanModel::Linear => {
+                        zeeman.sigma_plus = atom_info.mup / HBAR * magnetic_field.magnitude;
+                        zeeman.sigma_minus = atom_info.mum / HBAR * magnetic_field.magnitude;
+                        zeeman.sigma_pi = atom_info.muz / HBAR * magnetic_field.magnitude;
+                    }
+                    ZeemanModel::BreitRabi(parameters) => {
+                        let (sigma_plus, sigma_minus, sigma_pi) =
+                            parameters.transition_shifts(magnetic_field.magnitude);
+                        zeeman.sigma_plus = sigma_plus;
+                        zeeman.sigma_minus = sigma_minus;
+                        zeeman.sigma_pi = sigma_pi;
+                    }
+                },
+            );
     }
 }
 
@@ -139,4 +528,79 @@ pub mod tests {
             1e-5_f64
         );
     }
+
+    #[test]
+    fn test_breit_rabi_matches_diagonalisation() {
+        let parameters = BreitRabiParameters::rubidium87_d2();
+        for field in &[0.0, 1e-4, 0.03, 0.5] {
+            for (f, mf) in &[(2.0, 2.0), (2.0, -2.0), (2.0, 1.0), (1.0, 1.0), (1.0, -1.0)] {
+                let exact = breit_rabi_energy(
+                    &parameters.ground,
+                    parameters.nuclear_spin,
+                    parameters.g_i,
+                    *f,
+                    *mf,
+                    *field,
+                );
+                let diagonalised = hyperfine_energy(
+                    &parameters.ground,
+                    parameters.nuclear_spin,
+                    parameters.g_i,
+                    *f,
+                    *mf,
+                    *field,
+                );
+                assert_approx_eq!(exact, diagonalised, 1e-9 * exact.abs().max(1e-30));
+            }
+        }
+    }
+
+    #[test]
+    fn test_precomputed_sublevels() {
+        let parameters = BreitRabiParameters::rubidium87_d2();
+        let deserialized: BreitRabiParameters =
+            serde_json::from_str(&serde_json::to_string(&parameters).unwrap()).unwrap();
+        let field = 0.02;
+        let ground = breit_rabi_energy(&parameters.ground, 1.5, parameters.g_i, 2.0, 2.0, field)
+            - breit_rabi_energy(&parameters.ground, 1.5, parameters.g_i, 2.0, 2.0, 0.0);
+        for (q, shift) in &[
+            (1.0, parameters.transition_shifts(field).0),
+            (-1.0, deserialized.transition_shifts(field).1),
+            (0.0, parameters.transition_shifts(field).2),
+        ] {
+            let excited =
+                hyperfine_energy(
+                    &parameters.excited,
+                    1.5,
+                    parameters.g_i,
+                    3.0,
+                    2.0 + q,
+                    field,
+                ) - hyperfine_energy(&parameters.excited, 1.5, parameters.g_i, 3.0, 2.0 + q, 0.0);
+            let expected = (excited - ground) / HBAR;
+            assert_approx_eq!(*shift, expected, 1e-9 * expected.abs());
+        }
+    }
+
+    #[test]
+    fn test_breit_rabi_shifts() {
+        let parameters = BreitRabiParameters::rubidium87_d2();
+
+        // In weak fields, the stretched cycling transition is shifted by approximately mu_B B,
+        // as in the linear model for rubidium.
+        let field = 1e-5;
+        let (sigma_plus, _, _) = parameters.transition_shifts(field);
+        assert_approx_eq!(sigma_plus, BOHRMAG * field / HBAR, 1e-2 * sigma_plus);
+
+        // The stretched transition remains linear in strong fields, with (g_J' 3/2 - g_J 1/2) mu_B B.
+        let field = 0.1;
+        let (sigma_plus, sigma_minus, _) = parameters.transition_shifts(field);
+        let expected =
+            (parameters.excited.g_j * 1.5 - parameters.ground.g_j * 0.5) * BOHRMAG * field / HBAR;
+        assert_approx_eq!(sigma_plus, expected, 1e-6 * expected);
+
+        // The sigma minus transition is nonlinear, and departs from the weak-field value of (2/3 - 1) mu_B B.
+        let linear = (2.0 / 3.0 * 1.0 - 0.5 * 2.0) * BOHRMAG * field / HBAR;
+        assert!((sigma_minus - linear).abs() > 0.1 * linear.abs());
+    }
 }
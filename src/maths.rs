@@ -31,6 +31,119 @@ pub fn gaussian_dis(std: f64, distance: f64) -> f64 {
 	1.0 / (2.0 * PI * std * std) * EXP.powf(-distance * distance / 2.0 / (std * std))
 }
 
+/// Complete elliptic integral of the first kind, `K(m)`, for parameter `m = k^2`.
+///
+/// Calculated using the arithmetic-geometric mean.
+pub fn elliptic_k(m: f64) -> f64 {
+	let mut a = 1.0;
+	let mut g = (1.0 - m).sqrt();
+	while (a - g).abs() > 1.0e-15 * a {
+		let a_next = (a + g) / 2.0;
+		g = (a * g).sqrt();
+		a = a_next;
+	}
+	PI / (2.0 * a)
+}
+
+/// Complete elliptic integral of the second kind, `E(m)`, for parameter `m = k^2`.
+///
+/// Calculated using the arithmetic-geometric mean.
+pub fn elliptic_e(m: f64) -> f64 {
+	let mut a = 1.0;
+	let mut g = (1.0 - m).sqrt();
+	let mut sum = m / 2.0;
+	let mut power = 0.5;
+	while (a - g).abs() > 1.0e-15 * a {
+		let c = (a - g) / 2.0;
+		let a_next = (a + g) / 2.0;
+		g = (a * g).sqrt();
+		a = a_next;
+		power = power * 2.0;
+		sum = sum + power * c * c;
+	}
+	PI / (2.0 * a) * (1.0 - sum)
+}
+
+/// Smallest magnitude of the complementary modulus `kc` used by [elliptic_cel](fn.elliptic_cel.html).
+pub const MIN_KC: f64 = 1.0e-15;
+
+/// Bulirsch's generalised complete elliptic integral,
+/// `cel(kc, p, c, s) = int_0^(pi/2) (c cos^2 + s sin^2) / ((cos^2 + p sin^2) sqrt(cos^2 + kc^2 sin^2)) dphi`.
+///
+/// See Derby and Olbert, Am. J. Phys. 78, 229 (2010), doi:10.1119/1.3256157.
+///
+/// The integral diverges logarithmically as `kc` tends to zero. To keep the result finite, `kc` is
+/// limited to a minimum magnitude of `MIN_KC`.
+pub fn elliptic_cel(kc: f64, p: f64, c: f64, s: f64) -> f64 {
+	let tolerance = 1.0e-12;
+	let max_iterations = 100;
+	let kc = kc.abs().max(MIN_KC);
+	let mut k = kc;
+	let mut pp = p;
+	let mut cc = c;
+	let mut ss = s;
+	let mut em = 1.0;
+	if p > 0.0 {
+		pp = p.sqrt();
+		ss = s / pp;
+	} else {
+		let mut f = kc * kc;
+		let mut q = 1.0 - f;
+		let g = 1.0 - pp;
+		f = f - pp;
+		q = q * (ss - c * pp);
+		pp = (f / g).sqrt();
+		cc = (c - ss) / g;
+		ss = -q / (g * g * pp) + cc * pp;
+	}
+	let mut f = cc;
+	cc = cc + ss / pp;
+	let mut g = k / pp;
+	ss = 2.0 * (ss + f * g);
+	pp = g + pp;
+	g = em;
+	em = k + em;
+	let mut kk = k;
+	let mut iterations = 0;
+	while (g - k).abs() > g * tolerance && iterations < max_iterations {
+		iterations = iterations + 1;
+		k = 2.0 * kk.sqrt();
+		kk = k * em;
+		f = cc;
+		cc = cc + ss / pp;
+		g = kk / pp;
+		ss = 2.0 * (ss + f * g);
+		pp = g + pp;
+		g = em;
+		em = k + em;
+	}
+	(PI / 2.0) * (ss + cc * em) / (em * (em + pp))
+}
+
+/// The error function, `erf(x) = 2/sqrt(pi) int_0^x exp(-t^2) dt`.
+///
+/// Uses the Chebyshev approximation of the complementary error function from Numerical Recipes,
+/// which has a fractional error below 1.2e-7 everywhere.
+pub fn erf(x: f64) -> f64 {
+	let z = x.abs();
+	let t = 1.0 / (1.0 + 0.5 * z);
+	let erfc = t
+		* (-z * z - 1.26551223
+			+ t * (1.00002368
+				+ t * (0.37409196
+					+ t * (0.09678418
+						+ t * (-0.18628806
+							+ t * (0.27886807
+								+ t * (-1.13520398
+									+ t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+			.exp();
+	if x >= 0.0 {
+		1.0 - erfc
+	} else {
+		erfc - 1.0
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -43,4 +156,39 @@ mod tests {
 		let distance = get_minimum_distance_line_point(&pos, &centre, &dir);
 		assert!(distance > 0.942, distance < 0.943);
 	}
+
+	#[test]
+	fn test_erf() {
+		use assert_approx_eq::assert_approx_eq;
+		// Reference values from Abramowitz and Stegun, Table 7.1.
+		assert_approx_eq!(erf(0.0), 0.0, 1e-7);
+		assert_approx_eq!(erf(0.5), 0.5204998778, 1e-7);
+		assert_approx_eq!(erf(1.0), 0.8427007929, 1e-7);
+		assert_approx_eq!(erf(-1.0), -0.8427007929, 1e-7);
+		assert_approx_eq!(erf(2.0), 0.9953222650, 1e-7);
+		assert_eq!(erf(std::f64::INFINITY), 1.0);
+	}
+
+	#[test]
+	fn test_elliptic_integrals() {
+		use assert_approx_eq::assert_approx_eq;
+		// Reference values from Abramowitz and Stegun, Table 17.1.
+		assert_approx_eq!(elliptic_k(0.0), PI / 2.0, 1e-12);
+		assert_approx_eq!(elliptic_e(0.0), PI / 2.0, 1e-12);
+		assert_approx_eq!(elliptic_k(0.5), 1.854_074_677_301_372, 1e-12);
+		assert_approx_eq!(elliptic_e(0.5), 1.350_643_881_047_675, 1e-12);
+		assert_approx_eq!(elliptic_k(0.9), 2.578_092_113_348_173, 1e-12);
+		assert_approx_eq!(elliptic_e(0.9), 1.104_774_732_704_073, 1e-12);
+		// The generalised integral reduces to K and E for particular arguments.
+		let kc = 0.5_f64.sqrt();
+		assert_approx_eq!(elliptic_cel(kc, 1.0, 1.0, 1.0), elliptic_k(0.5), 1e-12);
+		assert_approx_eq!(elliptic_cel(kc, 1.0, 1.0, kc * kc), elliptic_e(0.5), 1e-12);
+	}
+
+	#[test]
+	fn test_elliptic_cel_finite_at_zero_kc() {
+		assert!(elliptic_cel(0.0, 1.0, 1.0, -1.0).is_finite());
+		assert!(elliptic_cel(0.0, 0.0, 1.0, 0.0).is_finite());
+		assert!(elliptic_cel(-0.0, 0.0, 1.0, 1.0).is_finite());
+	}
 }
@@ -1,8 +1,8 @@
 //! Writes diagnostic output to the console window.
 
 use crate::atom::*;
-use crate::integrator::{Step, Timestep};
-use specs::{Join, ReadExpect, ReadStorage, System};
+use crate::integrator::{SimulationTime, Step};
+use specs::{Join, Read, ReadExpect, ReadStorage, System};
 
 /// A system that writes diagnostic output to the console window.
 pub struct ConsoleOutputSystem;
@@ -10,14 +10,28 @@ pub struct ConsoleOutputSystem;
 impl<'a> System<'a> for ConsoleOutputSystem {
     type SystemData = (
         ReadStorage<'a, Atom>,
+        ReadStorage<'a, Weight>,
         ReadExpect<'a, Step>,
-        ReadExpect<'a, Timestep>,
+        Read<'a, SimulationTime>,
     );
-    fn run(&mut self, (atom, step, timestep): Self::SystemData) {
-        let _time = timestep.delta * step.n as f64;
+    fn run(&mut self, (atom, weights, step, time): Self::SystemData) {
         if step.n % 100 == 0 {
             let atom_number = (&atom).join().count();
-            println!("Step {}: simulating {} atoms.", step.n, atom_number);
+            if (&atom, &weights).join().next().is_some() {
+                let weighted_number: f64 = (&atom, weights.maybe())
+                    .join()
+                    .map(|(_, weight)| Weight::of(weight))
+                    .sum();
+                println!(
+                    "Step {} (t = {:.3e} s): simulating {} atoms, with a total weight of {}.",
+                    step.n, time.time, atom_number, weighted_number
+                );
+            } else {
+                println!(
+                    "Step {} (t = {:.3e} s): simulating {} atoms.",
+                    step.n, time.time, atom_number
+                );
+            }
         }
     }
 }
@@ -0,0 +1,251 @@
+//! Records atoms which escape the simulation, so that they can be replayed as a source in another simulation.
+//!
+//! Atoms are recorded when they cross a [RecordingPlane](struct.RecordingPlane.html), or when they leave
+//! the [SimulationVolume](../../sim_region/struct.SimulationVolume.html)s of the world. Records are only
+//! made if the [EscapedAtoms](struct.EscapedAtoms.html) resource has been added to the world.
+//!
+//! The recorded atoms can be written to a CSV file, which is read by
+//! [AtomFileSource::from_csv](../../atom_sources/file/struct.AtomFileSource.html#method.from_csv), or
+//! converted directly into an [AtomFileSource](../../atom_sources/file/struct.AtomFileSource.html).
+//! This allows a simulation to be split into stages; for instance, the beam produced by a 2D+ MOT can be
+//! simulated once, and then used to load many different 3D MOTs.
+
+use crate::atom::{Atom, Mass, Position, Velocity, Weight};
+use crate::atom_sources::file::{AtomFileError, AtomFileSource, AtomRecord, FileEmission};
+use crate::destructor::ToBeDestroyed;
+use crate::integrator::{SimulationTime, Timestep};
+use nalgebra::Vector3;
+use specs::prelude::*;
+
+/// A resource which holds the records of atoms that have escaped the simulation.
+#[derive(Default)]
+pub struct EscapedAtoms {
+    pub records: Vec<AtomRecord>,
+}
+impl EscapedAtoms {
+    /// Writes the records to a CSV file, in the format read by
+    /// [AtomFileSource::from_csv](../../atom_sources/file/struct.AtomFileSource.html#method.from_csv).
+    ///
+    /// Returns an error if the file cannot be created or written.
+    pub fn write_csv(&self, file_name: &str) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_path(file_name)?;
+        for record in &self.records {
+            writer.serialize(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Creates an [AtomFileSource](../../atom_sources/file/struct.AtomFileSource.html) which replays the
+    /// recorded atoms at the times they were recorded.
+    ///
+    /// Returns an error if any record refers to a species, as the source is created without species.
+    pub fn to_source(&self) -> Result<AtomFileSource, AtomFileError> {
+        AtomFileSource::new(self.records.clone(), FileEmission::Timed, Vec::new())
+    }
+
+    /// Adds a record of an atom at the given time.
+    ///
+    /// The `weight` is that of the atom's [Weight](../../atom/struct.Weight.html) component, if it has one.
+    pub fn record(
+        &mut self,
+        position: Vector3<f64>,
+        velocity: Vector3<f64>,
+        mass: f64,
+        time: f64,
+        weight: Option<&Weight>,
+    ) {
+        let mut record = AtomRecord::new(position, velocity);
+        record.mass = Some(mass);
+        record.time = time;
+        record.weight = weight.map(|weight| weight.value);
+        self.records.push(record);
+    }
+}
+
+/// A component which records atoms crossing a plane.
+///
+/// The plane passes through the `Position` of the entity. Atoms are recorded when they cross the plane
+/// travelling in the direction of `normal`.
+pub struct RecordingPlane {
+    /// A unit vector normal to the plane.
+    pub normal: Vector3<f64>,
+    /// If true, atoms are removed from the simulation once they have crossed the plane.
+    pub remove: bool,
+}
+impl RecordingPlane {
+    pub fn new(normal: Vector3<f64>, remove: bool) -> Self {
+        RecordingPlane {
+            normal: normal.normalize(),
+            remove: remove,
+        }
+    }
+}
+impl Component for RecordingPlane {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Records atoms which crossed a [RecordingPlane](struct.RecordingPlane.html) during the last step.
+///
+/// The position and time at which the atom crossed the plane are estimated by assuming the atom moved
+/// in a straight line during the step.
+pub struct RecordPlaneCrossingsSystem;
+impl<'a> System<'a> for RecordPlaneCrossingsSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, RecordingPlane>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, Mass>,
+        ReadStorage<'a, Weight>,
+        ReadStorage<'a, Atom>,
+        Option<Write<'a, EscapedAtoms>>,
+        ReadExpect<'a, Timestep>,
+        Read<'a, SimulationTime>,
+        Read<'a, LazyUpdate>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            planes,
+            positions,
+            velocities,
+            masses,
+            weights,
+            atoms,
+            escaped,
+            timestep,
+            time,
+            updater,
+        ): Self::SystemData,
+    ) {
+        let mut escaped = match escaped {
+            Some(escaped) => escaped,
+            None => return,
+        };
+        let current_time = time.time;
+        for (plane, plane_position) in (&planes, &positions).join() {
+            for (entity, position, velocity, mass, weight, _) in (
+                &entities,
+                &positions,
+                &velocities,
+                &masses,
+                weights.maybe(),
+                &atoms,
+            )
+                .join()
+            {
+                let distance = (position.pos - plane_position.pos).dot(&plane.normal);
+                let previous_distance = distance - timestep.delta * velocity.vel.dot(&plane.normal);
+                if previous_distance < 0.0 && distance >= 0.0 {
+                    let time_since_crossing =
+                        timestep.delta * distance / (distance - previous_distance);
+                    escaped.record(
+                        position.pos - velocity.vel * time_since_crossing,
+                        velocity.vel,
+                        mass.value,
+                        current_time - time_since_crossing,
+                        weight,
+                    );
+                    if plane.remove {
+                        updater.insert(entity, ToBeDestroyed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_record_plane_crossings() {
+        let mut test_world = World::new();
+        test_world.register::<RecordingPlane>();
+        test_world.register::<Position>();
+        test_world.register::<Velocity>();
+        test_world.register::<Mass>();
+        test_world.register::<Weight>();
+        test_world.register::<Atom>();
+        test_world.register::<ToBeDestroyed>();
+        test_world.insert(Timestep { delta: 1e-3 });
+        test_world.insert(SimulationTime { time: 1e-2 });
+        test_world.insert(EscapedAtoms::default());
+
+        test_world
+            .create_entity()
+            .with(RecordingPlane::new(Vector3::z(), true))
+            .with(Position::new())
+            .build();
+        let crossed = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.1, 0.0, 0.25e-3),
+            })
+            .with(Velocity {
+                vel: Vector3::new(0.0, 0.0, 1.0),
+            })
+            .with(Mass { value: 87.0 })
+            .with(Atom)
+            .build();
+        let reversed = test_world
+            .create_entity()
+            .with(Position {
+                pos: Vector3::new(0.0, 0.0, 0.25e-3),
+            })
+            .with(Velocity {
+                vel: Vector3::new(0.0, 0.0, -1.0),
+            })
+            .with(Mass { value: 87.0 })
+            .with(Atom)
+            .build();
+
+        let mut system = RecordPlaneCrossingsSystem;
+        system.run_now(&test_world);
+        test_world.maintain();
+
+        let escaped = test_world.read_resource::<EscapedAtoms>();
+        assert_eq!(escaped.records.len(), 1);
+        let record = &escaped.records[0];
+        assert_approx_eq!(record.time, 9.75e-3, 1e-12);
+        assert_approx_eq!(record.z, 0.0, 1e-12);
+        assert_eq!(record.x, 0.1);
+        assert_eq!(record.mass, Some(87.0));
+        let destroyed = test_world.read_storage::<ToBeDestroyed>();
+        assert!(destroyed.contains(crossed));
+        assert!(!destroyed.contains(reversed));
+    }
+    #[test]
+    fn test_write_csv() {
+        let mut escaped = EscapedAtoms::default();
+        escaped.record(
+            Vector3::new(0.1, 0.2, 0.3),
+            Vector3::new(1.0, 2.0, 3.0),
+            87.0,
+            1e-3,
+            None,
+        );
+        let file_name = std::env::temp_dir()
+            .join(format!("atomecs_{}_escaped.csv", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        escaped.write_csv(&file_name).unwrap();
+        let source = AtomFileSource::from_csv(&file_name, FileEmission::Timed, Vec::new()).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        assert_eq!(source.remaining(), 1);
+
+        let missing_directory = std::env::temp_dir()
+            .join("atomecs_missing_directory")
+            .join("escaped.csv");
+        assert!(escaped
+            .write_csv(missing_directory.to_str().unwrap())
+            .is_err());
+    }
+}
@@ -1,5 +1,6 @@
 //! Create output from the simulation, such as atomic trajectories.
 
 pub mod console_output;
+pub mod escape;
 pub mod file;
 pub mod memory_output;
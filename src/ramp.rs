@@ -14,7 +14,7 @@
 
 use specs::prelude::*;
 
-use crate::integrator::{Step, Timestep};
+use crate::integrator::SimulationTime;
 use std::marker::PhantomData;
 
 pub trait Lerp<T> {
@@ -36,13 +36,17 @@ impl<T> Ramp<T>
 where
     T: Lerp<T> + Component + Clone,
 {
+    /// Gets the value of the ramp at the given time, in s.
+    ///
+    /// The time may move in either direction between calls, as it does during the stages of the
+    /// multistage integrators.
     pub fn get_value(&mut self, current_time: f64) -> T {
-        // check if we need to advance cursor
-        if !self.at_end() {
-            let (t0, _) = &self.keyframes[self.prev + 1];
-            if current_time > *t0 {
-                self.prev = (self.prev + 1).min(self.keyframes.len() - 1);
-            }
+        // move the cursor forwards or backwards to the keyframe preceding the current time
+        while !self.at_end() && current_time > self.keyframes[self.prev + 1].0 {
+            self.prev = self.prev + 1;
+        }
+        while self.prev > 0 && current_time < self.keyframes[self.prev].0 {
+            self.prev = self.prev - 1;
         }
         // if at end, return last frame value.
         if self.at_end() {
@@ -103,12 +107,11 @@ where
     type SystemData = (
         WriteStorage<'a, T>,
         WriteStorage<'a, Ramp<T>>,
-        ReadExpect<'a, Timestep>,
-        ReadExpect<'a, Step>,
+        Read<'a, SimulationTime>,
     );
 
-    fn run(&mut self, (mut comps, mut ramps, timestep, step): Self::SystemData) {
-        let current_time = step.n as f64 * timestep.delta;
+    fn run(&mut self, (mut comps, mut ramps, time): Self::SystemData) {
+        let current_time = time.time;
 
         for (ramp, comp) in (&mut ramps, &mut comps).join() {
             comp.clone_from(&ramp.get_value(current_time));
@@ -169,9 +172,30 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_ramp_time_reversal() {
+        use assert_approx_eq::assert_approx_eq;
+
+        let mut frames = Vec::new();
+        frames.push((0.0, ALerpComp { value: 0.0 }));
+        frames.push((1.0, ALerpComp { value: 1.0 }));
+        frames.push((2.0, ALerpComp { value: 0.0 }));
+        frames.push((3.0, ALerpComp { value: 2.0 }));
+        let mut ramp = Ramp::new(frames);
+
+        // Jumping over several keyframes at once.
+        assert_approx_eq!(ramp.get_value(2.5).value, 1.0, std::f64::EPSILON);
+        // Moving back in time, as the stages of a multistage integrator do.
+        assert_approx_eq!(ramp.get_value(0.5).value, 0.5, std::f64::EPSILON);
+        assert_approx_eq!(ramp.get_value(1.5).value, 0.5, std::f64::EPSILON);
+        assert_approx_eq!(ramp.get_value(0.25).value, 0.25, std::f64::EPSILON);
+        assert_approx_eq!(ramp.get_value(3.5).value, 2.0, std::f64::EPSILON);
+        assert_approx_eq!(ramp.get_value(2.75).value, 1.5, std::f64::EPSILON);
+    }
+
     #[test]
     fn test_ramp_system() {
-        use crate::integrator::VelocityVerletIntegratePositionSystem;
+        use crate::integrator::{Step, Timestep, VelocityVerletIntegratePositionSystem};
         use assert_approx_eq::assert_approx_eq;
         use specs::{Builder, DispatcherBuilder, ReadStorage, World};
 
@@ -203,6 +227,7 @@ pub mod tests {
         let dt = 0.1;
         test_world.insert(Timestep { delta: dt });
         test_world.insert(Step { n: 0 });
+        test_world.insert(SimulationTime::default());
 
         // Perform dispatcher loop to ramp components.
         for i in 1..10 {
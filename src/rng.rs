@@ -0,0 +1,174 @@
+//! Reproducible generation of random numbers.
+//!
+//! By default, every simulation run draws different random numbers. To make a run reproducible,
+//! add a [RandomSeed](struct.RandomSeed.html) resource to the world. Runs with the same seed then
+//! produce bit-identical results.
+//!
+//! Each frame, the [UpdateFrameRandomKeySystem](struct.UpdateFrameRandomKeySystem.html) derives a key
+//! from the seed and the current step. Stochastic systems create [RandomStreams](struct.RandomStreams.html)
+//! from this key and the name of the system, so that every system draws from an independent stream.
+//! Systems which iterate over atoms in parallel draw from a separate stream for each atom, keyed by
+//! the atom's entity. The numbers drawn for each atom therefore do not depend on the order in which
+//! atoms are processed, or on the number of threads used.
+
+use crate::integrator::Step;
+use rand;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use specs::prelude::*;
+
+/// The random number generator used by the simulation.
+///
+/// `ChaCha8Rng` produces the same sequence on every platform, and supports independent streams.
+pub type SimulationRng = ChaCha8Rng;
+
+/// A resource which sets the seed of the simulation's random number generators.
+///
+/// If this resource is not present, a different seed is used for each run.
+pub struct RandomSeed {
+    pub seed: u64,
+}
+
+/// A resource which holds the random key for the current frame.
+///
+/// The key is updated each frame by the [UpdateFrameRandomKeySystem](struct.UpdateFrameRandomKeySystem.html).
+#[derive(Default)]
+pub struct FrameRandomKey {
+    pub key: u64,
+}
+
+impl FrameRandomKey {
+    /// Gets the key for the given substep of the frame.
+    ///
+    /// Systems which are run again at each substep draw from this key, so that their random numbers are
+    /// independent of those drawn for the frame and for other substeps.
+    pub fn for_substep(&self, substep: u32) -> FrameRandomKey {
+        FrameRandomKey {
+            key: mix(self.key, substep as u64),
+        }
+    }
+}
+
+/// Updates the [FrameRandomKey](struct.FrameRandomKey.html) at the start of each frame.
+pub struct UpdateFrameRandomKeySystem;
+impl<'a> System<'a> for UpdateFrameRandomKeySystem {
+    type SystemData = (
+        Option<Read<'a, RandomSeed>>,
+        Option<Read<'a, Step>>,
+        Write<'a, FrameRandomKey>,
+    );
+
+    fn run(&mut self, (seed, step, mut frame_key): Self::SystemData) {
+        frame_key.key = match seed {
+            Some(seed) => {
+                let step = match step {
+                    Some(step) => step.n,
+                    None => 0,
+                };
+                mix(mix(seed.seed, 0), step)
+            }
+            None => rand::thread_rng().gen(),
+        };
+    }
+}
+
+/// Independent streams of random numbers used by a single system in the current frame.
+pub struct RandomStreams {
+    key: u64,
+}
+impl RandomStreams {
+    /// Creates the random streams for the named system.
+    ///
+    /// If there is no [FrameRandomKey](struct.FrameRandomKey.html), a random key is used instead.
+    pub fn new(frame_key: Option<&FrameRandomKey>, system: &str) -> Self {
+        let key = match frame_key {
+            Some(frame_key) => frame_key.key,
+            None => rand::thread_rng().gen(),
+        };
+        RandomStreams {
+            key: mix(key, hash_name(system)),
+        }
+    }
+
+    /// Gets a generator for the system as a whole.
+    ///
+    /// Numbers drawn from this generator depend on the order in which they are drawn, so it should
+    /// only be used when iterating sequentially.
+    pub fn rng(&self) -> SimulationRng {
+        SimulationRng::seed_from_u64(self.key)
+    }
+
+    /// Gets a generator for the given entity, which is independent of the generators of other entities.
+    pub fn entity_rng(&self, entity: Entity) -> SimulationRng {
+        let mut rng = self.rng();
+        rng.set_stream(((entity.gen().id() as u64) << 32) | entity.id() as u64);
+        rng
+    }
+}
+
+/// Combines two values into a well-mixed 64 bit key, using the SplitMix64 finaliser.
+fn mix(a: u64, b: u64) -> u64 {
+    let b = b
+        .wrapping_add(0x9E3779B97F4A7C15)
+        .wrapping_mul(0xBF58476D1CE4E5B9);
+    let mut z = a ^ b;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Hashes a system name using FNV-1a, which is stable between builds.
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+
+    fn frame_key(seed: Option<u64>, step: u64) -> u64 {
+        let mut test_world = World::new();
+        if let Some(seed) = seed {
+            test_world.insert(RandomSeed { seed: seed });
+        }
+        test_world.insert(Step { n: step });
+        test_world.insert(FrameRandomKey::default());
+        UpdateFrameRandomKeySystem.run_now(&test_world);
+        let key = test_world.read_resource::<FrameRandomKey>().key;
+        key
+    }
+
+    #[test]
+    fn test_seeded_streams_are_reproducible() {
+        assert_eq!(frame_key(Some(7), 3), frame_key(Some(7), 3));
+        assert_ne!(frame_key(Some(7), 3), frame_key(Some(7), 4));
+        assert_ne!(frame_key(Some(7), 3), frame_key(Some(8), 3));
+        assert_ne!(frame_key(None, 3), frame_key(None, 3));
+
+        let key = FrameRandomKey {
+            key: frame_key(Some(7), 3),
+        };
+        let a: f64 = RandomStreams::new(Some(&key), "a").rng().gen();
+        let b: f64 = RandomStreams::new(Some(&key), "b").rng().gen();
+        assert_eq!(a, RandomStreams::new(Some(&key), "a").rng().gen::<f64>());
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_entity_streams_are_independent() {
+        let mut test_world = World::new();
+        let first = test_world.create_entity().build();
+        let second = test_world.create_entity().build();
+
+        let streams = RandomStreams::new(Some(&FrameRandomKey { key: 42 }), "test");
+        let first_value: u64 = streams.entity_rng(first).gen();
+        let second_value: u64 = streams.entity_rng(second).gen();
+        assert_ne!(first_value, second_value);
+        // The stream of an entity does not depend on which other streams have been used.
+        let again = RandomStreams::new(Some(&FrameRandomKey { key: 42 }), "test");
+        assert_eq!(again.entity_rng(first).gen::<u64>(), first_value);
+    }
+}
@@ -0,0 +1,360 @@
+//! Running the simulation until a stop condition is met.
+//!
+//! Rather than dispatching a fixed number of frames, a [RunController](struct.RunController.html) can be used
+//! to run the simulation until one of a set of [StopCondition](enum.StopCondition.html)s is met, eg:
+//!
+//! ```ignore
+//! let reason = RunController::new()
+//!     .until_time(1e-3)
+//!     .until_no_atoms()
+//!     .with_wall_clock_limit(Duration::from_secs(600))
+//!     .run(&mut dispatcher, &mut world);
+//! ```
+//!
+//! The conditions are checked at the end of each frame, after the world has been maintained. The time of the
+//! simulation is read from the [SimulationTime](../integrator/struct.SimulationTime.html) resource.
+
+use crate::atom::Atom;
+use crate::integrator::{SimulationTime, Step};
+use specs::prelude::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A condition which stops the simulation when met.
+pub enum StopCondition {
+    /// Stops once the [SimulationTime](../integrator/struct.SimulationTime.html) reaches the given time, in s.
+    EndTime(f64),
+    /// Stops once the given number of frames have been dispatched.
+    MaxFrames(u64),
+    /// Stops when the number of atoms falls to zero, after atoms have been present in the simulation.
+    NoAtoms,
+    /// Stops once the run has taken longer than the given wall-clock duration.
+    WallClock(Duration),
+    /// Stops once a watched observable has reached a steady state.
+    SteadyState(SteadyState),
+}
+
+/// Detects a steady state in an observable of the simulation, such as the number of trapped atoms.
+///
+/// The observable is sampled every `interval` frames. A steady state is reached when the last `samples`
+/// values of the observable all lie within a fraction `tolerance` of their mean. An observable which
+/// remains at zero, such as the number of trapped atoms before loading begins, is never steady.
+///
+/// Samples taken before the start time, set with [with_start_time](struct.SteadyState.html#method.with_start_time),
+/// are ignored.
+pub struct SteadyState {
+    observable: Box<dyn Fn(&World) -> f64>,
+    interval: u64,
+    samples: usize,
+    tolerance: f64,
+    start_time: f64,
+    history: VecDeque<f64>,
+}
+impl SteadyState {
+    /// Creates a new `SteadyState` condition.
+    ///
+    /// # Arguments
+    ///
+    /// `observable`: calculates the watched value from the world.
+    ///
+    /// `interval`: number of frames between samples of the observable.
+    ///
+    /// `samples`: number of consecutive samples which must agree.
+    ///
+    /// `tolerance`: maximum spread of the samples, as a fraction of their mean.
+    pub fn new<F>(observable: F, interval: u64, samples: usize, tolerance: f64) -> Self
+    where
+        F: Fn(&World) -> f64 + 'static,
+    {
+        if interval == 0 || samples < 2 {
+            panic!("SteadyState requires a non-zero interval and at least two samples.");
+        }
+        SteadyState {
+            observable: Box::new(observable),
+            interval: interval,
+            samples: samples,
+            tolerance: tolerance,
+            start_time: 0.0,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Ignores the observable until the simulation time reaches `start_time`, in s.
+    pub fn with_start_time(mut self, start_time: f64) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    /// Adds a sample of the observable to the history.
+    fn sample(&mut self, world: &World) {
+        self.history.push_back((self.observable)(world));
+        if self.history.len() > self.samples {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns true if the sampled values have reached a steady state.
+    fn is_steady(&self) -> bool {
+        if self.history.len() < self.samples {
+            return false;
+        }
+        let mean = self.history.iter().sum::<f64>() / self.history.len() as f64;
+        if mean == 0.0 {
+            return false;
+        }
+        let max = self.history.iter().cloned().fold(f64::MIN, f64::max);
+        let min = self.history.iter().cloned().fold(f64::MAX, f64::min);
+        max - min <= self.tolerance * mean.abs()
+    }
+}
+
+/// The reason a run was stopped.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+    EndTime,
+    MaxFrames,
+    NoAtoms,
+    WallClock,
+    SteadyState,
+}
+
+/// Runs the simulation until one of a number of [StopCondition](enum.StopCondition.html)s is met.
+pub struct RunController {
+    conditions: Vec<StopCondition>,
+}
+impl RunController {
+    pub fn new() -> Self {
+        RunController {
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Adds a [StopCondition](enum.StopCondition.html) to the controller.
+    pub fn until(&mut self, condition: StopCondition) -> &mut Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Stops the run once the simulation time reaches `time`, in s.
+    pub fn until_time(&mut self, time: f64) -> &mut Self {
+        self.until(StopCondition::EndTime(time))
+    }
+
+    /// Stops the run after `frames` frames.
+    pub fn until_frames(&mut self, frames: u64) -> &mut Self {
+        self.until(StopCondition::MaxFrames(frames))
+    }
+
+    /// Stops the run once all atoms have left the simulation.
+    pub fn until_no_atoms(&mut self) -> &mut Self {
+        self.until(StopCondition::NoAtoms)
+    }
+
+    /// Stops the run once the given observable has reached a steady state. See [SteadyState](struct.SteadyState.html).
+    pub fn until_steady_state<F>(
+        &mut self,
+        observable: F,
+        interval: u64,
+        samples: usize,
+        tolerance: f64,
+    ) -> &mut Self
+    where
+        F: Fn(&World) -> f64 + 'static,
+    {
+        self.until(StopCondition::SteadyState(SteadyState::new(
+            observable, interval, samples, tolerance,
+        )))
+    }
+
+    /// Stops the run once it has taken longer than `limit` in wall-clock time.
+    pub fn with_wall_clock_limit(&mut self, limit: Duration) -> &mut Self {
+        self.until(StopCondition::WallClock(limit))
+    }
+
+    /// Dispatches frames until one of the stop conditions is met, and returns the reason the run stopped.
+    ///
+    /// Panics if no stop conditions have been added.
+    pub fn run(&mut self, dispatcher: &mut Dispatcher, world: &mut World) -> StopReason {
+        if self.conditions.is_empty() {
+            panic!("RunController requires at least one stop condition.");
+        }
+        world
+            .entry::<SimulationTime>()
+            .or_insert_with(Default::default);
+        world.entry::<Step>().or_insert_with(|| Step { n: 0 });
+
+        let start = Instant::now();
+        let mut frames: u64 = 0;
+        let mut atoms_present = false;
+        loop {
+            dispatcher.dispatch(world);
+            world.maintain();
+            frames = frames + 1;
+
+            let atom_number = world.read_storage::<Atom>().join().count();
+            atoms_present = atoms_present || atom_number > 0;
+            let time = world.read_resource::<SimulationTime>().time;
+            for condition in self.conditions.iter_mut() {
+                let stop = match condition {
+                    StopCondition::EndTime(end) => {
+                        if time >= *end {
+                            Some(StopReason::EndTime)
+                        } else {
+                            None
+                        }
+                    }
+                    StopCondition::MaxFrames(max) => {
+                        if frames >= *max {
+                            Some(StopReason::MaxFrames)
+                        } else {
+                            None
+                        }
+                    }
+                    StopCondition::NoAtoms => {
+                        if atoms_present && atom_number == 0 {
+                            Some(StopReason::NoAtoms)
+                        } else {
+                            None
+                        }
+                    }
+                    StopCondition::WallClock(limit) => {
+                        if start.elapsed() >= *limit {
+                            Some(StopReason::WallClock)
+                        } else {
+                            None
+                        }
+                    }
+                    StopCondition::SteadyState(steady_state) => {
+                        if frames % steady_state.interval == 0 && time >= steady_state.start_time {
+                            steady_state.sample(world);
+                        }
+                        if steady_state.is_steady() {
+                            Some(StopReason::SteadyState)
+                        } else {
+                            None
+                        }
+                    }
+                };
+                if let Some(reason) = stop {
+                    return reason;
+                }
+            }
+        }
+    }
+}
+impl Default for RunController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::integrator::Timestep;
+    use specs::{Builder, DispatcherBuilder, World};
+
+    /// Advances the simulation time, and removes the atoms after a number of frames.
+    struct ClockSystem {
+        remove_atoms_after: f64,
+    }
+    impl<'a> System<'a> for ClockSystem {
+        type SystemData = (
+            Entities<'a>,
+            ReadStorage<'a, Atom>,
+            ReadExpect<'a, Timestep>,
+            Write<'a, SimulationTime>,
+        );
+        fn run(&mut self, (entities, atoms, timestep, mut time): Self::SystemData) {
+            time.time = time.time + timestep.delta;
+            if time.time > self.remove_atoms_after {
+                for (entity, _) in (&entities, &atoms).join() {
+                    entities.delete(entity).expect("Could not delete entity");
+                }
+            }
+        }
+    }
+
+    fn create_world(remove_atoms_after: f64) -> (World, Dispatcher<'static, 'static>) {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(
+                ClockSystem {
+                    remove_atoms_after: remove_atoms_after,
+                },
+                "clock",
+                &[],
+            )
+            .build();
+        dispatcher.setup(&mut world);
+        world.insert(Timestep { delta: 1.0 });
+        world.create_entity().with(Atom).build();
+        (world, dispatcher)
+    }
+
+    #[test]
+    fn test_run_until_time() {
+        let (mut world, mut dispatcher) = create_world(100.0);
+        let reason = RunController::new()
+            .until_time(10.0)
+            .until_no_atoms()
+            .run(&mut dispatcher, &mut world);
+        assert_eq!(reason, StopReason::EndTime);
+        assert_eq!(world.read_resource::<SimulationTime>().time, 10.0);
+    }
+
+    #[test]
+    fn test_run_until_no_atoms() {
+        let (mut world, mut dispatcher) = create_world(5.0);
+        let reason = RunController::new()
+            .until_time(10.0)
+            .until_no_atoms()
+            .run(&mut dispatcher, &mut world);
+        assert_eq!(reason, StopReason::NoAtoms);
+        assert_eq!(world.read_resource::<SimulationTime>().time, 6.0);
+    }
+
+    #[test]
+    fn test_run_until_steady_state() {
+        let (mut world, mut dispatcher) = create_world(100.0);
+        // observable approaches 1 as 1 - exp(-t / 5).
+        let reason = RunController::new()
+            .until_frames(1000)
+            .until_steady_state(
+                |world| 1.0 - (-world.read_resource::<SimulationTime>().time / 5.0).exp(),
+                2,
+                5,
+                0.01,
+            )
+            .run(&mut dispatcher, &mut world);
+        assert_eq!(reason, StopReason::SteadyState);
+        let time = world.read_resource::<SimulationTime>().time;
+        // spread over the last 8 s is below 1% once exp(-t/5) * (exp(8/5) - 1) < 0.01
+        assert!(time > 25.0 && time < 35.0);
+    }
+
+    #[test]
+    fn test_zero_observable_is_not_steady() {
+        let (mut world, mut dispatcher) = create_world(100.0);
+        let reason = RunController::default()
+            .until_frames(50)
+            .until_steady_state(|_| 0.0, 1, 5, 0.01)
+            .run(&mut dispatcher, &mut world);
+        assert_eq!(reason, StopReason::MaxFrames);
+    }
+
+    #[test]
+    fn test_steady_state_start_time() {
+        let (mut world, mut dispatcher) = create_world(100.0);
+        // observable is constant from the start.
+        let reason = RunController::new()
+            .until_frames(1000)
+            .until(StopCondition::SteadyState(
+                SteadyState::new(|_| 1.0, 1, 5, 0.01).with_start_time(20.0),
+            ))
+            .run(&mut dispatcher, &mut world);
+        assert_eq!(reason, StopReason::SteadyState);
+        assert_eq!(world.read_resource::<SimulationTime>().time, 24.0);
+    }
+}
@@ -1,7 +1,6 @@
 //! Support for different shapes.
 
 use nalgebra::Vector3;
-use rand;
 use rand::Rng;
 use specs::{Component, HashMapStorage};
 
@@ -11,9 +10,10 @@ pub trait Volume {
 
 pub trait Surface {
     /// Returns (random point, normal) on the surface, uniformly distributed. The normal points outwards.
-    fn get_random_point_on_surface(
+    fn get_random_point_on_surface<R: Rng + ?Sized>(
         &self,
         surface_position: &Vector3<f64>,
+        rng: &mut R,
     ) -> (Vector3<f64>, Vector3<f64>);
 }
 
@@ -64,12 +64,12 @@ impl Volume for Cylinder {
 }
 
 impl Surface for Cylinder {
-    fn get_random_point_on_surface(
+    fn get_random_point_on_surface<R: Rng + ?Sized>(
         &self,
         surface_position: &Vector3<f64>,
+        rng: &mut R,
     ) -> (Vector3<f64>, Vector3<f64>) {
         // Should we spawn a point on the ends or the sleeve?
-        let mut rng = rand::thread_rng();
         let spawn_on_ends = rng.gen_range(0.0..1.0) < (self.radius / (self.length + self.radius));
 
         if spawn_on_ends {
@@ -110,20 +110,17 @@ impl Volume for Sphere {
 }
 
 impl Surface for Sphere {
-    fn get_random_point_on_surface(
+    fn get_random_point_on_surface<R: Rng + ?Sized>(
         &self,
         surface_position: &Vector3<f64>,
+        rng: &mut R,
     ) -> (Vector3<f64>, Vector3<f64>) {
-        let mut rng = rand::thread_rng();
-
-        let theta = rng.gen_range(0.0..std::f64::consts::PI);
+        // cos(theta) is uniformly distributed for points uniform on the sphere.
+        let cos_theta: f64 = rng.gen_range(-1.0..1.0);
+        let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
         let phi = rng.gen_range(0.0..2.0 * std::f64::consts::PI);
 
-        let normal = Vector3::new(
-            theta.sin() * phi.cos(),
-            theta.sin() * phi.sin(),
-            theta.cos(),
-        );
+        let normal = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
         let position = surface_position + self.radius * normal;
         return (position, normal);
     }
@@ -149,20 +146,30 @@ impl Volume for Cuboid {
 }
 
 impl Surface for Cuboid {
-    fn get_random_point_on_surface(
+    fn get_random_point_on_surface<R: Rng + ?Sized>(
         &self,
         surface_position: &Vector3<f64>,
+        rng: &mut R,
     ) -> (Vector3<f64>, Vector3<f64>) {
-        let mut rng = rand::thread_rng();
-
         let mut point = Vector3::new(
             rng.gen_range(-self.half_width[0]..self.half_width[0]),
             rng.gen_range(-self.half_width[1]..self.half_width[1]),
             rng.gen_range(-self.half_width[2]..self.half_width[2]),
         );
 
-        // move to a random edge
-        let edge = rng.gen_range(0..6);
+        // move to a random face, weighted by the area of each face
+        let face_areas = [
+            self.half_width[1] * self.half_width[2],
+            self.half_width[0] * self.half_width[2],
+            self.half_width[0] * self.half_width[1],
+        ];
+        let mut f = rng.gen_range(0.0..face_areas.iter().sum::<f64>());
+        let mut axis = 0;
+        while axis < 2 && f >= face_areas[axis] {
+            f -= face_areas[axis];
+            axis += 1;
+        }
+        let edge = 2 * axis + rng.gen_range(0..2);
         match edge {
             0 => point[0] = -self.half_width[0],
             1 => point[0] = self.half_width[0],
@@ -7,8 +7,10 @@
 // Perhaps there is some nice macro I can write to produce the required attachment systems?
 // This pattern is also used elsewhere, eg `MagneticFieldSampler`.
 
-use crate::atom::Position;
+use crate::atom::{Mass, Position, Velocity, Weight};
 use crate::initiate::NewlyCreated;
+use crate::integrator::SimulationTime;
+use crate::output::escape::EscapedAtoms;
 use crate::shapes::{Cuboid, Cylinder, Sphere, Volume};
 use specs::prelude::*;
 use std::marker::PhantomData;
@@ -131,6 +133,42 @@ impl<'a> System<'a> for DeleteFailedRegionTestsSystem {
     }
 }
 
+/// This system records entities which are about to be deleted for leaving the simulation volume,
+/// if the [EscapedAtoms](../output/escape/struct.EscapedAtoms.html) resource has been added to the world.
+struct RecordEscapedAtomsSystem;
+impl<'a> System<'a> for RecordEscapedAtomsSystem {
+    type SystemData = (
+        ReadStorage<'a, RegionTest>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, Mass>,
+        ReadStorage<'a, Weight>,
+        Option<Write<'a, EscapedAtoms>>,
+        Option<Read<'a, SimulationTime>>,
+    );
+
+    fn run(
+        &mut self,
+        (tests, positions, velocities, masses, weights, escaped, time): Self::SystemData,
+    ) {
+        let (mut escaped, time) = match (escaped, time) {
+            (Some(escaped), Some(time)) => (escaped, time),
+            _ => return,
+        };
+        let current_time = time.time;
+        for (test, position, velocity, mass, weight) in
+            (&tests, &positions, &velocities, &masses, weights.maybe()).join()
+        {
+            match test.result {
+                Result::Reject | Result::Failed => {
+                    escaped.record(position.pos, velocity.vel, mass.value, current_time, weight)
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
 /// This sytem attaches [RegionTest](struct.RegionTest.html) components
 /// to all entities that are [NewlyCreated](struct.NewlyCreated.html).
 struct AttachRegionTestsToNewlyCreatedSystem;
@@ -185,10 +223,15 @@ pub fn add_systems_to_dispatch(
         "region_test_cylinder",
         &["region_test_cuboid"],
     );
+    builder.add(
+        RecordEscapedAtomsSystem,
+        "record_region_test_failure",
+        &["region_test_cylinder"],
+    );
     builder.add(
         DeleteFailedRegionTestsSystem,
         "delete_region_test_failure",
-        &["region_test_cylinder"],
+        &["record_region_test_failure"],
     );
     builder.add(
         AttachRegionTestsToNewlyCreatedSystem,
@@ -197,6 +240,29 @@ pub fn add_systems_to_dispatch(
     );
 }
 
+/// Adds the systems required by `sim_region` to the dispatcher as thread local systems.
+///
+/// Thread local systems run in the order they are added, after all other systems in the frame.
+///
+/// #Arguments
+///
+/// `builder`: the dispatch builder to modify
+pub fn add_thread_local_systems_to_dispatch(builder: &mut DispatcherBuilder<'static, 'static>) {
+    builder.add_thread_local(AttachRegionTestsToNewlyCreatedSystem);
+    builder.add_thread_local(ClearRegionTestSystem);
+    builder.add_thread_local(RegionTestSystem::<Sphere> {
+        marker: PhantomData,
+    });
+    builder.add_thread_local(RegionTestSystem::<Cuboid> {
+        marker: PhantomData,
+    });
+    builder.add_thread_local(RegionTestSystem::<Cylinder> {
+        marker: PhantomData,
+    });
+    builder.add_thread_local(RecordEscapedAtomsSystem);
+    builder.add_thread_local(DeleteFailedRegionTestsSystem);
+}
+
 /// Registers resources required by magnetics to the ecs world.
 pub fn register_components(world: &mut World) {
     world.register::<Sphere>();
@@ -361,6 +427,42 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_escaped_atoms_are_recorded() {
+        use crate::atom::{Mass, Velocity};
+        let mut test_world = World::new();
+        register_components(&mut test_world);
+        test_world.register::<Position>();
+        test_world.register::<Velocity>();
+        test_world.register::<Mass>();
+        test_world.register::<Weight>();
+        test_world.insert(EscapedAtoms::default());
+        test_world.insert(SimulationTime { time: 5e-6 });
+
+        for result in vec![Result::Failed, Result::Accept] {
+            test_world
+                .create_entity()
+                .with(RegionTest { result: result })
+                .with(Position {
+                    pos: Vector3::new(1.0, 2.0, 3.0),
+                })
+                .with(Velocity {
+                    vel: Vector3::new(4.0, 5.0, 6.0),
+                })
+                .with(Mass { value: 88.0 })
+                .build();
+        }
+
+        let mut system = RecordEscapedAtomsSystem;
+        system.run_now(&test_world);
+
+        let escaped = test_world.read_resource::<EscapedAtoms>();
+        assert_eq!(escaped.records.len(), 1);
+        assert_eq!(escaped.records[0].position(), Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(escaped.records[0].velocity(), Vector3::new(4.0, 5.0, 6.0));
+        assert!((escaped.records[0].time - 5e-6).abs() < 1e-15);
+    }
+
     #[test]
     fn test_region_tests_are_added() {
         let mut test_world = World::new();
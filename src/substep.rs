@@ -0,0 +1,461 @@
+//! Substepping of fast or strongly-forced atoms.
+//!
+//! The [Timestep](../integrator/struct.Timestep.html) must be small enough to resolve the motion of every atom.
+//! In simulations with a small number of fast atoms, such as those fed by an oven, most atoms could be
+//! integrated with a much larger timestep. When a [Substepping](struct.Substepping.html) resource is added to
+//! the world, atoms which exceed a speed or acceleration threshold are instead advanced with a number of
+//! substeps within each frame. The fields and forces acting on these atoms are recalculated at each substep.
+//!
+//! At the start of each frame, the [SelectSubsteppedAtomsSystem](struct.SelectSubsteppedAtomsSystem.html)
+//! marks the atoms to substep with a [Substepped](struct.Substepped.html) component. These atoms are skipped by
+//! the velocity-verlet integration systems, and remain at their position at the start of the step while the
+//! forces are calculated. The [SubstepIntegrationSystem](struct.SubstepIntegrationSystem.html) then advances
+//! them to the end of the step. It is added as a thread local system, ahead of the frame end systems added by the
+//! [AtomecsDispatcherBuilder](../ecs/struct.AtomecsDispatcherBuilder.html), so that output and region tests see
+//! all atoms at the end of the step. Systems added to the dispatcher with a dependency on
+//! [INTEGRATE_VELOCITY_SYSTEM_NAME](../integrator/constant.INTEGRATE_VELOCITY_SYSTEM_NAME.html) run before
+//! the substeps, and see substepped atoms at the start of the step.
+//!
+//! During each substep, the [SubstepMask](struct.SubstepMask.html) holds the substepped atoms. Field samplers and
+//! force systems only update the atoms selected by [active_atoms](fn.active_atoms.html), so the fields and forces
+//! on other atoms are left as they were calculated for the frame. The [Timestep](../integrator/struct.Timestep.html)
+//! is set to the duration of the substep, and the [FrameRandomKey](../rng/struct.FrameRandomKey.html) to a key
+//! derived for the substep, so that photon scattering is drawn independently for each substep. Events such as
+//! spin flips and atom emission still occur once per frame.
+//!
+//! Substepping is only supported by the [VelocityVerlet](../integrator/enum.Integrator.html) integrator. The
+//! [Substepping](struct.Substepping.html) resource is ignored by the other integrators.
+
+use crate::atom::{Force, Mass, Position, Velocity};
+use crate::constant;
+use crate::integrator::{OldForce, SimulationTime, Timestep};
+use crate::rng::FrameRandomKey;
+use nalgebra::Vector3;
+use specs::prelude::*;
+
+/// A resource which enables substepping of fast or strongly-forced atoms.
+///
+/// Only used with the [VelocityVerlet](../integrator/enum.Integrator.html) integrator.
+pub struct Substepping {
+    /// Atoms faster than this speed are substepped, in m/s.
+    pub max_speed: Option<f64>,
+    /// Atoms with a larger acceleration than this are substepped, in m/s^2.
+    pub max_acceleration: Option<f64>,
+    /// Number of substeps taken by each substepped atom per frame.
+    pub substeps: u32,
+}
+impl Substepping {
+    /// Returns true if an atom with the given speed and acceleration should be substepped.
+    pub fn requires_substeps(&self, speed: f64, acceleration: f64) -> bool {
+        let too_fast = match self.max_speed {
+            Some(max_speed) => speed > max_speed,
+            None => false,
+        };
+        let too_strongly_forced = match self.max_acceleration {
+            Some(max_acceleration) => acceleration > max_acceleration,
+            None => false,
+        };
+        too_fast || too_strongly_forced
+    }
+}
+
+/// Marks an atom which is advanced with substeps during the current frame.
+#[derive(Default)]
+pub struct Substepped;
+impl Component for Substepped {
+    type Storage = NullStorage<Self>;
+}
+
+/// A resource which holds the atoms being advanced by the current substep.
+///
+/// Outside of substeps, no atoms are held and every atom is updated.
+#[derive(Default)]
+pub struct SubstepMask {
+    atoms: Option<BitSet>,
+}
+
+/// Gets the atoms, out of `atoms`, whose fields and forces should be updated.
+///
+/// Field samplers and force systems join against the returned mask. During a substep only the substepped atoms
+/// are selected; otherwise, or if there is no [SubstepMask](struct.SubstepMask.html), every atom is selected.
+pub fn active_atoms(mask: Option<&SubstepMask>, atoms: &BitSet) -> BitSet {
+    match mask.and_then(|mask| mask.atoms.as_ref()) {
+        Some(substepped) => substepped.clone(),
+        None => atoms.clone(),
+    }
+}
+
+/// Marks atoms which require substeps at the start of each frame.
+///
+/// Atoms are selected using their velocity and the force calculated during the previous frame.
+pub struct SelectSubsteppedAtomsSystem;
+impl<'a> System<'a> for SelectSubsteppedAtomsSystem {
+    type SystemData = (
+        Entities<'a>,
+        Option<Read<'a, Substepping>>,
+        ReadStorage<'a, Velocity>,
+        ReadStorage<'a, Force>,
+        ReadStorage<'a, Mass>,
+        WriteStorage<'a, Substepped>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, substepping, velocities, forces, masses, mut substepped): Self::SystemData,
+    ) {
+        substepped.clear();
+        let substepping = match substepping {
+            Some(substepping) => substepping,
+            None => return,
+        };
+
+        for (entity, velocity, force, mass) in (&entities, &velocities, &forces, &masses).join() {
+            let acceleration = force.force.norm() / (constant::AMU * mass.value);
+            if substepping.requires_substeps(velocity.vel.norm(), acceleration) {
+                substepped
+                    .insert(entity, Substepped)
+                    .expect("Could not mark atom for substepping.");
+            }
+        }
+    }
+}
+
+/// Advances [Substepped](struct.Substepped.html) atoms to the end of the step, using the velocity-verlet method
+/// with a number of substeps.
+///
+/// The substeps start from the force stored in [OldForce](../integrator/struct.OldForce.html) at the start of
+/// the frame. The forces at each substep are calculated by running the `forces` dispatcher, with the substepped
+/// atoms moved to their positions at the substep and held in the [SubstepMask](struct.SubstepMask.html).
+///
+/// This system must be added to the dispatcher as a thread local system, so that it runs after the forces of the
+/// frame have been calculated.
+pub struct SubstepIntegrationSystem {
+    forces: Dispatcher<'static, 'static>,
+}
+impl SubstepIntegrationSystem {
+    /// Creates a new `SubstepIntegrationSystem`.
+    ///
+    /// # Arguments
+    ///
+    /// `forces`: a dispatcher which calculates the [Force](../atom/struct.Force.html) on each atom.
+    pub fn new(forces: Dispatcher<'static, 'static>) -> Self {
+        SubstepIntegrationSystem { forces: forces }
+    }
+}
+
+impl<'a> RunNow<'a> for SubstepIntegrationSystem {
+    fn run_now(&mut self, world: &'a World) {
+        let substeps = match world.try_fetch::<Substepping>() {
+            Some(substepping) => substepping.substeps.max(1),
+            None => return,
+        };
+
+        // (entity, mass in kg, position, velocity, acceleration) of each substepped atom.
+        let mut atoms: Vec<(Entity, f64, Vector3<f64>, Vector3<f64>, Vector3<f64>)>;
+        {
+            let (entities, substepped, positions, velocities, old_forces, masses, mut mask): (
+                Entities,
+                ReadStorage<Substepped>,
+                ReadStorage<Position>,
+                ReadStorage<Velocity>,
+                ReadStorage<OldForce>,
+                ReadStorage<Mass>,
+                Write<SubstepMask>,
+            ) = world.system_data();
+            atoms = (
+                &entities,
+                &substepped,
+                &positions,
+                &velocities,
+                &old_forces,
+                &masses,
+            )
+                .join()
+                .map(|(entity, _, position, velocity, old_force, mass)| {
+                    let mass = constant::AMU * mass.value;
+                    (
+                        entity,
+                        mass,
+                        position.pos,
+                        velocity.vel,
+                        old_force.0.force / mass,
+                    )
+                })
+                .collect();
+            if atoms.is_empty() {
+                return;
+            }
+            mask.atoms = Some(substepped.mask().clone());
+        }
+
+        let dt = world.read_resource::<Timestep>().delta;
+        let end_time = world.read_resource::<SimulationTime>().time;
+        let frame_key = world
+            .try_fetch::<FrameRandomKey>()
+            .map(|frame_key| frame_key.key);
+        let h = dt / substeps as f64;
+        world.write_resource::<Timestep>().delta = h;
+        for i in 1..=substeps {
+            {
+                let mut positions = world.write_storage::<Position>();
+                for (entity, _, position, velocity, acceleration) in atoms.iter_mut() {
+                    *position = *position + *velocity * h + *acceleration * h * h / 2.0;
+                    if let Some(stored) = positions.get_mut(*entity) {
+                        stored.pos = *position;
+                    }
+                }
+                world.write_resource::<SimulationTime>().time = end_time - dt + i as f64 * h;
+                if let (Some(key), Some(mut frame_key)) =
+                    (frame_key, world.try_fetch_mut::<FrameRandomKey>())
+                {
+                    *frame_key = FrameRandomKey { key }.for_substep(i);
+                }
+            }
+            self.forces.dispatch(world);
+            let forces = world.read_storage::<Force>();
+            for (entity, mass, _, velocity, acceleration) in atoms.iter_mut() {
+                let new_acceleration = match forces.get(*entity) {
+                    Some(force) => force.force / *mass,
+                    None => Vector3::new(0.0, 0.0, 0.0),
+                };
+                *velocity = *velocity + (*acceleration + new_acceleration) * h / 2.0;
+                *acceleration = new_acceleration;
+            }
+        }
+
+        world.write_resource::<SubstepMask>().atoms = None;
+        world.write_resource::<Timestep>().delta = dt;
+        world.write_resource::<SimulationTime>().time = end_time;
+        if let (Some(key), Some(mut frame_key)) =
+            (frame_key, world.try_fetch_mut::<FrameRandomKey>())
+        {
+            frame_key.key = key;
+        }
+        let mut velocities = world.write_storage::<Velocity>();
+        for (entity, _, _, velocity, _) in atoms.iter() {
+            if let Some(stored) = velocities.get_mut(*entity) {
+                stored.vel = *velocity;
+            }
+        }
+    }
+
+    fn setup(&mut self, world: &mut World) {
+        <(
+            ReadStorage<Substepped>,
+            ReadStorage<Position>,
+            ReadStorage<Velocity>,
+            ReadStorage<Force>,
+            ReadStorage<OldForce>,
+            ReadStorage<Mass>,
+            Write<SimulationTime>,
+            Write<SubstepMask>,
+        ) as SystemData>::setup(world);
+        self.forces.setup(world);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+
+    use super::*;
+    use crate::atom::ClearForceSystem;
+    use crate::integrator::{
+        OldForce, Step, VelocityVerletIntegratePositionSystem,
+        VelocityVerletIntegrateVelocitySystem,
+    };
+    use specs::{Builder, DispatcherBuilder, World};
+    use std::sync::{Arc, Mutex};
+
+    /// Applies a spring force towards the origin.
+    struct SpringForceSystem {
+        k: f64,
+    }
+    impl<'a> System<'a> for SpringForceSystem {
+        type SystemData = (
+            ReadStorage<'a, Position>,
+            WriteStorage<'a, Force>,
+            Option<Read<'a, SubstepMask>>,
+        );
+        fn run(&mut self, (positions, mut forces, substep_mask): Self::SystemData) {
+            let atoms = active_atoms(substep_mask.as_deref(), forces.mask());
+            for (_, position, force) in (&atoms, &positions, &mut forces).join() {
+                force.force = force.force - self.k * position.pos;
+            }
+        }
+    }
+
+    /// Integrates a slow and a fast atom in a harmonic trap with angular frequency 1,
+    /// returning the final positions of each.
+    fn integrate_spring(substepping: Option<Substepping>) -> (Vector3<f64>, Vector3<f64>) {
+        let spring = || SpringForceSystem { k: constant::AMU };
+        let forces = DispatcherBuilder::new()
+            .with(ClearForceSystem, "clear", &[])
+            .with(spring(), "spring", &["clear"])
+            .build();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(SelectSubsteppedAtomsSystem, "select_substepped_atoms", &[])
+            .with(
+                VelocityVerletIntegratePositionSystem,
+                "integrate_position",
+                &["select_substepped_atoms"],
+            )
+            .with(ClearForceSystem, "clear", &["integrate_position"])
+            .with(spring(), "spring", &["clear"])
+            .with(
+                VelocityVerletIntegrateVelocitySystem,
+                "integrate_velocity",
+                &["spring"],
+            )
+            .with_thread_local(SubstepIntegrationSystem::new(forces))
+            .build();
+        let mut world = World::new();
+        dispatcher.setup(&mut world);
+        world.insert(Timestep { delta: 0.2 });
+        world.insert(Step { n: 0 });
+        if let Some(substepping) = substepping {
+            world.insert(substepping);
+        }
+
+        let mut create_atom = |amplitude: f64| {
+            world
+                .create_entity()
+                .with(Position {
+                    pos: Vector3::new(amplitude, 0.0, 0.0),
+                })
+                .with(Velocity {
+                    vel: Vector3::new(0.0, 0.0, 0.0),
+                })
+                .with(Force {
+                    force: Vector3::new(-constant::AMU * amplitude, 0.0, 0.0),
+                })
+                .with(OldForce::default())
+                .with(Mass { value: 1.0 })
+                .build()
+        };
+        let slow = create_atom(0.1);
+        let fast = create_atom(10.0);
+
+        for _ in 0..50 {
+            dispatcher.dispatch(&world);
+            world.maintain();
+        }
+
+        let positions = world.read_storage::<Position>();
+        (
+            positions.get(slow).expect("atom not found").pos,
+            positions.get(fast).expect("atom not found").pos,
+        )
+    }
+
+    #[test]
+    fn test_substepping_improves_accuracy_of_fast_atoms() {
+        let (slow, fast) = integrate_spring(None);
+        let (substepped_slow, substepped_fast) = integrate_spring(Some(Substepping {
+            max_speed: Some(0.5),
+            max_acceleration: None,
+            substeps: 10,
+        }));
+
+        // slow atoms are unaffected by substepping.
+        assert_eq!(slow, substepped_slow);
+
+        let expected = 10.0 * (10.0 as f64).cos();
+        let error = (fast[0] - expected).abs();
+        let substepped_error = (substepped_fast[0] - expected).abs();
+        assert!(substepped_error < error / 10.0);
+        assert!(substepped_error < 1e-2);
+    }
+
+    /// Records the timestep, random key and active atoms seen by the force dispatcher.
+    struct RecordSubstepSystem {
+        records: Arc<Mutex<Vec<(f64, u64, usize)>>>,
+    }
+    impl<'a> System<'a> for RecordSubstepSystem {
+        type SystemData = (
+            ReadExpect<'a, Timestep>,
+            Read<'a, FrameRandomKey>,
+            ReadStorage<'a, Position>,
+            Option<Read<'a, SubstepMask>>,
+        );
+        fn run(&mut self, (timestep, key, positions, substep_mask): Self::SystemData) {
+            let atoms = active_atoms(substep_mask.as_deref(), positions.mask());
+            let number = (&atoms, &positions).join().count();
+            self.records
+                .lock()
+                .unwrap()
+                .push((timestep.delta, key.key, number));
+        }
+    }
+
+    #[test]
+    fn test_substeps_only_update_substepped_atoms() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let forces = DispatcherBuilder::new()
+            .with(
+                RecordSubstepSystem {
+                    records: records.clone(),
+                },
+                "record",
+                &[],
+            )
+            .build();
+        let mut system = SubstepIntegrationSystem::new(forces);
+        let mut world = World::new();
+        world.register::<Substepped>();
+        world.register::<Position>();
+        world.register::<Velocity>();
+        world.register::<Force>();
+        world.register::<OldForce>();
+        world.register::<Mass>();
+        system.setup(&mut world);
+        world.insert(Timestep { delta: 1.0 });
+        world.insert(FrameRandomKey { key: 42 });
+        world.insert(Substepping {
+            max_speed: None,
+            max_acceleration: None,
+            substeps: 4,
+        });
+        for substepped in [true, false, false].iter() {
+            let builder = world
+                .create_entity()
+                .with(Position::new())
+                .with(Velocity {
+                    vel: Vector3::new(1.0, 0.0, 0.0),
+                })
+                .with(Force::new())
+                .with(OldForce::default())
+                .with(Mass { value: 1.0 });
+            if *substepped {
+                builder.with(Substepped).build();
+            } else {
+                builder.build();
+            }
+        }
+
+        system.run_now(&world);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 4);
+        for (i, (delta, key, number)) in records.iter().enumerate() {
+            assert_eq!(*delta, 0.25);
+            assert_eq!(*number, 1);
+            assert_ne!(*key, 42);
+            assert!(records[..i].iter().all(|record| record.1 != *key));
+        }
+        assert_eq!(world.read_resource::<Timestep>().delta, 1.0);
+        assert_eq!(world.read_resource::<FrameRandomKey>().key, 42);
+        assert!(world.read_resource::<SubstepMask>().atoms.is_none());
+    }
+
+    #[test]
+    fn test_requires_substeps() {
+        let substepping = Substepping {
+            max_speed: Some(10.0),
+            max_acceleration: Some(100.0),
+            substeps: 4,
+        };
+        assert!(!substepping.requires_substeps(5.0, 50.0));
+        assert!(substepping.requires_substeps(20.0, 50.0));
+        assert!(substepping.requires_substeps(5.0, 200.0));
+    }
+}